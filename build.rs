@@ -170,7 +170,7 @@ fn main() {
     };
 
     let time_driver_singleton = match time_driver.as_ref().map(|x| x.as_ref()) {
-        None => "",
+        None | Some("rtc") => "",
         Some("tim1") => "TIM1",
         Some("tim2") => "TIM2",
         Some("tim3") => "TIM3",
@@ -197,6 +197,19 @@ fn main() {
         println!("cargo:rustc-cfg=time_driver_timer");
     }
 
+    // The low-power executor enters stop mode, which CH32V003 and CH641 don't have.
+    if env::var("CARGO_FEATURE_LOW_POWER").is_ok() && matches!(&*chip_family, "ch32v0" | "ch641") {
+        panic!("low-power requested, but the chip doesn't have a stop mode");
+    }
+
+    let time_driver_rtc = time_driver.as_deref() == Some("rtc");
+    if time_driver_rtc {
+        if !singletons.contains(&"RTC".to_string()) {
+            panic!("time-driver-rtc requested, but the chip doesn't have an RTC");
+        }
+        println!("cargo:rustc-cfg=time_driver_rtc");
+    }
+
     // ========
     // Write singletons
 
@@ -212,6 +225,8 @@ fn main() {
     let singleton_tokens: Vec<_> = singletons
         .iter()
         // .filter(|s| *s != &time_driver_singleton.to_string())
+        // The RTC is owned by its time driver.
+        .filter(|s| !(time_driver_rtc && *s == "RTC"))
         .map(|s| format_ident!("{}", s))
         .collect();
 
@@ -245,6 +260,7 @@ fn main() {
 
     // ========
     // Generate RccPeripheral and RemapPeripheral impls
+    let mut gate_unused = TokenStream::new();
     for p in METADATA.peripherals {
         if !singletons.contains(&p.name.to_string()) {
             continue;
@@ -271,19 +287,43 @@ fn main() {
             let en_reg = format_ident!("{}", en.register.to_ascii_lowercase());
             let set_en_field = format_ident!("set_{}", en.field.to_ascii_lowercase());
 
+            let en_field = format_ident!("{}", en.field.to_ascii_lowercase());
+
             let clk = format_ident!("{}", rcc.bus_clock.to_ascii_lowercase());
 
+            // Peripherals the HAL keeps running without a driver, or enables behind the refcounts.
+            let kind = p.registers.as_ref().map_or("", |r| r.kind);
+            if !["dma", "gpio", "afio", "pwr", "bkp", "rcc", "flash"].contains(&kind) {
+                gate_unused.extend(quote! {
+                    crate::rcc::disable_unused::<peripherals::#pname>();
+                });
+            }
+
             g.extend(quote! {
                 impl crate::peripheral::SealedRccPeripheral for peripherals::#pname {
                     fn frequency() -> crate::time::Hertz {
                         crate::rcc::clocks().#clk
                     }
-                    fn enable_and_reset_with_cs(_cs: critical_section::CriticalSection) {
+                    fn refcount() -> &'static critical_section::Mutex<core::cell::Cell<u8>> {
+                        static REFCOUNT: critical_section::Mutex<core::cell::Cell<u8>> =
+                            critical_section::Mutex::new(core::cell::Cell::new(0));
+                        &REFCOUNT
+                    }
+                    fn enable_and_reset_with_cs(cs: critical_section::CriticalSection) {
+                        let refcount = Self::refcount().borrow(cs);
+                        refcount.set(refcount.get().saturating_add(1));
                         crate::pac::RCC.#en_reg().modify(|w| w.#set_en_field(true));
                         #rst
                     }
-                    fn disable_with_cs(_cs: critical_section::CriticalSection) {
-                        crate::pac::RCC.#en_reg().modify(|w| w.#set_en_field(false));
+                    fn disable_with_cs(cs: critical_section::CriticalSection) {
+                        let refcount = Self::refcount().borrow(cs);
+                        refcount.set(refcount.get().saturating_sub(1));
+                        if refcount.get() == 0 {
+                            crate::pac::RCC.#en_reg().modify(|w| w.#set_en_field(false));
+                        }
+                    }
+                    fn is_enabled() -> bool {
+                        crate::pac::RCC.#en_reg().read().#en_field()
                     }
                 }
 
@@ -318,6 +358,12 @@ fn main() {
         }
     }
 
+    g.extend(quote! {
+        pub fn gate_unused() {
+            #gate_unused
+        }
+    });
+
     // ========
     // Generate fns to enable GPIO, DMA in RCC
     for kind in ["dma", "gpio"] {
@@ -361,6 +407,8 @@ fn main() {
         /*(("spi", "I2S_MCK"), quote!(crate::spi::MckPin)),
         (("spi", "I2S_CK"), quote!(crate::spi::CkPin)),
         (("spi", "I2S_WS"), quote!(crate::spi::WsPin)), */
+        (("can", "RX"), quote!(crate::can::RxPin)),
+        (("can", "TX"), quote!(crate::can::TxPin)),
         (("i2c", "SDA"), quote!(crate::i2c::SdaPin)),
         (("i2c", "SCL"), quote!(crate::i2c::SclPin)),
         (("timer", "CH1"), quote!(crate::timer::Channel1Pin)),
@@ -385,9 +433,22 @@ fn main() {
         (("sdio", "D6"), quote!(crate::sdio::D6Pin)),
         (("sdio", "D6"), quote!(crate::sdio::D7Pin)),
         (("sdio", "D8"), quote!(crate::sdio::D8Pin)),
+        (("eth", "REF_CLK"), quote!(crate::eth::RefClkPin)),
+        (("eth", "MDIO"), quote!(crate::eth::MdioPin)),
+        (("eth", "MDC"), quote!(crate::eth::MdcPin)),
+        (("eth", "CRS_DV"), quote!(crate::eth::CrsDvPin)),
+        (("eth", "RXD0"), quote!(crate::eth::RxD0Pin)),
+        (("eth", "RXD1"), quote!(crate::eth::RxD1Pin)),
+        (("eth", "TXD0"), quote!(crate::eth::TxD0Pin)),
+        (("eth", "TXD1"), quote!(crate::eth::TxD1Pin)),
+        (("eth", "TX_EN"), quote!(crate::eth::TxEnPin)),
         // USB is splitted into multiple impls
         (("usbd", "DP"), quote!(crate::usbd::DpPin)),
         (("usbd", "DM"), quote!(crate::usbd::DmPin)),
+        (("usbfs", "DP"), quote!(crate::usbfs::DpPin)),
+        (("usbfs", "DM"), quote!(crate::usbfs::DmPin)),
+        (("usbhs", "DP"), quote!(crate::usbhs::DpPin)),
+        (("usbhs", "DM"), quote!(crate::usbhs::DmPin)),
         // USBPD, handled by usbpd/mod.rs
         //(("usbpd", "CC1"), quote!(crate::usbpd::Cc1Pin)),
         //(("usbpd", "CC2"), quote!(crate::usbpd::Cc2Pin)),
@@ -459,6 +520,7 @@ fn main() {
         (("spi", "TX"), quote!(crate::spi::TxDma)),
         (("i2c", "RX"), quote!(crate::i2c::RxDma)),
         (("i2c", "TX"), quote!(crate::i2c::TxDma)),
+        (("timer", "UP"), quote!(crate::timer::UpDma)),
         (("timer", "CH1"), quote!(crate::timer::Ch1Dma)),
         (("timer", "CH2"), quote!(crate::timer::Ch2Dma)),
         (("timer", "CH3"), quote!(crate::timer::Ch3Dma)),
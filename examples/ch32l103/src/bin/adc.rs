@@ -17,7 +17,7 @@ async fn main(_spawner: Spawner) -> ! {
     let p = hal::init(config);
     hal::embassy::init();
 
-    let mut adc = hal::adc::Adc::new(p.ADC1, Default::default());
+    let mut adc = hal::adc::Adc::new(p.ADC1, Default::default()).unwrap();
 
     let mut ch = p.PA1;
 
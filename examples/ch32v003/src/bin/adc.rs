@@ -18,7 +18,7 @@ fn main() -> ! {
 
     let mut led = Output::new(p.PD6, Level::Low, Default::default());
 
-    let mut adc = hal::adc::Adc::new(p.ADC1, Default::default());
+    let mut adc = hal::adc::Adc::new(p.ADC1, Default::default()).unwrap();
     let mut pin = p.PA1;
 
     loop {
@@ -18,7 +18,7 @@ async fn main(spawner: Spawner) -> ! {
 
     let mut delay = Delay;
 
-    let mut adc = hal::adc::Adc::new(p.ADC1, Default::default());
+    let mut adc = hal::adc::Adc::new(p.ADC1, Default::default()).unwrap();
 
     let mut ch = p.PA5;
 
@@ -115,7 +115,7 @@ async fn main(spawner: Spawner) -> ! {
     let mut i2c_config = hal::i2c::Config::default();
     //  i2c_config.scl_pullup = true;
     //i2c_config.sda_pullup = true;
-    let mut i2c = I2c::new_blocking(p.I2C2, scl, sda, Hertz::hz(400_000), Default::default());
+    let mut i2c = I2c::new_blocking(p.I2C2, scl, sda, Hertz::hz(400_000), Default::default()).unwrap();
 
     let addr = 0x53;
 
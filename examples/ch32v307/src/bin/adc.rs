@@ -19,7 +19,7 @@ async fn main(spawner: Spawner) -> ! {
 
     let delay = Delay;
 
-    let mut adc = hal::adc::Adc::new(p.ADC1, Default::default());
+    let mut adc = hal::adc::Adc::new(p.ADC1, Default::default()).unwrap();
 
     let mut ch = p.PA1;
 
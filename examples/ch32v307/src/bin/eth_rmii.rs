@@ -0,0 +1,79 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use ch32_hal as hal;
+use embassy_executor::Spawner;
+use embassy_net::{Stack, StackResources};
+use embassy_time::Timer;
+use hal::eth::{self, Ethernet, GenericPhy, PacketQueue};
+use hal::{bind_interrupts, peripherals, println};
+use static_cell::StaticCell;
+
+bind_interrupts!(struct Irqs {
+    ETH => eth::InterruptHandler<peripherals::ETH>;
+});
+
+type Device = Ethernet<'static, peripherals::ETH, GenericPhy>;
+
+#[embassy_executor::task]
+async fn net_task(stack: &'static Stack<Device>) -> ! {
+    stack.run().await
+}
+
+#[embassy_executor::main(entry = "qingke_rt::entry")]
+async fn main(spawner: Spawner) -> ! {
+    hal::debug::SDIPrint::enable();
+    let mut config = hal::Config::default();
+    config.rcc = hal::rcc::Config::SYSCLK_FREQ_144MHZ_HSE;
+    let p = hal::init(config);
+    hal::embassy::init();
+
+    println!("Ethernet on a LAN8720 over RMII");
+
+    // Locally administered address, derived from the unique ID.
+    let uid = hal::signature::unique_id();
+    let mac_addr = [0x02, uid[0], uid[1], uid[2], uid[3], uid[4]];
+
+    static PACKETS: StaticCell<PacketQueue<4, 4>> = StaticCell::new();
+    // The LAN8720 module straps its MDIO address to 0.
+    let device = Ethernet::new_rmii(
+        PACKETS.init(PacketQueue::new()),
+        p.ETH,
+        Irqs,
+        p.PA1,
+        p.PA2,
+        p.PC1,
+        p.PA7,
+        p.PC4,
+        p.PC5,
+        p.PB12,
+        p.PB13,
+        p.PB11,
+        GenericPhy::new(0),
+        mac_addr,
+        Default::default(),
+    );
+
+    static RESOURCES: StaticCell<StackResources<2>> = StaticCell::new();
+    static STACK: StaticCell<Stack<Device>> = StaticCell::new();
+    let stack = &*STACK.init(Stack::new(
+        device,
+        embassy_net::Config::dhcpv4(Default::default()),
+        RESOURCES.init(StackResources::new()),
+        0x0123_4567_89ab_cdef,
+    ));
+    spawner.spawn(net_task(stack)).unwrap();
+
+    loop {
+        stack.wait_config_up().await;
+        if let Some(config) = stack.config_v4() {
+            println!("link up, address {}", config.address);
+        }
+
+        while stack.is_link_up() {
+            Timer::after_millis(500).await;
+        }
+        println!("link down");
+    }
+}
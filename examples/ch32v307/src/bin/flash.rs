@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use ch32_hal as hal;
+use embassy_executor::Spawner;
+use embassy_time::Timer;
+use hal::flash::{Flash, PAGE_SIZE};
+use hal::println;
+
+#[embassy_executor::main(entry = "qingke_rt::entry")]
+async fn main(_spawner: Spawner) -> ! {
+    hal::debug::SDIPrint::enable();
+    let p = hal::init(Default::default());
+    hal::embassy::init();
+
+    let mut flash = Flash::new(p.FLASH);
+
+    // The last page, away from the program.
+    let offset = (flash.capacity() - PAGE_SIZE) as u32;
+
+    let mut count = [0u8; 4];
+    flash.read(offset, &mut count).unwrap();
+    let count = match u32::from_le_bytes(count) {
+        u32::MAX => 0,
+        count => count,
+    };
+    println!("boot count: {}", count);
+
+    flash.erase_page(offset).unwrap();
+    flash.program_word(offset, count + 1).unwrap();
+
+    loop {
+        Timer::after_secs(1).await;
+    }
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    let _ = println!("\n\n\n{}", info);
+
+    loop {}
+}
@@ -53,7 +53,7 @@ async fn main(spawner: Spawner) -> ! {
 
     println!("init ok");
 
-    let mut i2c = I2c::new_blocking(p.I2C2, i2c_scl, i2c_sda, Hertz::khz(100), Default::default());
+    let mut i2c = I2c::new_blocking(p.I2C2, i2c_scl, i2c_sda, Hertz::khz(100), Default::default()).unwrap();
 
     // 7-bit address
     const FT24C32A_ADDR: u8 = 0b1010_000;
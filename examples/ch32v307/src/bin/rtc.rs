@@ -0,0 +1,47 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use ch32_hal as hal;
+use embassy_executor::Spawner;
+use embassy_time::Timer;
+use hal::println;
+use hal::rtc::{DateTime, Rtc};
+
+#[embassy_executor::main(entry = "qingke_rt::entry")]
+async fn main(_spawner: Spawner) -> ! {
+    hal::debug::SDIPrint::enable();
+    let mut config = hal::Config::default();
+    config.rcc = hal::rcc::Config::SYSCLK_FREQ_96MHZ_HSI;
+    config.rcc.ls = Some(hal::rcc::LsConfig::default_lse());
+    let p = hal::init(config);
+    hal::embassy::init();
+
+    let mut rtc = Rtc::new(p.RTC);
+
+    // The RTC keeps counting across resets, only set it the first time.
+    if rtc.now().unwrap().year() == 1970 {
+        rtc.set_datetime(DateTime::from(2024, 6, 1, 12, 0, 0).unwrap()).unwrap();
+    }
+
+    loop {
+        let now = rtc.now().unwrap();
+        println!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            now.year(),
+            now.month(),
+            now.day(),
+            now.hour(),
+            now.minute(),
+            now.second()
+        );
+        Timer::after_secs(1).await;
+    }
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    let _ = println!("\n\n\n{}", info);
+
+    loop {}
+}
@@ -0,0 +1,94 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use ch32_hal as hal;
+use embassy_executor::Spawner;
+use embassy_time::Timer;
+use hal::usbfs::host::{self, EndpointDescriptor, Host, HostError, InterfaceDescriptor, Pipe};
+use hal::{bind_interrupts, peripherals, println};
+
+bind_interrupts!(struct Irqs {
+    OTG_FS => host::HostInterruptHandler<peripherals::OTG_FS>;
+});
+
+#[embassy_executor::main(entry = "qingke_rt::entry")]
+async fn main(_spawner: Spawner) -> ! {
+    hal::debug::SDIPrint::enable();
+    let mut config = hal::Config::default();
+    config.rcc = hal::rcc::Config::SYSCLK_FREQ_144MHZ_HSE;
+    let p = hal::init(config);
+    hal::embassy::init();
+
+    println!("USB HID host");
+
+    let mut host = Host::new(p.OTG_FS, Irqs, p.PA12, p.PA11).unwrap();
+
+    loop {
+        host.wait_connected().await;
+        println!("device attached");
+
+        if let Err(e) = run(&mut host).await {
+            println!("error: {:?}", e);
+        }
+
+        host.wait_disconnected().await;
+        println!("device detached");
+    }
+}
+
+async fn run(host: &mut Host<'_, peripherals::OTG_FS>) -> Result<(), HostError> {
+    let device = host.enumerate(1).await?;
+    println!(
+        "device {:04x}:{:04x}",
+        device.descriptor.vendor_id, device.descriptor.product_id
+    );
+
+    let mut config = [0u8; 256];
+    let n = host.get_configuration_descriptor(&device, 0, &mut config).await?;
+    let config = &config[..n];
+
+    // Find the interrupt IN endpoint of the first boot keyboard interface.
+    let mut keyboard = false;
+    let mut pipe = None;
+    for (kind, descriptor) in host::descriptors(config) {
+        match kind {
+            host::DESCRIPTOR_INTERFACE => {
+                let interface = InterfaceDescriptor::parse(descriptor)?;
+                keyboard = interface.class == 3 && interface.subclass == 1 && interface.protocol == 1;
+            }
+            host::DESCRIPTOR_ENDPOINT if keyboard && pipe.is_none() => {
+                let endpoint = EndpointDescriptor::parse(descriptor)?;
+                if endpoint.is_in() {
+                    pipe = Pipe::new(&device, endpoint);
+                }
+            }
+            _ => {}
+        }
+    }
+    let Some(mut pipe) = pipe else {
+        println!("not a keyboard");
+        return Ok(());
+    };
+
+    // bConfigurationValue
+    host.set_configuration(&device, config[5]).await?;
+
+    let interval = pipe.endpoint().interval.max(1) as u64;
+    let mut report = [0u8; 8];
+    loop {
+        match host.in_transfer(&mut pipe, &mut report).await {
+            Ok(n) => println!("report: {:02x?}", &report[..n]),
+            Err(HostError::Nak) => {}
+            Err(e) => return Err(e),
+        }
+        Timer::after_millis(interval).await;
+    }
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    let _ = println!("\n\n\n{}", info);
+
+    loop {}
+}
@@ -0,0 +1,135 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use ch32_hal as hal;
+use embassy_executor::Spawner;
+use hal::usbfs::host::{self, EndpointDescriptor, Host, HostError, InterfaceDescriptor, Pipe};
+use hal::{bind_interrupts, peripherals, println};
+
+bind_interrupts!(struct Irqs {
+    OTG_FS => host::HostInterruptHandler<peripherals::OTG_FS>;
+});
+
+#[embassy_executor::main(entry = "qingke_rt::entry")]
+async fn main(_spawner: Spawner) -> ! {
+    hal::debug::SDIPrint::enable();
+    let mut config = hal::Config::default();
+    config.rcc = hal::rcc::Config::SYSCLK_FREQ_144MHZ_HSE;
+    let p = hal::init(config);
+    hal::embassy::init();
+
+    println!("USB mass storage host");
+
+    let mut host = Host::new(p.OTG_FS, Irqs, p.PA12, p.PA11).unwrap();
+
+    loop {
+        host.wait_connected().await;
+        println!("device attached");
+
+        if let Err(e) = run(&mut host).await {
+            println!("error: {:?}", e);
+        }
+
+        host.wait_disconnected().await;
+        println!("device detached");
+    }
+}
+
+async fn run(host: &mut Host<'_, peripherals::OTG_FS>) -> Result<(), HostError> {
+    let device = host.enumerate(1).await?;
+
+    let mut config = [0u8; 256];
+    let n = host.get_configuration_descriptor(&device, 0, &mut config).await?;
+    let config = &config[..n];
+
+    // Find the bulk endpoints of the first bulk-only SCSI interface.
+    let mut msc = false;
+    let (mut bulk_in, mut bulk_out) = (None, None);
+    for (kind, descriptor) in host::descriptors(config) {
+        match kind {
+            host::DESCRIPTOR_INTERFACE => {
+                let interface = InterfaceDescriptor::parse(descriptor)?;
+                msc = interface.class == 8 && interface.subclass == 6 && interface.protocol == 0x50;
+            }
+            host::DESCRIPTOR_ENDPOINT if msc => {
+                let endpoint = EndpointDescriptor::parse(descriptor)?;
+                match endpoint.is_in() {
+                    true if bulk_in.is_none() => bulk_in = Pipe::new(&device, endpoint),
+                    false if bulk_out.is_none() => bulk_out = Pipe::new(&device, endpoint),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    let (Some(mut bulk_in), Some(mut bulk_out)) = (bulk_in, bulk_out) else {
+        println!("not a mass storage device");
+        return Ok(());
+    };
+
+    // bConfigurationValue
+    host.set_configuration(&device, config[5]).await?;
+
+    // READ(10) of the first block
+    let mut block = [0u8; 512];
+    let read10 = [0x28, 0, 0, 0, 0, 0, 0, 0, 1, 0];
+    scsi_read(host, &mut bulk_in, &mut bulk_out, &read10, &mut block).await?;
+
+    println!("block 0 signature: {:02x} {:02x}", block[510], block[511]);
+    if block[510..] == [0x55, 0xAA] {
+        for (i, entry) in block[446..510].chunks(16).enumerate() {
+            let lba = u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]);
+            let sectors = u32::from_le_bytes([entry[12], entry[13], entry[14], entry[15]]);
+            println!(
+                "partition {}: type {:02x}, lba {}, {} sectors",
+                i, entry[4], lba, sectors
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Run a SCSI command with an IN data stage over the bulk-only transport.
+async fn scsi_read(
+    host: &mut Host<'_, peripherals::OTG_FS>,
+    bulk_in: &mut Pipe,
+    bulk_out: &mut Pipe,
+    command: &[u8],
+    data: &mut [u8],
+) -> Result<(), HostError> {
+    // Command block wrapper
+    let mut cbw = [0u8; 31];
+    cbw[0..4].copy_from_slice(b"USBC");
+    cbw[4..8].copy_from_slice(&1u32.to_le_bytes());
+    cbw[8..12].copy_from_slice(&(data.len() as u32).to_le_bytes());
+    cbw[12] = 0x80;
+    cbw[14] = command.len() as u8;
+    cbw[15..15 + command.len()].copy_from_slice(command);
+    host.out_transfer(bulk_out, &cbw).await?;
+
+    match host.in_transfer(bulk_in, data).await {
+        Err(HostError::Stall) => host.clear_halt(bulk_in).await?,
+        res => {
+            res?;
+        }
+    }
+
+    // Command status wrapper
+    let mut csw = [0u8; 13];
+    host.in_transfer(bulk_in, &mut csw).await?;
+    if &csw[0..4] != b"USBS" || csw[12] != 0 {
+        println!("command failed, status {}", csw[12]);
+        return Err(HostError::Protocol);
+    }
+
+    Ok(())
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    let _ = println!("\n\n\n{}", info);
+
+    loop {}
+}
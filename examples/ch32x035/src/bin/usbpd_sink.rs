@@ -0,0 +1,77 @@
+#![no_std]
+#![no_main]
+#![feature(type_alias_impl_trait)]
+
+use ch32_hal as hal;
+use embassy_executor::Spawner;
+use embassy_time::Timer;
+use hal::usbpd::message::{PowerDataObject, Request, SourceCapabilities};
+use hal::usbpd::{UsbPdPhy, UsbPdSink};
+use hal::{bind_interrupts, peripherals, println, usbpd};
+
+bind_interrupts!(struct Irqs {
+    USBPD => usbpd::InterruptHandler<peripherals::USBPD>;
+});
+
+/// Voltage to request, falls back to 5V if the source doesn't offer it.
+const VOLTAGE_MV: u32 = 9000;
+
+#[embassy_executor::main(entry = "qingke_rt::entry")]
+async fn main(_spawner: Spawner) -> ! {
+    hal::debug::SDIPrint::enable();
+    let mut p = hal::init(Default::default());
+    hal::embassy::init();
+
+    println!("USB PD sink");
+
+    let phy = loop {
+        match UsbPdPhy::new(&mut p.USBPD, Irqs, &mut p.PC14, &mut p.PC15) {
+            Ok(phy) => break phy,
+            Err(_) => Timer::after_millis(100).await,
+        }
+    };
+    let mut sink = UsbPdSink::new(phy);
+
+    let mut res = sink.negotiate(select).await;
+    loop {
+        match res {
+            Ok(request) => {
+                println!("contract: {:?}", sink.source_capabilities().get(request.position()));
+
+                // Stay in the contract until the source changes its capabilities.
+                res = match sink.wait_capabilities_change().await {
+                    Ok(caps) => {
+                        let request = select(&caps);
+                        sink.request(request).await.map(|_| request)
+                    }
+                    Err(e) => Err(e),
+                };
+            }
+            Err(e) => {
+                println!("negotiation failed: {:?}", e);
+                Timer::after_millis(100).await;
+                res = sink.negotiate(select).await;
+            }
+        }
+    }
+}
+
+fn select(caps: &SourceCapabilities) -> Request {
+    for (position, pdo) in caps.iter() {
+        println!("PDO {}: {:?}", position, pdo);
+    }
+    match caps.find_fixed(VOLTAGE_MV) {
+        Some((position, max_current_ma)) => Request::fixed(position, max_current_ma),
+        None => match caps.get(1) {
+            Some(PowerDataObject::Fixed { max_current_ma, .. }) => Request::fixed(1, max_current_ma),
+            _ => Request::fixed(1, 500),
+        },
+    }
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    let _ = println!("\n\n\n{}", info);
+
+    loop {}
+}
@@ -6,6 +6,7 @@ use embassy_sync::waitqueue::AtomicWaker;
 
 use crate::pac::adc::vals;
 pub use crate::pac::adc::vals::SampleTime;
+use crate::time::Hertz;
 use crate::{into_ref, peripherals, Peripheral};
 
 /// ADC bit resolution
@@ -17,8 +18,25 @@ pub const ADC_MAX: u32 = (1 << 12) - 1;
 // No calibration data, voltage should be 1.2V (1.16 to 1.24)
 pub const VREF_INT: u32 = 1200;
 
+/// Highest ADC clock frequency, conversions are wrong above it.
+#[cfg(any(adc_v0, adc_ch641))]
+pub const MAX_FREQUENCY: Hertz = Hertz(24_000_000);
+/// Highest ADC clock frequency, conversions are wrong above it.
+#[cfg(not(any(adc_v0, adc_ch641)))]
+pub const MAX_FREQUENCY: Hertz = Hertz(14_000_000);
+
+/// ADC errors
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The ADC clock is above [`MAX_FREQUENCY`]. Raise the ADC prescaler of the RCC config, or
+    /// [`Config::clkdiv`].
+    Frequency(Hertz),
+}
+
 pub struct Config {
-    /// Div1 to Div16
+    /// Div1 to Div16, after the ADC prescaler of the RCC, see [`Clocks::adc`](crate::rcc::Clocks::adc).
+    /// Only on chips with CTLR3.
     // raw values are 0 to 0b111
     pub clkdiv: u8,
     // TODO: handle "-1"
@@ -28,8 +46,8 @@ pub struct Config {
 impl Default for Config {
     fn default() -> Self {
         Self {
-            // Use Divide by 8 as default, maximum is 14 MHz
-            // Up to 144 MHz system clock can be reached
+            // Use Divide by 4 as default, maximum is 14 MHz
+            // With the RCC prescaler, up to 144 MHz system clock can be reached
             // Power on default is Divide by 2,
             clkdiv: 0b11,
             channel_count: 1,
@@ -55,12 +73,20 @@ pub struct Adc<'d, T: Instance> {
 }
 
 impl<'d, T: Instance> Adc<'d, T> {
+    /// Create a new ADC driver.
+    ///
+    /// Fails if the ADC clock is above [`MAX_FREQUENCY`].
     #[allow(unused)]
-    pub fn new(adc: impl Peripheral<P = T> + 'd, config: Config) -> Self {
+    pub fn new(adc: impl Peripheral<P = T> + 'd, config: Config) -> Result<Self, Error> {
+        let frequency = Self::calc_frequency(&config);
+        if frequency > MAX_FREQUENCY {
+            return Err(Error::Frequency(frequency));
+        }
+
         into_ref!(adc);
         T::enable_and_reset();
 
-        // TODO: ADCPRE
+        // ADCPRE is set by the RCC config
         // CTLR3 not avaiable to CH3V0, CH32V1
         #[cfg(any(adc_v3, adc_x0))]
         T::regs().ctlr3().modify(|w| w.set_clk_div(config.clkdiv));
@@ -85,7 +111,24 @@ impl<'d, T: Instance> Adc<'d, T> {
         // ADC ON
         T::regs().ctlr2().modify(|w| w.set_adon(true));
 
-        Self { adc }
+        Ok(Self { adc })
+    }
+
+    #[allow(unused_variables)]
+    fn calc_frequency(config: &Config) -> Hertz {
+        let clkdiv = 1u32;
+        #[cfg(any(adc_v3, adc_x0))]
+        let clkdiv = u32::from(config.clkdiv) + 1;
+        crate::rcc::clocks().adc / clkdiv
+    }
+
+    /// ADC clock frequency.
+    #[allow(unused_variables)]
+    pub fn frequency(&self) -> Hertz {
+        let clkdiv = 1u32;
+        #[cfg(any(adc_v3, adc_x0))]
+        let clkdiv = u32::from(T::regs().ctlr3().read().clk_div()) + 1;
+        crate::rcc::clocks().adc / clkdiv
     }
 
     // regular conversion
@@ -0,0 +1,270 @@
+//! Backup registers (BKP)
+//!
+//! The backup domain holds 16-bit data registers that keep their value across resets, and across
+//! power cycles with a battery on VBAT. They are cleared by a backup domain reset, e.g. when the
+//! RTC clock source changes.
+//!
+//! [`BackupRegisters::store`] and [`BackupRegisters::load`] keep a value over several registers,
+//! with a check word so a value never stored, or partly written, reads as `None`.
+//!
+//! The tamper pin, PC13, clears the data registers when it goes to its active level. [`Tamper`]
+//! enables it and waits for the event.
+
+use core::future::poll_fn;
+use core::ptr::{read_volatile, write_volatile};
+use core::task::Poll;
+
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::interrupt::typelevel::Interrupt;
+use crate::pac::{BKP, PWR, RCC};
+use crate::{interrupt, into_ref, peripherals, Peripheral, PeripheralRef};
+
+static TAMPER_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Number of data registers.
+#[cfg(d6)]
+pub const COUNT: usize = 10;
+/// Number of data registers.
+#[cfg(not(d6))]
+pub const COUNT: usize = 42;
+
+/// Value kept in backup registers, as 16-bit words
+pub trait BackupValue: Sized {
+    /// Number of words.
+    const WORDS: usize;
+
+    /// Write the value to `words`, `WORDS` long.
+    fn to_words(&self, words: &mut [u16]);
+
+    /// Read a value from `words`, `WORDS` long, `None` if it isn't valid.
+    fn from_words(words: &[u16]) -> Option<Self>;
+}
+
+macro_rules! impl_backup_value {
+    ($($t:ty),*) => {
+        $(
+            impl BackupValue for $t {
+                const WORDS: usize = (<$t>::BITS as usize).div_ceil(16);
+
+                fn to_words(&self, words: &mut [u16]) {
+                    for (i, word) in words.iter_mut().enumerate() {
+                        *word = (*self >> (16 * i)) as u16;
+                    }
+                }
+
+                fn from_words(words: &[u16]) -> Option<Self> {
+                    Some(
+                        words
+                            .iter()
+                            .enumerate()
+                            .fold(0, |v, (i, &word)| v | (<$t>::from(word) << (16 * i))),
+                    )
+                }
+            }
+        )*
+    };
+}
+
+impl_backup_value!(u16, u32, u64);
+
+impl BackupValue for bool {
+    const WORDS: usize = 1;
+
+    fn to_words(&self, words: &mut [u16]) {
+        words[0] = u16::from(*self);
+    }
+
+    fn from_words(words: &[u16]) -> Option<Self> {
+        match words[0] {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+}
+
+/// Check word of a stored value, never 0 so cleared registers don't pass.
+fn check_word(words: &[u16]) -> u16 {
+    words
+        .iter()
+        .fold(0xA5A5u16, |check, &word| check.rotate_left(5) ^ word)
+        .max(1)
+}
+
+fn enable_access() {
+    RCC.apb1pcenr().modify(|w| {
+        w.set_pwren(true);
+        w.set_bkpen(true);
+    });
+    PWR.ctlr().modify(|w| w.set_dbp(true));
+}
+
+/// Backup data registers driver
+pub struct BackupRegisters<'d> {
+    _peri: PeripheralRef<'d, peripherals::BKP>,
+}
+
+impl<'d> BackupRegisters<'d> {
+    /// Enable write access to the backup domain, the registers are left as is.
+    pub fn new(bkp: impl Peripheral<P = peripherals::BKP> + 'd) -> Self {
+        into_ref!(bkp);
+        enable_access();
+        Self { _peri: bkp }
+    }
+
+    fn register(index: usize) -> *mut u32 {
+        assert!(index < COUNT, "backup register out of range");
+        // DATAR1 to DATAR10, then DATAR11 to DATAR42 after the control registers.
+        let offset = if index < 10 {
+            0x04 + 4 * index
+        } else {
+            0x40 + 4 * (index - 10)
+        };
+        unsafe { (BKP.as_ptr() as *mut u8).add(offset) as *mut u32 }
+    }
+
+    /// Read data register `index`, from 0.
+    pub fn read(&self, index: usize) -> u16 {
+        unsafe { read_volatile(Self::register(index)) as u16 }
+    }
+
+    /// Write data register `index`, from 0.
+    pub fn write(&mut self, index: usize, value: u16) {
+        unsafe { write_volatile(Self::register(index), u32::from(value)) }
+    }
+
+    /// Clear all the data registers.
+    pub fn clear(&mut self) {
+        for index in 0..COUNT {
+            self.write(index, 0);
+        }
+    }
+
+    /// Store `value` from register `start`, in `T::WORDS + 1` registers.
+    pub fn store<T: BackupValue>(&mut self, start: usize, value: &T) {
+        let mut words = [0u16; COUNT];
+        let words = &mut words[..T::WORDS];
+        value.to_words(words);
+
+        // Invalidate first, so a reset in between leaves no valid value.
+        self.write(start + T::WORDS, 0);
+        for (i, &word) in words.iter().enumerate() {
+            self.write(start + i, word);
+        }
+        self.write(start + T::WORDS, check_word(words));
+    }
+
+    /// Load the value stored from register `start`, `None` if there is none.
+    pub fn load<T: BackupValue>(&self, start: usize) -> Option<T> {
+        let mut words = [0u16; COUNT];
+        let words = &mut words[..T::WORDS];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = self.read(start + i);
+        }
+
+        if self.read(start + T::WORDS) != check_word(words) {
+            return None;
+        }
+        T::from_words(words)
+    }
+
+    /// Invalidate the value stored from register `start`.
+    pub fn remove<T: BackupValue>(&mut self, start: usize) {
+        self.write(start + T::WORDS, 0);
+    }
+}
+
+/// Tamper interrupt handler.
+pub struct TamperInterruptHandler {
+    _private: (),
+}
+
+impl interrupt::typelevel::Handler<interrupt::typelevel::TAMPER> for TamperInterruptHandler {
+    unsafe fn on_interrupt() {
+        if BKP.tpcsr().read().tif() {
+            BKP.tpcsr().modify(|w| w.set_tpie(false));
+            TAMPER_WAKER.wake();
+        }
+    }
+}
+
+/// Active level of the tamper pin
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TamperLevel {
+    High,
+    Low,
+}
+
+/// Tamper detection on PC13
+///
+/// When the pin goes to its active level, the hardware clears the backup data registers and flags
+/// a tamper event.
+pub struct Tamper<'d> {
+    _pin: PeripheralRef<'d, peripherals::PC13>,
+}
+
+impl<'d> Tamper<'d> {
+    /// Enable tamper detection.
+    ///
+    /// The pin must be at its inactive level, or the event happens right away.
+    pub fn new(
+        pin: impl Peripheral<P = peripherals::PC13> + 'd,
+        _irq: impl interrupt::typelevel::Binding<interrupt::typelevel::TAMPER, TamperInterruptHandler> + 'd,
+        level: TamperLevel,
+    ) -> Self {
+        into_ref!(pin);
+        enable_access();
+
+        // The level is set while detection is off, changing it could trigger an event.
+        BKP.tpctlr().write(|w| w.set_tpal(level == TamperLevel::Low));
+        BKP.tpcsr().write(|w| {
+            w.set_cte(true);
+            w.set_cti(true);
+        });
+        BKP.tpctlr().modify(|w| w.set_tpe(true));
+
+        interrupt::typelevel::TAMPER::unpend();
+        unsafe { interrupt::typelevel::TAMPER::enable() };
+
+        Self { _pin: pin }
+    }
+
+    /// Whether a tamper event happened, since enabled or last cleared.
+    pub fn is_tampered(&self) -> bool {
+        BKP.tpcsr().read().tef()
+    }
+
+    /// Clear the tamper event.
+    pub fn clear(&mut self) {
+        BKP.tpcsr().modify(|w| {
+            w.set_cte(true);
+            w.set_cti(true);
+        });
+    }
+
+    /// Wait for a tamper event, and clear it.
+    pub async fn wait(&mut self) {
+        poll_fn(|cx| {
+            TAMPER_WAKER.register(cx.waker());
+            // Enabled before the check, the interrupt only flags events while enabled.
+            BKP.tpcsr().modify(|w| w.set_tpie(true));
+            if self.is_tampered() {
+                BKP.tpcsr().modify(|w| w.set_tpie(false));
+                self.clear();
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+impl<'d> Drop for Tamper<'d> {
+    fn drop(&mut self) {
+        BKP.tpcsr().modify(|w| w.set_tpie(false));
+        BKP.tpctlr().modify(|w| w.set_tpe(false));
+    }
+}
@@ -0,0 +1,110 @@
+//! WCH ISP bootloader
+//!
+//! The system flash holds the factory bootloader, which takes firmware updates over USB and UART
+//! with WCHISPTool or `wchisp`. [`enter_isp`] starts it from the application, e.g. on a command
+//! received over a CDC-ACM interface, without touching the BOOT0 pin.
+//!
+//! On the CH32V003, CH32X035 and CH32L103 the flash controller selects the system flash for the
+//! next software reset, which leaves the chip as the bootloader expects it. The CH32V103, CH32V20x
+//! and CH32V30x have no such switch: the application is torn down and the bootloader is jumped to.
+
+#[cfg(any(ch32v0, ch32x0, ch32l1))]
+mod regs {
+    pub const FLASH_STATR: *mut u32 = 0x4002_200C as *mut u32;
+    pub const FLASH_BOOT_MODEKEYR: *mut u32 = 0x4002_2028 as *mut u32;
+    /// Boot from the system flash after a software reset.
+    pub const STATR_BOOT_MODE: u32 = 1 << 14;
+    pub const KEY1: u32 = 0x4567_0123;
+    pub const KEY2: u32 = 0xCDEF_89AB;
+
+    pub const PFIC_CFGR: *mut u32 = 0xE000_E048 as *mut u32;
+    pub const CFGR_KEY3: u32 = 0xBEEF << 16;
+    pub const CFGR_SYSRESET: u32 = 1 << 7;
+}
+
+#[cfg(any(ch32v1, ch32v2, ch32v3))]
+mod regs {
+    /// Entry of the bootloader in the system flash.
+    #[cfg(ch32v1)]
+    pub const SYSTEM_FLASH: usize = 0x1FFF_F000;
+    #[cfg(any(ch32v2, ch32v3))]
+    pub const SYSTEM_FLASH: usize = 0x1FFF_8000;
+
+    pub const RCC_APB2PRSTR: *mut u32 = 0x4002_100C as *mut u32;
+    pub const RCC_APB1PRSTR: *mut u32 = 0x4002_1010 as *mut u32;
+    /// The CH32V103 has no AHB peripheral reset.
+    #[cfg(any(ch32v2, ch32v3))]
+    pub const RCC_AHBRSTR: *mut u32 = 0x4002_1028 as *mut u32;
+    /// Peripheral reset registers, pulsed to put the peripherals back in their reset state.
+    #[cfg(ch32v1)]
+    pub const RCC_PRSTR: [*mut u32; 2] = [RCC_APB2PRSTR, RCC_APB1PRSTR];
+    #[cfg(any(ch32v2, ch32v3))]
+    pub const RCC_PRSTR: [*mut u32; 3] = [RCC_APB2PRSTR, RCC_APB1PRSTR, RCC_AHBRSTR];
+
+    /// Interrupt clear-enable registers.
+    pub const PFIC_IRER: *mut [u32; 4] = 0xE000_E180 as *mut [u32; 4];
+    /// Interrupt clear-pending registers.
+    pub const PFIC_IPRR: *mut [u32; 4] = 0xE000_E280 as *mut [u32; 4];
+}
+
+/// Start the ISP bootloader, it doesn't return.
+///
+/// Pending writes, e.g. the answer to the command that triggered the update, must be flushed
+/// before: the USB device disappears from the bus right away.
+#[cfg(any(ch32v0, ch32x0, ch32l1))]
+pub fn enter_isp() -> ! {
+    use core::ptr::{read_volatile, write_volatile};
+
+    use regs::*;
+
+    unsafe {
+        qingke::riscv::interrupt::disable();
+
+        // The BOOT_MODE bit is locked after reset.
+        write_volatile(FLASH_BOOT_MODEKEYR, KEY1);
+        write_volatile(FLASH_BOOT_MODEKEYR, KEY2);
+        write_volatile(FLASH_STATR, read_volatile(FLASH_STATR) | STATR_BOOT_MODE);
+
+        write_volatile(PFIC_CFGR, CFGR_KEY3 | CFGR_SYSRESET);
+    }
+
+    loop {}
+}
+
+/// Start the ISP bootloader, it doesn't return.
+///
+/// Pending writes, e.g. the answer to the command that triggered the update, must be flushed
+/// before: the USB device disappears from the bus right away.
+#[cfg(any(ch32v1, ch32v2, ch32v3))]
+pub fn enter_isp() -> ! {
+    use core::ptr::write_volatile;
+
+    use regs::*;
+
+    use crate::pac::rcc::vals::Sw;
+    use crate::pac::RCC;
+
+    unsafe {
+        qingke::riscv::interrupt::disable();
+
+        // The bootloader configures the clocks and peripherals from their reset state.
+        write_volatile(PFIC_IRER, [u32::MAX; 4]);
+        write_volatile(PFIC_IPRR, [u32::MAX; 4]);
+
+        RCC.cfgr0().write(|_| {});
+        while RCC.cfgr0().read().sws() != Sw::HSI {}
+        RCC.ctlr().modify(|w| {
+            w.set_pllon(false);
+            w.set_hseon(false);
+        });
+        RCC.ctlr().modify(|w| w.set_hsebyp(false));
+
+        for reg in RCC_PRSTR {
+            write_volatile(reg, u32::MAX);
+            write_volatile(reg, 0);
+        }
+
+        let entry: extern "C" fn() -> ! = core::mem::transmute(SYSTEM_FLASH);
+        entry()
+    }
+}
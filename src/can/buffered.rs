@@ -0,0 +1,463 @@
+//! Interrupt-driven CAN driver with software buffers.
+//!
+//! Received frames are moved from the hardware FIFOs into a buffer from the interrupt, and written
+//! frames are queued and moved into the transmit mailboxes by priority, so the application doesn't
+//! have to keep up with the bus frame by frame.
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use core::task::Poll;
+
+use embassy_sync::waitqueue::AtomicWaker;
+
+use super::*;
+use crate::interrupt;
+use crate::interrupt::typelevel::Interrupt as _;
+
+/// TX interrupt handler.
+pub struct TxInterruptHandler<T: Instance> {
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Instance> interrupt::typelevel::Handler<T::TxInterrupt> for TxInterruptHandler<T> {
+    unsafe fn on_interrupt() {
+        // Clear the request completed flags, write 1 to clear.
+        T::regs().tstatr().write(|w| {
+            for mb in 0..3 {
+                w.set_rqcp(mb, true);
+            }
+        });
+
+        fill_mailboxes::<T>();
+        T::buffered_state().tx_waker.wake();
+    }
+}
+
+/// RX FIFO 0 interrupt handler.
+pub struct Rx0InterruptHandler<T: Instance> {
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Instance> interrupt::typelevel::Handler<T::Rx0Interrupt> for Rx0InterruptHandler<T> {
+    unsafe fn on_interrupt() {
+        on_rx_interrupt::<T>(Fifo::Fifo0);
+    }
+}
+
+/// RX FIFO 1 interrupt handler.
+pub struct Rx1InterruptHandler<T: Instance> {
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Instance> interrupt::typelevel::Handler<T::Rx1Interrupt> for Rx1InterruptHandler<T> {
+    unsafe fn on_interrupt() {
+        on_rx_interrupt::<T>(Fifo::Fifo1);
+    }
+}
+
+/// Status change and error interrupt handler.
+pub struct SceInterruptHandler<T: Instance> {
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Instance> interrupt::typelevel::Handler<T::SceInterrupt> for SceInterruptHandler<T> {
+    unsafe fn on_interrupt() {
+        // Write 1 to clear.
+        T::regs().statr().write(|w| w.set_erri(true));
+
+        // Raised when a flag gets set, report the most severe one.
+        let event = match Registers(T::regs()).bus_state() {
+            BusState::ErrorActive => return,
+            BusState::ErrorWarning => ErrorEvent::Warning,
+            BusState::ErrorPassive => ErrorEvent::Passive,
+            BusState::BusOff => ErrorEvent::BusOff,
+        };
+
+        let state = T::buffered_state();
+        state.error_events.fetch_or(1 << event as u8, Ordering::Relaxed);
+        state.error_waker.wake();
+    }
+}
+
+/// Bus error event, reported by [`BufferedCan::wait_error_event`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ErrorEvent {
+    /// An error counter reached the warning limit of 96.
+    Warning = 0,
+    /// An error counter went above 127, the controller became error passive.
+    Passive = 1,
+    /// The transmit error counter went above 255, the controller left the bus.
+    BusOff = 2,
+}
+
+fn on_rx_interrupt<T: Instance>(fifo: Fifo) {
+    let regs = Registers(T::regs());
+    let state = T::buffered_state();
+
+    critical_section::with(|cs| {
+        let mut rx = state.rx.borrow_ref_mut(cs);
+        loop {
+            let (pending, overrun) = regs.fifo_status(fifo);
+            if overrun {
+                state.rx_overrun.store(true, Ordering::Relaxed);
+            }
+            if pending == 0 {
+                break;
+            }
+            if rx.is_full() {
+                // Leave the frames in the hardware FIFO until the reader makes room.
+                set_rx_interrupts::<T>(false);
+                break;
+            }
+            rx.push(regs.read_fifo(fifo));
+        }
+    });
+
+    state.rx_waker.wake();
+}
+
+/// Move the highest priority queued frames into the empty mailboxes.
+fn fill_mailboxes<T: Instance>() {
+    let regs = Registers(T::regs());
+
+    critical_section::with(|cs| {
+        let mut tx = T::buffered_state().tx.borrow_ref_mut(cs);
+        while let Some(mailbox) = regs.free_mailbox() {
+            match tx.pop_highest_priority() {
+                Some(frame) => regs.write_mailbox(mailbox, &frame),
+                None => break,
+            }
+        }
+    });
+}
+
+fn set_rx_interrupts<T: Instance>(enable: bool) {
+    T::regs().intenr().modify(|w| {
+        w.set_fmpie0(enable);
+        w.set_fmpie1(enable);
+    });
+}
+
+fn set_error_interrupts<T: Instance>(enable: bool) {
+    T::regs().intenr().modify(|w| {
+        w.set_ewgie(enable);
+        w.set_epvie(enable);
+        w.set_bofie(enable);
+        w.set_errie(enable);
+    });
+}
+
+/// Frame storage borrowed from the user, used as a ring buffer.
+pub(crate) struct FrameBuf<F> {
+    ptr: *mut F,
+    cap: usize,
+    start: usize,
+    len: usize,
+}
+
+// The buffer is only accessed in critical sections.
+unsafe impl<F> Send for FrameBuf<F> {}
+
+impl<F> FrameBuf<F> {
+    const fn empty() -> Self {
+        Self {
+            ptr: core::ptr::null_mut(),
+            cap: 0,
+            start: 0,
+            len: 0,
+        }
+    }
+
+    fn new(buf: &mut [F]) -> Self {
+        Self {
+            ptr: buf.as_mut_ptr(),
+            cap: buf.len(),
+            start: 0,
+            len: 0,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == self.cap
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn slot(&self, i: usize) -> *mut F {
+        unsafe { self.ptr.add((self.start + i) % self.cap) }
+    }
+
+    /// Append `frame`, returns `false` if the buffer is full.
+    fn push(&mut self, frame: F) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        unsafe { self.slot(self.len).write(frame) };
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<F> {
+        if self.is_empty() {
+            return None;
+        }
+        let frame = unsafe { self.slot(0).read() };
+        self.start = (self.start + 1) % self.cap;
+        self.len -= 1;
+        Some(frame)
+    }
+}
+
+impl FrameBuf<Frame> {
+    /// Remove the frame that wins arbitration, the oldest one of equal priority.
+    fn pop_highest_priority(&mut self) -> Option<Frame> {
+        let mut best = 0;
+        for i in 1..self.len {
+            let (candidate, current) = unsafe { (&*self.slot(i), &*self.slot(best)) };
+            if candidate.has_priority_over(current) {
+                best = i;
+            }
+        }
+        if best == 0 {
+            return self.pop();
+        }
+
+        let frame = unsafe { self.slot(best).read() };
+        // Keep the queue order of the frames behind it.
+        for i in best..self.len - 1 {
+            unsafe { self.slot(i).write(self.slot(i + 1).read()) };
+        }
+        self.len -= 1;
+        Some(frame)
+    }
+}
+
+pub(crate) struct State {
+    tx_waker: AtomicWaker,
+    tx: critical_section::Mutex<RefCell<FrameBuf<Frame>>>,
+    rx_waker: AtomicWaker,
+    rx: critical_section::Mutex<RefCell<FrameBuf<Envelope>>>,
+    rx_overrun: AtomicBool,
+    error_waker: AtomicWaker,
+    error_events: AtomicU8,
+}
+
+impl State {
+    pub(crate) const fn new() -> Self {
+        Self {
+            tx_waker: AtomicWaker::new(),
+            tx: critical_section::Mutex::new(RefCell::new(FrameBuf::empty())),
+            rx_waker: AtomicWaker::new(),
+            rx: critical_section::Mutex::new(RefCell::new(FrameBuf::empty())),
+            rx_overrun: AtomicBool::new(false),
+            error_waker: AtomicWaker::new(),
+            error_events: AtomicU8::new(0),
+        }
+    }
+}
+
+/// Buffered CAN driver
+///
+/// Created with [`Can::buffered`].
+///
+/// Queued frames go out by priority. The mailboxes are sent in the order they were filled, so a
+/// frame can wait behind the up to three frames already moved into the mailboxes, and frames with
+/// the same ID are sent in the order they were written.
+pub struct BufferedCan<'d, T: Instance> {
+    can: Can<'d, T>,
+}
+
+impl<'d, T: Instance> Can<'d, T> {
+    /// Turn the driver into an interrupt-driven one, queueing up to `tx_buf.len()` frames to send
+    /// and buffering up to `rx_buf.len()` received frames.
+    pub fn buffered(
+        self,
+        _irq: impl interrupt::typelevel::Binding<T::TxInterrupt, TxInterruptHandler<T>>
+            + interrupt::typelevel::Binding<T::Rx0Interrupt, Rx0InterruptHandler<T>>
+            + interrupt::typelevel::Binding<T::Rx1Interrupt, Rx1InterruptHandler<T>>
+            + interrupt::typelevel::Binding<T::SceInterrupt, SceInterruptHandler<T>>
+            + 'd,
+        tx_buf: &'d mut [Frame],
+        rx_buf: &'d mut [Envelope],
+    ) -> BufferedCan<'d, T> {
+        assert!(!tx_buf.is_empty() && !rx_buf.is_empty());
+
+        let state = T::buffered_state();
+        critical_section::with(|cs| {
+            *state.tx.borrow_ref_mut(cs) = FrameBuf::new(tx_buf);
+            *state.rx.borrow_ref_mut(cs) = FrameBuf::new(rx_buf);
+        });
+        state.rx_overrun.store(false, Ordering::Relaxed);
+        state.error_events.store(0, Ordering::Relaxed);
+
+        // The queue is sorted by software, keep its order on the bus.
+        T::regs().ctlr().modify(|w| w.set_txfp(true));
+
+        T::regs().intenr().modify(|w| w.set_tmeie(true));
+        set_rx_interrupts::<T>(true);
+        set_error_interrupts::<T>(true);
+
+        T::TxInterrupt::unpend();
+        T::Rx0Interrupt::unpend();
+        T::Rx1Interrupt::unpend();
+        T::SceInterrupt::unpend();
+        unsafe {
+            T::TxInterrupt::enable();
+            T::Rx0Interrupt::enable();
+            T::Rx1Interrupt::enable();
+            T::SceInterrupt::enable();
+        }
+
+        BufferedCan { can: self }
+    }
+}
+
+impl<'d, T: Instance> BufferedCan<'d, T> {
+    /// Configure filter bank `index`, see [`Can::set_filter`].
+    pub fn set_filter(&mut self, index: u8, filter: Filter, fifo: Fifo) {
+        self.can.set_filter(index, filter, fifo);
+    }
+
+    /// Disable filter bank `index`.
+    pub fn disable_filter(&mut self, index: u8) {
+        self.can.disable_filter(index);
+    }
+
+    /// Current bus state.
+    pub fn bus_state(&self) -> BusState {
+        self.can.bus_state()
+    }
+
+    /// Current error counters.
+    pub fn error_counters(&self) -> ErrorCounters {
+        self.can.error_counters()
+    }
+
+    /// Start the recovery from bus-off, see [`Can::recover`].
+    pub fn recover(&mut self) {
+        self.can.recover();
+    }
+
+    /// Wait for a bus error event.
+    ///
+    /// Events raised since the last call are merged, the most severe one is returned.
+    pub async fn wait_error_event(&mut self) -> ErrorEvent {
+        poll_fn(|cx| {
+            let state = T::buffered_state();
+            state.error_waker.register(cx.waker());
+
+            let events = state.error_events.swap(0, Ordering::Relaxed);
+            if events & (1 << ErrorEvent::BusOff as u8) != 0 {
+                Poll::Ready(ErrorEvent::BusOff)
+            } else if events & (1 << ErrorEvent::Passive as u8) != 0 {
+                Poll::Ready(ErrorEvent::Passive)
+            } else if events != 0 {
+                Poll::Ready(ErrorEvent::Warning)
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Queue `frame`, returns `false` if the queue is full.
+    pub fn try_write(&mut self, frame: &Frame) -> bool {
+        let queued = critical_section::with(|cs| T::buffered_state().tx.borrow_ref_mut(cs).push(*frame));
+        if queued {
+            fill_mailboxes::<T>();
+        }
+        queued
+    }
+
+    /// Queue `frame`, waiting for room in the queue.
+    pub async fn write(&mut self, frame: &Frame) {
+        poll_fn(|cx| {
+            T::buffered_state().tx_waker.register(cx.waker());
+            if self.try_write(frame) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Wait until all queued frames were sent.
+    pub async fn flush(&mut self) {
+        poll_fn(|cx| {
+            let state = T::buffered_state();
+            state.tx_waker.register(cx.waker());
+
+            let queued = critical_section::with(|cs| !state.tx.borrow_ref(cs).is_empty());
+            if !queued && self.can.is_transmitter_idle() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Read a buffered frame, returns `Ok(None)` if the buffer is empty.
+    ///
+    /// A frame lost in a full hardware FIFO is reported once as [`Error::Overrun`].
+    pub fn try_read(&mut self) -> Result<Option<Frame>, Error> {
+        Ok(self.try_read_envelope()?.map(|envelope| envelope.frame))
+    }
+
+    /// Wait for a received frame.
+    pub async fn read(&mut self) -> Result<Frame, Error> {
+        Ok(self.read_envelope().await?.frame)
+    }
+
+    /// Read a buffered frame with its reception time, see [`try_read`](Self::try_read).
+    pub fn try_read_envelope(&mut self) -> Result<Option<Envelope>, Error> {
+        let state = T::buffered_state();
+
+        if state.rx_overrun.swap(false, Ordering::Relaxed) {
+            return Err(Error::Overrun);
+        }
+
+        let envelope = critical_section::with(|cs| {
+            let envelope = state.rx.borrow_ref_mut(cs).pop();
+            // Made room, restart moving frames out of the hardware FIFOs.
+            if envelope.is_some() {
+                set_rx_interrupts::<T>(true);
+            }
+            envelope
+        });
+        Ok(envelope)
+    }
+
+    /// Wait for a received frame with its reception time.
+    pub async fn read_envelope(&mut self) -> Result<Envelope, Error> {
+        poll_fn(|cx| {
+            T::buffered_state().rx_waker.register(cx.waker());
+            match self.try_read_envelope() {
+                Ok(None) => Poll::Pending,
+                res => Poll::Ready(res.map(Option::unwrap)),
+            }
+        })
+        .await
+    }
+}
+
+impl<'d, T: Instance> Drop for BufferedCan<'d, T> {
+    fn drop(&mut self) {
+        // The buffers are borrowed for 'd only.
+        let state = T::buffered_state();
+        critical_section::with(|cs| {
+            T::regs().intenr().modify(|w| w.set_tmeie(false));
+            set_rx_interrupts::<T>(false);
+            set_error_interrupts::<T>(false);
+
+            *state.tx.borrow_ref_mut(cs) = FrameBuf::empty();
+            *state.rx.borrow_ref_mut(cs) = FrameBuf::empty();
+        });
+    }
+}
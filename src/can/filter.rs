@@ -0,0 +1,51 @@
+//! Acceptance filters, selecting the received frames and their FIFO.
+
+use super::frame::{Id, MIR_IDE};
+
+/// Configuration of one 32-bit filter bank.
+///
+/// A filter matches on the ID and the ID type. Mask filters accept data and remote frames, list
+/// filters only data frames.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Filter {
+    pub(crate) list: bool,
+    pub(crate) fr1: u32,
+    pub(crate) fr2: u32,
+}
+
+impl Filter {
+    /// Accept every frame.
+    pub const fn accept_all() -> Self {
+        Self {
+            list: false,
+            fr1: 0,
+            fr2: 0,
+        }
+    }
+
+    /// Accept frames whose ID matches `id` in the bits set in `mask`.
+    ///
+    /// Only IDs of the same type as `id` match. `mask` is right-aligned like the ID, 11 or 29 bits.
+    pub const fn mask(id: Id, mask: u32) -> Self {
+        let mask = match id {
+            Id::Standard(_) => (mask & 0x7FF) << 21,
+            Id::Extended(_) => (mask & 0x1FFF_FFFF) << 3,
+        };
+
+        Self {
+            list: false,
+            fr1: id.to_mir(),
+            fr2: mask | MIR_IDE,
+        }
+    }
+
+    /// Accept data frames with one of the two IDs.
+    pub const fn list(ids: [Id; 2]) -> Self {
+        Self {
+            list: true,
+            fr1: ids[0].to_mir(),
+            fr2: ids[1].to_mir(),
+        }
+    }
+}
@@ -0,0 +1,298 @@
+//! CAN frame and identifier types.
+
+/// Standard 11-bit CAN identifier.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StandardId(u16);
+
+impl StandardId {
+    /// The highest, lowest priority, standard ID.
+    pub const MAX: Self = Self(0x7FF);
+
+    /// Create a standard ID, returns `None` if `raw` doesn't fit in 11 bits.
+    pub const fn new(raw: u16) -> Option<Self> {
+        if raw <= 0x7FF {
+            Some(Self(raw))
+        } else {
+            None
+        }
+    }
+
+    /// Raw value of the ID.
+    pub const fn as_raw(&self) -> u16 {
+        self.0
+    }
+}
+
+/// Extended 29-bit CAN identifier.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ExtendedId(u32);
+
+impl ExtendedId {
+    /// The highest, lowest priority, extended ID.
+    pub const MAX: Self = Self(0x1FFF_FFFF);
+
+    /// Create an extended ID, returns `None` if `raw` doesn't fit in 29 bits.
+    pub const fn new(raw: u32) -> Option<Self> {
+        if raw <= 0x1FFF_FFFF {
+            Some(Self(raw))
+        } else {
+            None
+        }
+    }
+
+    /// Raw value of the ID.
+    pub const fn as_raw(&self) -> u32 {
+        self.0
+    }
+
+    /// The 11 most significant bits, sent in the place of a standard ID.
+    pub const fn standard_id(&self) -> StandardId {
+        StandardId((self.0 >> 18) as u16)
+    }
+}
+
+/// CAN identifier, standard or extended.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Id {
+    /// Standard 11-bit identifier
+    Standard(StandardId),
+    /// Extended 29-bit identifier
+    Extended(ExtendedId),
+}
+
+impl From<StandardId> for Id {
+    fn from(id: StandardId) -> Self {
+        Id::Standard(id)
+    }
+}
+
+impl From<ExtendedId> for Id {
+    fn from(id: ExtendedId) -> Self {
+        Id::Extended(id)
+    }
+}
+
+impl From<embedded_can::StandardId> for StandardId {
+    fn from(id: embedded_can::StandardId) -> Self {
+        StandardId(id.as_raw())
+    }
+}
+
+impl From<StandardId> for embedded_can::StandardId {
+    fn from(id: StandardId) -> Self {
+        // Always in range.
+        embedded_can::StandardId::new(id.0).unwrap()
+    }
+}
+
+impl From<embedded_can::ExtendedId> for ExtendedId {
+    fn from(id: embedded_can::ExtendedId) -> Self {
+        ExtendedId(id.as_raw())
+    }
+}
+
+impl From<ExtendedId> for embedded_can::ExtendedId {
+    fn from(id: ExtendedId) -> Self {
+        // Always in range.
+        embedded_can::ExtendedId::new(id.0).unwrap()
+    }
+}
+
+impl From<embedded_can::Id> for Id {
+    fn from(id: embedded_can::Id) -> Self {
+        match id {
+            embedded_can::Id::Standard(id) => Id::Standard(id.into()),
+            embedded_can::Id::Extended(id) => Id::Extended(id.into()),
+        }
+    }
+}
+
+impl From<Id> for embedded_can::Id {
+    fn from(id: Id) -> Self {
+        match id {
+            Id::Standard(id) => embedded_can::Id::Standard(id.into()),
+            Id::Extended(id) => embedded_can::Id::Extended(id.into()),
+        }
+    }
+}
+
+impl Id {
+    /// Mailbox identifier register layout: STID[31:21], EXID[20:3], IDE[2], RTR[1].
+    pub(crate) const fn to_mir(self) -> u32 {
+        match self {
+            Id::Standard(id) => (id.0 as u32) << 21,
+            Id::Extended(id) => (id.0 << 3) | MIR_IDE,
+        }
+    }
+
+    pub(crate) const fn from_mir(mir: u32) -> Self {
+        if mir & MIR_IDE != 0 {
+            Id::Extended(ExtendedId(mir >> 3))
+        } else {
+            Id::Standard(StandardId((mir >> 21) as u16))
+        }
+    }
+}
+
+pub(crate) const MIR_IDE: u32 = 1 << 2;
+pub(crate) const MIR_RTR: u32 = 1 << 1;
+
+/// Classic CAN frame, with up to 8 data bytes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Frame {
+    id: Id,
+    remote: bool,
+    dlc: u8,
+    data: [u8; 8],
+}
+
+impl Default for Frame {
+    /// Empty data frame with the standard ID 0, used to initialize buffers.
+    fn default() -> Self {
+        Self {
+            id: Id::Standard(StandardId(0)),
+            remote: false,
+            dlc: 0,
+            data: [0; 8],
+        }
+    }
+}
+
+impl Frame {
+    /// Create a data frame, returns `None` if `data` is longer than 8 bytes.
+    pub fn new_data(id: impl Into<Id>, data: &[u8]) -> Option<Self> {
+        if data.len() > 8 {
+            return None;
+        }
+
+        let mut buf = [0; 8];
+        buf[..data.len()].copy_from_slice(data);
+
+        Some(Self {
+            id: id.into(),
+            remote: false,
+            dlc: data.len() as u8,
+            data: buf,
+        })
+    }
+
+    /// Create a remote frame requesting `dlc` bytes, returns `None` if `dlc` is above 8.
+    pub fn new_remote(id: impl Into<Id>, dlc: u8) -> Option<Self> {
+        if dlc > 8 {
+            return None;
+        }
+
+        Some(Self {
+            id: id.into(),
+            remote: true,
+            dlc,
+            data: [0; 8],
+        })
+    }
+
+    /// Identifier of the frame.
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// Whether this is a remote frame.
+    pub fn is_remote_frame(&self) -> bool {
+        self.remote
+    }
+
+    /// Data length code, the number of data bytes, or of requested bytes for a remote frame.
+    pub fn dlc(&self) -> u8 {
+        self.dlc
+    }
+
+    /// Data bytes, empty for a remote frame.
+    pub fn data(&self) -> &[u8] {
+        if self.remote {
+            &[]
+        } else {
+            &self.data[..self.dlc as usize]
+        }
+    }
+
+    /// Whether `self` wins arbitration against `other`.
+    ///
+    /// Lower IDs win, a standard ID wins against an extended ID of the same base ID, and a data
+    /// frame wins against a remote frame with the same ID.
+    pub fn has_priority_over(&self, other: &Frame) -> bool {
+        self.priority_key() < other.priority_key()
+    }
+
+    /// The bits sent during arbitration, in bus order.
+    fn priority_key(&self) -> u32 {
+        let rtr = self.remote as u32;
+        match self.id {
+            Id::Standard(id) => ((id.0 as u32) << 21) | (rtr << 20),
+            // SRR is sent recessive in place of the RTR bit of a standard frame, followed by IDE.
+            Id::Extended(id) => ((id.0 >> 18) << 21) | (1 << 20) | (1 << 19) | ((id.0 & 0x3FFFF) << 1) | rtr,
+        }
+    }
+
+    pub(crate) fn mir(&self) -> u32 {
+        self.id.to_mir() | if self.remote { MIR_RTR } else { 0 }
+    }
+
+    pub(crate) fn data_words(&self) -> (u32, u32) {
+        (
+            u32::from_le_bytes(self.data[..4].try_into().unwrap()),
+            u32::from_le_bytes(self.data[4..].try_into().unwrap()),
+        )
+    }
+
+    pub(crate) fn from_mailbox(mir: u32, dlc: u8, low: u32, high: u32) -> Self {
+        let mut data = [0; 8];
+        data[..4].copy_from_slice(&low.to_le_bytes());
+        data[4..].copy_from_slice(&high.to_le_bytes());
+
+        let remote = mir & MIR_RTR != 0;
+
+        Self {
+            id: Id::from_mir(mir),
+            remote,
+            // DLC values 9..=15 are valid on the bus and mean 8 bytes.
+            dlc: dlc.min(8),
+            data: if remote { [0; 8] } else { data },
+        }
+    }
+}
+
+impl embedded_can::Frame for Frame {
+    fn new(id: impl Into<embedded_can::Id>, data: &[u8]) -> Option<Self> {
+        Frame::new_data(Id::from(id.into()), data)
+    }
+
+    fn new_remote(id: impl Into<embedded_can::Id>, dlc: usize) -> Option<Self> {
+        if dlc > 8 {
+            return None;
+        }
+        Frame::new_remote(Id::from(id.into()), dlc as u8)
+    }
+
+    fn is_extended(&self) -> bool {
+        matches!(self.id, Id::Extended(_))
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        self.remote
+    }
+
+    fn id(&self) -> embedded_can::Id {
+        self.id.into()
+    }
+
+    fn dlc(&self) -> usize {
+        self.dlc as usize
+    }
+
+    fn data(&self) -> &[u8] {
+        Frame::data(self)
+    }
+}
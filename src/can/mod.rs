@@ -0,0 +1,562 @@
+//! Controller Area Network (CAN)
+//!
+//! Driver for the bxCAN-style controller of the CH32V2/V3: three transmit mailboxes, two receive
+//! FIFOs of three frames, and 32-bit acceptance filter banks.
+
+use crate::gpio::{AFType, AnyPin, Pull, SealedPin, Speed};
+use crate::{into_ref, peripherals, Peripheral, PeripheralRef};
+
+mod buffered;
+mod filter;
+mod frame;
+mod registers;
+pub mod util;
+
+pub use buffered::{
+    BufferedCan, ErrorEvent, Rx0InterruptHandler, Rx1InterruptHandler, SceInterruptHandler, TxInterruptHandler,
+};
+pub use filter::Filter;
+pub use frame::{ExtendedId, Frame, Id, StandardId};
+use registers::Registers;
+
+/// Number of filter banks of each CAN instance.
+pub const FILTER_BANKS: u8 = 14;
+
+/// CAN error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Error {
+    /// A frame was received while the FIFO was full, and was lost.
+    Overrun,
+}
+
+impl embedded_can::Error for Error {
+    fn kind(&self) -> embedded_can::ErrorKind {
+        match self {
+            Error::Overrun => embedded_can::ErrorKind::Overrun,
+        }
+    }
+}
+
+/// Bit timing, in time quanta of `prescaler` PCLK1 cycles.
+///
+/// The bit rate is `pclk1 / (prescaler * (1 + seg1 + seg2))`, the sample point is at
+/// `(1 + seg1) / (1 + seg1 + seg2)` of the bit.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BitTiming {
+    /// Clock prescaler, 1..=1024.
+    pub prescaler: u16,
+    /// Time segment before the sample point, including the propagation segment, 1..=16 quanta.
+    pub seg1: u8,
+    /// Time segment after the sample point, 1..=8 quanta.
+    pub seg2: u8,
+    /// Resynchronization jump width, 1..=4 quanta.
+    pub sjw: u8,
+}
+
+/// Bus state, from the error counters.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BusState {
+    /// Both error counters below 96.
+    ErrorActive,
+    /// An error counter reached 96.
+    ErrorWarning,
+    /// An error counter is above 127, errors are only signaled recessively.
+    ErrorPassive,
+    /// The transmit error counter is above 255, the controller is off the bus.
+    BusOff,
+}
+
+/// Transmit and receive error counters
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ErrorCounters {
+    /// Transmit error counter
+    pub transmit: u8,
+    /// Receive error counter
+    pub receive: u8,
+}
+
+/// Bus-off recovery policy
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BusOffRecovery {
+    /// Rejoin the bus by itself once 128 sequences of 11 recessive bits were seen.
+    Automatic,
+    /// Stay off the bus until [`Can::recover`] is called.
+    Manual,
+}
+
+/// Operating mode
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Mode {
+    /// Normal operation on the bus.
+    Normal,
+    /// Receive only, don't acknowledge frames nor send errors. Used for bus monitoring.
+    Silent,
+    /// Sent frames are received back, TX still drives the bus. Used for self-test.
+    Loopback,
+    /// Loopback without driving the bus. Used for self-test without disturbing the bus.
+    SilentLoopback,
+}
+
+/// CAN config
+#[non_exhaustive]
+#[derive(Copy, Clone)]
+pub struct Config {
+    /// Bit timing
+    pub timing: BitTiming,
+    /// Operating mode
+    pub mode: Mode,
+    /// Retransmit a frame until it is sent, after a lost arbitration or an error.
+    pub automatic_retransmit: bool,
+    /// Send the pending mailboxes in the order they were filled, instead of by ID priority.
+    pub transmit_fifo_order: bool,
+    /// Lock a full receive FIFO, dropping new frames instead of overwriting the last one.
+    pub receive_fifo_locked: bool,
+    /// How to leave the bus-off state.
+    pub bus_off_recovery: BusOffRecovery,
+    /// Time-triggered communication mode, run the bit time counter stamping received frames, see
+    /// [`Envelope::timestamp`].
+    pub time_triggered: bool,
+}
+
+impl Config {
+    /// Normal mode with the given bit timing.
+    pub const fn new(timing: BitTiming) -> Self {
+        Self {
+            timing,
+            mode: Mode::Normal,
+            automatic_retransmit: true,
+            transmit_fifo_order: false,
+            receive_fifo_locked: false,
+            bus_off_recovery: BusOffRecovery::Manual,
+            time_triggered: false,
+        }
+    }
+}
+
+/// Received frame with its reception time.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Envelope {
+    /// Received frame
+    pub frame: Frame,
+    /// Bit time counter at the start of the frame, 0 without [`Config::time_triggered`].
+    ///
+    /// The counter is clocked by the bit time and wraps at 16 bits.
+    pub timestamp: u16,
+    /// Time the frame was read out of the hardware FIFO, by the interrupt for [`BufferedCan`].
+    pub instant: embassy_time::Instant,
+}
+
+impl Default for Envelope {
+    /// Empty envelope, used to initialize buffers.
+    fn default() -> Self {
+        Self {
+            frame: Frame::default(),
+            timestamp: 0,
+            instant: embassy_time::Instant::from_ticks(0),
+        }
+    }
+}
+
+/// Transmit mailbox
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Mailbox {
+    /// Mailbox 0
+    Mailbox0 = 0,
+    /// Mailbox 1
+    Mailbox1 = 1,
+    /// Mailbox 2
+    Mailbox2 = 2,
+}
+
+/// Receive FIFO
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Fifo {
+    /// FIFO 0
+    Fifo0 = 0,
+    /// FIFO 1
+    Fifo1 = 1,
+}
+
+/// CAN driver
+pub struct Can<'d, T: Instance> {
+    _peri: PeripheralRef<'d, T>,
+    rx: PeripheralRef<'d, AnyPin>,
+    tx: PeripheralRef<'d, AnyPin>,
+}
+
+impl<'d, T: Instance> Can<'d, T> {
+    /// Create a new CAN driver and join the bus.
+    ///
+    /// Filter bank 0 accepts every frame into FIFO 0, see [`set_filter`](Self::set_filter).
+    ///
+    /// Joining the bus waits for 11 recessive bits, so this blocks while RX is held low.
+    ///
+    /// The filters of CAN2 are in CAN1, so the CAN1 clock must be running, e.g. by creating the
+    /// CAN1 driver first.
+    pub fn new<const REMAP: u8>(
+        peri: impl Peripheral<P = T> + 'd,
+        rx: impl Peripheral<P = impl RxPin<T, REMAP>> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T, REMAP>> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(peri, rx, tx);
+
+        T::enable_and_reset();
+        T::set_remap(REMAP);
+
+        rx.set_as_input(Pull::Up);
+        tx.set_as_af_output(AFType::OutputPushPull, Speed::High);
+
+        let mut this = Self {
+            _peri: peri,
+            rx: rx.map_into(),
+            tx: tx.map_into(),
+        };
+
+        this.set_config(&config);
+        this.set_filter(0, Filter::accept_all(), Fifo::Fifo0);
+
+        this
+    }
+
+    /// Create a new CAN driver in normal mode, with the bit timing calculated for `bitrate` and a
+    /// sample point at 87.5%, see [`util::calc_bit_timing`].
+    ///
+    /// Panics if the APB1 clock can't be divided down to exactly `bitrate`.
+    pub fn new_with_bitrate<const REMAP: u8>(
+        peri: impl Peripheral<P = T> + 'd,
+        rx: impl Peripheral<P = impl RxPin<T, REMAP>> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T, REMAP>> + 'd,
+        bitrate: u32,
+    ) -> Self {
+        Self::new(peri, rx, tx, Config::new(Self::bit_timing(bitrate)))
+    }
+
+    fn bit_timing(bitrate: u32) -> BitTiming {
+        util::calc_bit_timing(T::frequency(), bitrate, 875).expect("CAN bitrate not achievable from the APB1 clock")
+    }
+
+    /// Change the bit timing to the one calculated for `bitrate`, keeping the rest of the config.
+    ///
+    /// Panics if the APB1 clock can't be divided down to exactly `bitrate`.
+    pub fn set_bitrate(&mut self, bitrate: u32) {
+        let timing = Self::bit_timing(bitrate);
+
+        let regs = Registers(T::regs());
+        regs.enter_init_mode();
+
+        T::regs().btimr().modify(|w| {
+            w.set_brp(timing.prescaler - 1);
+            w.set_ts1(timing.seg1 - 1);
+            w.set_ts2(timing.seg2 - 1);
+            w.set_sjw(timing.sjw - 1);
+        });
+
+        regs.leave_init_mode();
+    }
+
+    /// Reconfigure the controller.
+    ///
+    /// The controller leaves the bus meanwhile, the mailboxes and receive FIFOs are kept.
+    pub fn set_config(&mut self, config: &Config) {
+        let timing = config.timing;
+        assert!((1..=1024).contains(&timing.prescaler));
+        assert!((1..=16).contains(&timing.seg1));
+        assert!((1..=8).contains(&timing.seg2));
+        assert!((1..=4).contains(&timing.sjw));
+
+        let regs = Registers(T::regs());
+        regs.enter_init_mode();
+
+        T::regs().ctlr().modify(|w| {
+            w.set_nart(!config.automatic_retransmit);
+            w.set_txfp(config.transmit_fifo_order);
+            w.set_rflm(config.receive_fifo_locked);
+            w.set_abom(config.bus_off_recovery == BusOffRecovery::Automatic);
+            w.set_ttcm(config.time_triggered);
+        });
+        T::regs().btimr().write(|w| {
+            w.set_brp(timing.prescaler - 1);
+            w.set_ts1(timing.seg1 - 1);
+            w.set_ts2(timing.seg2 - 1);
+            w.set_sjw(timing.sjw - 1);
+            w.set_silm(matches!(config.mode, Mode::Silent | Mode::SilentLoopback));
+            w.set_lbkm(matches!(config.mode, Mode::Loopback | Mode::SilentLoopback));
+        });
+
+        regs.leave_init_mode();
+    }
+
+    /// Configure filter bank `index` of this instance, `0..FILTER_BANKS`, to accept frames into
+    /// `fifo`.
+    ///
+    /// Frames matching no enabled filter are dropped.
+    pub fn set_filter(&mut self, index: u8, filter: Filter, fifo: Fifo) {
+        assert!(index < FILTER_BANKS);
+        let bank = (T::FILTER_BANK_START + index) as usize;
+
+        Self::modify_filters(|regs| {
+            regs.fwr().modify(|w| w.set_fact(bank, false));
+
+            regs.fscfgr().modify(|w| w.set_fsc(bank, true));
+            regs.fmcfgr().modify(|w| w.set_fbm(bank, filter.list));
+            regs.fafifor().modify(|w| w.set_ffa(bank, fifo == Fifo::Fifo1));
+            regs.fb(bank).fr1().write(|w| w.0 = filter.fr1);
+            regs.fb(bank).fr2().write(|w| w.0 = filter.fr2);
+
+            regs.fwr().modify(|w| w.set_fact(bank, true));
+        });
+    }
+
+    /// Disable filter bank `index`.
+    pub fn disable_filter(&mut self, index: u8) {
+        assert!(index < FILTER_BANKS);
+        let bank = (T::FILTER_BANK_START + index) as usize;
+
+        Self::modify_filters(|regs| regs.fwr().modify(|w| w.set_fact(bank, false)));
+    }
+
+    /// The filter banks of all instances are in CAN1, and can only be changed in filter
+    /// initialization mode, which pauses reception on all instances.
+    fn modify_filters(f: impl FnOnce(crate::pac::can::Can)) {
+        let regs = crate::pac::CAN1;
+
+        critical_section::with(|_| {
+            regs.fctlr().modify(|w| w.set_finit(true));
+            f(regs);
+            regs.fctlr().modify(|w| w.set_finit(false));
+        });
+    }
+
+    /// Queue `frame` in an empty mailbox.
+    ///
+    /// Returns the used mailbox, or `None` if all mailboxes are pending.
+    pub fn try_write(&mut self, frame: &Frame) -> Option<Mailbox> {
+        let regs = Registers(T::regs());
+        let mailbox = regs.free_mailbox()?;
+        regs.write_mailbox(mailbox, frame);
+        Some(mailbox)
+    }
+
+    /// Queue `frame`, waiting for an empty mailbox.
+    pub fn blocking_write(&mut self, frame: &Frame) -> Mailbox {
+        loop {
+            if let Some(mailbox) = self.try_write(frame) {
+                return mailbox;
+            }
+        }
+    }
+
+    /// Whether all mailboxes are empty, all queued frames were sent or aborted.
+    pub fn is_transmitter_idle(&self) -> bool {
+        let regs = Registers(T::regs());
+        [Mailbox::Mailbox0, Mailbox::Mailbox1, Mailbox::Mailbox2]
+            .into_iter()
+            .all(|mb| regs.is_mailbox_empty(mb))
+    }
+
+    /// Wait until all queued frames were sent.
+    pub fn blocking_flush(&mut self) {
+        while !self.is_transmitter_idle() {}
+    }
+
+    /// Abort the frame pending in `mailbox`.
+    ///
+    /// Returns `true` if the frame was aborted, `false` if it had already been sent.
+    pub fn abort(&mut self, mailbox: Mailbox) -> bool {
+        Registers(T::regs()).abort(mailbox)
+    }
+
+    /// Current bus state.
+    pub fn bus_state(&self) -> BusState {
+        Registers(T::regs()).bus_state()
+    }
+
+    /// Current error counters.
+    pub fn error_counters(&self) -> ErrorCounters {
+        Registers(T::regs()).error_counters()
+    }
+
+    /// Start the recovery from bus-off, with [`BusOffRecovery::Manual`].
+    ///
+    /// The controller rejoins the bus once 128 sequences of 11 recessive bits were seen, and blocks
+    /// meanwhile while RX is held low.
+    pub fn recover(&mut self) {
+        let regs = Registers(T::regs());
+        regs.enter_init_mode();
+        regs.leave_init_mode();
+    }
+
+    /// Read a received frame, from FIFO 0 first.
+    ///
+    /// Returns `Ok(None)` if both FIFOs are empty. A lost frame is reported once as
+    /// [`Error::Overrun`], the frames still in the FIFO are read by the next calls.
+    pub fn try_read(&mut self) -> Result<Option<Frame>, Error> {
+        Ok(self.try_read_envelope()?.map(|envelope| envelope.frame))
+    }
+
+    /// Wait for a received frame.
+    pub fn blocking_read(&mut self) -> Result<Frame, Error> {
+        Ok(self.blocking_read_envelope()?.frame)
+    }
+
+    /// Read a received frame with its reception time, see [`try_read`](Self::try_read).
+    pub fn try_read_envelope(&mut self) -> Result<Option<Envelope>, Error> {
+        let regs = Registers(T::regs());
+
+        for fifo in [Fifo::Fifo0, Fifo::Fifo1] {
+            let (pending, overrun) = regs.fifo_status(fifo);
+            if overrun {
+                return Err(Error::Overrun);
+            }
+            if pending > 0 {
+                return Ok(Some(regs.read_fifo(fifo)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Wait for a received frame with its reception time.
+    pub fn blocking_read_envelope(&mut self) -> Result<Envelope, Error> {
+        loop {
+            if let Some(envelope) = self.try_read_envelope()? {
+                return Ok(envelope);
+            }
+        }
+    }
+}
+
+impl<'d, T: Instance> Drop for Can<'d, T> {
+    fn drop(&mut self) {
+        // Back to sleep mode, leaving the bus.
+        T::regs().ctlr().write(|w| w.set_reset(true));
+
+        self.rx.set_as_disconnected();
+        self.tx.set_as_disconnected();
+
+        T::disable();
+    }
+}
+
+impl<'d, T: Instance> embedded_can::nb::Can for Can<'d, T> {
+    type Frame = Frame;
+    type Error = Error;
+
+    /// Queue `frame` in an empty mailbox, or in place of the lowest priority pending frame if
+    /// `frame` has priority over it, returning the replaced frame.
+    fn transmit(&mut self, frame: &Frame) -> nb::Result<Option<Frame>, Error> {
+        if self.try_write(frame).is_some() {
+            return Ok(None);
+        }
+
+        let regs = Registers(T::regs());
+        let mut lowest = Mailbox::Mailbox0;
+        let mut lowest_frame = regs.read_mailbox(lowest);
+        for mailbox in [Mailbox::Mailbox1, Mailbox::Mailbox2] {
+            let pending = regs.read_mailbox(mailbox);
+            if lowest_frame.has_priority_over(&pending) {
+                lowest = mailbox;
+                lowest_frame = pending;
+            }
+        }
+
+        if !frame.has_priority_over(&lowest_frame) {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        // The frame may have been sent meanwhile, then there is nothing to hand back.
+        let replaced = regs.abort(lowest).then_some(lowest_frame);
+        regs.write_mailbox(lowest, frame);
+        Ok(replaced)
+    }
+
+    fn receive(&mut self) -> nb::Result<Frame, Error> {
+        match self.try_read()? {
+            Some(frame) => Ok(frame),
+            None => Err(nb::Error::WouldBlock),
+        }
+    }
+}
+
+impl<'d, T: Instance> embedded_can::blocking::Can for Can<'d, T> {
+    type Frame = Frame;
+    type Error = Error;
+
+    fn transmit(&mut self, frame: &Frame) -> Result<(), Error> {
+        self.blocking_write(frame);
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Frame, Error> {
+        self.blocking_read()
+    }
+}
+
+trait SealedInstance: crate::peripheral::RccPeripheral + crate::peripheral::RemapPeripheral {
+    /// First filter bank of this instance in CAN1.
+    const FILTER_BANK_START: u8;
+
+    fn regs() -> crate::pac::can::Can;
+    fn buffered_state() -> &'static buffered::State;
+}
+
+/// CAN peripheral instance
+#[allow(private_bounds)]
+pub trait Instance: SealedInstance + 'static {
+    /// Transmit interrupt for this instance
+    type TxInterrupt: crate::interrupt::typelevel::Interrupt;
+    /// FIFO 0 receive interrupt for this instance
+    type Rx0Interrupt: crate::interrupt::typelevel::Interrupt;
+    /// FIFO 1 receive interrupt for this instance
+    type Rx1Interrupt: crate::interrupt::typelevel::Interrupt;
+    /// Status change and error interrupt for this instance
+    type SceInterrupt: crate::interrupt::typelevel::Interrupt;
+}
+
+macro_rules! impl_can {
+    ($inst:ident, $filter_bank_start:expr) => {
+        impl SealedInstance for peripherals::$inst {
+            const FILTER_BANK_START: u8 = $filter_bank_start;
+
+            fn regs() -> crate::pac::can::Can {
+                crate::pac::$inst
+            }
+
+            fn buffered_state() -> &'static buffered::State {
+                static STATE: buffered::State = buffered::State::new();
+                &STATE
+            }
+        }
+
+        impl Instance for peripherals::$inst {
+            type TxInterrupt = crate::_generated::peripheral_interrupts::$inst::TX;
+            type Rx0Interrupt = crate::_generated::peripheral_interrupts::$inst::RX0;
+            type Rx1Interrupt = crate::_generated::peripheral_interrupts::$inst::RX1;
+            type SceInterrupt = crate::_generated::peripheral_interrupts::$inst::SCE;
+        }
+    };
+}
+
+// CAN2 uses the filter banks from 14, the reset value of CAN2SB.
+foreach_peripheral!(
+    (can, CAN1) => {
+        impl_can!(CAN1, 0);
+    };
+    (can, CAN2) => {
+        impl_can!(CAN2, 14);
+    };
+);
+
+pin_trait!(RxPin, Instance);
+pin_trait!(TxPin, Instance);
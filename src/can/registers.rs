@@ -0,0 +1,128 @@
+//! Register level helpers shared by the CAN drivers.
+
+use super::frame::Frame;
+use super::{BusState, Envelope, ErrorCounters, Fifo, Mailbox};
+
+pub(crate) struct Registers(pub crate::pac::can::Can);
+
+impl Registers {
+    /// Request initialization mode and wait until the controller has entered it.
+    pub fn enter_init_mode(&self) {
+        self.0.ctlr().modify(|w| {
+            w.set_sleep(false);
+            w.set_inrq(true);
+        });
+        while !self.0.statr().read().inak() {}
+    }
+
+    /// Leave initialization mode. The controller joins the bus after 11 consecutive recessive bits.
+    pub fn leave_init_mode(&self) {
+        self.0.ctlr().modify(|w| {
+            w.set_sleep(false);
+            w.set_inrq(false);
+        });
+        while self.0.statr().read().inak() {}
+    }
+
+    pub fn is_mailbox_empty(&self, mailbox: Mailbox) -> bool {
+        self.0.tstatr().read().tme(mailbox as usize)
+    }
+
+    /// Index of an empty transmit mailbox, if any.
+    pub fn free_mailbox(&self) -> Option<Mailbox> {
+        let tstatr = self.0.tstatr().read();
+        [Mailbox::Mailbox0, Mailbox::Mailbox1, Mailbox::Mailbox2]
+            .into_iter()
+            .find(|&mb| tstatr.tme(mb as usize))
+    }
+
+    /// Fill the empty `mailbox` with `frame` and request its transmission.
+    pub fn write_mailbox(&self, mailbox: Mailbox, frame: &Frame) {
+        let mb = mailbox as usize;
+        let (low, high) = frame.data_words();
+
+        self.0.txmdtr(mb).write(|w| w.set_dlc(frame.dlc()));
+        self.0.txmdlr(mb).write(|w| w.0 = low);
+        self.0.txmdhr(mb).write(|w| w.0 = high);
+        // TXRQ is bit 0 of the identifier register.
+        self.0.txmir(mb).write(|w| w.0 = frame.mir() | 1);
+    }
+
+    /// Read back the frame pending in `mailbox`.
+    pub fn read_mailbox(&self, mailbox: Mailbox) -> Frame {
+        let mb = mailbox as usize;
+        Frame::from_mailbox(
+            self.0.txmir(mb).read().0,
+            self.0.txmdtr(mb).read().dlc(),
+            self.0.txmdlr(mb).read().0,
+            self.0.txmdhr(mb).read().0,
+        )
+    }
+
+    /// Request the abort of a pending transmission, and wait until the mailbox is empty.
+    ///
+    /// Returns `true` if the frame was aborted, `false` if it was sent in the meantime or the
+    /// mailbox was already empty.
+    pub fn abort(&self, mailbox: Mailbox) -> bool {
+        let mb = mailbox as usize;
+        if self.is_mailbox_empty(mailbox) {
+            return false;
+        }
+
+        self.0.tstatr().write(|w| w.set_abrq(mb, true));
+        while !self.is_mailbox_empty(mailbox) {}
+
+        // The frame was sent if the transmission completed before the abort.
+        !self.0.tstatr().read().txok(mb)
+    }
+
+    /// Number of frames pending in `fifo`, and whether one was lost since the last check.
+    pub fn fifo_status(&self, fifo: Fifo) -> (u8, bool) {
+        let rfifo = self.0.rfifo(fifo as usize).read();
+        if rfifo.fovr() {
+            // Write 1 to clear.
+            self.0.rfifo(fifo as usize).write(|w| w.set_fovr(true));
+        }
+        (rfifo.fmp(), rfifo.fovr())
+    }
+
+    /// Read the oldest frame of a non-empty `fifo` and release its slot.
+    pub fn read_fifo(&self, fifo: Fifo) -> Envelope {
+        let n = fifo as usize;
+        let rxmdtr = self.0.rxmdtr(n).read();
+        let frame = Frame::from_mailbox(
+            self.0.rxmir(n).read().0,
+            rxmdtr.dlc(),
+            self.0.rxmdlr(n).read().0,
+            self.0.rxmdhr(n).read().0,
+        );
+        self.0.rfifo(n).write(|w| w.set_rfom(true));
+
+        Envelope {
+            frame,
+            timestamp: rxmdtr.time(),
+            instant: embassy_time::Instant::now(),
+        }
+    }
+
+    pub fn bus_state(&self) -> BusState {
+        let errsr = self.0.errsr().read();
+        if errsr.boff() {
+            BusState::BusOff
+        } else if errsr.epvf() {
+            BusState::ErrorPassive
+        } else if errsr.ewgf() {
+            BusState::ErrorWarning
+        } else {
+            BusState::ErrorActive
+        }
+    }
+
+    pub fn error_counters(&self) -> ErrorCounters {
+        let errsr = self.0.errsr().read();
+        ErrorCounters {
+            transmit: errsr.tec(),
+            receive: errsr.rec(),
+        }
+    }
+}
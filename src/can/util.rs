@@ -0,0 +1,58 @@
+//! Bit timing calculation.
+
+use super::BitTiming;
+use crate::time::Hertz;
+
+/// Calculate the bit timing for `bitrate` from the CAN clock `pclk`, the APB1 clock.
+///
+/// `sample_point` is in tenths of a percent of the bit, e.g. 875 for the usual 87.5%. The timing
+/// with the most time quanta per bit is chosen among those closest to the sample point, and the
+/// resynchronization jump width is as wide as allowed, up to 4 quanta.
+///
+/// Returns `None` if `pclk` can't be divided down to exactly `bitrate`.
+pub const fn calc_bit_timing(pclk: Hertz, bitrate: u32, sample_point: u16) -> Option<BitTiming> {
+    if pclk.0 == 0 || bitrate == 0 || sample_point == 0 || sample_point >= 1000 {
+        return None;
+    }
+
+    let mut best: Option<BitTiming> = None;
+    let mut best_error = u32::MAX;
+
+    // 1 + seg1 + seg2 quanta per bit, from 1 + 16 + 8 down to 1 + 1 + 1.
+    let mut quanta = 25;
+    while quanta >= 3 {
+        let clocks = bitrate as u64 * quanta as u64;
+        if pclk.0 as u64 % clocks == 0 && pclk.0 as u64 / clocks <= 1024 {
+            let prescaler = (pclk.0 as u64 / clocks) as u16;
+
+            // Quanta up to the sample point, including the sync segment, rounded to nearest, and
+            // clamped to keep both segments within their ranges.
+            let mut sample = (quanta * sample_point as u32 + 500) / 1000;
+            let min = if quanta > 10 { quanta - 8 } else { 2 };
+            let max = if quanta > 18 { 17 } else { quanta - 1 };
+            if sample < min {
+                sample = min;
+            }
+            if sample > max {
+                sample = max;
+            }
+
+            let seg1 = sample - 1;
+            let seg2 = quanta - sample;
+
+            let error = (sample * 1000 / quanta).abs_diff(sample_point as u32);
+            if error < best_error {
+                best_error = error;
+                best = Some(BitTiming {
+                    prescaler,
+                    seg1: seg1 as u8,
+                    seg2: seg2 as u8,
+                    sjw: if seg2 < 4 { seg2 as u8 } else { 4 },
+                });
+            }
+        }
+        quanta -= 1;
+    }
+
+    best
+}
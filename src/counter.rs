@@ -0,0 +1,119 @@
+//! Monotonic 64-bit cycle counter.
+//!
+//! Backed by the 64-bit SysTick counter of Qingke V3 and V4 cores. On Qingke V4 it counts HCLK
+//! cycles, on Qingke V3, whose SysTick has no clock select, HCLK/8. It is never reloaded and
+//! would take thousands of years to wrap, so it can be used for profiling and timestamping
+//! regardless of whether embassy-time is in use.
+//!
+//! The counter rate follows HCLK, and ticks are converted at the current rate: an interval that
+//! spans an [`rcc::reclock`](crate::rcc::reclock) must be converted before it.
+//!
+//! Note: the embassy SysTick time driver shares this counter (read-only), so both can be used together.
+
+use core::ops::Sub;
+
+use crate::pac::SYSTICK;
+use crate::time::Hertz;
+
+/// Start the SysTick counter at HCLK if no one else did.
+#[cfg(qingke_v4)]
+pub(crate) unsafe fn init() {
+    use crate::pac::systick::vals;
+
+    SYSTICK.ctlr().modify(|w| {
+        w.set_mode(vals::Mode::UPCOUNT);
+        w.set_stre(false);
+        w.set_stclk(vals::Stclk::HCLK);
+        w.set_ste(true);
+    });
+}
+
+/// Start the SysTick counter, it counts up at HCLK/8.
+#[cfg(qingke_v3)]
+pub(crate) unsafe fn init() {
+    SYSTICK.ctlr().modify(|w| w.set_ste(true));
+}
+
+/// Raw counter value, in ticks of [`frequency()`].
+#[inline]
+pub fn cycle_counter() -> u64 {
+    SYSTICK.cnt().read()
+}
+
+/// Counter frequency, from the current HCLK.
+#[cfg(qingke_v4)]
+#[inline]
+pub fn frequency() -> Hertz {
+    crate::rcc::clocks().hclk
+}
+
+/// Counter frequency, from the current HCLK.
+#[cfg(qingke_v3)]
+#[inline]
+pub fn frequency() -> Hertz {
+    crate::rcc::clocks().hclk / 8u32
+}
+
+/// A point in time, as measured by [`cycle_counter()`].
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Instant {
+    ticks: u64,
+}
+
+impl Instant {
+    /// The current instant.
+    #[inline]
+    pub fn now() -> Self {
+        Self { ticks: cycle_counter() }
+    }
+
+    /// Create an instant from a raw counter value.
+    pub const fn from_ticks(ticks: u64) -> Self {
+        Self { ticks }
+    }
+
+    /// Raw counter value of this instant.
+    pub const fn as_ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Ticks elapsed between `earlier` and `self`, saturating at 0.
+    pub fn ticks_since(&self, earlier: Instant) -> u64 {
+        self.ticks.saturating_sub(earlier.ticks)
+    }
+
+    /// Ticks elapsed since this instant.
+    pub fn elapsed_ticks(&self) -> u64 {
+        Instant::now().ticks_since(*self)
+    }
+
+    /// Nanoseconds elapsed since this instant.
+    pub fn elapsed_nanos(&self) -> u64 {
+        ticks_to_nanos(self.elapsed_ticks())
+    }
+
+    /// Microseconds elapsed since this instant.
+    pub fn elapsed_micros(&self) -> u64 {
+        self.elapsed_nanos() / 1_000
+    }
+}
+
+impl Sub for Instant {
+    /// Elapsed ticks.
+    type Output = u64;
+
+    fn sub(self, rhs: Instant) -> u64 {
+        self.ticks_since(rhs)
+    }
+}
+
+/// Convert counter ticks to nanoseconds.
+pub fn ticks_to_nanos(ticks: u64) -> u64 {
+    (ticks as u128 * 1_000_000_000 / frequency().0 as u128) as u64
+}
+
+/// Convert nanoseconds to counter ticks, rounding up.
+pub fn nanos_to_ticks(nanos: u64) -> u64 {
+    (nanos as u128 * frequency().0 as u128).div_ceil(1_000_000_000) as u64
+}
@@ -0,0 +1,166 @@
+//! Debug support in low-power modes and while halted (DBGMCU)
+//!
+//! By default the debug interface stops working once the chip sleeps, and the watchdogs and timers
+//! keep counting while the core is halted by the debugger, so the IWDG resets the chip on a
+//! breakpoint. [`configure`] keeps the debug interface alive in the low-power modes and stops the
+//! watchdogs while halted, [`freeze_timer`] does the same for a timer.
+//!
+//! The low-power modes draw more current with the debug interface kept alive: for measurements,
+//! leave them at their default.
+//!
+//! CH32V003 and CH641 map the register at `0xE000_D000`, CH32V103 at `0xE004_2004`, the others
+//! read and write it as CSR `0x7C0`.
+
+/// Low-power modes the debug interface keeps working in, and watchdogs stopped while halted
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    /// Keep the debug interface working in sleep mode.
+    #[cfg(not(qingke_v2))]
+    pub sleep: bool,
+    /// Keep the debug interface and HCLK running in stop mode.
+    #[cfg(not(qingke_v2))]
+    pub stop: bool,
+    /// Keep the debug interface and HCLK running in standby mode.
+    pub standby: bool,
+    /// Stop the independent watchdog while the core is halted.
+    pub iwdg_stop: bool,
+    /// Stop the window watchdog while the core is halted.
+    pub wwdg_stop: bool,
+}
+
+#[cfg(qingke_v2)]
+mod bits {
+    pub const STANDBY: u32 = 1 << 2;
+    pub const IWDG_STOP: u32 = 1 << 0;
+    pub const WWDG_STOP: u32 = 1 << 1;
+}
+
+#[cfg(not(qingke_v2))]
+mod bits {
+    pub const SLEEP: u32 = 1 << 0;
+    pub const STOP: u32 = 1 << 1;
+    pub const STANDBY: u32 = 1 << 2;
+    pub const IWDG_STOP: u32 = 1 << 8;
+    pub const WWDG_STOP: u32 = 1 << 9;
+}
+
+/// Timers that can be stopped while the core is halted
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Timer {
+    #[cfg(peri_tim1)]
+    Tim1,
+    #[cfg(peri_tim2)]
+    Tim2,
+    #[cfg(peri_tim3)]
+    Tim3,
+    #[cfg(peri_tim4)]
+    Tim4,
+    #[cfg(peri_tim5)]
+    Tim5,
+    #[cfg(peri_tim6)]
+    Tim6,
+    #[cfg(peri_tim7)]
+    Tim7,
+    #[cfg(peri_tim8)]
+    Tim8,
+    #[cfg(peri_tim9)]
+    Tim9,
+    #[cfg(peri_tim10)]
+    Tim10,
+}
+
+impl Timer {
+    fn bit(self) -> u32 {
+        #[cfg(qingke_v2)]
+        let bit = match self {
+            #[cfg(peri_tim1)]
+            Timer::Tim1 => 4,
+            #[cfg(peri_tim2)]
+            Timer::Tim2 => 5,
+            #[allow(unreachable_patterns)]
+            _ => unreachable!(),
+        };
+        #[cfg(not(qingke_v2))]
+        let bit = match self {
+            #[cfg(peri_tim1)]
+            Timer::Tim1 => 10,
+            #[cfg(peri_tim2)]
+            Timer::Tim2 => 11,
+            #[cfg(peri_tim3)]
+            Timer::Tim3 => 12,
+            #[cfg(peri_tim4)]
+            Timer::Tim4 => 13,
+            #[cfg(peri_tim8)]
+            Timer::Tim8 => 17,
+            #[cfg(peri_tim5)]
+            Timer::Tim5 => 18,
+            #[cfg(peri_tim6)]
+            Timer::Tim6 => 19,
+            #[cfg(peri_tim7)]
+            Timer::Tim7 => 20,
+            #[cfg(peri_tim9)]
+            Timer::Tim9 => 21,
+            #[cfg(peri_tim10)]
+            Timer::Tim10 => 22,
+        };
+        1 << bit
+    }
+}
+
+#[cfg(qingke_v2)]
+const DBGMCU_CR: *mut u32 = 0xE000_D000 as *mut u32;
+#[cfg(qingke_v3)]
+const DBGMCU_CR: *mut u32 = 0xE004_2004 as *mut u32;
+
+#[cfg(any(qingke_v2, qingke_v3))]
+fn read() -> u32 {
+    unsafe { DBGMCU_CR.read_volatile() }
+}
+
+#[cfg(any(qingke_v2, qingke_v3))]
+fn write(value: u32) {
+    unsafe { DBGMCU_CR.write_volatile(value) }
+}
+
+#[cfg(not(any(qingke_v2, qingke_v3)))]
+fn read() -> u32 {
+    let value: u32;
+    unsafe { core::arch::asm!("csrr {0}, 0x7C0", out(reg) value) };
+    value
+}
+
+#[cfg(not(any(qingke_v2, qingke_v3)))]
+fn write(value: u32) {
+    unsafe { core::arch::asm!("csrw 0x7C0, {0}", in(reg) value) };
+}
+
+fn modify(mask: u32, value: u32) {
+    critical_section::with(|_| write((read() & !mask) | value));
+}
+
+/// Apply `config`, the timer bits are kept.
+pub fn configure(config: Config) {
+    let flags = [
+        #[cfg(not(qingke_v2))]
+        (bits::SLEEP, config.sleep),
+        #[cfg(not(qingke_v2))]
+        (bits::STOP, config.stop),
+        (bits::STANDBY, config.standby),
+        (bits::IWDG_STOP, config.iwdg_stop),
+        (bits::WWDG_STOP, config.wwdg_stop),
+    ];
+    let mask = flags.iter().fold(0, |mask, (bit, _)| mask | bit);
+    let value = flags
+        .iter()
+        .filter(|(_, set)| *set)
+        .fold(0, |value, (bit, _)| value | bit);
+    modify(mask, value);
+}
+
+/// Stop `timer` while the core is halted, or let it count, the default.
+pub fn freeze_timer(timer: Timer, freeze: bool) {
+    let bit = timer.bit();
+    modify(bit, if freeze { bit } else { 0 });
+}
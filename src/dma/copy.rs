@@ -0,0 +1,90 @@
+//! Memory-to-memory copies
+
+use super::word::Word;
+use super::{AnyChannel, Channel, Priority, Transfer, TransferOptions};
+use crate::{into_ref, Peripheral, PeripheralRef};
+
+/// Longest transfer, the counter is 16-bit.
+const MAX_WORDS: usize = 0xFFFF;
+
+/// Copy the bytes before and after the widest word both `src` and `dst` can be aligned to, from the
+/// same byte offset, and return that word size with the aligned bodies.
+fn copy_ends<'s, 'd>(src: &'s [u8], dst: &'d mut [u8]) -> (usize, &'s [u8], &'d mut [u8]) {
+    assert_eq!(src.len(), dst.len());
+
+    let (src_addr, dst_addr) = (src.as_ptr() as usize, dst.as_ptr() as usize);
+    let word = match src_addr ^ dst_addr {
+        x if x % 4 == 0 => 4,
+        x if x % 2 == 0 => 2,
+        _ => 1,
+    };
+    let head = ((word - src_addr % word) % word).min(src.len());
+    let end = src.len() - (src.len() - head) % word;
+
+    dst[..head].copy_from_slice(&src[..head]);
+    dst[end..].copy_from_slice(&src[end..]);
+    (word, &src[head..end], &mut dst[head..end])
+}
+
+fn options() -> TransferOptions {
+    TransferOptions {
+        // Peripheral requests on the same controller go first.
+        priority: Priority::Low,
+        ..Default::default()
+    }
+}
+
+/// Reinterpret the aligned `src` and `dst` bodies as words, and split them into transfers.
+///
+/// # Safety
+///
+/// Both slices must be aligned to `W`, and as long, a multiple of its size.
+unsafe fn chunks<'s, 'd, W: Word>(src: &'s [u8], dst: &'d mut [u8]) -> impl Iterator<Item = (&'s [W], &'d mut [W])> {
+    let words = dst.len() / core::mem::size_of::<W>();
+    let src = core::slice::from_raw_parts(src.as_ptr() as *const W, words);
+    let dst = core::slice::from_raw_parts_mut(dst.as_mut_ptr() as *mut W, words);
+    src.chunks(MAX_WORDS).zip(dst.chunks_mut(MAX_WORDS))
+}
+
+async fn copy_words<W: Word>(channel: &mut PeripheralRef<'_, AnyChannel>, src: &[u8], dst: &mut [u8]) {
+    for (src, dst) in unsafe { chunks::<W>(src, dst) } {
+        unsafe { Transfer::new_mem_to_mem_raw(channel.reborrow(), src, dst, options()) }.await;
+    }
+}
+
+fn blocking_copy_words<W: Word>(channel: &mut PeripheralRef<'_, AnyChannel>, src: &[u8], dst: &mut [u8]) {
+    for (src, dst) in unsafe { chunks::<W>(src, dst) } {
+        unsafe { Transfer::new_mem_to_mem_raw(channel.reborrow(), src, dst, options()) }.blocking_wait();
+    }
+}
+
+/// Copy `src` to `dst` by DMA, the task can run something else meanwhile.
+///
+/// The bulk is moved in 32-bit words when `src` and `dst` have the same offset from a word boundary,
+/// in halfwords or bytes otherwise. The unaligned bytes at the ends are copied by the CPU.
+///
+/// Panics if `src` and `dst` differ in length.
+pub async fn mem_to_mem<'a>(channel: impl Peripheral<P = impl Channel> + 'a, src: &[u8], dst: &mut [u8]) {
+    into_ref!(channel);
+    let mut channel: PeripheralRef<'a, AnyChannel> = channel.map_into();
+
+    match copy_ends(src, dst) {
+        (4, src, dst) => copy_words::<u32>(&mut channel, src, dst).await,
+        (2, src, dst) => copy_words::<u16>(&mut channel, src, dst).await,
+        (_, src, dst) => copy_words::<u8>(&mut channel, src, dst).await,
+    }
+}
+
+/// Copy `src` to `dst` by DMA, waiting for the end.
+///
+/// The CPU only spins meanwhile, interrupts still run. See [`mem_to_mem`].
+pub fn blocking_mem_to_mem<'a>(channel: impl Peripheral<P = impl Channel> + 'a, src: &[u8], dst: &mut [u8]) {
+    into_ref!(channel);
+    let mut channel: PeripheralRef<'a, AnyChannel> = channel.map_into();
+
+    match copy_ends(src, dst) {
+        (4, src, dst) => blocking_copy_words::<u32>(&mut channel, src, dst),
+        (2, src, dst) => blocking_copy_words::<u16>(&mut channel, src, dst),
+        (_, src, dst) => blocking_copy_words::<u8>(&mut channel, src, dst),
+    }
+}
@@ -95,7 +95,7 @@ mod bdma_only {
         fn from(raw: Dir) -> Self {
             match raw {
                 Dir::MemoryToPeripheral => Self::FROMMEMORY,
-                Dir::PeripheralToMemory => Self::FROMPERIPHERAL,
+                Dir::PeripheralToMemory | Dir::MemoryToMemory => Self::FROMPERIPHERAL,
             }
         }
     }
@@ -104,15 +104,28 @@ mod bdma_only {
 pub(crate) struct ChannelState {
     waker: AtomicWaker,
     complete_count: AtomicUsize,
+    half_count: AtomicUsize,
 }
 
 impl ChannelState {
     pub(crate) const NEW: Self = Self {
         waker: AtomicWaker::new(),
         complete_count: AtomicUsize::new(0),
+        half_count: AtomicUsize::new(0),
     };
 }
 
+/// Count an event from the interrupt, without atomic read-modify-write on QingKe V2.
+fn increment(count: &AtomicUsize) {
+    #[cfg(not(qingke_v2))]
+    count.fetch_add(1, Ordering::Release);
+    #[cfg(qingke_v2)]
+    critical_section::with(|_| {
+        let x = count.load(Ordering::Relaxed);
+        count.store(x + 1, Ordering::Release);
+    })
+}
+
 /// safety: must be called only once
 pub(crate) unsafe fn init(cs: critical_section::CriticalSection, dma_priority: interrupt::Priority) {
     foreach_interrupt! {
@@ -141,16 +154,11 @@ impl AnyChannel {
                 if isr.htif(info.num) && cr.read().htie() {
                     // Acknowledge half transfer complete interrupt
                     r.ifcr().write(|w| w.set_htif(info.num, true));
+                    increment(&state.half_count);
                 } else if isr.tcif(info.num) && cr.read().tcie() {
                     // Acknowledge transfer complete interrupt
                     r.ifcr().write(|w| w.set_tcif(info.num, true));
-                    #[cfg(not(qingke_v2))]
-                    state.complete_count.fetch_add(1, Ordering::Release);
-                    #[cfg(qingke_v2)]
-                    critical_section::with(|_| {
-                        let x = state.complete_count.load(Ordering::Relaxed);
-                        state.complete_count.store(x + 1, Ordering::Release);
-                    })
+                    increment(&state.complete_count);
                 } else {
                     return;
                 }
@@ -181,6 +189,7 @@ impl AnyChannel {
                 let ch = r.ch(info.num);
 
                 state.complete_count.store(0, Ordering::Release);
+                state.half_count.store(0, Ordering::Release);
                 self.clear_irqs();
 
                 ch.par().write_value(peri_addr as u32); // PADDR
@@ -190,6 +199,8 @@ impl AnyChannel {
                     w.set_psize(data_size.into());
                     w.set_msize(data_size.into());
                     w.set_minc(incr_mem);
+                    w.set_pinc(dir == Dir::MemoryToMemory);
+                    w.set_mem2mem(dir == Dir::MemoryToMemory);
                     w.set_dir(dir.into());
                     w.set_teie(true); // error
                     w.set_tcie(options.complete_transfer_ir); // tx complete
@@ -280,9 +291,20 @@ impl AnyChannel {
 }
 
 /// DMA transfer.
+///
+/// Awaiting it waits for the end. A circular transfer never ends: [`wait_half_transfer`] and
+/// [`wait_transfer_complete`] wait for each half of the buffer instead, to process one half while
+/// the DMA moves the other.
+///
+/// [`wait_half_transfer`]: Self::wait_half_transfer
+/// [`wait_transfer_complete`]: Self::wait_transfer_complete
 #[must_use = "futures do nothing unless you `.await` or poll them"]
 pub struct Transfer<'a> {
     channel: PeripheralRef<'a, AnyChannel>,
+    /// Half transfer events returned by `wait_half_transfer`.
+    half_seen: usize,
+    /// Transfer complete events returned by `wait_transfer_complete`.
+    complete_seen: usize,
 }
 
 impl<'a> Transfer<'a> {
@@ -384,6 +406,40 @@ impl<'a> Transfer<'a> {
         )
     }
 
+    /// Create a new memory-to-memory DMA transfer, using raw pointers.
+    ///
+    /// The copy runs as fast as the bus allows, without a peripheral request.
+    ///
+    /// # Safety
+    ///
+    /// `src` must stay readable, and `dst` writable and not accessed otherwise, until the transfer
+    /// is over or dropped.
+    pub unsafe fn new_mem_to_mem_raw<W: Word>(
+        channel: impl Peripheral<P = impl Channel> + 'a,
+        src: *const [W],
+        dst: *mut [W],
+        options: TransferOptions,
+    ) -> Self {
+        into_ref!(channel);
+
+        let (src_ptr, src_len) = super::slice_ptr_parts(src);
+        let (dst_ptr, dst_len) = super::slice_ptr_parts_mut(dst);
+        assert_eq!(src_len, dst_len);
+        assert!(dst_len > 0 && dst_len <= 0xFFFF);
+
+        Self::new_inner(
+            channel.map_into(),
+            (),
+            Dir::MemoryToMemory,
+            src_ptr as *const u32,
+            dst_ptr as *mut u32,
+            dst_len,
+            true,
+            W::size(),
+            options,
+        )
+    }
+
     unsafe fn new_inner(
         channel: PeripheralRef<'a, AnyChannel>,
         _request: Request,
@@ -400,7 +456,11 @@ impl<'a> Transfer<'a> {
         );
         channel.start();
 
-        Self { channel }
+        Self {
+            channel,
+            half_seen: 0,
+            complete_seen: 0,
+        }
     }
 
     /// Request the transfer to stop.
@@ -424,6 +484,69 @@ impl<'a> Transfer<'a> {
         self.channel.get_remaining_transfers()
     }
 
+    /// Let a circular transfer complete its current pass over the buffer, then stop.
+    ///
+    /// Requires the transfer complete interrupt to be enabled.
+    pub async fn stop_at_end(&mut self) {
+        self.channel.disable_circular_mode();
+
+        // Without circular mode, the counter stays at 0 once the pass is complete
+        poll_fn(|cx| {
+            let state: &ChannelState = &STATE[self.channel.id as usize];
+            state.waker.register(cx.waker());
+
+            compiler_fence(Ordering::SeqCst);
+
+            if self.get_remaining_transfers() == 0 {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+
+        // "Subsequent reads and writes cannot be moved ahead of preceding reads."
+        fence(Ordering::SeqCst);
+    }
+
+    /// Wait for the DMA to reach the middle of the buffer.
+    ///
+    /// Returns the number of half transfers since the previous call, more than 1 if some were
+    /// missed. Requires [`TransferOptions::half_transfer_ir`].
+    pub async fn wait_half_transfer(&mut self) -> usize {
+        let state: &ChannelState = &STATE[self.channel.id as usize];
+        let count = wait_count(state, &state.half_count, self.half_seen).await;
+        count.wrapping_sub(core::mem::replace(&mut self.half_seen, count))
+    }
+
+    /// Wait for the DMA to reach the end of the buffer, wrapping around in circular mode.
+    ///
+    /// Returns the number of passes over the buffer since the previous call, more than 1 if some
+    /// were missed. Requires [`TransferOptions::complete_transfer_ir`].
+    pub async fn wait_transfer_complete(&mut self) -> usize {
+        let state: &ChannelState = &STATE[self.channel.id as usize];
+        let count = wait_count(state, &state.complete_count, self.complete_seen).await;
+        count.wrapping_sub(core::mem::replace(&mut self.complete_seen, count))
+    }
+
+    /// Number of half transfers since the start, for use with [`set_waker`](Self::set_waker).
+    pub fn half_transfer_count(&self) -> usize {
+        STATE[self.channel.id as usize].half_count.load(Ordering::Acquire)
+    }
+
+    /// Number of transfer completes since the start, for use with [`set_waker`](Self::set_waker).
+    pub fn transfer_complete_count(&self) -> usize {
+        STATE[self.channel.id as usize].complete_count.load(Ordering::Acquire)
+    }
+
+    /// Set a waker to be woken on the half transfer and transfer complete events, to poll the
+    /// counts from a hand-written future.
+    ///
+    /// The channel has one waker, replaced by the next wait.
+    pub fn set_waker(&mut self, waker: &Waker) {
+        STATE[self.channel.id as usize].waker.register(waker);
+    }
+
     /// Blocking wait until the transfer finishes.
     pub fn blocking_wait(mut self) {
         while self.is_running() {}
@@ -461,6 +584,23 @@ impl<'a> Future for Transfer<'a> {
     }
 }
 
+/// Wait for the event `count` of `state` to differ from `seen`, and return it.
+async fn wait_count(state: &ChannelState, count: &AtomicUsize, seen: usize) -> usize {
+    poll_fn(|cx| {
+        state.waker.register(cx.waker());
+
+        compiler_fence(Ordering::SeqCst);
+
+        let count = count.load(Ordering::Acquire);
+        if count != seen {
+            Poll::Ready(count)
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
 // ==============================
 
 struct DmaCtrlImpl<'a>(PeripheralRef<'a, AnyChannel>);
@@ -492,6 +632,10 @@ impl<'a> DmaCtrl for DmaCtrlImpl<'a> {
 }
 
 /// Ringbuffer for receiving data using DMA circular mode.
+///
+/// The DMA writes the buffer over and over, the reader follows it through the remaining transfer
+/// count (NDTR) and the number of wrap-arounds counted by the transfer complete interrupt. Elements
+/// overwritten before they were read are reported as [`OverrunError`].
 pub struct ReadableRingBuffer<'a, W: Word> {
     channel: PeripheralRef<'a, AnyChannel>,
     ringbuf: ReadableDmaRingBuffer<'a, W>,
@@ -499,6 +643,11 @@ pub struct ReadableRingBuffer<'a, W: Word> {
 
 impl<'a, W: Word> ReadableRingBuffer<'a, W> {
     /// Create a new ring buffer.
+    ///
+    /// # Safety
+    ///
+    /// `peri_addr` must be the data register of the peripheral the channel is requested by, and
+    /// stay readable by the DMA as long as the ring buffer exists.
     pub unsafe fn new(
         channel: impl Peripheral<P = impl Channel> + 'a,
         _request: Request,
@@ -555,6 +704,25 @@ impl<'a, W: Word> ReadableRingBuffer<'a, W> {
         self.ringbuf.read(&mut DmaCtrlImpl(self.channel.reborrow()), buf)
     }
 
+    /// Number of elements available for reading.
+    ///
+    /// OverrunError is returned if the DMA has overwritten elements not read yet.
+    pub fn len(&mut self) -> Result<usize, OverrunError> {
+        self.ringbuf.len(&mut DmaCtrlImpl(self.channel.reborrow()))
+    }
+
+    /// Whether no element is available for reading, see [`len`](Self::len).
+    pub fn is_empty(&mut self) -> Result<bool, OverrunError> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Drop all unread elements and continue reading from the current DMA position.
+    ///
+    /// Returns the number of elements discarded, including the ones overwritten after an overrun.
+    pub fn discard(&mut self) -> usize {
+        self.ringbuf.discard(&mut DmaCtrlImpl(self.channel.reborrow()))
+    }
+
     /// Read an exact number of elements from the ringbuffer.
     ///
     /// Returns the remaining number of elements available for immediate reading.
@@ -628,6 +796,10 @@ impl<'a, W: Word> Drop for ReadableRingBuffer<'a, W> {
 }
 
 /// Ringbuffer for writing data using DMA circular mode.
+///
+/// The DMA sends the buffer over and over, the writer stays ahead of it, tracked like
+/// [`ReadableRingBuffer`]. [`OverrunError`] is returned when the DMA caught up with the writer
+/// and sent stale elements.
 pub struct WritableRingBuffer<'a, W: Word> {
     channel: PeripheralRef<'a, AnyChannel>,
     ringbuf: WritableDmaRingBuffer<'a, W>,
@@ -635,6 +807,11 @@ pub struct WritableRingBuffer<'a, W: Word> {
 
 impl<'a, W: Word> WritableRingBuffer<'a, W> {
     /// Create a new ring buffer.
+    ///
+    /// # Safety
+    ///
+    /// `peri_addr` must be the data register of the peripheral the channel is requested by, and
+    /// stay writable by the DMA as long as the ring buffer exists.
     pub unsafe fn new(
         channel: impl Peripheral<P = impl Channel> + 'a,
         _request: Request,
@@ -754,3 +931,168 @@ impl<'a, W: Word> Drop for WritableRingBuffer<'a, W> {
         fence(Ordering::SeqCst);
     }
 }
+
+/// Double buffer for continuously receiving data using DMA circular mode.
+///
+/// The buffer is split into two halves. While the DMA fills one half, the other one can be
+/// processed in place, see [`read_chunk`](Self::read_chunk).
+pub struct ReadableDoubleBuffer<'a, W: Word> {
+    channel: PeripheralRef<'a, AnyChannel>,
+    buffer: &'a mut [W],
+    /// Number of halves handed out so far.
+    consumed: usize,
+    /// Whether the previous call handed out a half.
+    has_prev: bool,
+}
+
+impl<'a, W: Word> ReadableDoubleBuffer<'a, W> {
+    /// Create a new double buffer. The length of `buffer` must be even.
+    ///
+    /// # Safety
+    ///
+    /// `peri_addr` must be the data register of the peripheral, and stay readable by the DMA as long
+    /// as the double buffer exists. `request` must be the one of that peripheral on this channel.
+    pub unsafe fn new(
+        channel: impl Peripheral<P = impl Channel> + 'a,
+        _request: Request,
+        peri_addr: *mut W,
+        buffer: &'a mut [W],
+        mut options: TransferOptions,
+    ) -> Self {
+        into_ref!(channel);
+        let channel: PeripheralRef<'a, AnyChannel> = channel.map_into();
+
+        assert!(buffer.len() >= 2 && buffer.len() % 2 == 0);
+
+        options.half_transfer_ir = true;
+        options.complete_transfer_ir = true;
+        options.circular = true;
+
+        channel.configure(
+            _request,
+            Dir::PeripheralToMemory,
+            peri_addr as *mut u32,
+            buffer.as_mut_ptr() as *mut u32,
+            buffer.len(),
+            true,
+            W::size(),
+            options,
+        );
+
+        Self {
+            channel,
+            buffer,
+            consumed: 0,
+            has_prev: false,
+        }
+    }
+
+    /// Start the DMA transfer.
+    ///
+    /// You must call this after creating it for it to work.
+    pub fn start(&mut self) {
+        self.channel.start()
+    }
+
+    /// Length of one half of the buffer.
+    pub const fn chunk_len(&self) -> usize {
+        self.buffer.len() / 2
+    }
+
+    /// Wait until the DMA has filled the next half, and return it.
+    ///
+    /// The returned half is not written by the DMA until the other half has been filled,
+    /// so it must be processed within that time.
+    ///
+    /// OverrunError is returned if the DMA has overwritten a half that had not been returned yet,
+    /// or the half returned by the previous call. The next call continues with the half after
+    /// the current DMA position.
+    pub async fn read_chunk(&mut self) -> Result<&[W], OverrunError> {
+        // The half handed out by the previous call must not have been overwritten while it was processed.
+        let limit = self.consumed + if self.has_prev { 1 } else { 2 };
+        if self.produced() >= limit {
+            return Err(self.overrun());
+        }
+        self.has_prev = false;
+
+        let produced = poll_fn(|cx| {
+            DmaCtrlImpl(self.channel.reborrow()).set_waker(cx.waker());
+
+            compiler_fence(Ordering::SeqCst);
+
+            let produced = self.produced();
+            if produced > self.consumed {
+                Poll::Ready(produced)
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+
+        if produced >= self.consumed + 2 {
+            return Err(self.overrun());
+        }
+
+        let half = self.chunk_len();
+        let start = (self.consumed % 2) * half;
+        self.consumed += 1;
+        self.has_prev = true;
+
+        // "Subsequent reads and writes cannot be moved ahead of preceding reads."
+        fence(Ordering::SeqCst);
+
+        Ok(&self.buffer[start..start + half])
+    }
+
+    /// Set a waker to be woken when a half has been filled.
+    pub fn set_waker(&mut self, waker: &Waker) {
+        DmaCtrlImpl(self.channel.reborrow()).set_waker(waker);
+    }
+
+    /// Request DMA to stop.
+    ///
+    /// This doesn't immediately stop the transfer, you have to wait until [`is_running`](Self::is_running) returns false.
+    pub fn request_stop(&mut self) {
+        self.channel.request_stop()
+    }
+
+    /// Return whether DMA is still running.
+    ///
+    /// If this returns `false`, it can be because either the transfer finished, or
+    /// it was requested to stop early with [`request_stop`](Self::request_stop).
+    pub fn is_running(&mut self) -> bool {
+        self.channel.is_running()
+    }
+
+    /// Number of halves filled by the DMA since it was started.
+    fn produced(&self) -> usize {
+        let ctrl = DmaCtrlImpl(unsafe { self.channel.clone_unchecked() });
+        let cap = self.buffer.len();
+
+        // Retry if a wrap around was accounted while reading the position.
+        loop {
+            let count = ctrl.get_complete_count();
+            let pos = cap - ctrl.get_remaining_transfers();
+            if count == ctrl.get_complete_count() {
+                return 2 * count + if pos >= cap / 2 { 1 } else { 0 };
+            }
+        }
+    }
+
+    /// Skip everything received so far.
+    fn overrun(&mut self) -> OverrunError {
+        self.consumed = self.produced();
+        self.has_prev = false;
+        OverrunError
+    }
+}
+
+impl<'a, W: Word> Drop for ReadableDoubleBuffer<'a, W> {
+    fn drop(&mut self) {
+        self.request_stop();
+        while self.is_running() {}
+
+        // "Subsequent reads and writes cannot be moved ahead of preceding reads."
+        fence(Ordering::SeqCst);
+    }
+}
@@ -1,5 +1,9 @@
 //! DMA
 //!
+//! [`Transfer`] moves one buffer, [`ReadableRingBuffer`] and [`WritableRingBuffer`] stream through
+//! a buffer in circular mode, for drivers and applications to build continuous reception or
+//! transmission on, e.g. `RingBufferedUartRx` and the I2S driver.
+//!
 //! TODO: DMA2 with CH8 to CH11, which are handled in Exxx registers
 
 #![macro_use]
@@ -18,6 +22,12 @@ mod util;
 pub(crate) use util::*;
 
 pub(crate) mod ringbuffer;
+pub use ringbuffer::OverrunError;
+
+#[cfg(any(bdma, dma))]
+mod copy;
+#[cfg(any(bdma, dma))]
+pub use copy::*;
 
 /// "No DMA" placeholder.
 ///
@@ -35,6 +45,8 @@ impl_peripheral!(NoDma);
 enum Dir {
     MemoryToPeripheral,
     PeripheralToMemory,
+    /// From the peripheral address to the memory one, both incremented, without a request.
+    MemoryToMemory,
 }
 
 pub type Request = ();
@@ -78,6 +78,21 @@ impl<'a, W: Word> ReadableDmaRingBuffer<'a, W> {
         self.cap() - dma.get_remaining_transfers()
     }
 
+    /// Number of elements available for reading.
+    ///
+    /// OverrunError is returned if the dma writer has overtaken the unread elements.
+    pub fn len(&mut self, dma: &mut impl DmaCtrl) -> Result<usize, OverrunError> {
+        let (pos, complete_count) = critical_section::with(|_| (self.pos(dma), dma.get_complete_count()));
+
+        // `start` is in the lap the dma writer was in when the complete counter was last reset
+        let len = (complete_count * self.cap() + pos).saturating_sub(self.start);
+        if len > self.cap() {
+            Err(OverrunError)
+        } else {
+            Ok(len)
+        }
+    }
+
     /// Read an exact number of elements from the ringbuffer.
     ///
     /// Returns the remaining number of elements available for immediate reading.
@@ -218,6 +233,20 @@ impl<'a, W: Word> ReadableDmaRingBuffer<'a, W> {
             }
         }
     }
+
+    /// Drop all unread elements and continue from the current position of the dma writer.
+    ///
+    /// Returns the number of elements discarded, including the ones overwritten after an overrun.
+    pub fn discard(&mut self, dma: &mut impl DmaCtrl) -> usize {
+        let (pos, complete_count) = critical_section::with(|_| (self.pos(dma), dma.reset_complete_count()));
+
+        // `start` is in the lap the dma writer was in when the complete counter was last reset
+        let discarded = (complete_count * self.cap() + pos).saturating_sub(self.start);
+        self.start = pos;
+
+        discarded
+    }
+
     /// Copy from the dma buffer at `data_range` into `buf`
     fn copy_to(&mut self, buf: &mut [W], data_range: Range<usize>) -> usize {
         // Limit the number of elements that can be copied
@@ -451,6 +480,67 @@ mod tests {
         assert_eq!(0, ringbuf.start);
     }
 
+    #[test]
+    fn discard_after_overrun() {
+        let mut dma = TestCircularTransfer::new(16);
+
+        let mut dma_buf = [0u8; 16];
+        let mut ringbuf = ReadableDmaRingBuffer::new(&mut dma_buf);
+
+        dma.setup(vec![
+            TestCircularTransferRequest::PositionRequest(4),
+            TestCircularTransferRequest::PositionRequest(4),
+            TestCircularTransferRequest::GetCompleteCount(0),
+        ]);
+        let mut buf = [0; 4];
+        assert_eq!(4, ringbuf.read(&mut dma, &mut buf).unwrap().0);
+        assert_eq!(4, ringbuf.start);
+
+        // The dma writer wrapped twice and is now at 6
+        dma.setup(vec![
+            TestCircularTransferRequest::PositionRequest(6),
+            TestCircularTransferRequest::ResetCompleteCount(2),
+        ]);
+        assert_eq!(2 * 16 + 6 - 4, ringbuf.discard(&mut dma));
+        assert_eq!(6, ringbuf.start);
+
+        dma.setup(vec![
+            TestCircularTransferRequest::PositionRequest(6),
+            TestCircularTransferRequest::GetCompleteCount(0),
+        ]);
+        assert_eq!(0, ringbuf.read(&mut dma, &mut buf).unwrap().0);
+    }
+
+    #[test]
+    fn len_until_overrun() {
+        let mut dma = TestCircularTransfer::new(16);
+
+        let mut dma_buf = [0u8; 16];
+        let mut ringbuf = ReadableDmaRingBuffer::new(&mut dma_buf);
+
+        dma.setup(vec![
+            TestCircularTransferRequest::PositionRequest(4),
+            TestCircularTransferRequest::PositionRequest(4),
+            TestCircularTransferRequest::GetCompleteCount(0),
+        ]);
+        let mut buf = [0; 4];
+        assert_eq!(4, ringbuf.read(&mut dma, &mut buf).unwrap().0);
+
+        // The dma writer wrapped and is now at 2
+        dma.setup(vec![
+            TestCircularTransferRequest::PositionRequest(2),
+            TestCircularTransferRequest::GetCompleteCount(1),
+        ]);
+        assert_eq!(Ok(16 + 2 - 4), ringbuf.len(&mut dma));
+
+        // The dma writer went past the unread elements
+        dma.setup(vec![
+            TestCircularTransferRequest::PositionRequest(5),
+            TestCircularTransferRequest::GetCompleteCount(1),
+        ]);
+        assert_eq!(Err(OverrunError), ringbuf.len(&mut dma));
+    }
+
     #[test]
     fn can_read() {
         let mut dma = TestCircularTransfer::new(16);
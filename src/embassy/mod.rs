@@ -2,7 +2,7 @@
 ///
 /// This module provides the time driver for the Embassy framework.
 
-#[cfg(all(qingke_v4, not(time_driver_timer)))]
+#[cfg(all(qingke_v4, not(time_driver_timer), not(time_driver_rtc)))]
 #[path = "time_driver_systick.rs"]
 pub mod time_driver_impl;
 
@@ -10,11 +10,15 @@ pub mod time_driver_impl;
 #[path = "time_driver_tim.rs"]
 pub mod time_driver_impl;
 
+#[cfg(time_driver_rtc)]
+#[path = "time_driver_rtc.rs"]
+pub mod time_driver_impl;
+
 // This should be called after global clocks inited
 pub fn init() {
-    #[cfg(all(qingke_v4, not(time_driver_timer)))]
+    #[cfg(all(qingke_v4, not(time_driver_timer), not(time_driver_rtc)))]
     time_driver_impl::init();
 
-    #[cfg(time_driver_timer)]
+    #[cfg(any(time_driver_timer, time_driver_rtc))]
     critical_section::with(|cs| time_driver_impl::init(cs));
 }
@@ -0,0 +1,322 @@
+//! Time driver implementation for the RTC.
+//!
+//! The RTC runs from LSE or LSI in the backup domain, so it keeps counting in Stop mode, and its
+//! alarm, on EXTI line 17, wakes the chip up. The resolution is about 1 ms.
+//!
+//! The RTC is taken by the driver, it isn't in `Peripherals`.
+
+#![allow(non_snake_case)]
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use core::{mem, ptr};
+
+use critical_section::CriticalSection;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time_driver::{AlarmHandle, Driver, TICK_HZ};
+use qingke_rt::interrupt;
+
+use crate::interrupt::typelevel::Interrupt;
+use crate::pac::rtc::Rtc;
+use crate::pac::{EXTI, RTC};
+
+const ALARM_COUNT: usize = 1;
+
+/// Counter frequency, the RTC clock is divided down to about this.
+const COUNTER_HZ: u32 = 1024;
+
+/// EXTI line of the RTC alarm.
+const EXTI_LINE_RTC_ALARM: usize = 17;
+
+#[cfg(feature = "rt")]
+#[interrupt]
+fn RTC() {
+    DRIVER.on_interrupt()
+}
+
+#[cfg(feature = "rt")]
+#[interrupt]
+fn RTCAlarm() {
+    DRIVER.on_interrupt()
+}
+
+fn regs() -> Rtc {
+    RTC
+}
+
+/// Write the configuration registers, in configuration mode.
+fn configure(f: impl FnOnce(Rtc)) {
+    let r = regs();
+    while !r.ctlrl().read().rtoff() {}
+    r.ctlrl().modify(|w| w.set_cnf(true));
+    f(r);
+    r.ctlrl().modify(|w| w.set_cnf(false));
+    while !r.ctlrl().read().rtoff() {}
+}
+
+fn counter() -> u32 {
+    let r = regs();
+    // The halves are read separately, the low one may wrap in between.
+    loop {
+        let high = r.cnth().read().0 & 0xFFFF;
+        let low = r.cntl().read().0 & 0xFFFF;
+        if r.cnth().read().0 & 0xFFFF == high {
+            return (high << 16) | low;
+        }
+    }
+}
+
+struct AlarmState {
+    timestamp: Cell<u64>,
+
+    // This is really a Option<(fn(*mut ()), *mut ())>
+    // but fn pointers aren't allowed in const yet
+    callback: Cell<*const ()>,
+    ctx: Cell<*mut ()>,
+}
+
+unsafe impl Send for AlarmState {}
+
+impl AlarmState {
+    const fn new() -> Self {
+        Self {
+            timestamp: Cell::new(u64::MAX),
+            callback: Cell::new(ptr::null()),
+            ctx: Cell::new(ptr::null_mut()),
+        }
+    }
+}
+
+pub(crate) struct RtcDriver {
+    /// Number of counter overflows since boot.
+    period: AtomicU32,
+    /// RTC clock cycles per counter increment.
+    prescaler: AtomicU32,
+    /// RTC clock frequency.
+    frequency: AtomicU32,
+    alarm_count: AtomicU8,
+    /// Timestamp at which to fire alarm. u64::MAX if no alarm is scheduled.
+    alarms: Mutex<CriticalSectionRawMutex, [AlarmState; ALARM_COUNT]>,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ALARM_STATE_NEW: AlarmState = AlarmState::new();
+
+embassy_time_driver::time_driver_impl!(static DRIVER: RtcDriver = RtcDriver {
+    period: AtomicU32::new(0),
+    prescaler: AtomicU32::new(1),
+    frequency: AtomicU32::new(1), // avoid div by zero
+    alarm_count: AtomicU8::new(0),
+    alarms: Mutex::const_new(CriticalSectionRawMutex::new(), [ALARM_STATE_NEW; ALARM_COUNT]),
+});
+
+impl RtcDriver {
+    fn init(&'static self, _cs: CriticalSection) {
+        let r = regs();
+
+        let frequency = crate::rcc::clocks().rtc.expect("RTC clock not enabled").0;
+        let prescaler = frequency / COUNTER_HZ;
+        self.frequency.store(frequency, Ordering::Relaxed);
+        self.prescaler.store(prescaler, Ordering::Relaxed);
+
+        r.ctlrl().modify(|w| w.set_rsf(false));
+        while !r.ctlrl().read().rsf() {}
+
+        configure(|r| {
+            r.pscrh().write(|w| w.0 = ((prescaler - 1) >> 16) & 0x0F);
+            r.pscrl().write(|w| w.0 = (prescaler - 1) & 0xFFFF);
+            r.cnth().write(|w| w.0 = 0);
+            r.cntl().write(|w| w.0 = 0);
+            r.alrmh().write(|w| w.0 = 0xFFFF);
+            r.alrml().write(|w| w.0 = 0xFFFF);
+        });
+
+        r.ctlrl().modify(|w| {
+            w.set_alrf(false);
+            w.set_owf(false);
+        });
+        r.ctlrh().write(|w| w.set_owie(true));
+
+        // The alarm wakes the chip from Stop mode through EXTI.
+        EXTI.rtenr().modify(|w| w.0 |= 1 << EXTI_LINE_RTC_ALARM);
+        EXTI.intenr().modify(|w| w.0 |= 1 << EXTI_LINE_RTC_ALARM);
+
+        crate::interrupt::typelevel::RTC::unpend();
+        crate::interrupt::typelevel::RTCAlarm::unpend();
+        unsafe {
+            crate::interrupt::typelevel::RTC::enable();
+            crate::interrupt::typelevel::RTCAlarm::enable();
+        }
+    }
+
+    /// Counter value to timestamp.
+    fn ticks(&self, count: u64) -> u64 {
+        let prescaler = self.prescaler.load(Ordering::Relaxed) as u64;
+        let frequency = self.frequency.load(Ordering::Relaxed) as u64;
+        // Whole seconds first, so it doesn't overflow.
+        let cycles = count * prescaler;
+        cycles / frequency * TICK_HZ + cycles % frequency * TICK_HZ / frequency
+    }
+
+    /// First counter value at or after the timestamp.
+    fn count(&self, timestamp: u64) -> u64 {
+        let prescaler = self.prescaler.load(Ordering::Relaxed) as u64;
+        let frequency = self.frequency.load(Ordering::Relaxed) as u64;
+        (timestamp as u128 * frequency as u128).div_ceil(prescaler as u128 * TICK_HZ as u128) as u64
+    }
+
+    /// Counter value, extended with the overflows.
+    fn now_count(&self) -> u64 {
+        critical_section::with(|_| {
+            let count = counter();
+            let mut period = self.period.load(Ordering::Relaxed);
+            // An overflow not handled yet, the counter was read after it.
+            if regs().ctlrl().read().owf() && count < 0x8000_0000 {
+                period += 1;
+            }
+            ((period as u64) << 32) | count as u64
+        })
+    }
+
+    fn on_interrupt(&self) {
+        let r = regs();
+
+        critical_section::with(|cs| {
+            EXTI.intfr().write(|w| w.0 = 1 << EXTI_LINE_RTC_ALARM);
+
+            let flags = r.ctlrl().read();
+            r.ctlrl().modify(|w| {
+                w.set_alrf(false);
+                w.set_owf(false);
+            });
+
+            if flags.owf() {
+                self.period
+                    .store(self.period.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+                // An alarm more than a period away may be due in this one.
+                for alarm in self.alarms.borrow(cs) {
+                    let timestamp = alarm.timestamp.get();
+                    if timestamp != u64::MAX {
+                        self.arm(timestamp);
+                    }
+                }
+            }
+
+            for n in 0..ALARM_COUNT {
+                let timestamp = self.alarms.borrow(cs)[n].timestamp.get();
+                if timestamp <= self.now() {
+                    self.trigger_alarm(n, cs);
+                }
+            }
+        })
+    }
+
+    /// Set the alarm register if `timestamp` is in the current period. Returns `false` if it has
+    /// passed.
+    fn arm(&self, timestamp: u64) -> bool {
+        let r = regs();
+
+        let count = self.count(timestamp);
+        let now = self.now_count();
+        if count <= now {
+            r.ctlrh().modify(|w| w.set_alrie(false));
+            return false;
+        }
+
+        // The alarm fires when the counter equals the register, the overflow handler arms
+        // alarms of later periods.
+        if count >> 32 == now >> 32 {
+            configure(|r| {
+                r.alrmh().write(|w| w.0 = (count as u32) >> 16);
+                r.alrml().write(|w| w.0 = count as u32 & 0xFFFF);
+            });
+            r.ctlrh().modify(|w| w.set_alrie(true));
+        } else {
+            r.ctlrh().modify(|w| w.set_alrie(false));
+        }
+
+        // The alarm register is written in the RTC clock domain, the counter may have passed it
+        // meanwhile.
+        count > self.now_count()
+    }
+
+    fn get_alarm<'a>(&'a self, cs: CriticalSection<'a>, alarm: AlarmHandle) -> &'a AlarmState {
+        // safety: we're allowed to assume the AlarmState is created by us, and
+        // we never create one that's out of bounds.
+        unsafe { self.alarms.borrow(cs).get_unchecked(alarm.id() as usize) }
+    }
+
+    fn trigger_alarm(&self, n: usize, cs: CriticalSection) {
+        let alarm = &self.alarms.borrow(cs)[n];
+        alarm.timestamp.set(u64::MAX);
+
+        // Call after clearing alarm, so the callback can set another alarm.
+
+        // safety:
+        // - we can ignore the possibility of `f` being unset (null) because of the safety contract of `allocate_alarm`.
+        // - other than that we only store valid function pointers into alarm.callback
+        let f: fn(*mut ()) = unsafe { mem::transmute(alarm.callback.get()) };
+        f(alarm.ctx.get());
+    }
+}
+
+impl Driver for RtcDriver {
+    fn now(&self) -> u64 {
+        self.ticks(self.now_count())
+    }
+
+    unsafe fn allocate_alarm(&self) -> Option<AlarmHandle> {
+        critical_section::with(|_| {
+            let id = self.alarm_count.load(Ordering::Relaxed);
+            if id < ALARM_COUNT as u8 {
+                self.alarm_count.store(id + 1, Ordering::Relaxed);
+                Some(AlarmHandle::new(id))
+            } else {
+                None
+            }
+        })
+    }
+
+    fn set_alarm_callback(&self, alarm: AlarmHandle, callback: fn(*mut ()), ctx: *mut ()) {
+        critical_section::with(|cs| {
+            let alarm = self.get_alarm(cs, alarm);
+
+            alarm.callback.set(callback as *const ());
+            alarm.ctx.set(ctx);
+        })
+    }
+
+    fn set_alarm(&self, alarm: AlarmHandle, timestamp: u64) -> bool {
+        critical_section::with(|cs| {
+            let alarm = self.get_alarm(cs, alarm);
+            alarm.timestamp.set(timestamp);
+
+            if !self.arm(timestamp) {
+                // If alarm timestamp has passed the alarm will not fire.
+                // Disarm the alarm and return `false` to indicate that.
+                // It is the caller's responsibility to handle this ambiguity.
+                alarm.timestamp.set(u64::MAX);
+                return false;
+            }
+
+            true
+        })
+    }
+}
+
+pub(crate) fn init(cs: CriticalSection) {
+    DRIVER.init(cs)
+}
+
+/// Timestamp of the next alarm, `u64::MAX` if none is set.
+#[allow(unused)]
+pub(crate) fn next_alarm(cs: CriticalSection) -> u64 {
+    DRIVER
+        .alarms
+        .borrow(cs)
+        .iter()
+        .map(|alarm| alarm.timestamp.get())
+        .min()
+        .unwrap_or(u64::MAX)
+}
@@ -56,7 +56,7 @@ impl SystickDriver {
         let rb = &crate::pac::SYSTICK;
         let hclk = crate::rcc::clocks().hclk.0 as u64;
 
-        let cnt_per_second = hclk / 8; // HCLK/8
+        let cnt_per_second = hclk; // HCLK, as the cycle counter
         let cnt_per_tick = cnt_per_second / embassy_time_driver::TICK_HZ;
 
         self.period.store(cnt_per_tick as u32, Ordering::Relaxed);
@@ -72,7 +72,7 @@ impl SystickDriver {
                 //  w.set_init(true);
                 w.set_mode(vals::Mode::UPCOUNT);
                 w.set_stre(false);
-                w.set_stclk(vals::Stclk::HCLK_DIV8);
+                w.set_stclk(vals::Stclk::HCLK);
                 w.set_ste(true);
             });
         })
@@ -0,0 +1,251 @@
+//! DMA descriptor rings
+//!
+//! Descriptors are chained (`TCH`/`RCH`) rather than laid out as a contiguous ring, each one points
+//! to its buffer and to the next descriptor.
+
+use core::ptr::{addr_of, addr_of_mut, read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+
+use super::{Packet, RX_BUFFER_SIZE, TX_BUFFER_SIZE};
+use crate::pac::eth::Eth;
+
+/// Owned by the DMA.
+const DES0_OWN: u32 = 1 << 31;
+
+/// Interrupt on completion.
+const TDES0_IC: u32 = 1 << 30;
+/// Last segment of the frame.
+const TDES0_LS: u32 = 1 << 29;
+/// First segment of the frame.
+const TDES0_FS: u32 = 1 << 28;
+/// Insert the IPv4 header checksum and the TCP/UDP/ICMP checksum, pseudo-header included.
+const TDES0_CIC_FULL: u32 = 0b11 << 22;
+/// Second address is the next descriptor.
+const TDES0_TCH: u32 = 1 << 20;
+const TDES1_TBS1_MASK: u32 = 0x1FFF;
+
+/// Frame length, including the CRC.
+const RDES0_FL_SHIFT: u32 = 16;
+const RDES0_FL_MASK: u32 = 0x3FFF;
+/// Error summary.
+const RDES0_ES: u32 = 1 << 15;
+const RDES0_FS: u32 = 1 << 9;
+const RDES0_LS: u32 = 1 << 8;
+/// Second address is the next descriptor.
+const RDES1_RCH: u32 = 1 << 14;
+const RDES1_RBS1_MASK: u32 = 0x1FFF;
+
+const CRC_SIZE: usize = 4;
+
+#[repr(C, align(4))]
+struct Descriptor {
+    des0: u32,
+    des1: u32,
+    des2: u32,
+    des3: u32,
+}
+
+impl Descriptor {
+    const fn new() -> Self {
+        Self {
+            des0: 0,
+            des1: 0,
+            des2: 0,
+            des3: 0,
+        }
+    }
+
+    fn des0(&self) -> u32 {
+        unsafe { read_volatile(addr_of!(self.des0)) }
+    }
+
+    fn set_des0(&mut self, val: u32) {
+        unsafe { write_volatile(addr_of_mut!(self.des0), val) }
+    }
+
+    fn set_des1(&mut self, val: u32) {
+        unsafe { write_volatile(addr_of_mut!(self.des1), val) }
+    }
+
+    fn set_des2(&mut self, val: u32) {
+        unsafe { write_volatile(addr_of_mut!(self.des2), val) }
+    }
+
+    fn set_des3(&mut self, val: u32) {
+        unsafe { write_volatile(addr_of_mut!(self.des3), val) }
+    }
+
+    fn owned_by_dma(&self) -> bool {
+        self.des0() & DES0_OWN != 0
+    }
+}
+
+/// Transmit descriptor
+#[repr(transparent)]
+pub struct TDes(Descriptor);
+
+impl TDes {
+    pub const fn new() -> Self {
+        Self(Descriptor::new())
+    }
+}
+
+/// Receive descriptor
+#[repr(transparent)]
+pub struct RDes(Descriptor);
+
+impl RDes {
+    pub const fn new() -> Self {
+        Self(Descriptor::new())
+    }
+}
+
+pub(crate) struct TDesRing<'a> {
+    regs: Eth,
+    descriptors: &'a mut [TDes],
+    buffers: &'a mut [Packet<TX_BUFFER_SIZE>],
+    index: usize,
+    /// `TDES0` flags of every frame.
+    flags: u32,
+}
+
+impl<'a> TDesRing<'a> {
+    /// Chain the descriptors and hand the ring to the DMA.
+    pub fn new(
+        regs: Eth,
+        descriptors: &'a mut [TDes],
+        buffers: &'a mut [Packet<TX_BUFFER_SIZE>],
+        checksum_insertion: bool,
+    ) -> Self {
+        assert!(!descriptors.is_empty());
+        assert_eq!(descriptors.len(), buffers.len());
+
+        let len = descriptors.len();
+        for i in 0..len {
+            let next = addr_of!(descriptors[(i + 1) % len]) as u32;
+            let desc = &mut descriptors[i].0;
+            desc.set_des0(TDES0_TCH);
+            desc.set_des1(0);
+            desc.set_des2(buffers[i].0.as_ptr() as u32);
+            desc.set_des3(next);
+        }
+
+        regs.dmatdlar().write(|w| w.0 = descriptors.as_ptr() as u32);
+
+        Self {
+            regs,
+            descriptors,
+            buffers,
+            index: 0,
+            flags: if checksum_insertion {
+                TDES0_CIC_FULL | TDES0_TCH
+            } else {
+                TDES0_TCH
+            },
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.descriptors.len()
+    }
+
+    /// Buffer of the next descriptor, if the DMA is done with it.
+    pub fn available(&mut self) -> Option<&mut [u8]> {
+        if self.descriptors[self.index].0.owned_by_dma() {
+            None
+        } else {
+            Some(&mut self.buffers[self.index].0)
+        }
+    }
+
+    /// Send the first `len` bytes of the buffer returned by [`Self::available`].
+    pub fn transmit(&mut self, len: usize) {
+        let desc = &mut self.descriptors[self.index].0;
+        assert!(!desc.owned_by_dma());
+
+        desc.set_des1(len as u32 & TDES1_TBS1_MASK);
+        // The buffer must be written before the DMA owns it.
+        fence(Ordering::Release);
+        desc.set_des0(DES0_OWN | TDES0_IC | TDES0_LS | TDES0_FS | self.flags);
+
+        self.index = (self.index + 1) % self.descriptors.len();
+
+        // Resume a suspended DMA.
+        self.regs.dmatpdr().write(|w| w.0 = 0);
+    }
+}
+
+pub(crate) struct RDesRing<'a> {
+    regs: Eth,
+    descriptors: &'a mut [RDes],
+    buffers: &'a mut [Packet<RX_BUFFER_SIZE>],
+    index: usize,
+}
+
+impl<'a> RDesRing<'a> {
+    /// Chain the descriptors and hand the ring to the DMA, every descriptor owned by it.
+    pub fn new(regs: Eth, descriptors: &'a mut [RDes], buffers: &'a mut [Packet<RX_BUFFER_SIZE>]) -> Self {
+        assert!(!descriptors.is_empty());
+        assert_eq!(descriptors.len(), buffers.len());
+
+        let len = descriptors.len();
+        for i in 0..len {
+            let next = addr_of!(descriptors[(i + 1) % len]) as u32;
+            let desc = &mut descriptors[i].0;
+            desc.set_des1(RDES1_RCH | (RX_BUFFER_SIZE as u32 & RDES1_RBS1_MASK));
+            desc.set_des2(buffers[i].0.as_mut_ptr() as u32);
+            desc.set_des3(next);
+            desc.set_des0(DES0_OWN);
+        }
+
+        regs.dmardlar().write(|w| w.0 = descriptors.as_ptr() as u32);
+
+        Self {
+            regs,
+            descriptors,
+            buffers,
+            index: 0,
+        }
+    }
+
+    /// Resume a DMA suspended for lack of descriptors.
+    pub fn demand_poll(&self) {
+        self.regs.dmarpdr().write(|w| w.0 = 0);
+    }
+
+    /// The next received frame, without its CRC. Frames with errors, or larger than a buffer, are
+    /// dropped.
+    pub fn available(&mut self) -> Option<&mut [u8]> {
+        loop {
+            let desc = &self.descriptors[self.index].0;
+            if desc.owned_by_dma() {
+                return None;
+            }
+
+            let des0 = desc.des0();
+            let complete = des0 & (RDES0_FS | RDES0_LS) == RDES0_FS | RDES0_LS;
+            if des0 & RDES0_ES != 0 || !complete {
+                self.pop_packet();
+                continue;
+            }
+
+            // The frame must be read after the DMA released the descriptor.
+            fence(Ordering::Acquire);
+
+            let len = ((des0 >> RDES0_FL_SHIFT) & RDES0_FL_MASK) as usize;
+            let len = len.saturating_sub(CRC_SIZE).min(RX_BUFFER_SIZE);
+            return Some(&mut self.buffers[self.index].0[..len]);
+        }
+    }
+
+    /// Give the current descriptor back to the DMA.
+    pub fn pop_packet(&mut self) {
+        let desc = &mut self.descriptors[self.index].0;
+        // The buffer must be read before the DMA owns it.
+        fence(Ordering::Release);
+        desc.set_des0(DES0_OWN);
+
+        self.index = (self.index + 1) % self.descriptors.len();
+        self.demand_poll();
+    }
+}
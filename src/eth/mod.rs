@@ -0,0 +1,630 @@
+//! Ethernet (ETH)
+//!
+//! Implements [`embassy_net_driver`] for the Ethernet MAC of the CH32V307, with its built-in
+//! 10 Mbps PHY or an external 100 Mbps PHY on RMII.
+//!
+//! Frames are moved by the DMA of the MAC through rings of descriptors and buffers, in a
+//! [`PacketQueue`] provided by the application. The link is read from the PHY over MDIO, see
+//! [`Phy`]: the built-in PHY raises an interrupt when the link goes up or down, external ones are
+//! polled.
+//!
+//! The MAC drops frames not addressed to it, see [`Config`] for the filters, and can compute and
+//! check the IPv4, TCP, UDP and ICMP checksums instead of the stack.
+//!
+//! [`Ethernet::power_down`] leaves the MAC waiting for a magic packet or wake-up frame, for
+//! Wake-on-LAN.
+mod desc;
+mod phy;
+mod wake;
+
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Context;
+
+use embassy_net_driver::{Capabilities, Checksum, HardwareAddress, LinkState};
+use embassy_sync::waitqueue::AtomicWaker;
+
+pub use self::desc::{RDes, TDes};
+use self::desc::{RDesRing, TDesRing};
+pub use self::phy::*;
+pub use self::wake::{WakeConfig, WakeEvent, WakeUpFrame};
+use crate::gpio::{AFType, AnyPin, Pull, SealedPin, Speed};
+use crate::interrupt::typelevel::Interrupt as _;
+use crate::pac::eth::Eth as RegBlock;
+use crate::pac::{AFIO, EXTEND, RCC};
+use crate::peripheral::RccPeripheral;
+use crate::{interrupt, into_ref, peripherals, Peripheral, PeripheralRef};
+
+const MTU: usize = 1514;
+const TX_BUFFER_SIZE: usize = 1514;
+/// Room for the CRC, kept by the MAC.
+const RX_BUFFER_SIZE: usize = 1536;
+
+static WAKER: AtomicWaker = AtomicWaker::new();
+/// The built-in PHY reported a link change, or the link state wasn't read yet.
+static LINK_CHANGED: AtomicBool = AtomicBool::new(false);
+
+/// Interrupt handler.
+pub struct InterruptHandler<T: Instance> {
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandler<T> {
+    unsafe fn on_interrupt() {
+        let regs = T::regs();
+        let st = regs.dmasr().read();
+
+        if st.plsc() {
+            LINK_CHANGED.store(true, Ordering::Relaxed);
+        }
+        if st.pmts() {
+            // Reading the status clears the interrupt.
+            let pmt = regs.macpmtcsr().read();
+            wake::on_pmt_interrupt(pmt.mpr(), pmt.wfr());
+        }
+
+        // Write 1 to clear.
+        regs.dmasr().write(|w| {
+            w.set_nis(true);
+            w.set_ais(true);
+            w.set_rs(true);
+            w.set_ts(true);
+            w.set_rbus(true);
+            w.set_tbus(true);
+            w.set_plsc(true);
+        });
+
+        WAKER.wake();
+    }
+}
+
+/// Ethernet frame buffer.
+#[repr(C, align(4))]
+pub struct Packet<const N: usize>([u8; N]);
+
+/// Descriptors and buffers of the `TX` transmit and `RX` receive frames.
+pub struct PacketQueue<const TX: usize, const RX: usize> {
+    tx_desc: [TDes; TX],
+    rx_desc: [RDes; RX],
+    tx_buf: [Packet<TX_BUFFER_SIZE>; TX],
+    rx_buf: [Packet<RX_BUFFER_SIZE>; RX],
+}
+
+impl<const TX: usize, const RX: usize> PacketQueue<TX, RX> {
+    /// Create a new packet queue.
+    pub const fn new() -> Self {
+        const NEW_TDES: TDes = TDes::new();
+        const NEW_RDES: RDes = RDes::new();
+        const NEW_TX_BUF: Packet<TX_BUFFER_SIZE> = Packet([0; TX_BUFFER_SIZE]);
+        const NEW_RX_BUF: Packet<RX_BUFFER_SIZE> = Packet([0; RX_BUFFER_SIZE]);
+
+        Self {
+            tx_desc: [NEW_TDES; TX],
+            rx_desc: [NEW_RDES; RX],
+            tx_buf: [NEW_TX_BUF; TX],
+            rx_buf: [NEW_RX_BUF; RX],
+        }
+    }
+}
+
+/// Destination address filter, for unicast or multicast frames
+///
+/// [`AddressFilter::Hash`] and [`AddressFilter::PerfectOrHash`] share a bit of the MAC, when one
+/// filter is `PerfectOrHash` a `Hash` filter passes the perfect matches too.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AddressFilter {
+    /// Pass frames to the MAC addresses, see [`Ethernet::set_address`].
+    Perfect,
+    /// Pass frames to the addresses in the hash table, see [`Config::hash_add`].
+    Hash,
+    /// Pass frames to the MAC addresses or the addresses in the hash table.
+    PerfectOrHash,
+}
+
+#[non_exhaustive]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Config {
+    /// Pass all frames, whatever the filters.
+    pub promiscuous: bool,
+    pub unicast_filter: AddressFilter,
+    pub multicast_filter: AddressFilter,
+    /// Pass all multicast frames, whatever `multicast_filter`.
+    pub pass_all_multicast: bool,
+    /// Pass broadcast frames.
+    pub broadcast: bool,
+    /// Hash table of the destination addresses, see [`Config::hash_add`].
+    pub hash_table: u64,
+
+    /// Compute the IPv4 header, TCP, UDP and ICMP checksums of transmitted frames, and drop
+    /// received frames with a wrong one.
+    pub checksum_offload: bool,
+}
+
+impl Default for Config {
+    /// Unicast frames to the MAC address, all multicast and broadcast frames, checksum offload
+    fn default() -> Self {
+        Self {
+            promiscuous: false,
+            unicast_filter: AddressFilter::Perfect,
+            multicast_filter: AddressFilter::Perfect,
+            // For IPv6 and mDNS, the stack doesn't report the groups it joins.
+            pass_all_multicast: true,
+            broadcast: true,
+            hash_table: 0,
+
+            checksum_offload: true,
+        }
+    }
+}
+
+impl Config {
+    /// Add `addr` to the hash table.
+    ///
+    /// The table has 64 bits, other addresses with the same hash pass the filter too.
+    pub fn hash_add(&mut self, addr: [u8; 6]) {
+        self.hash_table |= 1 << hash_index(addr);
+    }
+}
+
+/// Bit of the hash table for `addr`: the upper 6 bits of its reversed and inverted CRC-32.
+fn hash_index(addr: [u8; 6]) -> u32 {
+    let mut crc = u32::MAX;
+    for byte in addr {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    (!crc).reverse_bits() >> 26
+}
+
+/// Ethernet driver.
+pub struct Ethernet<'d, T: Instance, P: Phy = InternalPhy> {
+    _peri: PeripheralRef<'d, T>,
+    _pins: Option<[PeripheralRef<'d, AnyPin>; 9]>,
+    tx: TDesRing<'d>,
+    rx: RDesRing<'d>,
+    mac_addr: [u8; 6],
+    checksum_offload: bool,
+    station_management: EthernetStationManagement<T>,
+    phy: P,
+    link: Option<Link>,
+}
+
+impl<'d, T: Instance> Ethernet<'d, T> {
+    /// Create a new Ethernet driver on the built-in 10 Mbps PHY.
+    pub fn new<const TX: usize, const RX: usize>(
+        queue: &'d mut PacketQueue<TX, RX>,
+        peri: impl Peripheral<P = T> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+        mac_addr: [u8; 6],
+        config: Config,
+    ) -> Self {
+        into_ref!(peri);
+
+        Self::enable_clocks();
+        // Power up the PHY, the MAC runs from its clocks.
+        EXTEND.ctr().modify(|w| w.set_eth_10m_en(true));
+
+        Self::new_inner(queue, peri, None, InternalPhy::new(), mac_addr, config)
+    }
+}
+
+impl<'d, T: Instance, P: Phy> Ethernet<'d, T, P> {
+    /// Create a new Ethernet driver on an external PHY, connected over RMII.
+    ///
+    /// The PHY provides the 50 MHz reference clock on `ref_clk`.
+    pub fn new_rmii<const TX: usize, const RX: usize>(
+        queue: &'d mut PacketQueue<TX, RX>,
+        peri: impl Peripheral<P = T> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+        ref_clk: impl Peripheral<P = impl RefClkPin<T, 0>> + 'd,
+        mdio: impl Peripheral<P = impl MdioPin<T, 0>> + 'd,
+        mdc: impl Peripheral<P = impl MdcPin<T, 0>> + 'd,
+        crs_dv: impl Peripheral<P = impl CrsDvPin<T, 0>> + 'd,
+        rx_d0: impl Peripheral<P = impl RxD0Pin<T, 0>> + 'd,
+        rx_d1: impl Peripheral<P = impl RxD1Pin<T, 0>> + 'd,
+        tx_d0: impl Peripheral<P = impl TxD0Pin<T, 0>> + 'd,
+        tx_d1: impl Peripheral<P = impl TxD1Pin<T, 0>> + 'd,
+        tx_en: impl Peripheral<P = impl TxEnPin<T, 0>> + 'd,
+        phy: P,
+        mac_addr: [u8; 6],
+        config: Config,
+    ) -> Self {
+        into_ref!(peri, ref_clk, mdio, mdc, crs_dv, rx_d0, rx_d1, tx_d0, tx_d1, tx_en);
+
+        critical_section::with(|_| {
+            ref_clk.set_as_input(Pull::None);
+            crs_dv.set_as_input(Pull::None);
+            rx_d0.set_as_input(Pull::None);
+            rx_d1.set_as_input(Pull::None);
+            mdio.set_as_af_output(AFType::OutputPushPull, Speed::High);
+            mdc.set_as_af_output(AFType::OutputPushPull, Speed::High);
+            tx_d0.set_as_af_output(AFType::OutputPushPull, Speed::High);
+            tx_d1.set_as_af_output(AFType::OutputPushPull, Speed::High);
+            tx_en.set_as_af_output(AFType::OutputPushPull, Speed::High);
+        });
+
+        // The interface is selected while the MAC is stopped, before its reset.
+        AFIO.pcfr1().modify(|w| w.set_mii_rmii_sel(true));
+        Self::enable_clocks();
+
+        let pins = [
+            ref_clk.map_into(),
+            mdio.map_into(),
+            mdc.map_into(),
+            crs_dv.map_into(),
+            rx_d0.map_into(),
+            rx_d1.map_into(),
+            tx_d0.map_into(),
+            tx_d1.map_into(),
+            tx_en.map_into(),
+        ];
+
+        Self::new_inner(queue, peri, Some(pins), phy, mac_addr, config)
+    }
+
+    fn enable_clocks() {
+        T::enable_and_reset();
+        RCC.ahbpcenr().modify(|w| {
+            w.set_ethmactxen(true);
+            w.set_ethmacrxen(true);
+        });
+    }
+
+    fn new_inner<const TX: usize, const RX: usize>(
+        queue: &'d mut PacketQueue<TX, RX>,
+        peri: PeripheralRef<'d, T>,
+        pins: Option<[PeripheralRef<'d, AnyPin>; 9]>,
+        mut phy: P,
+        mac_addr: [u8; 6],
+        config: Config,
+    ) -> Self {
+        let regs = T::regs();
+
+        // Reset the MAC and DMA.
+        regs.dmabmr().modify(|w| w.set_sr(true));
+        while regs.dmabmr().read().sr() {}
+
+        // MDC at most 2.5 MHz.
+        let hclk = crate::rcc::clocks().hclk.0;
+        regs.macmiiar().write(|w| {
+            w.set_cr(match hclk {
+                0..=35_000_000 => 0b010,            // HCLK / 16
+                35_000_001..=60_000_000 => 0b011,   // HCLK / 26
+                60_000_001..=100_000_000 => 0b000,  // HCLK / 42
+                100_000_001..=150_000_000 => 0b001, // HCLK / 62
+                _ => 0b100,                         // HCLK / 102
+            })
+        });
+
+        let mut station_management = EthernetStationManagement { _phantom: PhantomData };
+        phy.phy_reset(&mut station_management);
+
+        regs.maccr().write(|w| {
+            // Termination resistors of the built-in PHY.
+            w.set_pr(pins.is_none());
+            w.set_ipco(config.checksum_offload);
+        });
+
+        let mut this = Self {
+            _peri: peri,
+            _pins: pins,
+            tx: TDesRing::new(regs, &mut queue.tx_desc, &mut queue.tx_buf, config.checksum_offload),
+            rx: RDesRing::new(regs, &mut queue.rx_desc, &mut queue.rx_buf),
+            mac_addr,
+            checksum_offload: config.checksum_offload,
+            station_management,
+            phy,
+            link: None,
+        };
+        this.set_filter(&config);
+        this.set_address(0, Some(mac_addr));
+
+        // Whole frames in the FIFOs, so frames are never underrun.
+        regs.dmaomr().write(|w| {
+            w.set_rsf(true);
+            w.set_tsf(true);
+        });
+
+        regs.maccr().modify(|w| {
+            w.set_te(true);
+            w.set_re(true);
+        });
+
+        regs.dmaomr().modify(|w| w.set_ftf(true));
+        while regs.dmaomr().read().ftf() {}
+
+        regs.dmaomr().modify(|w| {
+            w.set_st(true);
+            w.set_sr(true);
+        });
+        this.rx.demand_poll();
+
+        regs.dmaier().write(|w| {
+            w.set_nise(true);
+            w.set_rie(true);
+            w.set_tie(true);
+            w.set_plsce(this._pins.is_none());
+        });
+
+        T::Interrupt::unpend();
+        unsafe { T::Interrupt::enable() };
+
+        this
+    }
+
+    /// Apply the address filters of `config`.
+    pub fn set_filter(&mut self, config: &Config) {
+        let regs = T::regs();
+        regs.machthr().write(|w| w.0 = (config.hash_table >> 32) as u32);
+        regs.machtlr().write(|w| w.0 = config.hash_table as u32);
+
+        regs.macffr().write(|w| {
+            w.set_pm(config.promiscuous);
+            w.set_bfd(!config.broadcast);
+
+            w.set_hu(config.unicast_filter != AddressFilter::Perfect);
+            w.set_hm(config.multicast_filter != AddressFilter::Perfect);
+            w.set_hpf(
+                config.unicast_filter == AddressFilter::PerfectOrHash
+                    || config.multicast_filter == AddressFilter::PerfectOrHash,
+            );
+            w.set_pam(config.pass_all_multicast);
+        });
+    }
+
+    /// Set the perfect filter address `index`, 0 to 3, or disable it with `None`.
+    ///
+    /// Address 0 is the MAC address, it can't be disabled. The others pass unicast or multicast
+    /// frames to them through the [`AddressFilter::Perfect`] filters.
+    pub fn set_address(&mut self, index: usize, addr: Option<[u8; 6]>) {
+        assert!(index < 4);
+        assert!(index != 0 || addr.is_some());
+
+        let [a0, a1, a2, a3, a4, a5] = addr.unwrap_or_default();
+        let high = u32::from(a4) | (u32::from(a5) << 8) | (u32::from(addr.is_some()) << 31);
+        let low = u32::from_le_bytes([a0, a1, a2, a3]);
+
+        let regs = T::regs();
+        // The address is latched when the low register is written.
+        match index {
+            0 => {
+                regs.maca0hr().write(|w| w.0 = high);
+                regs.maca0lr().write(|w| w.0 = low);
+            }
+            1 => {
+                regs.maca1hr().write(|w| w.0 = high);
+                regs.maca1lr().write(|w| w.0 = low);
+            }
+            2 => {
+                regs.maca2hr().write(|w| w.0 = high);
+                regs.maca2lr().write(|w| w.0 = low);
+            }
+            _ => {
+                regs.maca3hr().write(|w| w.0 = high);
+                regs.maca3lr().write(|w| w.0 = low);
+            }
+        }
+
+        if index == 0 {
+            self.mac_addr = addr.unwrap();
+        }
+    }
+
+    /// Whether the link is up, as last reported by the PHY.
+    pub fn is_link_up(&self) -> bool {
+        self.link.is_some()
+    }
+
+    /// The link, as last reported by the PHY.
+    pub fn link(&self) -> Option<Link> {
+        self.link
+    }
+
+    /// The PHY.
+    pub fn phy(&mut self) -> &mut P {
+        &mut self.phy
+    }
+
+    /// Access to the PHY registers, e.g. the vendor-specific ones.
+    pub fn station_management(&mut self) -> &mut EthernetStationManagement<T> {
+        &mut self.station_management
+    }
+}
+
+impl<'d, T: Instance, P: Phy> Drop for Ethernet<'d, T, P> {
+    fn drop(&mut self) {
+        T::Interrupt::disable();
+
+        let regs = T::regs();
+        regs.dmaomr().modify(|w| {
+            w.set_st(false);
+            w.set_sr(false);
+        });
+        regs.maccr().modify(|w| {
+            w.set_te(false);
+            w.set_re(false);
+        });
+
+        if self._pins.is_some() {
+            AFIO.pcfr1().modify(|w| w.set_mii_rmii_sel(false));
+        } else {
+            EXTEND.ctr().modify(|w| w.set_eth_10m_en(false));
+        }
+    }
+}
+
+impl<'d, T: Instance, P: Phy> embassy_net_driver::Driver for Ethernet<'d, T, P> {
+    type RxToken<'a>
+        = RxToken<'a, 'd>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = TxToken<'a, 'd>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, cx: &mut Context) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        WAKER.register(cx.waker());
+        if self.rx.available().is_some() && self.tx.available().is_some() {
+            Some((RxToken { rx: &mut self.rx }, TxToken { tx: &mut self.tx }))
+        } else {
+            None
+        }
+    }
+
+    fn transmit(&mut self, cx: &mut Context) -> Option<Self::TxToken<'_>> {
+        WAKER.register(cx.waker());
+        if self.tx.available().is_some() {
+            Some(TxToken { tx: &mut self.tx })
+        } else {
+            None
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let mut caps = Capabilities::default();
+        caps.max_transmission_unit = MTU;
+        caps.max_burst_size = Some(self.tx.len());
+        if self.checksum_offload {
+            caps.checksum.ipv4 = Checksum::None;
+            caps.checksum.tcp = Checksum::None;
+            caps.checksum.udp = Checksum::None;
+            caps.checksum.icmpv4 = Checksum::None;
+            caps.checksum.icmpv6 = Checksum::None;
+        }
+        caps
+    }
+
+    fn link_state(&mut self, cx: &mut Context) -> LinkState {
+        WAKER.register(cx.waker());
+        let link = self.phy.poll_link(&mut self.station_management, cx);
+        if link != self.link {
+            // The MAC follows the speed and duplex mode negotiated by the PHY.
+            if let Some(link) = link {
+                T::regs().maccr().modify(|w| {
+                    w.set_fes(link.speed == LinkSpeed::Mbps100);
+                    w.set_dm(link.full_duplex);
+                });
+            }
+            self.link = link;
+        }
+
+        match self.link {
+            Some(_) => LinkState::Up,
+            None => LinkState::Down,
+        }
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        HardwareAddress::Ethernet(self.mac_addr)
+    }
+}
+
+/// Received frame.
+pub struct RxToken<'a, 'd> {
+    rx: &'a mut RDesRing<'d>,
+}
+
+impl<'a, 'd> embassy_net_driver::RxToken for RxToken<'a, 'd> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        // `receive` only hands out a token for an available frame.
+        let pkt = self.rx.available().unwrap();
+        let r = f(pkt);
+        self.rx.pop_packet();
+        r
+    }
+}
+
+/// Frame to transmit.
+pub struct TxToken<'a, 'd> {
+    tx: &'a mut TDesRing<'d>,
+}
+
+impl<'a, 'd> embassy_net_driver::TxToken for TxToken<'a, 'd> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        // `transmit` only hands out a token for an available buffer.
+        let pkt = self.tx.available().unwrap();
+        let r = f(&mut pkt[..len]);
+        self.tx.transmit(len);
+        r
+    }
+}
+
+/// Station management interface of the MAC.
+pub struct EthernetStationManagement<T: Instance> {
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Instance> StationManagement for EthernetStationManagement<T> {
+    fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16 {
+        let regs = T::regs();
+        regs.macmiiar().modify(|w| {
+            w.set_pa(phy_addr);
+            w.set_mr(reg);
+            w.set_mw(false);
+            w.set_mb(true);
+        });
+        while regs.macmiiar().read().mb() {}
+        regs.macmiidr().read().md()
+    }
+
+    fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16) {
+        let regs = T::regs();
+        regs.macmiidr().write(|w| w.set_md(val));
+        regs.macmiiar().modify(|w| {
+            w.set_pa(phy_addr);
+            w.set_mr(reg);
+            w.set_mw(true);
+            w.set_mb(true);
+        });
+        while regs.macmiiar().read().mb() {}
+    }
+}
+
+trait SealedInstance {
+    fn regs() -> RegBlock;
+}
+
+/// Ethernet instance trait.
+#[allow(private_bounds)]
+pub trait Instance: SealedInstance + RccPeripheral + Send + 'static {
+    /// Interrupt for this instance.
+    type Interrupt: interrupt::typelevel::Interrupt;
+}
+
+pin_trait!(RefClkPin, Instance);
+pin_trait!(MdioPin, Instance);
+pin_trait!(MdcPin, Instance);
+pin_trait!(CrsDvPin, Instance);
+pin_trait!(RxD0Pin, Instance);
+pin_trait!(RxD1Pin, Instance);
+pin_trait!(TxD0Pin, Instance);
+pin_trait!(TxD1Pin, Instance);
+pin_trait!(TxEnPin, Instance);
+
+foreach_peripheral!(
+    (eth, $inst:ident) => {
+        impl SealedInstance for peripherals::$inst {
+            fn regs() -> RegBlock {
+                crate::pac::$inst
+            }
+        }
+
+        impl Instance for peripherals::$inst {
+            type Interrupt = crate::interrupt::typelevel::$inst;
+        }
+    };
+);
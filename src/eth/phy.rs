@@ -0,0 +1,192 @@
+//! PHY management over MDIO
+//!
+//! The MAC talks to its PHY through the station management interface (SMI), the MDIO/MDC pair,
+//! which reads and writes the registers standardized by IEEE 802.3 clause 22.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::Ordering;
+use core::task::Context;
+
+use embassy_time::{Duration, Timer};
+
+use super::LINK_CHANGED;
+
+/// Basic control register
+pub const PHY_REG_BCR: u8 = 0;
+/// Basic status register
+pub const PHY_REG_BSR: u8 = 1;
+/// PHY identifier 1
+pub const PHY_REG_ID1: u8 = 2;
+/// PHY identifier 2
+pub const PHY_REG_ID2: u8 = 3;
+/// Auto-negotiation advertisement
+pub const PHY_REG_ANAR: u8 = 4;
+/// Auto-negotiation link partner ability
+pub const PHY_REG_ANLPAR: u8 = 5;
+
+const PHY_BCR_RESET: u16 = 1 << 15;
+const PHY_BCR_AN_ENABLE: u16 = 1 << 12;
+const PHY_BCR_AN_RESTART: u16 = 1 << 9;
+const PHY_BSR_LINK_STATUS: u16 = 1 << 2;
+
+const PHY_AN_10BASE_T_FD: u16 = 1 << 6;
+const PHY_AN_100BASE_TX: u16 = 1 << 7;
+const PHY_AN_100BASE_TX_FD: u16 = 1 << 8;
+
+/// MDIO address of the built-in PHY.
+const INTERNAL_PHY_ADDRESS: u8 = 1;
+
+/// Station management interface, access to the PHY registers.
+pub trait StationManagement {
+    /// Read register `reg` of the PHY at `phy_addr`.
+    fn smi_read(&mut self, phy_addr: u8, reg: u8) -> u16;
+    /// Write register `reg` of the PHY at `phy_addr`.
+    fn smi_write(&mut self, phy_addr: u8, reg: u8, val: u16);
+}
+
+/// Link speed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LinkSpeed {
+    Mbps10,
+    Mbps100,
+}
+
+/// Speed and duplex mode of an established link
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Link {
+    pub speed: LinkSpeed,
+    pub full_duplex: bool,
+}
+
+/// Ethernet PHY
+pub trait Phy {
+    /// Reset the PHY, and start auto-negotiation.
+    fn phy_reset<S: StationManagement>(&mut self, sm: &mut S);
+
+    /// Current link, `None` while it is down.
+    ///
+    /// `cx` must be woken when the link may have changed, by an interrupt or a timer.
+    fn poll_link<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context) -> Option<Link>;
+}
+
+/// Reset the PHY at `phy_addr`, and wait until it is done.
+fn reset<S: StationManagement>(sm: &mut S, phy_addr: u8) {
+    sm.smi_write(phy_addr, PHY_REG_BCR, PHY_BCR_RESET);
+    while sm.smi_read(phy_addr, PHY_REG_BCR) & PHY_BCR_RESET != 0 {}
+}
+
+/// Whether the link of the PHY at `phy_addr` is up.
+fn link_up<S: StationManagement>(sm: &mut S, phy_addr: u8) -> bool {
+    // The link status bit latches low, the second read is the current state.
+    sm.smi_read(phy_addr, PHY_REG_BSR);
+    sm.smi_read(phy_addr, PHY_REG_BSR) & PHY_BSR_LINK_STATUS != 0
+}
+
+/// Built-in 10 Mbps PHY of the CH32V307
+///
+/// It raises the Ethernet interrupt when the link goes up or down, so the link is only read then.
+pub struct InternalPhy {
+    link: Option<Link>,
+}
+
+impl InternalPhy {
+    pub(super) const fn new() -> Self {
+        Self { link: None }
+    }
+}
+
+impl Phy for InternalPhy {
+    fn phy_reset<S: StationManagement>(&mut self, sm: &mut S) {
+        reset(sm, INTERNAL_PHY_ADDRESS);
+        LINK_CHANGED.store(true, Ordering::Relaxed);
+    }
+
+    fn poll_link<S: StationManagement>(&mut self, sm: &mut S, _cx: &mut Context) -> Option<Link> {
+        if LINK_CHANGED.swap(false, Ordering::Relaxed) {
+            self.link = link_up(sm, INTERNAL_PHY_ADDRESS).then(|| {
+                let partner = sm.smi_read(INTERNAL_PHY_ADDRESS, PHY_REG_ANLPAR);
+                Link {
+                    speed: LinkSpeed::Mbps10,
+                    full_duplex: partner & PHY_AN_10BASE_T_FD != 0,
+                }
+            });
+        }
+        self.link
+    }
+}
+
+/// External PHY with the IEEE 802.3 registers only, e.g. LAN8720 or DP83848
+///
+/// The link is polled every `poll_interval`, these PHYs signal link changes on a pin of their own
+/// if at all.
+pub struct GenericPhy {
+    phy_addr: u8,
+    poll_interval: Duration,
+    timer: Option<Timer>,
+    link: Option<Link>,
+}
+
+impl GenericPhy {
+    /// PHY at MDIO address `phy_addr`, its link polled every second.
+    pub fn new(phy_addr: u8) -> Self {
+        Self::with_poll_interval(phy_addr, Duration::from_secs(1))
+    }
+
+    /// PHY at MDIO address `phy_addr`, its link polled every `poll_interval`.
+    pub fn with_poll_interval(phy_addr: u8, poll_interval: Duration) -> Self {
+        Self {
+            phy_addr,
+            poll_interval,
+            timer: None,
+            link: None,
+        }
+    }
+
+    /// Best mode advertised by both ends.
+    fn read_link<S: StationManagement>(&self, sm: &mut S) -> Option<Link> {
+        if !link_up(sm, self.phy_addr) {
+            return None;
+        }
+
+        let common = sm.smi_read(self.phy_addr, PHY_REG_ANAR) & sm.smi_read(self.phy_addr, PHY_REG_ANLPAR);
+        let (speed, full_duplex) = if common & PHY_AN_100BASE_TX_FD != 0 {
+            (LinkSpeed::Mbps100, true)
+        } else if common & PHY_AN_100BASE_TX != 0 {
+            (LinkSpeed::Mbps100, false)
+        } else if common & PHY_AN_10BASE_T_FD != 0 {
+            (LinkSpeed::Mbps10, true)
+        } else {
+            (LinkSpeed::Mbps10, false)
+        };
+        Some(Link { speed, full_duplex })
+    }
+}
+
+impl Phy for GenericPhy {
+    fn phy_reset<S: StationManagement>(&mut self, sm: &mut S) {
+        reset(sm, self.phy_addr);
+        sm.smi_write(self.phy_addr, PHY_REG_BCR, PHY_BCR_AN_ENABLE | PHY_BCR_AN_RESTART);
+        self.timer = None;
+        self.link = None;
+    }
+
+    fn poll_link<S: StationManagement>(&mut self, sm: &mut S, cx: &mut Context) -> Option<Link> {
+        let due = match &mut self.timer {
+            Some(timer) => Pin::new(timer).poll(cx).is_ready(),
+            None => true,
+        };
+
+        if due {
+            self.link = self.read_link(sm);
+
+            // Polled once so it wakes `cx`.
+            let mut timer = Timer::after(self.poll_interval);
+            let _ = Pin::new(&mut timer).poll(cx);
+            self.timer = Some(timer);
+        }
+        self.link
+    }
+}
@@ -0,0 +1,189 @@
+//! Wake-on-LAN
+//!
+//! In power-down mode the MAC drops every frame, and only looks for a magic packet or one of four
+//! programmable wake-up frames. Detection raises the Ethernet interrupt and EXTI line 19, as an
+//! event, so the chip can sleep or stop meanwhile.
+
+use core::future::poll_fn;
+use core::sync::atomic::{AtomicU8, Ordering};
+use core::task::Poll;
+
+use super::{Ethernet, Instance, Phy, WAKER};
+use crate::pac::EXTI;
+
+/// EXTI line of the Ethernet wake-up event.
+const EXTI_LINE_ETH_WAKEUP: usize = 19;
+
+const WAKE_NONE: u8 = 0;
+const WAKE_MAGIC_PACKET: u8 = 1;
+const WAKE_FRAME: u8 = 2;
+
+/// Last wake-up, set by the interrupt handler since reading the status clears it.
+static WAKE_EVENT: AtomicU8 = AtomicU8::new(WAKE_NONE);
+
+pub(super) fn on_pmt_interrupt(magic_packet: bool, wake_up_frame: bool) {
+    if magic_packet {
+        WAKE_EVENT.store(WAKE_MAGIC_PACKET, Ordering::Relaxed);
+    } else if wake_up_frame {
+        WAKE_EVENT.store(WAKE_FRAME, Ordering::Relaxed);
+    }
+}
+
+/// Frame that wakes the MAC
+///
+/// Bit `i` of `mask` selects byte `offset + i` of the frame, the CRC-16 of the selected bytes must
+/// match `crc`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WakeUpFrame {
+    /// Offset of the first byte checked, from the destination address.
+    pub offset: u8,
+    pub mask: u32,
+    pub crc: u16,
+    /// Match multicast frames only.
+    pub multicast: bool,
+}
+
+impl WakeUpFrame {
+    /// Match the bytes of `frame` selected by `offset` and `mask`.
+    pub fn new(frame: &[u8], offset: u8, mask: u32) -> Self {
+        let mut crc = u16::MAX;
+        for i in (0..32).filter(|i| mask & (1 << i) != 0) {
+            crc ^= u16::from(frame[usize::from(offset) + i]);
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ 0xA001 } else { crc >> 1 };
+            }
+        }
+
+        Self {
+            offset,
+            mask,
+            crc,
+            multicast: false,
+        }
+    }
+}
+
+/// Frames that wake the MAC from power-down
+#[non_exhaustive]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct WakeConfig {
+    /// Wake on a magic packet: 6 bytes of `0xFF` and the MAC address repeated 16 times.
+    pub magic_packet: bool,
+    pub wake_up_frames: [Option<WakeUpFrame>; 4],
+    /// Also wake on any unicast frame passing the address filter.
+    pub global_unicast: bool,
+}
+
+impl Default for WakeConfig {
+    /// Magic packets only
+    fn default() -> Self {
+        Self {
+            magic_packet: true,
+            wake_up_frames: [None; 4],
+            global_unicast: false,
+        }
+    }
+}
+
+/// What woke the MAC
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WakeEvent {
+    MagicPacket,
+    /// A wake-up frame, or a unicast frame with [`WakeConfig::global_unicast`].
+    WakeUpFrame,
+}
+
+impl<'d, T: Instance, P: Phy> Ethernet<'d, T, P> {
+    /// Stop transmitting and receiving frames, until a frame of `config` is received.
+    ///
+    /// Frames queued for transmission are sent first. Call [`Self::power_up`] once woken.
+    pub fn power_down(&mut self, config: &WakeConfig) {
+        let regs = T::regs();
+
+        regs.dmaomr().modify(|w| w.set_st(false));
+        while regs.dmasr().read().tps() != 0 {}
+        regs.maccr().modify(|w| {
+            w.set_te(false);
+            w.set_re(false);
+        });
+        // Let the DMA drain the receive FIFO.
+        while regs.dmasr().read().rps() == 0b111 {}
+        regs.dmaomr().modify(|w| w.set_sr(false));
+
+        // The filter registers are written in sequence, from the first one.
+        regs.macpmtcsr().modify(|w| w.set_wffrpr(true));
+        let mut words = [0u32; 8];
+        for (i, frame) in config.wake_up_frames.iter().enumerate() {
+            if let Some(frame) = frame {
+                words[i] = frame.mask;
+                words[4] |= (1 | (u32::from(frame.multicast) << 3)) << (8 * i);
+                words[5] |= u32::from(frame.offset) << (8 * i);
+                words[6 + i / 2] |= u32::from(frame.crc) << (16 * (i % 2));
+            }
+        }
+        for word in words {
+            regs.macrwuffr().write(|w| w.0 = word);
+        }
+
+        critical_section::with(|_| {
+            EXTI.rtenr().modify(|w| w.0 |= 1 << EXTI_LINE_ETH_WAKEUP);
+            EXTI.evenr().modify(|w| w.0 |= 1 << EXTI_LINE_ETH_WAKEUP);
+        });
+
+        WAKE_EVENT.store(WAKE_NONE, Ordering::Relaxed);
+        regs.macpmtcsr().write(|w| {
+            w.set_mpe(config.magic_packet);
+            w.set_wfe(config.wake_up_frames.iter().any(Option::is_some));
+            w.set_gu(config.global_unicast);
+            w.set_pd(true);
+        });
+        // The receiver looks for wake-up frames, the DMA stays off.
+        regs.maccr().modify(|w| w.set_re(true));
+    }
+
+    /// Whether the MAC is in power-down mode, it leaves it by itself on a wake-up.
+    pub fn is_powered_down(&self) -> bool {
+        T::regs().macpmtcsr().read().pd()
+    }
+
+    /// Wait until the MAC is woken.
+    pub async fn wait_for_wake(&mut self) -> WakeEvent {
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+            match WAKE_EVENT.load(Ordering::Relaxed) {
+                WAKE_MAGIC_PACKET => Poll::Ready(WakeEvent::MagicPacket),
+                WAKE_FRAME => Poll::Ready(WakeEvent::WakeUpFrame),
+                _ => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Leave power-down mode, and start transmitting and receiving frames again.
+    ///
+    /// Returns what woke the MAC, `None` if it wasn't woken.
+    pub fn power_up(&mut self) -> Option<WakeEvent> {
+        let regs = T::regs();
+
+        regs.macpmtcsr().write(|w| w.set_pd(false));
+        critical_section::with(|_| {
+            EXTI.rtenr().modify(|w| w.0 &= !(1 << EXTI_LINE_ETH_WAKEUP));
+            EXTI.evenr().modify(|w| w.0 &= !(1 << EXTI_LINE_ETH_WAKEUP));
+        });
+
+        regs.maccr().modify(|w| w.set_te(true));
+        regs.dmaomr().modify(|w| {
+            w.set_st(true);
+            w.set_sr(true);
+        });
+        self.rx.demand_poll();
+
+        match WAKE_EVENT.swap(WAKE_NONE, Ordering::Relaxed) {
+            WAKE_MAGIC_PACKET => Some(WakeEvent::MagicPacket),
+            WAKE_FRAME => Some(WakeEvent::WakeUpFrame),
+            _ => None,
+        }
+    }
+}
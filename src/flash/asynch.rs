@@ -0,0 +1,76 @@
+//! Async erase and program
+//!
+//! The flash is busy for milliseconds per page, during which the CPU can't fetch from it. The async
+//! methods work by fast mode pages and yield between them, so other tasks and interrupts run
+//! between the pages instead of waiting for a whole erase or write.
+
+use embassy_futures::yield_now;
+
+use super::{Error, Flash, FAST_PAGE_SIZE, WRITE_SIZE};
+
+impl<'d> Flash<'d> {
+    /// Erase from `from` to `to`, multiples of [`FAST_PAGE_SIZE`], by fast mode pages.
+    pub async fn erase_async(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        if from as usize % FAST_PAGE_SIZE != 0 || to as usize % FAST_PAGE_SIZE != 0 {
+            return Err(Error::Unaligned);
+        }
+        if from > to {
+            return Err(Error::Size);
+        }
+        self.check_range(from, (to - from) as usize)?;
+
+        for offset in (from..to).step_by(FAST_PAGE_SIZE) {
+            self.erase_fast_page(offset)?;
+            yield_now().await;
+        }
+        Ok(())
+    }
+
+    /// Program `bytes` at `offset`, which must be erased. Both are multiples of [`WRITE_SIZE`].
+    ///
+    /// Yields after every fast mode page, or part of it at the ends of the range.
+    pub async fn write_async(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        if offset as usize % WRITE_SIZE != 0 || bytes.len() % WRITE_SIZE != 0 {
+            return Err(Error::Unaligned);
+        }
+        self.check_range(offset, bytes.len())?;
+
+        let mut offset = offset;
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            // Up to the next page boundary.
+            let len = (FAST_PAGE_SIZE - offset as usize % FAST_PAGE_SIZE).min(bytes.len());
+            let (chunk, rest) = bytes.split_at(len);
+            self.write(offset, chunk)?;
+            offset += len as u32;
+            bytes = rest;
+            yield_now().await;
+        }
+        Ok(())
+    }
+}
+
+impl<'d> embedded_storage_async::nor_flash::ReadNorFlash for Flash<'d> {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        Flash::read(self, offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        Flash::capacity(self)
+    }
+}
+
+impl<'d> embedded_storage_async::nor_flash::NorFlash for Flash<'d> {
+    const WRITE_SIZE: usize = WRITE_SIZE;
+    const ERASE_SIZE: usize = FAST_PAGE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.erase_async(from, to).await
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.write_async(offset, bytes).await
+    }
+}
@@ -0,0 +1,377 @@
+//! Flash memory (FLASH)
+//!
+//! [`Flash`] erases and programs the internal flash from the application, for configuration
+//! storage or firmware updates. Offsets are from the start of the flash, and it implements the
+//! `embedded-storage` NOR flash traits.
+//!
+//! The flash is erased by pages and programmed by halfwords. The CPU stalls if it fetches from the
+//! flash meanwhile, interrupt handlers included. Erased flash reads as [`ERASED_WORD`]: all `0xFF`
+//! on CH32V003 and CH32V103, but `0xE339E339` on CH32V20x and CH32V30x.
+//!
+//! Fast mode erases and programs smaller pages, [`FAST_PAGE_SIZE`], at once: programming a page
+//! takes about as long as a single halfword. [`Flash::erase`] and [`Flash::write`] use it for the
+//! whole pages in their range, which speeds up firmware updates by an order of magnitude.
+//!
+//! With the `embassy` feature, [`Flash::erase_async`] and [`Flash::write_async`] yield between fast
+//! mode pages. With `highcode` too, the wait for the flash runs from RAM. [`partitions`] lays out
+//! the flash for embassy-boot A/B updates.
+//!
+//! On CH32V305/307, [`SramCodeMode`] is the split of the SRAM between code flash and data, set in
+//! the option bytes and checked at boot with [`check_sram_code_mode`].
+
+#[cfg(feature = "embassy")]
+mod asynch;
+#[cfg(feature = "embassy")]
+pub mod partitions;
+#[cfg(d8c)]
+mod split;
+
+use core::ptr::{read_volatile, write_volatile};
+
+use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+
+#[cfg(d8c)]
+pub use self::split::{check_sram_code_mode, sram_code_mode, SplitMismatch, SramCodeMode};
+use crate::pac::FLASH;
+use crate::{into_ref, peripherals, Peripheral, PeripheralRef};
+
+/// Address of the flash, as read and programmed.
+pub const FLASH_BASE: usize = 0x0800_0000;
+
+/// Erase unit, in bytes.
+#[cfg(any(ch32v0, ch32v1))]
+pub const PAGE_SIZE: usize = 1024;
+/// Erase unit, in bytes.
+#[cfg(any(ch32v2, ch32v3))]
+pub const PAGE_SIZE: usize = 4096;
+
+/// Fast mode erase and program unit, in bytes.
+#[cfg(ch32v0)]
+pub const FAST_PAGE_SIZE: usize = 64;
+/// Fast mode erase and program unit, in bytes.
+#[cfg(ch32v1)]
+pub const FAST_PAGE_SIZE: usize = 128;
+/// Fast mode erase and program unit, in bytes.
+#[cfg(any(ch32v2, ch32v3))]
+pub const FAST_PAGE_SIZE: usize = 256;
+
+/// Bytes loaded in the fast mode page buffer at once.
+#[cfg(ch32v1)]
+const BUFFER_LOAD_SIZE: usize = 16;
+#[cfg(not(ch32v1))]
+const BUFFER_LOAD_SIZE: usize = 4;
+
+/// Value of an erased word, as read.
+#[cfg(any(ch32v0, ch32v1))]
+pub const ERASED_WORD: u32 = 0xFFFF_FFFF;
+/// Value of an erased word, as read.
+#[cfg(any(ch32v2, ch32v3))]
+pub const ERASED_WORD: u32 = 0xE339_E339;
+
+/// Program unit, in bytes.
+pub const WRITE_SIZE: usize = 2;
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xCDEF_89AB;
+
+/// Flash errors
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The range is out of the flash.
+    Size,
+    /// The offset or length isn't a multiple of the write or erase size.
+    Unaligned,
+    /// The page is write protected by the option bytes.
+    Protected,
+    /// The data read back differs, e.g. the flash wasn't erased before programming.
+    Verify,
+}
+
+impl NorFlashError for Error {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Error::Size => NorFlashErrorKind::OutOfBounds,
+            Error::Unaligned => NorFlashErrorKind::NotAligned,
+            _ => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+/// Internal flash driver
+pub struct Flash<'d> {
+    _inner: PeripheralRef<'d, peripherals::FLASH>,
+}
+
+impl<'d> Flash<'d> {
+    pub fn new(p: impl Peripheral<P = peripherals::FLASH> + 'd) -> Self {
+        into_ref!(p);
+        Self { _inner: p }
+    }
+
+    /// Size of the flash, in bytes.
+    pub fn capacity(&self) -> usize {
+        usize::from(crate::signature::flash_size_kb()) * 1024
+    }
+
+    fn check_range(&self, offset: u32, len: usize) -> Result<(), Error> {
+        match (offset as usize).checked_add(len) {
+            Some(end) if end <= self.capacity() => Ok(()),
+            _ => Err(Error::Size),
+        }
+    }
+
+    /// Read `bytes` from `offset`.
+    pub fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        self.check_range(offset, bytes.len())?;
+        let src = (FLASH_BASE + offset as usize) as *const u8;
+        unsafe { core::ptr::copy_nonoverlapping(src, bytes.as_mut_ptr(), bytes.len()) };
+        Ok(())
+    }
+
+    /// Erase the page at `offset`, a multiple of [`PAGE_SIZE`].
+    pub fn erase_page(&mut self, offset: u32) -> Result<(), Error> {
+        if offset as usize % PAGE_SIZE != 0 {
+            return Err(Error::Unaligned);
+        }
+        self.check_range(offset, PAGE_SIZE)?;
+
+        let address = FLASH_BASE + offset as usize;
+        unlocked(|| {
+            FLASH.ctlr().modify(|w| w.set_per(true));
+            FLASH.addr().write(|w| w.0 = address as u32);
+            FLASH.ctlr().modify(|w| w.set_strt(true));
+            let result = wait_ready();
+            FLASH.ctlr().modify(|w| w.set_per(false));
+            result
+        })?;
+
+        verify_erased(address, PAGE_SIZE)
+    }
+
+    /// Erase the fast mode page at `offset`, a multiple of [`FAST_PAGE_SIZE`].
+    pub fn erase_fast_page(&mut self, offset: u32) -> Result<(), Error> {
+        if offset as usize % FAST_PAGE_SIZE != 0 {
+            return Err(Error::Unaligned);
+        }
+        self.check_range(offset, FAST_PAGE_SIZE)?;
+
+        let address = FLASH_BASE + offset as usize;
+        fast_unlocked(|| {
+            FLASH.ctlr().modify(|w| w.set_fter(true));
+            FLASH.addr().write(|w| w.0 = address as u32);
+            FLASH.ctlr().modify(|w| w.set_strt(true));
+            let result = wait_ready();
+            FLASH.ctlr().modify(|w| w.set_fter(false));
+            result
+        })?;
+
+        verify_erased(address, FAST_PAGE_SIZE)
+    }
+
+    /// Erase from `from` to `to`, multiples of [`FAST_PAGE_SIZE`].
+    ///
+    /// Whole pages are erased at once, the rest by fast mode pages.
+    pub fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        if from as usize % FAST_PAGE_SIZE != 0 || to as usize % FAST_PAGE_SIZE != 0 {
+            return Err(Error::Unaligned);
+        }
+        if from > to {
+            return Err(Error::Size);
+        }
+        self.check_range(from, (to - from) as usize)?;
+
+        let mut offset = from;
+        while offset < to {
+            if offset as usize % PAGE_SIZE == 0 && (to - offset) as usize >= PAGE_SIZE {
+                self.erase_page(offset)?;
+                offset += PAGE_SIZE as u32;
+            } else {
+                self.erase_fast_page(offset)?;
+                offset += FAST_PAGE_SIZE as u32;
+            }
+        }
+        Ok(())
+    }
+
+    /// Program the halfword at `offset`, which must be erased.
+    pub fn program_halfword(&mut self, offset: u32, value: u16) -> Result<(), Error> {
+        if offset % 2 != 0 {
+            return Err(Error::Unaligned);
+        }
+        self.check_range(offset, 2)?;
+
+        let address = FLASH_BASE + offset as usize;
+        unlocked(|| {
+            FLASH.ctlr().modify(|w| w.set_pg(true));
+            unsafe { write_volatile(address as *mut u16, value) };
+            let result = wait_ready();
+            FLASH.ctlr().modify(|w| w.set_pg(false));
+            result
+        })?;
+
+        if unsafe { read_volatile(address as *const u16) } != value {
+            return Err(Error::Verify);
+        }
+        Ok(())
+    }
+
+    /// Program the word at `offset`, which must be erased, as two halfwords.
+    pub fn program_word(&mut self, offset: u32, value: u32) -> Result<(), Error> {
+        if offset % 4 != 0 {
+            return Err(Error::Unaligned);
+        }
+        self.program_halfword(offset, value as u16)?;
+        self.program_halfword(offset + 2, (value >> 16) as u16)
+    }
+
+    /// Program the fast mode page at `offset`, which must be erased.
+    pub fn program_fast_page(&mut self, offset: u32, data: &[u8; FAST_PAGE_SIZE]) -> Result<(), Error> {
+        if offset as usize % FAST_PAGE_SIZE != 0 {
+            return Err(Error::Unaligned);
+        }
+        self.check_range(offset, FAST_PAGE_SIZE)?;
+
+        let address = FLASH_BASE + offset as usize;
+        fast_unlocked(|| {
+            FLASH.ctlr().modify(|w| w.set_ftpg(true));
+            FLASH.ctlr().modify(|w| w.set_bufrst(true));
+            let mut result = wait_ready();
+
+            // The page buffer is loaded through writes to the page, by words.
+            for (i, load) in data.chunks_exact(BUFFER_LOAD_SIZE).enumerate() {
+                if result.is_err() {
+                    break;
+                }
+                for (j, word) in load.chunks_exact(4).enumerate() {
+                    let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+                    let word_address = address + i * BUFFER_LOAD_SIZE + j * 4;
+                    unsafe { write_volatile(word_address as *mut u32, word) };
+                }
+                FLASH.ctlr().modify(|w| w.set_bufload(true));
+                result = wait_ready();
+            }
+
+            if result.is_ok() {
+                FLASH.addr().write(|w| w.0 = address as u32);
+                FLASH.ctlr().modify(|w| w.set_strt(true));
+                result = wait_ready();
+            }
+            FLASH.ctlr().modify(|w| w.set_ftpg(false));
+            result
+        })?;
+
+        let programmed = unsafe { core::slice::from_raw_parts(address as *const u8, FAST_PAGE_SIZE) };
+        if programmed != data {
+            return Err(Error::Verify);
+        }
+        Ok(())
+    }
+
+    /// Program `bytes` at `offset`, which must be erased. Both are multiples of [`WRITE_SIZE`].
+    ///
+    /// Whole fast mode pages are programmed at once, the rest by halfwords.
+    pub fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        if offset as usize % WRITE_SIZE != 0 || bytes.len() % WRITE_SIZE != 0 {
+            return Err(Error::Unaligned);
+        }
+        self.check_range(offset, bytes.len())?;
+
+        let mut offset = offset;
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            if offset as usize % FAST_PAGE_SIZE == 0 && bytes.len() >= FAST_PAGE_SIZE {
+                let (page, rest) = bytes.split_at(FAST_PAGE_SIZE);
+                self.program_fast_page(offset, page.try_into().unwrap())?;
+                offset += FAST_PAGE_SIZE as u32;
+                bytes = rest;
+            } else {
+                self.program_halfword(offset, u16::from_le_bytes([bytes[0], bytes[1]]))?;
+                offset += WRITE_SIZE as u32;
+                bytes = &bytes[WRITE_SIZE..];
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run `f` with the flash controller unlocked, and lock it back.
+fn unlocked<R>(f: impl FnOnce() -> R) -> R {
+    if FLASH.ctlr().read().lock() {
+        FLASH.keyr().write(|w| w.0 = KEY1);
+        FLASH.keyr().write(|w| w.0 = KEY2);
+    }
+    let result = f();
+    FLASH.ctlr().modify(|w| w.set_lock(true));
+    result
+}
+
+/// Run `f` with the flash controller unlocked in fast mode, and lock it back.
+fn fast_unlocked<R>(f: impl FnOnce() -> R) -> R {
+    unlocked(|| {
+        if FLASH.ctlr().read().flock() {
+            FLASH.modekeyr().write(|w| w.0 = KEY1);
+            FLASH.modekeyr().write(|w| w.0 = KEY2);
+        }
+        let result = f();
+        FLASH.ctlr().modify(|w| w.set_flock(true));
+        result
+    })
+}
+
+/// Wait for the end of an operation, and clear its flags.
+#[cfg_attr(feature = "highcode", qingke_rt::highcode)]
+fn wait_ready() -> Result<(), Error> {
+    while FLASH.statr().read().bsy() {}
+
+    let statr = FLASH.statr().read();
+    FLASH.statr().write(|w| {
+        w.set_eop(true);
+        w.set_wrprterr(true);
+    });
+    if statr.wrprterr() {
+        return Err(Error::Protected);
+    }
+    Ok(())
+}
+
+/// Check the `len` bytes at `address` read as erased.
+fn verify_erased(address: usize, len: usize) -> Result<(), Error> {
+    for i in (0..len).step_by(4) {
+        if unsafe { read_volatile((address + i) as *const u32) } != ERASED_WORD {
+            return Err(Error::Verify);
+        }
+    }
+    Ok(())
+}
+
+impl<'d> ErrorType for Flash<'d> {
+    type Error = Error;
+}
+
+impl<'d> ReadNorFlash for Flash<'d> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        Flash::read(self, offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        Flash::capacity(self)
+    }
+}
+
+/// Erased bytes read as [`ERASED_WORD`], which isn't all `0xFF` on CH32V20x and CH32V30x: users
+/// that look for erased flash, like `embassy-boot` for its state, must compare to it.
+impl<'d> NorFlash for Flash<'d> {
+    const WRITE_SIZE: usize = WRITE_SIZE;
+    const ERASE_SIZE: usize = FAST_PAGE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        Flash::erase(self, from, to)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        Flash::write(self, offset, bytes)
+    }
+}
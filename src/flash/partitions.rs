@@ -0,0 +1,139 @@
+//! Partitions for A/B firmware updates
+//!
+//! embassy-boot copies a new firmware from a DFU partition to the active one, swapping them page by
+//! page, and records its progress in a state partition. [`Partitions`] checks a layout against the
+//! flash geometry, and splits a shared [`Flash`] into the three partitions.
+//!
+//! The DFU partition is one erase unit larger than the active one, for the swap, and the state
+//! partition holds at least one erase unit.
+//!
+//! embassy-boot expects erased flash to read as all `0xFF`, or `0x00`: on CH32V20x and CH32V30x it
+//! reads as [`ERASED_WORD`](super::ERASED_WORD), so the state partition must not be checked by
+//! reading it back as erased.
+
+use core::cell::RefCell;
+use core::ops::{Deref, DerefMut, Range};
+
+use embassy_embedded_hal::flash::partition::BlockingPartition;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use super::{Flash, FAST_PAGE_SIZE, WRITE_SIZE};
+
+/// Erase unit of the partitions, in bytes, the flash `ERASE_SIZE`.
+pub const ERASE_SIZE: usize = FAST_PAGE_SIZE;
+
+/// Buffer aligned for flash writes, e.g. the embassy-boot state and update buffers
+#[repr(C, align(4))]
+pub struct AlignedBuffer<const N: usize>(pub [u8; N]);
+
+impl<const N: usize> AlignedBuffer<N> {
+    pub const fn new() -> Self {
+        Self([0; N])
+    }
+}
+
+impl<const N: usize> Default for AlignedBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Deref for AlignedBuffer<N> {
+    type Target = [u8; N];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<const N: usize> DerefMut for AlignedBuffer<N> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// Buffer for the embassy-boot state, one write unit.
+pub type StateBuffer = AlignedBuffer<WRITE_SIZE>;
+
+/// Buffer for a page of the swap, one erase unit.
+pub type PageBuffer = AlignedBuffer<ERASE_SIZE>;
+
+/// Errors of [`Partitions::new`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LayoutError {
+    /// A partition doesn't start or end on an erase unit.
+    Unaligned,
+    /// Two partitions overlap.
+    Overlap,
+    /// The DFU partition is smaller than the active one plus an erase unit.
+    DfuTooSmall,
+    /// The state partition is empty.
+    StateTooSmall,
+}
+
+/// Flash offsets of the active, DFU and state partitions
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Partitions {
+    active: Range<u32>,
+    dfu: Range<u32>,
+    state: Range<u32>,
+}
+
+impl Partitions {
+    pub fn new(active: Range<u32>, dfu: Range<u32>, state: Range<u32>) -> Result<Self, LayoutError> {
+        let ranges = [&active, &dfu, &state];
+
+        if ranges
+            .iter()
+            .any(|r| r.start as usize % ERASE_SIZE != 0 || r.end as usize % ERASE_SIZE != 0)
+        {
+            return Err(LayoutError::Unaligned);
+        }
+        for (i, a) in ranges.iter().enumerate() {
+            for b in &ranges[i + 1..] {
+                if a.start < b.end && b.start < a.end {
+                    return Err(LayoutError::Overlap);
+                }
+            }
+        }
+        if dfu.len() < active.len() + ERASE_SIZE {
+            return Err(LayoutError::DfuTooSmall);
+        }
+        if state.is_empty() {
+            return Err(LayoutError::StateTooSmall);
+        }
+
+        Ok(Self { active, dfu, state })
+    }
+
+    pub fn active(&self) -> Range<u32> {
+        self.active.clone()
+    }
+
+    pub fn dfu(&self) -> Range<u32> {
+        self.dfu.clone()
+    }
+
+    pub fn state(&self) -> Range<u32> {
+        self.state.clone()
+    }
+
+    /// Split `flash` into the active, DFU and state partitions, as blocking NOR flashes.
+    ///
+    /// Offsets in a partition are from its start. The partitions can be handed to the embassy-boot
+    /// `BootLoaderConfig` or `FirmwareUpdaterConfig`.
+    #[allow(clippy::type_complexity)]
+    pub fn split<'a, 'd, M: RawMutex>(
+        &self,
+        flash: &'a Mutex<M, RefCell<Flash<'d>>>,
+    ) -> (
+        BlockingPartition<'a, M, Flash<'d>>,
+        BlockingPartition<'a, M, Flash<'d>>,
+        BlockingPartition<'a, M, Flash<'d>>,
+    ) {
+        let partition = |r: &Range<u32>| BlockingPartition::new(flash, r.start, r.end - r.start);
+        (partition(&self.active), partition(&self.dfu), partition(&self.state))
+    }
+}
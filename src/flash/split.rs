@@ -0,0 +1,154 @@
+//! Flash/SRAM split of CH32V305/307
+//!
+//! The code flash runs without wait states from a 320K SRAM, shared with the data SRAM. The split
+//! between the two is set by the `SRAM_CODE_MODE` bits of the user option byte, and applies from
+//! the next reset. It must match the `FLASH` and `RAM` lengths of `memory.x`: with a larger RAM,
+//! the stack is past the end of the SRAM, with a larger flash, the end of the program isn't loaded.
+
+use core::ptr::{read_volatile, write_volatile};
+
+use super::{unlocked, wait_ready, Error, Flash, KEY1, KEY2};
+use crate::pac::FLASH;
+
+/// Address of the option bytes, 8 halfwords: RDPR, USER, DATA0, DATA1, WRPR0 to WRPR3.
+const OPTION_BYTES: usize = 0x1FFF_F800;
+const OPTION_BYTES_COUNT: usize = 8;
+const USER_INDEX: usize = 1;
+
+const SRAM_CODE_MODE_SHIFT: u8 = 6;
+
+/// Code flash and SRAM sizes
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SramCodeMode {
+    /// 192K flash, 128K SRAM.
+    Flash192Ram128,
+    /// 224K flash, 96K SRAM.
+    Flash224Ram96,
+    /// 256K flash, 64K SRAM.
+    Flash256Ram64,
+    /// 288K flash, 32K SRAM.
+    Flash288Ram32,
+}
+
+impl SramCodeMode {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0b00 => Self::Flash192Ram128,
+            0b01 => Self::Flash224Ram96,
+            0b10 => Self::Flash256Ram64,
+            _ => Self::Flash288Ram32,
+        }
+    }
+
+    fn to_bits(self) -> u8 {
+        match self {
+            Self::Flash192Ram128 => 0b00,
+            Self::Flash224Ram96 => 0b01,
+            Self::Flash256Ram64 => 0b10,
+            Self::Flash288Ram32 => 0b11,
+        }
+    }
+
+    /// Code flash size, in KByte.
+    pub fn flash_kb(&self) -> u32 {
+        192 + 32 * u32::from(self.to_bits())
+    }
+
+    /// SRAM size, in KByte.
+    pub fn ram_kb(&self) -> u32 {
+        320 - self.flash_kb()
+    }
+}
+
+/// The split of the chip differs from the expected one, see [`check_sram_code_mode`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SplitMismatch {
+    pub expected: SramCodeMode,
+    pub actual: SramCodeMode,
+}
+
+impl core::fmt::Display for SplitMismatch {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "memory.x assumes {}K flash + {}K RAM, the option bytes set {}K + {}K",
+            self.expected.flash_kb(),
+            self.expected.ram_kb(),
+            self.actual.flash_kb(),
+            self.actual.ram_kb()
+        )
+    }
+}
+
+fn read_option_bytes() -> [u16; OPTION_BYTES_COUNT] {
+    core::array::from_fn(|i| unsafe { read_volatile((OPTION_BYTES as *const u16).add(i)) })
+}
+
+/// Split stored in the option bytes, the one in use unless they were changed since reset.
+pub fn sram_code_mode() -> SramCodeMode {
+    let user = read_option_bytes()[USER_INDEX] as u8;
+    SramCodeMode::from_bits(user >> SRAM_CODE_MODE_SHIFT)
+}
+
+/// Check the split of the chip is `expected`, the one `memory.x` is written for.
+///
+/// Meant to be called first thing at boot, so a mismatch is reported rather than crashing later.
+pub fn check_sram_code_mode(expected: SramCodeMode) -> Result<(), SplitMismatch> {
+    let actual = sram_code_mode();
+    if actual != expected {
+        return Err(SplitMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+impl<'d> Flash<'d> {
+    /// Store the split in the option bytes, it applies from the next reset.
+    ///
+    /// The other option bytes, read and write protection included, are kept. A reset during the
+    /// update leaves the flash read protected, which erases it when removed.
+    pub fn set_sram_code_mode(&mut self, mode: SramCodeMode) -> Result<(), Error> {
+        let mut option_bytes = read_option_bytes();
+        let user = option_bytes[USER_INDEX] as u8;
+        let user = (user & !(0b11 << SRAM_CODE_MODE_SHIFT)) | (mode.to_bits() << SRAM_CODE_MODE_SHIFT);
+        if user == option_bytes[USER_INDEX] as u8 {
+            return Ok(());
+        }
+        option_bytes[USER_INDEX] = u16::from(user);
+
+        unlocked(|| {
+            if !FLASH.ctlr().read().obwre() {
+                FLASH.obkeyr().write(|w| w.0 = KEY1);
+                FLASH.obkeyr().write(|w| w.0 = KEY2);
+            }
+
+            FLASH.ctlr().modify(|w| w.set_ober(true));
+            FLASH.ctlr().modify(|w| w.set_strt(true));
+            let result = wait_ready();
+            FLASH.ctlr().modify(|w| w.set_ober(false));
+            result?;
+
+            // The complements in the upper bytes are written by the hardware.
+            FLASH.ctlr().modify(|w| w.set_obpg(true));
+            let mut result = Ok(());
+            for (i, &value) in option_bytes.iter().enumerate() {
+                unsafe { write_volatile((OPTION_BYTES as *mut u16).add(i), value & 0xFF) };
+                result = wait_ready();
+                if result.is_err() {
+                    break;
+                }
+            }
+            FLASH.ctlr().modify(|w| {
+                w.set_obpg(false);
+                w.set_obwre(false);
+            });
+            result
+        })?;
+
+        if sram_code_mode() != mode {
+            return Err(Error::Verify);
+        }
+        Ok(())
+    }
+}
@@ -6,16 +6,20 @@ use core::task::Poll;
 
 use embassy_futures::select::{select, Either};
 use embassy_sync::waitqueue::AtomicWaker;
+use embassy_time::{Duration, Timer};
 use embedded_hal::i2c::Operation;
 
 use crate::dma::ChannelAndRequest;
-use crate::gpio::AFType;
-use crate::gpio::Speed;
+use crate::gpio::{AFType, AnyPin, Pull, SealedPin, Speed};
 use crate::internal::drop::OnDrop;
+use crate::low_power::LowPowerPeripheral;
 use crate::mode::{Async, Blocking, Mode};
 // use crate::interrupt::Interrupt;
 use crate::time::Hertz;
-use crate::{interrupt, into_ref, peripherals, Peripheral};
+use crate::{interrupt, into_ref, peripherals, Peripheral, PeripheralRef};
+
+mod slave;
+pub use slave::{I2cSlave, SlaveCommand, SlaveCommandKind, SlaveConfig};
 
 /// Event interrupt handler.
 pub struct EventInterruptHandler<T: Instance> {
@@ -59,8 +63,8 @@ pub unsafe fn on_interrupt<T: Instance>() {
 pub enum Error {
     /// Bus error. (BERR)
     Bus,
-    /// Arbitration lost (ARLO)
-    Arbitration,
+    /// Arbitration lost to another master (ARLO). The peripheral is back in slave mode.
+    ArbitrationLost,
     /// ACK not received (either to the address or to a data byte) (AF)
     Nack,
     /// Timeout
@@ -73,9 +77,26 @@ pub enum Error {
     ZeroLengthTransfer,
 }
 
+/// I2C config error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError {
+    /// The bus frequency is 0 or above 1 MHz.
+    FrequencyOutOfRange,
+    /// The bus frequency is too low to be generated from the APB1 clock.
+    FrequencyTooLow,
+    /// The APB1 clock is too low: 2 MHz at least, 4 MHz above 100 kHz, and the bus frequency times
+    /// 3, or 25 with [`Duty::Duty16_9`], in fast mode.
+    PclkTooLow,
+}
+
+/// SCL low/high ratio in fast mode and Fast-mode Plus.
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub enum Duty {
+    /// t_low/t_high = 2, reaches 400 kHz exactly from APB1 clocks that are multiples of 1.2 MHz.
     Duty2_1 = 0,
+    /// t_low/t_high = 16/9, reaches 400 kHz exactly from APB1 clocks that are multiples of 10 MHz,
+    /// and leaves more low time to slow targets.
     Duty16_9 = 1,
 }
 
@@ -83,68 +104,220 @@ pub enum Duty {
 #[non_exhaustive]
 #[derive(Copy, Clone)]
 pub struct Config {
-    /// Timeout.
-    #[cfg(feature = "time")]
-    pub timeout: embassy_time::Duration,
+    /// Timeout of a whole operation, `None` to wait forever.
+    ///
+    /// Blocking operations check it against the cycle counter, or embassy-time on CH32V003, async
+    /// operations sleep on an embassy-time timer. A missing pull-up or a target stuck stretching the
+    /// clock then returns [`Error::Timeout`].
+    pub timeout: Option<Duration>,
+    /// Watchdog for targets stretching the clock: the longest wait for a single bus event (START,
+    /// address or data byte), `None` to only rely on [`timeout`](Self::timeout).
+    ///
+    /// When it expires the operation fails with [`Error::Timeout`] and the bus is recovered, see
+    /// [`I2c::recover_bus`]. It must be longer than one byte on the bus.
+    pub stretch_timeout: Option<Duration>,
+    /// Duty cycle above 100 kHz, ignored in standard mode.
     pub duty: Duty,
+    /// How many times an operation is restarted after losing arbitration to another master, before
+    /// returning [`Error::ArbitrationLost`].
+    pub arbitration_retries: u8,
+    /// Async transfers of at least this many bytes are moved by DMA, if the driver has DMA channels.
+    ///
+    /// Shorter ones are moved by the event interrupts, which costs less than setting up a DMA transfer
+    /// for a few bytes.
+    pub dma_threshold: usize,
+}
+
+/// Clock register values for a bus frequency.
+struct Timing {
+    freq_range: u8,
+    #[cfg_attr(not(i2c_v3), allow(unused))]
+    trise: u8,
+    fast: bool,
+    duty: bool,
+    ccr: u16,
+}
+
+impl Timing {
+    /// The bus frequency is rounded down to what the APB1 clock can generate.
+    ///
+    /// Up to 100 kHz is standard mode, up to 400 kHz fast mode, up to 1 MHz Fast-mode Plus. The
+    /// latter is generated like fast mode, with a shorter rise time.
+    fn new(pclk: Hertz, freq: Hertz, duty: Duty) -> Result<Self, ConfigError> {
+        /// CCR is 12-bit.
+        const CCR_MAX: u32 = 0xFFF;
+
+        let freq_range = pclk.0 / 1_000_000;
+        let i2c_clk = freq.0;
+
+        if i2c_clk == 0 || i2c_clk > 1_000_000 {
+            return Err(ConfigError::FrequencyOutOfRange);
+        }
+        if freq_range < 2 {
+            return Err(ConfigError::PclkTooLow);
+        }
+
+        if i2c_clk <= 100_000 {
+            // SCL is high and low for CCR clock periods each, at least 4 in standard mode.
+            let ccr = pclk.0.div_ceil(i2c_clk * 2).max(4);
+            if ccr > CCR_MAX {
+                return Err(ConfigError::FrequencyTooLow);
+            }
+
+            Ok(Self {
+                freq_range: freq_range as u8,
+                // Maximum rise time of 1000 ns
+                trise: (freq_range + 1) as u8,
+                fast: false,
+                duty: false,
+                ccr: ccr as u16,
+            })
+        } else {
+            // SCL is low for 2 or 16 and high for 1 or 9 times CCR clock periods.
+            let periods = match duty {
+                Duty::Duty2_1 => 3,
+                Duty::Duty16_9 => 25,
+            };
+            if freq_range < 4 || pclk.0 < i2c_clk * periods {
+                return Err(ConfigError::PclkTooLow);
+            }
+
+            // Maximum rise time of 300 ns, 120 ns in Fast-mode Plus
+            let trise_ns = if i2c_clk <= 400_000 { 300 } else { 120 };
+
+            Ok(Self {
+                freq_range: freq_range as u8,
+                trise: (freq_range * trise_ns / 1000 + 1) as u8,
+                fast: true,
+                duty: duty == Duty::Duty16_9,
+                ccr: pclk.0.div_ceil(i2c_clk * periods).clamp(1, CCR_MAX) as u16,
+            })
+        }
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            #[cfg(feature = "time")]
-            timeout: embassy_time::Duration::from_millis(1000),
+            timeout: Some(Duration::from_millis(1000)),
+            stretch_timeout: None,
             duty: Duty::Duty2_1,
+            arbitration_retries: 3,
+            dma_threshold: 4,
         }
     }
 }
 
+/// Clock of the timeouts: the cycle counter where the core has one, embassy-time on QingKe V2,
+/// whose 32-bit SysTick is restarted by every `delay` call.
+#[cfg(any(qingke_v3, qingke_v4))]
+mod ticks {
+    use embassy_time::Duration;
+
+    use crate::counter;
+
+    pub fn now() -> u64 {
+        counter::cycle_counter()
+    }
+
+    pub fn from_duration(duration: Duration) -> u64 {
+        counter::nanos_to_ticks(duration.as_micros() * 1_000)
+    }
+
+    pub fn to_duration(ticks: u64) -> Duration {
+        Duration::from_micros(counter::ticks_to_nanos(ticks) / 1_000)
+    }
+}
+
+#[cfg(qingke_v2)]
+mod ticks {
+    use embassy_time::{Duration, Instant};
+
+    pub fn now() -> u64 {
+        Instant::now().as_ticks()
+    }
+
+    pub fn from_duration(duration: Duration) -> u64 {
+        duration.as_ticks()
+    }
+
+    pub fn to_duration(ticks: u64) -> Duration {
+        Duration::from_ticks(ticks)
+    }
+}
+
 #[derive(Copy, Clone)]
 struct Timeout {
-    #[cfg(feature = "time")]
-    deadline: Instant,
+    /// Deadline in [`ticks`].
+    deadline: Option<u64>,
+    /// Longest wait for one bus event, in [`ticks`].
+    stretch: Option<u64>,
 }
 
-#[allow(dead_code)]
 impl Timeout {
-    #[inline]
-    fn check(self) -> Result<(), Error> {
-        #[cfg(feature = "time")]
-        if Instant::now() > self.deadline {
-            return Err(Error::Timeout);
+    fn new(timeout: Option<Duration>, stretch: Option<Duration>) -> Self {
+        Self {
+            deadline: timeout.map(|t| ticks::now() + ticks::from_duration(t)),
+            stretch: stretch.map(ticks::from_duration),
         }
+    }
 
-        Ok(())
+    /// Deadline for the next bus event, or the next `bytes` bytes moved by DMA.
+    fn step(self, bytes: u32) -> Self {
+        let Some(stretch) = self.stretch else {
+            return self;
+        };
+
+        let step = ticks::now() + stretch * bytes as u64;
+
+        Self {
+            deadline: Some(self.deadline.map_or(step, |deadline| deadline.min(step))),
+            stretch: self.stretch,
+        }
     }
 
     #[inline]
-    fn with<R>(self, fut: impl Future<Output = Result<R, Error>>) -> impl Future<Output = Result<R, Error>> {
-        #[cfg(feature = "time")]
-        {
-            use futures::FutureExt;
-
-            embassy_futures::select::select(embassy_time::Timer::at(self.deadline), fut).map(|r| match r {
-                embassy_futures::select::Either::First(_) => Err(Error::Timeout),
-                embassy_futures::select::Either::Second(r) => r,
-            })
+    fn check(self) -> Result<(), Error> {
+        match self.deadline {
+            Some(deadline) if ticks::now() > deadline => Err(Error::Timeout),
+            _ => Ok(()),
         }
+    }
+
+    async fn with<R>(self, fut: impl Future<Output = Result<R, Error>>) -> Result<R, Error> {
+        let Some(deadline) = self.deadline else {
+            return fut.await;
+        };
 
-        #[cfg(not(feature = "time"))]
-        fut
+        let remaining = deadline.saturating_sub(ticks::now());
+        let timer = Timer::after(ticks::to_duration(remaining));
+
+        match select(timer, fut).await {
+            Either::First(_) => Err(Error::Timeout),
+            Either::Second(r) => r,
+        }
     }
 }
 
 /// I2C driver.
 pub struct I2c<'d, T: Instance, M: Mode> {
+    scl: PeripheralRef<'d, AnyPin>,
+    sda: PeripheralRef<'d, AnyPin>,
     tx_dma: Option<ChannelAndRequest<'d>>,
     rx_dma: Option<ChannelAndRequest<'d>>,
-    #[cfg(feature = "time")]
-    timeout: Duration,
+    timeout: Option<Duration>,
+    stretch_timeout: Option<Duration>,
+    arbitration_retries: u8,
+    dma_threshold: usize,
+    /// Bus frequency and duty cycle saved by [`LowPowerPeripheral::suspend`].
+    suspended: Option<(Hertz, Duty)>,
     _phantom: PhantomData<(&'d mut T, M)>,
 }
 
 impl<'d, T: Instance> I2c<'d, T, Async> {
     /// Create a new I2C driver.
+    ///
+    /// Fails if `freq` is above 1 MHz or can't be generated from the APB1 clock.
     pub fn new<const REMAP: u8>(
         peri: impl Peripheral<P = T> + 'd,
         scl: impl Peripheral<P = impl SclPin<T, REMAP>> + 'd,
@@ -156,20 +329,40 @@ impl<'d, T: Instance> I2c<'d, T, Async> {
         rx_dma: impl Peripheral<P = impl RxDma<T>> + 'd,
         freq: Hertz,
         config: Config,
-    ) -> Self {
+    ) -> Result<Self, ConfigError> {
         Self::new_inner(peri, scl, sda, new_dma!(tx_dma), new_dma!(rx_dma), freq, config)
     }
+
+    /// Create a new I2C driver that moves the data with the event interrupts instead of DMA.
+    ///
+    /// The task still sleeps while the target stretches the clock, at the cost of one interrupt per byte.
+    ///
+    /// Fails if `freq` is above 1 MHz or can't be generated from the APB1 clock.
+    pub fn new_without_dma<const REMAP: u8>(
+        peri: impl Peripheral<P = T> + 'd,
+        scl: impl Peripheral<P = impl SclPin<T, REMAP>> + 'd,
+        sda: impl Peripheral<P = impl SdaPin<T, REMAP>> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::EventInterrupt, EventInterruptHandler<T>>
+            + interrupt::typelevel::Binding<T::ErrorInterrupt, ErrorInterruptHandler<T>>
+            + 'd,
+        freq: Hertz,
+        config: Config,
+    ) -> Result<Self, ConfigError> {
+        Self::new_inner(peri, scl, sda, None, None, freq, config)
+    }
 }
 
 impl<'d, T: Instance> I2c<'d, T, Blocking> {
     /// Create a new blocking I2C driver.
+    ///
+    /// Fails if `freq` is above 1 MHz or can't be generated from the APB1 clock.
     pub fn new_blocking<const REMAP: u8>(
         peri: impl Peripheral<P = T> + 'd,
         scl: impl Peripheral<P = impl SclPin<T, REMAP>> + 'd,
         sda: impl Peripheral<P = impl SdaPin<T, REMAP>> + 'd,
         freq: Hertz,
         config: Config,
-    ) -> Self {
+    ) -> Result<Self, ConfigError> {
         Self::new_inner(peri, scl, sda, None, None, freq, config)
     }
 }
@@ -184,11 +377,13 @@ impl<'d, T: Instance, M: Mode> I2c<'d, T, M> {
         rx_dma: Option<ChannelAndRequest<'d>>,
         freq: Hertz,
         config: Config,
-    ) -> Self {
+    ) -> Result<Self, ConfigError> {
         use crate::interrupt::typelevel::Interrupt;
 
         into_ref!(scl, sda);
 
+        let timing = Timing::new(T::frequency(), freq, config.duty)?;
+
         T::enable_and_reset();
 
         T::set_remap(REMAP);
@@ -200,29 +395,36 @@ impl<'d, T: Instance, M: Mode> I2c<'d, T, M> {
         unsafe { T::ErrorInterrupt::enable() };
 
         let mut this = Self {
+            scl: scl.map_into(),
+            sda: sda.map_into(),
             tx_dma,
             rx_dma,
-            #[cfg(feature = "time")]
             timeout: config.timeout,
+            stretch_timeout: config.stretch_timeout,
+            arbitration_retries: config.arbitration_retries,
+            dma_threshold: config.dma_threshold,
+            suspended: None,
             _phantom: PhantomData,
         };
 
-        this.init(freq, config);
+        this.init(&timing);
+
+        Ok(this)
+    }
 
-        this
+    /// Change the timeout of the following operations, `None` to wait forever.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
     }
 
     fn timeout(&self) -> Timeout {
-        Timeout {
-            #[cfg(feature = "time")]
-            deadline: Instant::now() + self.timeout,
-        }
+        Timeout::new(self.timeout, self.stretch_timeout)
     }
 }
 
 impl<'d, T: Instance, M: Mode> I2c<'d, T, M> {
     // init as master mode
-    fn init(&mut self, freq: Hertz, config: Config) {
+    fn init(&mut self, timing: &Timing) {
         let regs = T::regs();
 
         regs.ctlr1().modify(|w| w.set_pe(false)); // disale i2c
@@ -231,41 +433,15 @@ impl<'d, T: Instance, M: Mode> I2c<'d, T, M> {
         regs.ctlr1().modify(|w| w.set_swrst(true));
         regs.ctlr1().modify(|w| w.set_swrst(false));
 
-        let freq_in = T::frequency().0;
-        let freq_range = freq_in / 1_000_000;
-
-        // assert!(freq_range >= 2 && freq_range <= 36); // MHz
-
-        regs.ctlr2().modify(|w| w.set_freq(freq_range as u8)); // set i2c clock in
+        regs.ctlr2().modify(|w| w.set_freq(timing.freq_range)); // set i2c clock in
 
-        let i2c_clk = freq.0;
-        if i2c_clk <= 100_000 {
-            let tmp = freq_in / (i2c_clk * 2);
-            let tmp = u32::max(tmp, 0x04);
-
-            #[cfg(i2c_v3)]
-            regs.rtr().write(|w| w.set_trise((freq_range + 1) as u8));
-            regs.ckcfgr().write(|w| {
-                // w.set_f_s(false);
-                w.set_ccr(tmp as _);
-            });
-        } else {
-            // high speed, use duty cycle
-            let tmp = if config.duty == Duty::Duty2_1 {
-                freq_in / (i2c_clk * 3)
-            } else {
-                freq_in / (i2c_clk * 25)
-            };
-            let tmp = u32::max(tmp, 0x01);
-
-            #[cfg(i2c_v3)]
-            regs.rtr().write(|w| w.set_trise((freq_range * 300 / 1000 + 1) as u8));
-            regs.ckcfgr().write(|w| {
-                w.set_f_s(true);
-                w.set_duty(config.duty as u8 != 0);
-                w.set_ccr(tmp as u16);
-            });
-        }
+        #[cfg(i2c_v3)]
+        regs.rtr().write(|w| w.set_trise(timing.trise));
+        regs.ckcfgr().write(|w| {
+            w.set_f_s(timing.fast);
+            w.set_duty(timing.duty);
+            w.set_ccr(timing.ccr);
+        });
 
         regs.ctlr1().modify(|w| w.set_pe(true));
     }
@@ -298,7 +474,7 @@ impl<'d, T: Instance, M: Mode> I2c<'d, T, M> {
 
         if star1.arlo() {
             T::regs().star1().modify(|w| w.set_arlo(false));
-            return Err(Error::Arbitration);
+            return Err(Error::ArbitrationLost);
         }
 
         // The errata indicates that BERR may be incorrectly detected. It recommends ignoring and
@@ -319,13 +495,14 @@ impl<'d, T: Instance, M: Mode> I2c<'d, T, M> {
             });
 
             // Wait until START condition was generated
+            let step = timeout.step(1);
             while !Self::check_and_clear_error_flags()?.sb() {
-                timeout.check()?;
+                step.check()?;
             }
 
             // Check if we were the ones to generate START
             if T::regs().ctlr1().read().start() || !T::regs().star2().read().msl() {
-                return Err(Error::Arbitration);
+                return Err(Error::ArbitrationLost);
             }
 
             // Set up current address we're trying to talk to
@@ -334,8 +511,9 @@ impl<'d, T: Instance, M: Mode> I2c<'d, T, M> {
             // Wait until address was sent
             // Wait for the address to be acknowledged
             // Check for any I2C errors. If a NACK occurs, the ADDR bit will never be set.
+            let step = timeout.step(1);
             while !Self::check_and_clear_error_flags()?.addr() {
-                timeout.check()?;
+                step.check()?;
             }
 
             // Clear condition by reading SR2
@@ -358,11 +536,12 @@ impl<'d, T: Instance, M: Mode> I2c<'d, T, M> {
 
     fn send_byte(&self, byte: u8, timeout: Timeout) -> Result<(), Error> {
         // Wait until we're ready for sending
+        let step = timeout.step(1);
         while {
             // Check for any I2C errors. If a NACK occurs, the ADDR bit will never be set.
             !Self::check_and_clear_error_flags()?.tx_e()
         } {
-            timeout.check()?;
+            step.check()?;
         }
 
         // Push out a byte of data
@@ -371,24 +550,26 @@ impl<'d, T: Instance, M: Mode> I2c<'d, T, M> {
             .write(|reg: &mut ch32_metapac::i2c::regs::Datar| reg.set_datar(byte));
 
         // Wait until byte is transferred
+        let step = timeout.step(1);
         while {
             // Check for any potential error conditions.
             !Self::check_and_clear_error_flags()?.btf()
         } {
-            timeout.check()?;
+            step.check()?;
         }
 
         Ok(())
     }
 
     fn recv_byte(&self, timeout: Timeout) -> Result<u8, Error> {
+        let step = timeout.step(1);
         while {
             // Check for any potential error conditions.
             Self::check_and_clear_error_flags()?;
 
             !T::regs().star1().read().rx_ne()
         } {
-            timeout.check()?;
+            step.check()?;
         }
 
         let value = T::regs().datar().read().datar();
@@ -414,13 +595,14 @@ impl<'d, T: Instance, M: Mode> I2c<'d, T, M> {
             });
 
             // Wait until START condition was generated
+            let step = timeout.step(1);
             while !Self::check_and_clear_error_flags()?.sb() {
-                timeout.check()?;
+                step.check()?;
             }
 
             // Check if we were the ones to generate START
             if T::regs().ctlr1().read().start() || !T::regs().star2().read().msl() {
-                return Err(Error::Arbitration);
+                return Err(Error::ArbitrationLost);
             }
 
             // Set up current address we're trying to talk to
@@ -428,8 +610,9 @@ impl<'d, T: Instance, M: Mode> I2c<'d, T, M> {
 
             // Wait until address was sent
             // Wait for the address to be acknowledged
+            let step = timeout.step(1);
             while !Self::check_and_clear_error_flags()?.addr() {
-                timeout.check()?;
+                step.check()?;
             }
 
             // Clear condition by reading SR2
@@ -460,15 +643,14 @@ impl<'d, T: Instance, M: Mode> I2c<'d, T, M> {
 
     /// Blocking read.
     pub fn blocking_read(&mut self, addr: u8, read: &mut [u8]) -> Result<(), Error> {
-        self.blocking_read_timeout(addr, read, self.timeout(), FrameOptions::FirstAndLastFrame)
+        self.blocking_retry(|this, timeout| {
+            this.blocking_read_timeout(addr, read, timeout, FrameOptions::FirstAndLastFrame)
+        })
     }
 
     /// Blocking write.
     pub fn blocking_write(&mut self, addr: u8, write: &[u8]) -> Result<(), Error> {
-        self.write_bytes(addr, write, self.timeout(), FrameOptions::FirstAndLastFrame)?;
-
-        // Fallthrough is success
-        Ok(())
+        self.blocking_retry(|this, timeout| this.write_bytes(addr, write, timeout, FrameOptions::FirstAndLastFrame))
     }
 
     /// Blocking write, restart, read.
@@ -479,30 +661,135 @@ impl<'d, T: Instance, M: Mode> I2c<'d, T, M> {
             return Err(Error::Overrun);
         }
 
-        let timeout = self.timeout();
-
-        self.write_bytes(addr, write, timeout, FrameOptions::FirstFrame)?;
-        self.blocking_read_timeout(addr, read, timeout, FrameOptions::FirstAndLastFrame)?;
-
-        Ok(())
+        self.blocking_retry(|this, timeout| {
+            this.write_bytes(addr, write, timeout, FrameOptions::FirstFrame)?;
+            this.blocking_read_timeout(addr, read, timeout, FrameOptions::FirstAndLastFrame)
+        })
     }
 
     /// Blocking transaction with operations.
     ///
     /// Consecutive operations of same type are merged. See [transaction contract] for details.
     ///
-    /// [transaction contract]: embedded_hal_1::i2c::I2c::transaction
+    /// [transaction contract]: embedded_hal::i2c::I2c::transaction
     pub fn blocking_transaction(&mut self, addr: u8, operations: &mut [Operation<'_>]) -> Result<(), Error> {
+        self.blocking_retry(|this, timeout| {
+            for (op, frame) in operation_frames(operations)? {
+                match op {
+                    Operation::Read(read) => this.blocking_read_timeout(addr, read, timeout, frame)?,
+                    Operation::Write(write) => this.write_bytes(addr, write, timeout, frame)?,
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Run a blocking operation, restarting it after a lost arbitration.
+    fn blocking_retry(&mut self, mut op: impl FnMut(&mut Self, Timeout) -> Result<(), Error>) -> Result<(), Error> {
         let timeout = self.timeout();
+        let mut retries = self.arbitration_retries;
 
-        for (op, frame) in operation_frames(operations)? {
-            match op {
-                Operation::Read(read) => self.blocking_read_timeout(addr, read, timeout, frame)?,
-                Operation::Write(write) => self.write_bytes(addr, write, timeout, frame)?,
+        loop {
+            let res = op(self, timeout);
+            if !Self::retry_arbitration(&res, &mut retries) {
+                return self.recover_on_timeout(res);
             }
         }
+    }
 
-        Ok(())
+    /// Whether an operation that ended with `res` is to be restarted.
+    ///
+    /// After losing arbitration the peripheral is back in slave mode, and the hardware holds a new
+    /// START until the winning master has released the bus, so the operation can simply run again.
+    fn retry_arbitration(res: &Result<(), Error>, retries: &mut u8) -> bool {
+        if *res == Err(Error::ArbitrationLost) && *retries > 0 {
+            *retries -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Recover the bus if an operation timed out, see [`recover_bus`](Self::recover_bus).
+    fn recover_on_timeout(&mut self, res: Result<(), Error>) -> Result<(), Error> {
+        if res == Err(Error::Timeout) {
+            self.recover_bus();
+        }
+
+        res
+    }
+
+    /// Release the bus after an abandoned operation.
+    ///
+    /// A STOP condition is sent first. If the bus stays busy, e.g. because a target still drives
+    /// SDA low in the middle of a byte, the peripheral is disabled, SCL is pulsed by hand until the
+    /// target lets go of SDA, a STOP is generated and the peripheral is reset. Nothing can be done
+    /// about a target holding SCL low, the next operation will time out again.
+    pub fn recover_bus(&mut self) {
+        let regs = T::regs();
+
+        regs.ctlr1().modify(|w| w.set_stop(true));
+
+        // Longer than a byte at 100 kHz
+        embassy_time::block_for(Duration::from_micros(100));
+        if !regs.star2().read().busy() {
+            return;
+        }
+
+        // The reset clears the timing
+        let ctlr2 = regs.ctlr2().read();
+        let ckcfgr = regs.ckcfgr().read();
+        #[cfg(i2c_v3)]
+        let rtr = regs.rtr().read();
+
+        regs.ctlr1().modify(|w| w.set_pe(false));
+
+        // Open drain by hand: driving low, or released as input to the pull-ups
+        let drive = |pin: &PeripheralRef<'d, AnyPin>, low: bool| {
+            if low {
+                pin.set_low();
+                pin.set_as_output(Speed::High);
+            } else {
+                pin.set_as_input(Pull::None);
+            }
+        };
+        let is_high = |pin: &PeripheralRef<'d, AnyPin>| pin.block().indr().read().idr(pin._pin() as usize);
+        let half_period = || embassy_time::block_for(Duration::from_micros(5));
+
+        drive(&self.sda, false);
+        // Up to 9 clocks finish the byte the target is sending, and its (N)ACK bit
+        for _ in 0..9 {
+            if is_high(&self.sda) {
+                break;
+            }
+            drive(&self.scl, true);
+            half_period();
+            drive(&self.scl, false);
+            half_period();
+        }
+
+        // STOP: SDA rising while SCL is high
+        drive(&self.scl, true);
+        drive(&self.sda, true);
+        half_period();
+        drive(&self.scl, false);
+        half_period();
+        drive(&self.sda, false);
+        half_period();
+
+        self.scl.set_as_af_output(AFType::OutputOpenDrain, Speed::High);
+        self.sda.set_as_af_output(AFType::OutputOpenDrain, Speed::High);
+
+        regs.ctlr1().modify(|w| w.set_swrst(true));
+        regs.ctlr1().modify(|w| w.set_swrst(false));
+
+        regs.ctlr2().write_value(ctlr2);
+        #[cfg(i2c_v3)]
+        regs.rtr().write_value(rtr);
+        regs.ckcfgr().write_value(ckcfgr);
+
+        regs.ctlr1().modify(|w| w.set_pe(true));
     }
 
     // Async
@@ -519,14 +806,22 @@ impl<'d, T: Instance, M: Mode> I2c<'d, T, M> {
 // ======== Async
 
 impl<'d, T: Instance> I2c<'d, T, Async> {
-    async fn write_frame(&mut self, address: u8, write: &[u8], frame: FrameOptions) -> Result<(), Error> {
+    async fn write_frame(
+        &mut self,
+        address: u8,
+        write: &[u8],
+        frame: FrameOptions,
+        timeout: Timeout,
+    ) -> Result<(), Error> {
+        let use_dma = self.tx_dma.is_some() && write.len() >= self.dma_threshold;
+
         T::regs().ctlr2().modify(|w| {
             // Note: Do not enable the ITBUFEN bit in the I2C_CR2 register if DMA is used for
             // reception.
             w.set_itbufen(false);
             // DMA mode can be enabled for transmission by setting the DMAEN bit in the I2C_CR2
             // register.
-            w.set_dmaen(true);
+            w.set_dmaen(use_dma);
             // Sending NACK is not necessary (nor possible) for write transfer.
             w.set_last(false);
         });
@@ -536,6 +831,7 @@ impl<'d, T: Instance> I2c<'d, T, Async> {
         let on_drop = OnDrop::new(|| {
             T::regs().ctlr2().modify(|w| {
                 w.set_dmaen(false);
+                w.set_itbufen(false);
                 w.set_iterren(false);
                 w.set_itevten(false);
             })
@@ -550,110 +846,135 @@ impl<'d, T: Instance> I2c<'d, T, Async> {
             });
 
             // Wait until START condition was generated
-            poll_fn(|cx| {
-                state.waker.register(cx.waker());
-
-                match Self::check_and_clear_error_flags() {
-                    Err(e) => Poll::Ready(Err(e)),
-                    Ok(sr1) => {
-                        if sr1.sb() {
-                            Poll::Ready(Ok(()))
-                        } else {
-                            // When pending, (re-)enable interrupts to wake us up.
-                            Self::enable_interrupts();
-                            Poll::Pending
+            timeout
+                .step(1)
+                .with(poll_fn(|cx| {
+                    state.waker.register(cx.waker());
+
+                    match Self::check_and_clear_error_flags() {
+                        Err(e) => Poll::Ready(Err(e)),
+                        Ok(sr1) => {
+                            if sr1.sb() {
+                                Poll::Ready(Ok(()))
+                            } else {
+                                // When pending, (re-)enable interrupts to wake us up.
+                                Self::enable_interrupts();
+                                Poll::Pending
+                            }
                         }
                     }
-                }
-            })
-            .await?;
+                }))
+                .await?;
 
             // Check if we were the ones to generate START
             if T::regs().ctlr1().read().start() || !T::regs().star2().read().msl() {
-                return Err(Error::Arbitration);
+                return Err(Error::ArbitrationLost);
             }
 
             // Set up current address we're trying to talk to
             T::regs().datar().write(|reg| reg.set_datar(address << 1));
 
             // Wait for the address to be acknowledged
-            poll_fn(|cx| {
-                state.waker.register(cx.waker());
-
-                match Self::check_and_clear_error_flags() {
-                    Err(e) => Poll::Ready(Err(e)),
-                    Ok(sr1) => {
-                        if sr1.addr() {
-                            Poll::Ready(Ok(()))
-                        } else {
-                            // When pending, (re-)enable interrupts to wake us up.
-                            Self::enable_interrupts();
-                            Poll::Pending
+            timeout
+                .step(1)
+                .with(poll_fn(|cx| {
+                    state.waker.register(cx.waker());
+
+                    match Self::check_and_clear_error_flags() {
+                        Err(e) => Poll::Ready(Err(e)),
+                        Ok(sr1) => {
+                            if sr1.addr() {
+                                Poll::Ready(Ok(()))
+                            } else {
+                                // When pending, (re-)enable interrupts to wake us up.
+                                Self::enable_interrupts();
+                                Poll::Pending
+                            }
                         }
                     }
-                }
-            })
-            .await?;
+                }))
+                .await?;
 
             // Clear condition by reading SR2
             T::regs().star2().read();
         }
 
-        let dma_transfer = unsafe {
-            // Set the I2C_DR register address in the DMA_SxPAR register. The data will be moved to
-            // this address from the memory after each TxE event.
-            let dst = T::regs().datar().as_ptr() as *mut u8;
+        if let Some(tx_dma) = self.tx_dma.as_mut().filter(|_| use_dma) {
+            // The DMA moves at most 0xFFFF bytes per transfer, TxE holds the bus in between.
+            for chunk in write.chunks(0xFFFF) {
+                let dma_transfer = unsafe {
+                    // Set the I2C_DR register address in the DMA_SxPAR register. The data will be moved to
+                    // this address from the memory after each TxE event.
+                    let dst = T::regs().datar().as_ptr() as *mut u8;
 
-            self.tx_dma.as_mut().unwrap().write(write, dst, Default::default())
-        };
+                    tx_dma.write(chunk, dst, Default::default())
+                };
 
-        // Wait for bytes to be sent, or an error to occur.
-        let poll_error = poll_fn(|cx| {
-            state.waker.register(cx.waker());
+                // Wait for bytes to be sent, or an error to occur.
+                let poll_error = poll_fn(|cx| {
+                    state.waker.register(cx.waker());
 
-            match Self::check_and_clear_error_flags() {
-                Err(e) => Poll::Ready(Err::<(), Error>(e)),
-                Ok(_) => {
-                    // When pending, (re-)enable interrupts to wake us up.
-                    Self::enable_interrupts();
-                    Poll::Pending
-                }
-            }
-        });
+                    match Self::check_and_clear_error_flags() {
+                        Err(e) => Poll::Ready(Err::<(), Error>(e)),
+                        Ok(_) => {
+                            // When pending, (re-)enable interrupts to wake us up.
+                            Self::enable_interrupts();
+                            Poll::Pending
+                        }
+                    }
+                });
 
-        // Wait for either the DMA transfer to successfully finish, or an I2C error to occur.
-        match select(dma_transfer, poll_error).await {
-            Either::Second(Err(e)) => Err(e),
-            _ => Ok(()),
-        }?;
+                // Wait for either the DMA transfer to successfully finish, or an I2C error to occur.
+                let transfer = async {
+                    match select(dma_transfer, poll_error).await {
+                        Either::Second(Err(e)) => Err(e),
+                        _ => Ok(()),
+                    }
+                };
+                timeout.step(chunk.len() as u32).with(transfer).await?;
+            }
 
-        T::regs().ctlr2().modify(|w| {
-            w.set_dmaen(false);
-        });
+            T::regs().ctlr2().modify(|w| {
+                w.set_dmaen(false);
+            });
+        } else {
+            for byte in write {
+                Self::wait_for_event(timeout, true, |sr1| sr1.tx_e()).await?;
+                T::regs().datar().write(|reg| reg.set_datar(*byte));
+            }
 
-        if frame.send_stop() {
-            // The I2C transfer itself will take longer than the DMA transfer, so wait for that to finish too.
+            // TxE stays set while waiting for BTF below.
+            T::regs().ctlr2().modify(|w| w.set_itbufen(false));
+        }
 
+        // The I2C transfer itself will take longer than the DMA transfer, so wait for that to finish too.
+        // A repeated START programmed while the last byte still waits in DR would come before it, so
+        // this is needed whether or not the frame ends with a STOP. BTF is never set if nothing was sent.
+        if !write.is_empty() {
             // 18.3.8 “Master transmitter: In the interrupt routine after the EOT interrupt, disable DMA
             // requests then wait for a BTF event before programming the Stop condition.”
-            poll_fn(|cx| {
-                state.waker.register(cx.waker());
-
-                match Self::check_and_clear_error_flags() {
-                    Err(e) => Poll::Ready(Err(e)),
-                    Ok(sr1) => {
-                        if sr1.btf() {
-                            Poll::Ready(Ok(()))
-                        } else {
-                            // When pending, (re-)enable interrupts to wake us up.
-                            Self::enable_interrupts();
-                            Poll::Pending
+            timeout
+                .step(1)
+                .with(poll_fn(|cx| {
+                    state.waker.register(cx.waker());
+
+                    match Self::check_and_clear_error_flags() {
+                        Err(e) => Poll::Ready(Err(e)),
+                        Ok(sr1) => {
+                            if sr1.btf() {
+                                Poll::Ready(Ok(()))
+                            } else {
+                                // When pending, (re-)enable interrupts to wake us up.
+                                Self::enable_interrupts();
+                                Poll::Pending
+                            }
                         }
                     }
-                }
-            })
-            .await?;
+                }))
+                .await?;
+        }
 
+        if frame.send_stop() {
             T::regs().ctlr1().modify(|w| {
                 w.set_stop(true);
             });
@@ -667,27 +988,63 @@ impl<'d, T: Instance> I2c<'d, T, Async> {
 
     /// Write.
     pub async fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Error> {
-        self.write_frame(address, write, FrameOptions::FirstAndLastFrame)
-            .await?;
+        let timeout = self.timeout();
 
-        Ok(())
+        let res = timeout
+            .with(async {
+                let mut retries = self.arbitration_retries;
+                loop {
+                    let res = self
+                        .write_frame(address, write, FrameOptions::FirstAndLastFrame, timeout)
+                        .await;
+                    if !Self::retry_arbitration(&res, &mut retries) {
+                        return res;
+                    }
+                }
+            })
+            .await;
+
+        self.recover_on_timeout(res)
     }
 
     /// Read.
     pub async fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
-        self.read_frame(address, buffer, FrameOptions::FirstAndLastFrame)
-            .await?;
+        let timeout = self.timeout();
 
-        Ok(())
+        let res = timeout
+            .with(async {
+                let mut retries = self.arbitration_retries;
+                loop {
+                    let res = self
+                        .read_frame(address, buffer, FrameOptions::FirstAndLastFrame, timeout)
+                        .await;
+                    if !Self::retry_arbitration(&res, &mut retries) {
+                        return res;
+                    }
+                }
+            })
+            .await;
+
+        self.recover_on_timeout(res)
     }
 
-    async fn read_frame(&mut self, address: u8, buffer: &mut [u8], frame: FrameOptions) -> Result<(), Error> {
+    async fn read_frame(
+        &mut self,
+        address: u8,
+        buffer: &mut [u8],
+        frame: FrameOptions,
+        timeout: Timeout,
+    ) -> Result<(), Error> {
         if buffer.is_empty() {
             return Err(Error::Overrun);
         }
 
         // Some branches below depend on whether the buffer contains only a single byte.
         let single_byte = buffer.len() == 1;
+        // The LAST bit only NACKs the end of a single DMA transfer, longer reads use interrupts.
+        let use_dma = self.rx_dma.is_some() && (self.dma_threshold..=0xFFFF).contains(&buffer.len());
+        // Without DMA, a two byte reception is NACKed with POS so the driver can't be late for it.
+        let two_bytes = !use_dma && frame.send_start() && frame.send_nack() && buffer.len() == 2;
 
         T::regs().ctlr2().modify(|w| {
             // Note: Do not enable the ITBUFEN bit in the I2C_CR2 register if DMA is used for
@@ -695,18 +1052,21 @@ impl<'d, T: Instance> I2c<'d, T, Async> {
             w.set_itbufen(false);
             // DMA mode can be enabled for transmission by setting the DMAEN bit in the I2C_CR2
             // register.
-            w.set_dmaen(true);
+            w.set_dmaen(use_dma);
             // If, in the I2C_CR2 register, the LAST bit is set, I2C automatically sends a NACK
             // after the next byte following EOT_1. The user can generate a Stop condition in
             // the DMA Transfer Complete interrupt routine if enabled.
-            w.set_last(frame.send_nack() && !single_byte);
+            w.set_last(use_dma && frame.send_nack() && !single_byte);
         });
 
         // Sentinel to disable transfer when an error occurs or future is canceled.
         // TODO: Generate STOP condition on cancel?
         let on_drop = OnDrop::new(|| {
+            T::regs().ctlr1().modify(|w| w.set_pos(false));
             T::regs().ctlr2().modify(|w| {
+                w.set_last(false);
                 w.set_dmaen(false);
+                w.set_itbufen(false);
                 w.set_iterren(false);
                 w.set_itevten(false);
             })
@@ -722,50 +1082,54 @@ impl<'d, T: Instance> I2c<'d, T, Async> {
             });
 
             // Wait until START condition was generated
-            poll_fn(|cx| {
-                state.waker.register(cx.waker());
-
-                match Self::check_and_clear_error_flags() {
-                    Err(e) => Poll::Ready(Err(e)),
-                    Ok(sr1) => {
-                        if sr1.sb() {
-                            Poll::Ready(Ok(()))
-                        } else {
-                            // When pending, (re-)enable interrupts to wake us up.
-                            Self::enable_interrupts();
-                            Poll::Pending
+            timeout
+                .step(1)
+                .with(poll_fn(|cx| {
+                    state.waker.register(cx.waker());
+
+                    match Self::check_and_clear_error_flags() {
+                        Err(e) => Poll::Ready(Err(e)),
+                        Ok(sr1) => {
+                            if sr1.sb() {
+                                Poll::Ready(Ok(()))
+                            } else {
+                                // When pending, (re-)enable interrupts to wake us up.
+                                Self::enable_interrupts();
+                                Poll::Pending
+                            }
                         }
                     }
-                }
-            })
-            .await?;
+                }))
+                .await?;
 
             // Check if we were the ones to generate START
             if T::regs().ctlr1().read().start() || !T::regs().star2().read().msl() {
-                return Err(Error::Arbitration);
+                return Err(Error::ArbitrationLost);
             }
 
             // Set up current address we're trying to talk to
             T::regs().datar().write(|reg| reg.set_datar((address << 1) + 1));
 
             // Wait for the address to be acknowledged
-            poll_fn(|cx| {
-                state.waker.register(cx.waker());
-
-                match Self::check_and_clear_error_flags() {
-                    Err(e) => Poll::Ready(Err(e)),
-                    Ok(sr1) => {
-                        if sr1.addr() {
-                            Poll::Ready(Ok(()))
-                        } else {
-                            // When pending, (re-)enable interrupts to wake us up.
-                            Self::enable_interrupts();
-                            Poll::Pending
+            timeout
+                .step(1)
+                .with(poll_fn(|cx| {
+                    state.waker.register(cx.waker());
+
+                    match Self::check_and_clear_error_flags() {
+                        Err(e) => Poll::Ready(Err(e)),
+                        Ok(sr1) => {
+                            if sr1.addr() {
+                                Poll::Ready(Ok(()))
+                            } else {
+                                // When pending, (re-)enable interrupts to wake us up.
+                                Self::enable_interrupts();
+                                Poll::Pending
+                            }
                         }
                     }
-                }
-            })
-            .await?;
+                }))
+                .await?;
 
             // 18.3.8: When a single byte must be received: the NACK must be programmed during EV6
             // event, i.e. program ACK=0 when ADDR=1, before clearing ADDR flag.
@@ -775,6 +1139,15 @@ impl<'d, T: Instance> I2c<'d, T, Async> {
                 });
             }
 
+            // 18.3.8: When two bytes must be received without DMA: set POS and clear ACK before
+            // clearing ADDR, the NACK then goes to the second byte.
+            if two_bytes {
+                T::regs().ctlr1().modify(|w| {
+                    w.set_pos(true);
+                    w.set_ack(false);
+                });
+            }
+
             // Clear condition by reading SR2
             T::regs().star2().read();
         } else {
@@ -796,12 +1169,21 @@ impl<'d, T: Instance> I2c<'d, T, Async> {
             });
         }
 
+        let Some(rx_dma) = self.rx_dma.as_mut().filter(|_| use_dma) else {
+            Self::read_bytes_irq(buffer, frame, two_bytes, timeout).await?;
+
+            drop(on_drop);
+
+            return Ok(());
+        };
+
+        let len = buffer.len() as u32;
         let dma_transfer = unsafe {
             // Set the I2C_DR register address in the DMA_SxPAR register. The data will be moved
             // from this address from the memory after each RxE event.
             let src = T::regs().datar().as_ptr() as *mut u8;
 
-            self.rx_dma.as_mut().unwrap().read(src, buffer, Default::default())
+            rx_dma.read(src, buffer, Default::default())
         };
 
         // Wait for bytes to be received, or an error to occur.
@@ -818,10 +1200,13 @@ impl<'d, T: Instance> I2c<'d, T, Async> {
             }
         });
 
-        match select(dma_transfer, poll_error).await {
-            Either::Second(Err(e)) => Err(e),
-            _ => Ok(()),
-        }?;
+        let transfer = async {
+            match select(dma_transfer, poll_error).await {
+                Either::Second(Err(e)) => Err(e),
+                _ => Ok(()),
+            }
+        };
+        timeout.step(len).with(transfer).await?;
 
         T::regs().ctlr2().modify(|w| {
             w.set_dmaen(false);
@@ -839,6 +1224,107 @@ impl<'d, T: Instance> I2c<'d, T, Async> {
         Ok(())
     }
 
+    /// Receive a frame byte by byte, woken by the RxNE and BTF events.
+    ///
+    /// The ACK bit has to be cleared before the last byte is clocked in. For three or more bytes
+    /// this is done while the target is held by BTF, so a late wakeup can't cause an extra byte to
+    /// be acknowledged.
+    async fn read_bytes_irq(
+        buffer: &mut [u8],
+        frame: FrameOptions,
+        two_bytes: bool,
+        timeout: Timeout,
+    ) -> Result<(), Error> {
+        let regs = T::regs();
+
+        let head = match buffer.len() {
+            _ if !frame.send_nack() => buffer.len(),
+            len => len.saturating_sub(3),
+        };
+        let (head, tail) = buffer.split_at_mut(head);
+
+        for byte in head {
+            *byte = Self::recv_byte_irq(timeout).await?;
+        }
+
+        match tail {
+            [] => {}
+            // NACK and STOP were already programmed for a single byte.
+            [last] => *last = Self::recv_byte_irq(timeout).await?,
+            [first, last] if two_bytes => {
+                // Both bytes are received once BTF is set, the second one NACKed.
+                Self::wait_for_event(timeout, false, |sr1| sr1.btf()).await?;
+                regs.ctlr1().modify(|w| w.set_stop(frame.send_stop()));
+                *first = regs.datar().read().datar();
+                *last = regs.datar().read().datar();
+                regs.ctlr1().modify(|w| w.set_pos(false));
+            }
+            [first, last] => {
+                // Continued frame: the first byte is already being received, NACK the next one.
+                Self::wait_for_event(timeout, true, |sr1| sr1.rx_ne()).await?;
+                regs.ctlr1().modify(|w| {
+                    w.set_ack(false);
+                    w.set_stop(frame.send_stop());
+                });
+                *first = regs.datar().read().datar();
+                *last = Self::recv_byte_irq(timeout).await?;
+            }
+            [first, second, last] => {
+                // Data N-2 in DR and N-1 in the shift register, the bus is stretched.
+                Self::wait_for_event(timeout, false, |sr1| sr1.btf()).await?;
+                regs.ctlr1().modify(|w| w.set_ack(false));
+                *first = regs.datar().read().datar();
+
+                // Data N-1 in DR and N (NACKed) in the shift register.
+                Self::wait_for_event(timeout, false, |sr1| sr1.btf()).await?;
+                regs.ctlr1().modify(|w| w.set_stop(frame.send_stop()));
+                *second = regs.datar().read().datar();
+                *last = Self::recv_byte_irq(timeout).await?;
+            }
+            _ => unreachable!(),
+        }
+
+        Ok(())
+    }
+
+    async fn recv_byte_irq(timeout: Timeout) -> Result<u8, Error> {
+        Self::wait_for_event(timeout, true, |sr1| sr1.rx_ne()).await?;
+
+        Ok(T::regs().datar().read().datar())
+    }
+
+    /// Wait until `f` returns true for the status register, or an error occurs.
+    ///
+    /// `buffer_events` additionally wakes the task on TxE and RxNE, used when no DMA is moving the data.
+    async fn wait_for_event(
+        timeout: Timeout,
+        buffer_events: bool,
+        f: impl Fn(crate::pac::i2c::regs::Star1) -> bool,
+    ) -> Result<crate::pac::i2c::regs::Star1, Error> {
+        let state = T::state();
+
+        timeout
+            .step(1)
+            .with(poll_fn(|cx| {
+                state.waker.register(cx.waker());
+
+                match Self::check_and_clear_error_flags() {
+                    Err(e) => Poll::Ready(Err(e)),
+                    Ok(sr1) if f(sr1) => Poll::Ready(Ok(sr1)),
+                    Ok(_) => {
+                        // When pending, (re-)enable interrupts to wake us up.
+                        T::regs().ctlr2().modify(|w| {
+                            w.set_itbufen(buffer_events);
+                            w.set_iterren(true);
+                            w.set_itevten(true);
+                        });
+                        Poll::Pending
+                    }
+                }
+            }))
+            .await
+    }
+
     /// Write, restart, read.
     pub async fn write_read(&mut self, address: u8, write: &[u8], read: &mut [u8]) -> Result<(), Error> {
         // Check empty read buffer before starting transaction. Otherwise, we would not generate the
@@ -847,32 +1333,96 @@ impl<'d, T: Instance> I2c<'d, T, Async> {
             return Err(Error::Overrun);
         }
 
-        self.write_frame(address, write, FrameOptions::FirstFrame).await?;
-        self.read_frame(address, read, FrameOptions::FirstAndLastFrame).await
+        let timeout = self.timeout();
+
+        let res = timeout
+            .with(async {
+                let mut retries = self.arbitration_retries;
+                loop {
+                    let mut res = self
+                        .write_frame(address, write, FrameOptions::FirstFrame, timeout)
+                        .await;
+                    if res.is_ok() {
+                        res = self
+                            .read_frame(address, read, FrameOptions::FirstAndLastFrame, timeout)
+                            .await;
+                    }
+                    if !Self::retry_arbitration(&res, &mut retries) {
+                        return res;
+                    }
+                }
+            })
+            .await;
+
+        self.recover_on_timeout(res)
     }
 
     /// Transaction with operations.
     ///
     /// Consecutive operations of same type are merged. See [transaction contract] for details.
     ///
-    /// [transaction contract]: embedded_hal_1::i2c::I2c::transaction
+    /// [transaction contract]: embedded_hal::i2c::I2c::transaction
     pub async fn transaction(&mut self, addr: u8, operations: &mut [Operation<'_>]) -> Result<(), Error> {
-        for (op, frame) in operation_frames(operations)? {
-            match op {
-                Operation::Read(read) => self.read_frame(addr, read, frame).await?,
-                Operation::Write(write) => self.write_frame(addr, write, frame).await?,
-            }
-        }
+        let timeout = self.timeout();
 
-        Ok(())
+        let res = timeout
+            .with(async {
+                let mut retries = self.arbitration_retries;
+                'retry: loop {
+                    for (op, frame) in operation_frames(operations)? {
+                        let res = match op {
+                            Operation::Read(read) => self.read_frame(addr, read, frame, timeout).await,
+                            Operation::Write(write) => self.write_frame(addr, write, frame, timeout).await,
+                        };
+                        if res.is_err() {
+                            if Self::retry_arbitration(&res, &mut retries) {
+                                continue 'retry;
+                            }
+                            return res;
+                        }
+                    }
+
+                    return Ok(());
+                }
+            })
+            .await;
+
+        self.recover_on_timeout(res)
     }
 }
 
 // ======== Common
 
+impl<'d, T: Instance, M: Mode> LowPowerPeripheral for I2c<'d, T, M> {
+    /// Save the bus frequency, from the clock registers at the current APB1 clock.
+    fn suspend(&mut self) {
+        let ckcfgr = T::regs().ckcfgr().read();
+        let (periods, duty) = match (ckcfgr.f_s(), ckcfgr.duty()) {
+            (false, _) => (2, Duty::Duty2_1),
+            (true, false) => (3, Duty::Duty2_1),
+            (true, true) => (25, Duty::Duty16_9),
+        };
+        let ccr = u32::from(ckcfgr.ccr()).max(1);
+        self.suspended = Some((Hertz(T::frequency().0 / (ccr * periods)), duty));
+    }
+
+    /// Set the timing for the saved bus frequency again, at the APB1 clock after wake-up.
+    ///
+    /// The peripheral stays disabled if the APB1 clock after wake-up can't generate it.
+    fn resume(&mut self) {
+        if let Some((freq, duty)) = self.suspended.take() {
+            match Timing::new(T::frequency(), freq, duty) {
+                Ok(timing) => self.init(&timing),
+                Err(_) => T::regs().ctlr1().modify(|w| w.set_pe(false)),
+            }
+        }
+    }
+}
+
 impl<'d, T: Instance, M: Mode> Drop for I2c<'d, T, M> {
     fn drop(&mut self) {
         T::regs().ctlr1().modify(|w| w.set_pe(false));
+        T::disable();
     }
 }
 
@@ -933,7 +1483,7 @@ impl embedded_hal::i2c::Error for Error {
     fn kind(&self) -> embedded_hal::i2c::ErrorKind {
         match *self {
             Self::Bus => embedded_hal::i2c::ErrorKind::Bus,
-            Self::Arbitration => embedded_hal::i2c::ErrorKind::ArbitrationLoss,
+            Self::ArbitrationLost => embedded_hal::i2c::ErrorKind::ArbitrationLoss,
             Self::Nack => embedded_hal::i2c::ErrorKind::NoAcknowledge(embedded_hal::i2c::NoAcknowledgeSource::Unknown),
             Self::Timeout => embedded_hal::i2c::ErrorKind::Other,
             Self::Crc => embedded_hal::i2c::ErrorKind::Other,
@@ -965,9 +1515,7 @@ impl<'d, T: Instance, M: Mode> embedded_hal::i2c::I2c for I2c<'d, T, M> {
         address: u8,
         operations: &mut [embedded_hal::i2c::Operation<'_>],
     ) -> Result<(), Self::Error> {
-        let _ = address;
-        let _ = operations;
-        todo!()
+        self.blocking_transaction(address, operations)
     }
 }
 
@@ -989,9 +1537,7 @@ impl<'d, T: Instance> embedded_hal_async::i2c::I2c for I2c<'d, T, Async> {
         address: u8,
         operations: &mut [embedded_hal_async::i2c::Operation<'_>],
     ) -> Result<(), Self::Error> {
-        let _ = address;
-        let _ = operations;
-        todo!()
+        self.transaction(address, operations).await
     }
 }
 
@@ -1090,7 +1636,7 @@ impl FrameOptions {
 /// Returns necessary frame options for each operation to uphold the [transaction contract] and have
 /// the right start/stop/(N)ACK conditions on the wire.
 ///
-/// [transaction contract]: embedded_hal_1::i2c::I2c::transaction
+/// [transaction contract]: embedded_hal::i2c::I2c::transaction
 #[allow(dead_code)]
 fn operation_frames<'a, 'b: 'a>(
     operations: &'a mut [embedded_hal::i2c::Operation<'b>],
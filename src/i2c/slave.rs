@@ -0,0 +1,302 @@
+//! I2C slave (target) mode, answering a bus master on an own address.
+
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::task::Poll;
+
+use super::{Error, ErrorInterruptHandler, EventInterruptHandler, Instance, SclPin, SdaPin};
+use crate::gpio::{AFType, Speed};
+use crate::internal::drop::OnDrop;
+use crate::pac::i2c::regs::Star1;
+use crate::{interrupt, into_ref, Peripheral};
+
+/// I2C slave config
+#[non_exhaustive]
+#[derive(Copy, Clone)]
+pub struct SlaveConfig {
+    /// Own 7-bit address.
+    pub address: u8,
+    /// Second own 7-bit address (OAR2), to answer as two devices.
+    ///
+    /// The peripheral compares addresses exactly, it has no address mask.
+    pub secondary_address: Option<u8>,
+    /// Also acknowledge the general call address (0x00).
+    pub general_call: bool,
+    /// Hold SCL low while the driver is not ready to receive or send a byte.
+    ///
+    /// Disable it for masters that don't support clock stretching. Each byte then has to be read
+    /// or written within one byte time on the bus, a late `respond_to_*` call returns
+    /// [`Error::Overrun`], and [`listen`](I2cSlave::listen) no longer holds the bus until the
+    /// answer is prepared.
+    pub clock_stretching: bool,
+}
+
+impl SlaveConfig {
+    /// Respond to the 7-bit `address`.
+    pub const fn new(address: u8) -> Self {
+        Self {
+            address,
+            secondary_address: None,
+            general_call: false,
+            clock_stretching: true,
+        }
+    }
+}
+
+/// Transaction requested by the master, returned by [`I2cSlave::listen`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SlaveCommand {
+    /// Transfer direction.
+    pub kind: SlaveCommandKind,
+    /// The matched 7-bit address: the primary or secondary own address, or 0x00 for a general call.
+    pub address: u8,
+}
+
+/// Transfer direction of a [`SlaveCommand`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SlaveCommandKind {
+    /// The master reads from us, answer with [`I2cSlave::respond_to_read`].
+    Read,
+    /// The master writes to us, receive with [`I2cSlave::respond_to_write`].
+    Write,
+}
+
+/// I2C slave driver.
+///
+/// The bus is held by clock stretching between [`listen`](Self::listen) returning and the
+/// matching `respond_to_*` call, so the answer to a read can be prepared from the preceding write,
+/// like the register address of an emulated EEPROM.
+pub struct I2cSlave<'d, T: Instance> {
+    address: u8,
+    secondary_address: Option<u8>,
+    _phantom: PhantomData<&'d mut T>,
+}
+
+impl<'d, T: Instance> I2cSlave<'d, T> {
+    /// Create a new I2C slave driver.
+    pub fn new<const REMAP: u8>(
+        _peri: impl Peripheral<P = T> + 'd,
+        scl: impl Peripheral<P = impl SclPin<T, REMAP>> + 'd,
+        sda: impl Peripheral<P = impl SdaPin<T, REMAP>> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::EventInterrupt, EventInterruptHandler<T>>
+            + interrupt::typelevel::Binding<T::ErrorInterrupt, ErrorInterruptHandler<T>>
+            + 'd,
+        config: SlaveConfig,
+    ) -> Self {
+        use crate::interrupt::typelevel::Interrupt;
+
+        into_ref!(scl, sda);
+
+        T::enable_and_reset();
+
+        T::set_remap(REMAP);
+
+        scl.set_as_af_output(AFType::OutputOpenDrain, Speed::High);
+        sda.set_as_af_output(AFType::OutputOpenDrain, Speed::High);
+
+        unsafe { T::EventInterrupt::enable() };
+        unsafe { T::ErrorInterrupt::enable() };
+
+        let regs = T::regs();
+
+        regs.ctlr1().modify(|w| w.set_pe(false));
+
+        regs.ctlr1().modify(|w| w.set_swrst(true));
+        regs.ctlr1().modify(|w| w.set_swrst(false));
+
+        // The input clock is needed for the data setup time, also in slave mode.
+        let freq_range = T::frequency().0 / 1_000_000;
+        regs.ctlr2().modify(|w| w.set_freq(freq_range as u8));
+
+        regs.oaddr1().write(|w| {
+            w.set_addmode(false);
+            w.set_add7_1(config.address);
+        });
+        regs.oaddr2().write(|w| {
+            w.set_endual(config.secondary_address.is_some());
+            w.set_add2(config.secondary_address.unwrap_or(0));
+        });
+
+        regs.ctlr1().modify(|w| {
+            w.set_engc(config.general_call);
+            w.set_nostretch(!config.clock_stretching);
+            w.set_pe(true);
+        });
+        // ACK is cleared by hardware while the peripheral is disabled.
+        regs.ctlr1().modify(|w| w.set_ack(true));
+
+        Self {
+            address: config.address,
+            secondary_address: config.secondary_address,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Wait until the master addresses us, and return the requested transfer direction and the
+    /// matched address.
+    pub async fn listen(&mut self) -> Result<SlaveCommand, Error> {
+        let regs = T::regs();
+
+        let _on_drop = Self::disable_interrupts_on_drop();
+
+        Self::wait_for_event(false, |sr1| {
+            // Leftovers of the previous transaction.
+            if sr1.stopf() {
+                Self::clear_stop();
+            }
+            if sr1.af() {
+                regs.star1().modify(|w| w.set_af(false));
+            }
+            sr1.addr()
+        })
+        .await?;
+
+        // Reading SR2 after SR1 clears ADDR. The clock is stretched until the data register is
+        // read or written.
+        let sr2 = regs.star2().read();
+
+        let kind = if sr2.tra() {
+            SlaveCommandKind::Read
+        } else {
+            SlaveCommandKind::Write
+        };
+        let address = match self.secondary_address {
+            _ if sr2.gencall() => 0x00,
+            Some(secondary) if sr2.dualf() => secondary,
+            _ => self.address,
+        };
+
+        Ok(SlaveCommand { kind, address })
+    }
+
+    /// Receive the bytes written by the master, until it sends a STOP or a repeated START.
+    ///
+    /// Returns the number of bytes stored in `buf`. Bytes that don't fit are acknowledged and dropped.
+    pub async fn respond_to_write(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let regs = T::regs();
+
+        let _on_drop = Self::disable_interrupts_on_drop();
+
+        let mut len = 0;
+        loop {
+            let sr1 = Self::wait_for_event(true, |sr1| sr1.rx_ne() || sr1.stopf() || sr1.addr()).await?;
+
+            if sr1.rx_ne() {
+                let byte = regs.datar().read().datar();
+                if let Some(slot) = buf.get_mut(len) {
+                    *slot = byte;
+                    len += 1;
+                }
+                continue;
+            }
+
+            // A repeated START leaves ADDR set for the next `listen`.
+            if sr1.stopf() {
+                Self::clear_stop();
+            }
+
+            return Ok(len);
+        }
+    }
+
+    /// Send `buf` to the master, until it NACKs a byte to end the read.
+    ///
+    /// If the master reads more than `buf.len()` bytes, it gets `0xFF` for the rest.
+    /// Returns the number of bytes of `buf` that were sent.
+    pub async fn respond_to_read(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let regs = T::regs();
+
+        let _on_drop = Self::disable_interrupts_on_drop();
+
+        let mut written = 0;
+        loop {
+            let sr1 = Self::wait_for_event(true, |sr1| sr1.af() || sr1.tx_e() || sr1.stopf() || sr1.addr()).await?;
+
+            if sr1.af() {
+                regs.star1().modify(|w| w.set_af(false));
+
+                // The byte written after the NACKed one is still waiting in the data register.
+                let sent = if sr1.tx_e() { written } else { written.saturating_sub(1) };
+                return Ok(sent.min(buf.len()));
+            }
+
+            if sr1.tx_e() {
+                let byte = buf.get(written).copied().unwrap_or(0xFF);
+                regs.datar().write(|w| w.set_datar(byte));
+                written += 1;
+                continue;
+            }
+
+            if sr1.stopf() {
+                Self::clear_stop();
+            }
+
+            return Ok(written.min(buf.len()));
+        }
+    }
+
+    fn clear_stop() {
+        // STOPF is cleared by reading SR1 (done by the caller) followed by a write to CR1.
+        T::regs().ctlr1().modify(|_| {});
+    }
+
+    fn check_and_clear_error_flags() -> Result<Star1, Error> {
+        let star1 = T::regs().star1().read();
+
+        if star1.ovr() {
+            T::regs().star1().modify(|w| w.set_ovr(false));
+            return Err(Error::Overrun);
+        }
+
+        // See the master driver, BERR may be incorrectly detected.
+        if star1.berr() {
+            T::regs().star1().modify(|w| w.set_berr(false));
+        }
+
+        // AF is not an error for a slave, it ends a read by the master.
+        Ok(star1)
+    }
+
+    /// Wait until `f` returns true for the status register, or an error occurs.
+    async fn wait_for_event(buffer_events: bool, mut f: impl FnMut(Star1) -> bool) -> Result<Star1, Error> {
+        let state = T::state();
+
+        poll_fn(|cx| {
+            state.waker.register(cx.waker());
+
+            match Self::check_and_clear_error_flags() {
+                Err(e) => Poll::Ready(Err(e)),
+                Ok(sr1) if f(sr1) => Poll::Ready(Ok(sr1)),
+                Ok(_) => {
+                    // When pending, (re-)enable interrupts to wake us up.
+                    T::regs().ctlr2().modify(|w| {
+                        w.set_itbufen(buffer_events);
+                        w.set_iterren(true);
+                        w.set_itevten(true);
+                    });
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    fn disable_interrupts_on_drop() -> OnDrop<impl FnOnce()> {
+        OnDrop::new(|| {
+            T::regs().ctlr2().modify(|w| {
+                w.set_itbufen(false);
+                w.set_iterren(false);
+                w.set_itevten(false);
+            })
+        })
+    }
+}
+
+impl<'d, T: Instance> Drop for I2cSlave<'d, T> {
+    fn drop(&mut self) {
+        T::regs().ctlr1().modify(|w| w.set_pe(false));
+        T::disable();
+    }
+}
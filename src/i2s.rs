@@ -0,0 +1,391 @@
+//! I2S, Inter-IC Sound, on the SPI2 and SPI3 peripherals
+//!
+//! Capabilities:
+//!
+//! - Master transmit and master receive
+//! - Philips, MSB-justified, LSB-justified and PCM standards
+//! - 16, 24 or 32-bit data in 16 or 32-bit channel frames
+//! - Continuous transfer through a circular DMA buffer, refilled half by half
+//!
+//! CK is the SCK pin, WS the NSS pin and SD the MOSI pin. The MCK output is not supported.
+
+use crate::dma::{ReadableRingBuffer, TransferOptions, WritableRingBuffer};
+use crate::gpio::{AFType, AnyPin, Pull, SealedPin, Speed};
+use crate::spi::{CsPin, MosiPin, RxDma, SckPin, SealedInstance, TxDma};
+use crate::time::Hertz;
+use crate::{into_ref, peripherals, Peripheral, PeripheralRef};
+
+/// I2S error
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The DMA buffer was not refilled, or not read, in time.
+    Overrun,
+}
+
+/// I2S standard
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Standard {
+    /// Philips standard
+    Philips,
+    /// MSB-justified standard
+    MsbFirst,
+    /// LSB-justified standard
+    LsbFirst,
+    /// PCM standard with a one bit long frame sync
+    PcmShortSync,
+    /// PCM standard with a 13 bits long frame sync
+    PcmLongSync,
+}
+
+impl Standard {
+    const fn i2sstd(&self) -> u8 {
+        match self {
+            Standard::Philips => 0b00,
+            Standard::MsbFirst => 0b01,
+            Standard::LsbFirst => 0b10,
+            Standard::PcmShortSync | Standard::PcmLongSync => 0b11,
+        }
+    }
+
+    const fn pcmsync(&self) -> bool {
+        matches!(self, Standard::PcmLongSync)
+    }
+}
+
+/// Data length and channel frame length
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Format {
+    /// 16-bit data in a 16-bit channel frame
+    Data16Channel16,
+    /// 16-bit data in a 32-bit channel frame
+    Data16Channel32,
+    /// 24-bit data in a 32-bit channel frame
+    Data24Channel32,
+    /// 32-bit data in a 32-bit channel frame
+    Data32Channel32,
+}
+
+impl Format {
+    const fn datlen(&self) -> u8 {
+        match self {
+            Format::Data16Channel16 | Format::Data16Channel32 => 0b00,
+            Format::Data24Channel32 => 0b01,
+            Format::Data32Channel32 => 0b10,
+        }
+    }
+
+    const fn chlen(&self) -> bool {
+        !matches!(self, Format::Data16Channel16)
+    }
+}
+
+/// Clock polarity
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockPolarity {
+    /// CK idles low
+    IdleLow,
+    /// CK idles high
+    IdleHigh,
+}
+
+/// I2S configuration
+#[non_exhaustive]
+#[derive(Copy, Clone)]
+pub struct Config {
+    pub standard: Standard,
+    pub format: Format,
+    pub clock_polarity: ClockPolarity,
+    /// Sample rate, per channel
+    pub frequency: Hertz,
+}
+
+impl Default for Config {
+    /// Philips, 16-bit, 48kHz
+    fn default() -> Self {
+        Self {
+            standard: Standard::Philips,
+            format: Format::Data16Channel16,
+            clock_polarity: ClockPolarity::IdleLow,
+            frequency: Hertz::hz(48_000),
+        }
+    }
+}
+
+/// Direction of the transfer
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Function {
+    Transmit,
+    Receive,
+}
+
+enum RingBuffer<'d> {
+    Writable(WritableRingBuffer<'d, u16>),
+    Readable(ReadableRingBuffer<'d, u16>),
+}
+
+/// I2S driver.
+///
+/// Samples are exchanged as raw `u16` halfwords: one per channel for 16-bit data, two (most
+/// significant half first) for 24 and 32-bit data. Left and right channels alternate.
+pub struct I2S<'d, T: Instance> {
+    _peri: PeripheralRef<'d, T>,
+    sd: PeripheralRef<'d, AnyPin>,
+    ws: PeripheralRef<'d, AnyPin>,
+    ck: PeripheralRef<'d, AnyPin>,
+    ring_buf: RingBuffer<'d>,
+}
+
+impl<'d, T: Instance> I2S<'d, T> {
+    /// Create a master transmitter.
+    ///
+    /// `dma_buf` is played in a loop, the free half is refilled by [`write`](Self::write).
+    pub fn new_txonly<const REMAP: u8>(
+        peri: impl Peripheral<P = T> + 'd,
+        sd: impl Peripheral<P = impl MosiPin<T, REMAP>> + 'd,
+        ws: impl Peripheral<P = impl CsPin<T, REMAP>> + 'd,
+        ck: impl Peripheral<P = impl SckPin<T, REMAP>> + 'd,
+        tx_dma: impl Peripheral<P = impl TxDma<T>> + 'd,
+        dma_buf: &'d mut [u16],
+        config: Config,
+    ) -> Self {
+        into_ref!(peri, sd, ws, ck, tx_dma);
+
+        T::set_remap(REMAP);
+
+        sd.set_as_af_output(AFType::OutputPushPull, Speed::High);
+        ws.set_as_af_output(AFType::OutputPushPull, Speed::High);
+        ck.set_as_af_output(AFType::OutputPushPull, Speed::High);
+
+        let request = tx_dma.request();
+        let ring_buf = unsafe {
+            WritableRingBuffer::new(
+                tx_dma,
+                request,
+                T::REGS.datar().as_ptr() as *mut u16,
+                dma_buf,
+                dma_options(),
+            )
+        };
+
+        Self::new_inner(
+            peri,
+            sd.map_into(),
+            ws.map_into(),
+            ck.map_into(),
+            RingBuffer::Writable(ring_buf),
+            Function::Transmit,
+            config,
+        )
+    }
+
+    /// Create a master receiver.
+    ///
+    /// `dma_buf` is filled in a loop, the filled half is taken out by [`read`](Self::read).
+    pub fn new_rxonly<const REMAP: u8>(
+        peri: impl Peripheral<P = T> + 'd,
+        sd: impl Peripheral<P = impl MosiPin<T, REMAP>> + 'd,
+        ws: impl Peripheral<P = impl CsPin<T, REMAP>> + 'd,
+        ck: impl Peripheral<P = impl SckPin<T, REMAP>> + 'd,
+        rx_dma: impl Peripheral<P = impl RxDma<T>> + 'd,
+        dma_buf: &'d mut [u16],
+        config: Config,
+    ) -> Self {
+        into_ref!(peri, sd, ws, ck, rx_dma);
+
+        T::set_remap(REMAP);
+
+        sd.set_as_input(Pull::None);
+        ws.set_as_af_output(AFType::OutputPushPull, Speed::High);
+        ck.set_as_af_output(AFType::OutputPushPull, Speed::High);
+
+        let request = rx_dma.request();
+        let ring_buf = unsafe {
+            ReadableRingBuffer::new(
+                rx_dma,
+                request,
+                T::REGS.datar().as_ptr() as *mut u16,
+                dma_buf,
+                dma_options(),
+            )
+        };
+
+        Self::new_inner(
+            peri,
+            sd.map_into(),
+            ws.map_into(),
+            ck.map_into(),
+            RingBuffer::Readable(ring_buf),
+            Function::Receive,
+            config,
+        )
+    }
+
+    fn new_inner(
+        peri: PeripheralRef<'d, T>,
+        sd: PeripheralRef<'d, AnyPin>,
+        ws: PeripheralRef<'d, AnyPin>,
+        ck: PeripheralRef<'d, AnyPin>,
+        ring_buf: RingBuffer<'d>,
+        function: Function,
+        config: Config,
+    ) -> Self {
+        T::enable_and_reset();
+
+        let (div, odd) = calculate_prescaler(T::frequency().0, &config);
+
+        let regs = T::REGS;
+        regs.i2spr().write(|w| {
+            w.set_i2sdiv(div);
+            w.set_odd(odd);
+            w.set_mckoe(false);
+        });
+        regs.i2scfgr().write(|w| {
+            w.set_i2smod(true);
+            // master transmit or master receive
+            w.set_i2scfg(match function {
+                Function::Transmit => 0b10,
+                Function::Receive => 0b11,
+            });
+            w.set_i2sstd(config.standard.i2sstd());
+            w.set_pcmsync(config.standard.pcmsync());
+            w.set_ckpol(config.clock_polarity == ClockPolarity::IdleHigh);
+            w.set_datlen(config.format.datlen());
+            w.set_chlen(config.format.chlen());
+            w.set_i2se(false);
+        });
+
+        Self {
+            _peri: peri,
+            sd,
+            ws,
+            ck,
+            ring_buf,
+        }
+    }
+
+    /// Start the DMA and the I2S clocks.
+    ///
+    /// For output, fill the buffer with a first [`write`](Self::write) beforehand.
+    pub fn start(&mut self) {
+        let regs = T::REGS;
+        match &mut self.ring_buf {
+            RingBuffer::Writable(rb) => {
+                rb.start();
+                regs.ctlr2().modify(|w| w.set_txdmaen(true));
+            }
+            RingBuffer::Readable(rb) => {
+                rb.start();
+                regs.ctlr2().modify(|w| w.set_rxdmaen(true));
+            }
+        }
+        regs.i2scfgr().modify(|w| w.set_i2se(true));
+    }
+
+    /// Stop the I2S clocks and the DMA.
+    pub fn stop(&mut self) {
+        let regs = T::REGS;
+
+        if let RingBuffer::Writable(_) = self.ring_buf {
+            // let the last frame go out
+            while !regs.statr().read().txe() {}
+            while regs.statr().read().bsy() {}
+        }
+        regs.i2scfgr().modify(|w| w.set_i2se(false));
+        regs.ctlr2().modify(|w| {
+            w.set_txdmaen(false);
+            w.set_rxdmaen(false);
+        });
+
+        match &mut self.ring_buf {
+            RingBuffer::Writable(rb) => {
+                rb.request_stop();
+                while rb.is_running() {}
+            }
+            RingBuffer::Readable(rb) => {
+                rb.request_stop();
+                while rb.is_running() {}
+            }
+        }
+    }
+
+    /// Queue samples for output, waiting for room in the DMA buffer.
+    ///
+    /// Returns [`Error::Overrun`] if the DMA caught up with the written data, i.e. old samples
+    /// were played again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the driver was created with [`new_rxonly`](Self::new_rxonly).
+    pub async fn write(&mut self, data: &[u16]) -> Result<(), Error> {
+        match &mut self.ring_buf {
+            RingBuffer::Writable(rb) => rb.write_exact(data).await.map_err(|_| Error::Overrun)?,
+            RingBuffer::Readable(_) => panic!("I2S: not a transmitter"),
+        };
+        Ok(())
+    }
+
+    /// Read received samples, waiting until `data` can be filled.
+    ///
+    /// Returns [`Error::Overrun`] if samples were overwritten before they were read.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the driver was created with [`new_txonly`](Self::new_txonly).
+    pub async fn read(&mut self, data: &mut [u16]) -> Result<(), Error> {
+        match &mut self.ring_buf {
+            RingBuffer::Readable(rb) => rb.read_exact(data).await.map_err(|_| Error::Overrun)?,
+            RingBuffer::Writable(_) => panic!("I2S: not a receiver"),
+        };
+        Ok(())
+    }
+}
+
+impl<'d, T: Instance> Drop for I2S<'d, T> {
+    fn drop(&mut self) {
+        self.stop();
+
+        self.sd.set_as_disconnected();
+        self.ws.set_as_disconnected();
+        self.ck.set_as_disconnected();
+
+        T::disable();
+    }
+}
+
+// Wake up on each half of the buffer
+fn dma_options() -> TransferOptions {
+    TransferOptions {
+        half_transfer_ir: true,
+        ..Default::default()
+    }
+}
+
+// Get I2SPR.I2SDIV and I2SPR.ODD
+//
+// Fs = I2SCLK / (channel frame bits * 2 * (2 * I2SDIV + ODD))
+fn calculate_prescaler(i2s_clk: u32, config: &Config) -> (u8, bool) {
+    let frame_bits = if config.format.chlen() { 64 } else { 32 };
+
+    let fs = config.frequency.0;
+    let total = (i2s_clk + fs * frame_bits / 2) / (fs * frame_bits);
+
+    // I2SDIV must be at least 2
+    let total = total.clamp(4, 511);
+    ((total / 2) as u8, total % 2 == 1)
+}
+
+/// I2S capable SPI instance.
+pub trait Instance: crate::spi::Instance {}
+
+macro_rules! impl_i2s {
+    ($inst:ident) => {
+        impl Instance for peripherals::$inst {}
+    };
+}
+
+impl_i2s!(SPI2);
+impl_i2s!(SPI3);
@@ -1,3 +1,4 @@
 // TODO: replace with embassy-hal-internal
 
+pub mod atomic_ring_buffer;
 pub mod drop;
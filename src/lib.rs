@@ -47,20 +47,38 @@ mod interrupt_ext;
 
 pub use crate::_generated::{peripherals, Peripherals};
 
+#[cfg(any(qingke_v3, qingke_v4))]
+pub mod counter;
+#[cfg(any(ch32v0, ch32x0, ch32l1, ch32v1, ch32v2, ch32v3))]
+pub mod bootloader;
 #[cfg(any(systick_rv2, systick_rv3))]
 pub mod delay;
 pub mod dma;
 
 #[cfg(adc)]
 pub mod adc;
+#[cfg(all(bkp, any(ch32v2, ch32v3)))]
+pub mod backup;
+#[cfg(can)]
+pub mod can;
 #[cfg(peri_dac1)]
 pub mod dac;
+pub mod dbgmcu;
+#[cfg(eth)]
+pub mod eth;
 pub mod exti;
+#[cfg(any(ch32v0, ch32v1, ch32v2, ch32v3))]
+pub mod flash;
 pub mod gpio;
 #[cfg(i2c)]
 pub mod i2c;
+#[cfg(all(ch32v3, peri_spi2, peri_spi3))]
+pub mod i2s;
+pub mod low_power;
 #[cfg(rng)]
 pub mod rng;
+#[cfg(all(rtc, any(ch32v2, ch32v3)))]
+pub mod rtc;
 #[cfg(sdio_v3)]
 pub mod sdio;
 pub mod signature;
@@ -69,15 +87,24 @@ pub mod spi;
 #[cfg(any(timer_x0, timer_v3))]
 pub mod timer;
 pub mod usart;
+#[cfg(iwdg)]
+pub mod watchdog;
 
 #[cfg(usb)]
 pub mod usb;
 #[cfg(usbd)]
 pub mod usbd;
+#[cfg(usbfs)]
+pub mod usbfs;
+#[cfg(usbhs)]
+pub mod usbhs;
 
 #[cfg(usbpd)]
 pub mod usbpd;
 
+#[cfg(feature = "usb-logger")]
+pub mod usb_logger;
+
 #[cfg(feature = "embassy")]
 pub mod embassy;
 
@@ -97,6 +124,8 @@ pub use crate::_generated::interrupt;
 pub struct Config {
     pub rcc: rcc::Config,
     pub dma_interrupt_priority: interrupt::Priority,
+    /// What to do if HSE or the PLL doesn't start.
+    pub clock_fallback: rcc::ClockFallback,
 }
 
 impl Default for Config {
@@ -104,16 +133,19 @@ impl Default for Config {
         Self {
             rcc: Default::default(),
             dma_interrupt_priority: interrupt::Priority::P0,
+            clock_fallback: Default::default(),
         }
     }
 }
 
 pub fn init(config: Config) -> Peripherals {
     unsafe {
-        rcc::init(config.rcc);
+        rcc::init_or_fallback(config.rcc, config.clock_fallback);
 
         #[cfg(any(systick_rv2, systick_rv3))]
         delay::Delay::init();
+        #[cfg(any(qingke_v3, qingke_v4))]
+        counter::init();
     }
 
     ::critical_section::with(|cs| unsafe {
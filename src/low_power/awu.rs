@@ -0,0 +1,106 @@
+//! Auto-wakeup unit of CH32V003
+//!
+//! The AWU counts LSI, divided by a prescaler, up to a 6-bit window value, and then raises EXTI
+//! line 9, which wakes the chip up from [`standby`](super::standby). The period is from about
+//! 16 µs to about 30 s, at the nominal LSI frequency.
+//!
+//! LSI is only within about ±10%: measure it, e.g. with a timer against HSE, and set it by
+//! [`set_lsi_frequency`] for accurate periods.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_time::Duration;
+
+use crate::pac::{EXTI, PWR, RCC};
+use crate::rcc::LSI_FREQUENCY;
+use crate::time::Hertz;
+
+/// EXTI line of the AWU.
+const EXTI_LINE_AWU: usize = 9;
+
+/// Largest window value.
+const WINDOW_MAX: u64 = 0x3F;
+
+/// Prescaler register values and their division.
+const PRESCALERS: [(u32, u64); 15] = [
+    (0b0000, 1),
+    (0b0010, 2),
+    (0b0011, 4),
+    (0b0100, 8),
+    (0b0101, 16),
+    (0b0110, 32),
+    (0b0111, 64),
+    (0b1000, 128),
+    (0b1001, 256),
+    (0b1010, 512),
+    (0b1011, 1024),
+    (0b1100, 2048),
+    (0b1101, 4096),
+    (0b1110, 10240),
+    (0b1111, 61440),
+];
+
+static LSI_HZ: AtomicU32 = AtomicU32::new(LSI_FREQUENCY.0);
+
+/// AWU errors
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AwuError {
+    /// The period is longer than the AWU counts, see [`max_period`].
+    TooLong,
+}
+
+/// Set the LSI frequency the periods are computed from, once measured.
+pub fn set_lsi_frequency(frequency: Hertz) {
+    LSI_HZ.store(frequency.0, Ordering::Relaxed);
+}
+
+/// LSI frequency the periods are computed from, [`LSI_FREQUENCY`] unless set.
+pub fn lsi_frequency() -> Hertz {
+    Hertz(LSI_HZ.load(Ordering::Relaxed))
+}
+
+/// Longest period of the AWU.
+pub fn max_period() -> Duration {
+    let (_, div) = PRESCALERS[PRESCALERS.len() - 1];
+    period(div, WINDOW_MAX)
+}
+
+fn period(div: u64, window: u64) -> Duration {
+    Duration::from_micros(div * window * 1_000_000 / u64::from(LSI_HZ.load(Ordering::Relaxed)))
+}
+
+/// Wake the chip up from standby after `duration`, then every `duration` until [`disable_awu`].
+///
+/// The period is rounded up to the resolution of the AWU, the smaller the shorter it is. Returns
+/// the period, at the LSI frequency of [`lsi_frequency`].
+pub fn wake_after(duration: Duration) -> Result<Duration, AwuError> {
+    let lsi = u64::from(LSI_HZ.load(Ordering::Relaxed));
+    let cycles = (duration.as_micros() * lsi).div_ceil(1_000_000).max(1);
+    let (psc, div) = *PRESCALERS
+        .iter()
+        .find(|(_, div)| cycles.div_ceil(*div) <= WINDOW_MAX)
+        .ok_or(AwuError::TooLong)?;
+    let window = cycles.div_ceil(div);
+
+    RCC.rstsckr().modify(|w| w.set_lsion(true));
+    while !RCC.rstsckr().read().lsirdy() {}
+    super::enable_pwr();
+
+    PWR.awupsc().write(|w| w.0 = psc);
+    PWR.awuwr().write(|w| w.0 = window as u32);
+    PWR.awucsr().modify(|w| w.set_awuen(true));
+
+    // An event, so it wakes the chip up without an interrupt handler.
+    EXTI.evenr().modify(|w| w.0 |= 1 << EXTI_LINE_AWU);
+    EXTI.rtenr().modify(|w| w.0 |= 1 << EXTI_LINE_AWU);
+
+    Ok(period(div, window))
+}
+
+/// Stop the AWU.
+pub fn disable_awu() {
+    PWR.awucsr().modify(|w| w.set_awuen(false));
+    EXTI.evenr().modify(|w| w.0 &= !(1 << EXTI_LINE_AWU));
+    EXTI.rtenr().modify(|w| w.0 &= !(1 << EXTI_LINE_AWU));
+}
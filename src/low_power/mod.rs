@@ -0,0 +1,361 @@
+//! Power saving
+//!
+//! [`gate_unused`] stops the clocks of the peripherals no driver uses. The clocks of the others
+//! are gated when their drivers are dropped, see [`rcc::refcount`](crate::rcc::refcount).
+//!
+//! The low-power modes, from the lightest:
+//!
+//! - [`sleep`]: the core stops, peripherals keep running, any enabled interrupt wakes it up.
+//! - [`stop`]: all clocks stop except LSI and LSE, SRAM and registers are kept. An EXTI line wakes
+//!   it up, e.g. a pin, the RTC alarm or the USB wakeup. SYSCLK is HSI on wake, `stop` starts HSE
+//!   and the PLL again before returning. Not on CH32V003.
+//! - [`standby`]: the core domain is off, only the backup domain, the RTC and the independent
+//!   watchdog run. The WKUP pin, the RTC alarm or a reset wake it up, through a reset, see
+//!   [`enable_wakeup_pin`] and [`standby_wakeup`]. On CH32V003, it is what others call stop: the
+//!   chip resumes, from the AWU or an EXTI line.
+//!
+//! On CH32V003, [`wake_after`] schedules the wake-up from standby with the auto-wakeup unit.
+//!
+//! Debugging stops working in stop and standby, unless enabled in [`dbgmcu`](crate::dbgmcu).
+//!
+//! Drivers depending on the bus clocks implement [`LowPowerPeripheral`], and are handed to `stop`,
+//! which suspends them before and resumes them after.
+//!
+//! With the `low-power` feature, [`Executor`] enters stop mode whenever no task runs and the next
+//! timer is far enough. The RTC time driver keeps counting meanwhile, and its alarm wakes the chip
+//! up, so `embassy-time` stays right. Only EXTI lines wake the chip from stop mode: a task waiting
+//! on another interrupt, e.g. a UART transfer, holds a [`StopGuard`] meanwhile. The executor doesn't
+//! own the drivers, so it suspends none: it relies on the clocks coming back as they were. Not on
+//! CH32V003 and CH641, which have no stop mode.
+
+#[cfg(all(feature = "low-power", not(any(ch32v0, ch641))))]
+use core::marker::PhantomData;
+#[cfg(all(feature = "low-power", not(any(ch32v0, ch641))))]
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+#[cfg(all(feature = "low-power", not(any(ch32v0, ch641))))]
+use embassy_executor::{raw, Spawner};
+
+use crate::pac::{PWR, RCC};
+
+#[cfg(ch32v0)]
+mod awu;
+#[cfg(ch32v0)]
+pub use awu::*;
+
+/// PFIC system control register.
+const PFIC_SCTLR: *mut u32 = 0xE000_ED10 as *mut u32;
+/// Deep sleep, stop or standby, on WFI.
+const SCTLR_SLEEPDEEP: u32 = 1 << 2;
+/// WFI waits for an event, as WFE.
+#[cfg(any(ch32v0, ch641))]
+const SCTLR_WFITOWFE: u32 = 1 << 3;
+
+/// Gate the clocks of all peripherals not used by a driver.
+///
+/// GPIO, AFIO, DMA, PWR, BKP and the flash interface are kept running, the HAL uses them without a
+/// driver. Peripherals accessed through the PAC only must be enabled again afterwards.
+pub fn gate_unused() {
+    crate::_generated::gate_unused();
+}
+
+/// Stop mode config
+#[cfg(not(any(ch32v0, ch641)))]
+#[derive(Clone, Copy, Default)]
+pub struct StopConfig {
+    /// Voltage regulator in low-power mode: lower current, slower wake-up.
+    pub low_power_regulator: bool,
+}
+
+/// A driver that must be told about stop mode
+///
+/// The clocks stop meanwhile, and may differ on wake-up, e.g. HSI if HSE doesn't start again.
+/// `suspend` finishes the frame being sent and saves what depends on the bus clock, `resume`
+/// computes it again from the clocks then in effect. No transfer, DMA included, is in progress
+/// while the driver is mutably borrowed, so there is no transfer state to save.
+pub trait LowPowerPeripheral {
+    /// Prepare for stop mode.
+    fn suspend(&mut self);
+    /// Restore the driver after wake-up.
+    fn resume(&mut self);
+}
+
+fn modify_sctlr(bits: u32, set: bool) {
+    unsafe {
+        let sctlr = PFIC_SCTLR.read_volatile();
+        let sctlr = if set { sctlr | bits } else { sctlr & !bits };
+        PFIC_SCTLR.write_volatile(sctlr);
+    }
+}
+
+fn set_sleepdeep(deep: bool) {
+    modify_sctlr(SCTLR_SLEEPDEEP, deep);
+}
+
+fn enable_pwr() {
+    RCC.apb1pcenr().modify(|w| w.set_pwren(true));
+}
+
+/// Sleep until an interrupt.
+pub fn sleep() {
+    set_sleepdeep(false);
+    unsafe { qingke::riscv::asm::wfi() };
+}
+
+/// Enter stop mode until an EXTI line wakes the chip up, then restore the clocks.
+///
+/// The line must be enabled as an interrupt, in EXTI and PFIC. `peripherals` are suspended before,
+/// and resumed after, in reverse order.
+#[cfg(not(any(ch32v0, ch641)))]
+pub fn stop(config: StopConfig, peripherals: &mut [&mut dyn LowPowerPeripheral]) {
+    enable_pwr();
+    PWR.ctlr().modify(|w| {
+        w.set_pdds(false);
+        w.set_lpds(config.low_power_regulator);
+    });
+
+    suspended(peripherals, deep_sleep);
+}
+
+/// Enter standby mode, the chip resets on wake-up.
+///
+/// The wake-up flag is cleared first, or the chip wouldn't enter standby.
+#[cfg(not(any(ch32v0, ch641)))]
+pub fn standby() -> ! {
+    enable_pwr();
+    PWR.ctlr().modify(|w| {
+        w.set_cwuf(true);
+        w.set_pdds(true);
+    });
+
+    set_sleepdeep(true);
+    loop {
+        unsafe { qingke::riscv::asm::wfi() };
+    }
+}
+
+/// What woke the chip up from standby
+#[cfg(not(any(ch32v0, ch641)))]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StandbyWakeup {
+    /// A rising edge of the WKUP pin, or the RTC alarm.
+    WakeupEvent,
+    /// The reset pin or the independent watchdog.
+    Reset,
+}
+
+/// Let a rising edge of the WKUP pin, PA0, wake the chip up from standby.
+///
+/// The pin is an input with a pull-down from then on, whatever its GPIO configuration. The
+/// wake-up flag is set if the pin is already high, see [`standby`].
+#[cfg(not(any(ch32v0, ch641)))]
+pub fn enable_wakeup_pin() {
+    enable_pwr();
+    PWR.csr().modify(|w| w.set_ewup(true));
+}
+
+/// Give the WKUP pin back to GPIO.
+#[cfg(not(any(ch32v0, ch641)))]
+pub fn disable_wakeup_pin() {
+    enable_pwr();
+    PWR.csr().modify(|w| w.set_ewup(false));
+}
+
+/// Whether the chip was reset out of standby, and by what, `None` after any other reset.
+///
+/// The flags are kept until [`clear_wakeup_flags`] or a power-on reset, so call it once read.
+#[cfg(not(any(ch32v0, ch641)))]
+pub fn standby_wakeup() -> Option<StandbyWakeup> {
+    enable_pwr();
+    let csr = PWR.csr().read();
+    match (csr.sbf(), csr.wuf()) {
+        (false, _) => None,
+        (true, true) => Some(StandbyWakeup::WakeupEvent),
+        (true, false) => Some(StandbyWakeup::Reset),
+    }
+}
+
+/// Clear the standby and wake-up flags.
+#[cfg(not(any(ch32v0, ch641)))]
+pub fn clear_wakeup_flags() {
+    enable_pwr();
+    PWR.ctlr().modify(|w| {
+        w.set_csbf(true);
+        w.set_cwuf(true);
+    });
+}
+
+/// Enter standby mode until the AWU or an EXTI line wakes the chip up, then restore the clocks.
+///
+/// The wake-up source is enabled in EXTI as an event, e.g. by [`wake_after`], or as an interrupt.
+/// `peripherals` are suspended meanwhile, as by [`stop`] on the other chips.
+#[cfg(any(ch32v0, ch641))]
+pub fn standby(peripherals: &mut [&mut dyn LowPowerPeripheral]) {
+    enable_pwr();
+    PWR.ctlr().modify(|w| w.set_pdds(true));
+
+    suspended(peripherals, || {
+        modify_sctlr(SCTLR_WFITOWFE, true);
+        deep_sleep();
+        modify_sctlr(SCTLR_WFITOWFE, false);
+    });
+}
+
+fn suspended(peripherals: &mut [&mut dyn LowPowerPeripheral], f: impl FnOnce()) {
+    for p in peripherals.iter_mut() {
+        p.suspend();
+    }
+    f();
+    for p in peripherals.iter_mut().rev() {
+        p.resume();
+    }
+}
+
+/// WFI in deep sleep, then start HSE and the PLL again if they were running, and switch SYSCLK
+/// back. They stop meanwhile, SYSCLK is HSI on wake-up, the prescalers are kept.
+fn deep_sleep() {
+    #[cfg(not(ch32x0))]
+    let (ctlr, sw) = (RCC.ctlr().read(), RCC.cfgr0().read().sw());
+
+    set_sleepdeep(true);
+    unsafe { qingke::riscv::asm::wfi() };
+    set_sleepdeep(false);
+
+    // SYSCLK is always HSI on CH32X035.
+    #[cfg(not(ch32x0))]
+    {
+        #[cfg(not(ch641))]
+        if ctlr.hseon() {
+            RCC.ctlr().modify(|w| w.set_hseon(true));
+            while !RCC.ctlr().read().hserdy() {}
+        }
+        if ctlr.pllon() {
+            RCC.ctlr().modify(|w| w.set_pllon(true));
+            while !RCC.ctlr().read().pllrdy() {}
+        }
+        RCC.cfgr0().modify(|w| w.set_sw(sw));
+        while RCC.cfgr0().read().sws() != sw {}
+    }
+}
+
+/// Time to the next timer under which [`Executor`] only sleeps, 10 ms: waking up from stop starts
+/// HSE and the PLL again, and the RTC counts by about 1 ms.
+#[cfg(all(feature = "low-power", not(any(ch32v0, ch641))))]
+const MIN_STOP_TICKS: u64 = embassy_time_driver::TICK_HZ / 100;
+
+#[cfg(all(feature = "low-power", not(any(ch32v0, ch641))))]
+static STOP_GUARDS: AtomicU8 = AtomicU8::new(0);
+
+/// Keeps [`Executor`] out of stop mode while alive, see [`StopGuard::new`]
+#[cfg(all(feature = "low-power", not(any(ch32v0, ch641))))]
+pub struct StopGuard {
+    _private: (),
+}
+
+#[cfg(all(feature = "low-power", not(any(ch32v0, ch641))))]
+impl StopGuard {
+    /// Keep the clocks running, e.g. for a transfer whose peripheral would stop in stop mode.
+    pub fn new() -> Self {
+        critical_section::with(|_| {
+            let guards = STOP_GUARDS.load(Ordering::Relaxed);
+            STOP_GUARDS.store(guards.checked_add(1).expect("too many stop guards"), Ordering::Relaxed);
+        });
+        Self { _private: () }
+    }
+}
+
+#[cfg(all(feature = "low-power", not(any(ch32v0, ch641))))]
+impl Default for StopGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(feature = "low-power", not(any(ch32v0, ch641))))]
+impl Drop for StopGuard {
+    fn drop(&mut self) {
+        critical_section::with(|_| {
+            let guards = STOP_GUARDS.load(Ordering::Relaxed);
+            STOP_GUARDS.store(guards - 1, Ordering::Relaxed);
+        });
+    }
+}
+
+/// Work pending for [`Executor`].
+#[cfg(all(feature = "low-power", not(any(ch32v0, ch641))))]
+static SIGNAL_WORK: AtomicBool = AtomicBool::new(false);
+
+#[cfg(all(feature = "low-power", not(any(ch32v0, ch641))))]
+#[export_name = "__pender"]
+fn __pender(_context: *mut ()) {
+    SIGNAL_WORK.store(true, Ordering::SeqCst);
+}
+
+/// Thread mode executor, entering stop mode when idle
+///
+/// It replaces the `arch-riscv32` executor of embassy-executor, whose feature must be off, and so
+/// `#[embassy_executor::main]`:
+///
+/// ```ignore
+/// static EXECUTOR: StaticCell<low_power::Executor> = StaticCell::new();
+///
+/// #[qingke_rt::entry]
+/// fn main() -> ! {
+///     let p = hal::init(Default::default());
+///     EXECUTOR.init(low_power::Executor::new()).run(|spawner| {
+///         spawner.spawn(blink(p.PA15.degrade())).unwrap();
+///     })
+/// }
+/// ```
+#[cfg(all(feature = "low-power", not(any(ch32v0, ch641))))]
+pub struct Executor {
+    inner: raw::Executor,
+    not_send: PhantomData<*mut ()>,
+}
+
+#[cfg(all(feature = "low-power", not(any(ch32v0, ch641))))]
+impl Executor {
+    /// Create a new executor, spawn its tasks from [`run`](Self::run).
+    pub fn new() -> Self {
+        Self {
+            inner: raw::Executor::new(core::ptr::null_mut()),
+            not_send: PhantomData,
+        }
+    }
+
+    /// Run the executor, after `init` spawned the first tasks.
+    pub fn run(&'static mut self, init: impl FnOnce(Spawner)) -> ! {
+        init(self.inner.spawner());
+
+        loop {
+            unsafe { self.inner.poll() };
+
+            // A pending interrupt ends WFI, and runs once the critical section is left.
+            critical_section::with(|cs| {
+                if !SIGNAL_WORK.load(Ordering::SeqCst) {
+                    let now = embassy_time_driver::now();
+                    let next = crate::embassy::time_driver_impl::next_alarm(cs);
+                    if STOP_GUARDS.load(Ordering::Relaxed) == 0 && next.saturating_sub(now) > MIN_STOP_TICKS {
+                        stop(
+                            StopConfig {
+                                low_power_regulator: true,
+                            },
+                            &mut [],
+                        );
+                    } else {
+                        sleep();
+                    }
+                }
+                SIGNAL_WORK.store(false, Ordering::SeqCst);
+            });
+        }
+    }
+}
+
+#[cfg(all(feature = "low-power", not(any(ch32v0, ch641))))]
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -310,8 +310,11 @@ macro_rules! impl_peripheral {
 
 pub(crate) trait SealedRccPeripheral {
     fn frequency() -> crate::time::Hertz;
+    /// Number of drivers using the peripheral, its clock is gated when it drops to 0.
+    fn refcount() -> &'static critical_section::Mutex<core::cell::Cell<u8>>;
     fn enable_and_reset_with_cs(cs: CriticalSection);
     fn disable_with_cs(cs: CriticalSection);
+    fn is_enabled() -> bool;
 
     fn enable_and_reset() {
         critical_section::with(|cs| Self::enable_and_reset_with_cs(cs))
@@ -33,6 +33,18 @@ impl Config {
         apb2_pre: APBPrescaler::DIV1,
     };
 }
+/// Presets, from HSI only
+impl Config {
+    /// 48 MHz, HSI through the PLL.
+    pub const fn sysclk_48mhz_hsi() -> Self {
+        Self::SYSCLK_FREQ_48MHZ_HSI
+    }
+
+    /// 24 MHz.
+    pub const fn sysclk_24mhz_hsi() -> Self {
+        Self::SYSCLK_FREQ_24MHZ_HSI
+    }
+}
 impl Default for Config {
     fn default() -> Self {
         Config {
@@ -44,7 +56,7 @@ impl Default for Config {
 }
 
 #[allow(unused_variables)]
-pub(crate) unsafe fn init(config: Config) {
+pub(crate) unsafe fn init(config: Config) -> Result<(), super::ClockError> {
     let sysclk = match config.sys {
         Sysclk::HSI => {
             // HSI default enabled
@@ -52,7 +64,10 @@ pub(crate) unsafe fn init(config: Config) {
         }
         Sysclk::PLL => {
             RCC.ctlr().modify(|w| w.set_pllon(true));
-            while !RCC.ctlr().read().pllrdy() {}
+            if !super::wait_for(super::PLL_TIMEOUT_MS, HSI_FREQUENCY, || RCC.ctlr().read().pllrdy()) {
+                RCC.ctlr().modify(|w| w.set_pllon(false));
+                return Err(super::ClockError::PllLockTimeout);
+            }
 
             RCC.cfgr0().modify(|w| w.set_sw(Sysclk::PLL));
             while RCC.cfgr0().read().sws() != Sysclk::PLL {}
@@ -99,6 +114,11 @@ pub(crate) unsafe fn init(config: Config) {
 
     super::CLOCKS.pclk1_tim = Hertz(sysclk);
     super::CLOCKS.pclk2_tim = Hertz(sysclk);
+
+    // APB2 is only the ADC clock.
+    super::CLOCKS.adc = pclk2;
+
+    Ok(())
 }
 
 impl ops::Div<APBPrescaler> for Hertz {
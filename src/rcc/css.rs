@@ -0,0 +1,130 @@
+//! Clock security system (CSS)
+//!
+//! With [`Config::css`](super::Config::css), the CSS watches HSE. When it fails, the hardware
+//! switches SYSCLK to HSI, stops HSE and the PLL, and raises an NMI.
+//!
+//! [`on_nmi`] handles it, from the `NonMaskableInt` handler of the application:
+//!
+//! ```ignore
+//! #[qingke_rt::interrupt(core)]
+//! fn NonMaskableInt() {
+//!     unsafe { hal::rcc::css::on_nmi() };
+//! }
+//! ```
+//!
+//! It only uses atomics, as the NMI can interrupt critical sections, and pends the RCC interrupt.
+//! That one, bound to [`InterruptHandler`], calls the callback and wakes [`CssMonitor::wait`].
+//! Peripherals keep their prescalers, so their clocks are lower until reconfigured.
+
+use core::future::poll_fn;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::Poll;
+
+use embassy_sync::waitqueue::AtomicWaker;
+
+use super::HSI_FREQUENCY;
+use crate::interrupt;
+use crate::interrupt::typelevel::Interrupt;
+use crate::pac::RCC;
+
+/// An HSE failure happened since boot.
+static FAILED: AtomicBool = AtomicBool::new(false);
+/// A failure not reported by the RCC interrupt yet.
+static PENDING: AtomicBool = AtomicBool::new(false);
+/// The `fn()` callback, 0 if unset.
+static CALLBACK: AtomicUsize = AtomicUsize::new(0);
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Handle a CSS event, call from the `NonMaskableInt` handler.
+///
+/// # Safety
+///
+/// Must only be called from the NMI handler.
+pub unsafe fn on_nmi() {
+    if !RCC.intr().read().cssf() {
+        return;
+    }
+    RCC.intr().modify(|w| w.set_cssc(true));
+
+    // SYSCLK is HSI now, the bus prescalers are unchanged.
+    let cfgr0 = RCC.cfgr0().read();
+    let hclk = HSI_FREQUENCY / cfgr0.hpre();
+    let (pclk1, pclk1_tim) = super::rcc_impl::calc_pclk(hclk, cfgr0.ppre1());
+    let (pclk2, pclk2_tim) = super::rcc_impl::calc_pclk(hclk, cfgr0.ppre2());
+    super::CLOCKS.sysclk = HSI_FREQUENCY;
+    super::CLOCKS.hclk = hclk;
+    super::CLOCKS.pclk1 = pclk1;
+    super::CLOCKS.pclk2 = pclk2;
+    super::CLOCKS.pclk1_tim = pclk1_tim;
+    super::CLOCKS.pclk2_tim = pclk2_tim;
+    super::CLOCKS.adc = pclk2 / cfgr0.adcpre();
+
+    FAILED.store(true, Ordering::Release);
+    PENDING.store(true, Ordering::Release);
+    interrupt::typelevel::RCC::pend();
+}
+
+/// Whether HSE failed since boot, SYSCLK is HSI then.
+pub fn has_failed() -> bool {
+    FAILED.load(Ordering::Acquire)
+}
+
+/// RCC interrupt handler, reports the CSS events.
+pub struct InterruptHandler {
+    _private: (),
+}
+
+impl interrupt::typelevel::Handler<interrupt::typelevel::RCC> for InterruptHandler {
+    unsafe fn on_interrupt() {
+        if !PENDING.swap(false, Ordering::AcqRel) {
+            return;
+        }
+
+        let callback = CALLBACK.load(Ordering::Acquire);
+        if callback != 0 {
+            let callback: fn() = core::mem::transmute(callback);
+            callback();
+        }
+        WAKER.wake();
+    }
+}
+
+/// Reports HSE failures
+pub struct CssMonitor {
+    _private: (),
+}
+
+impl CssMonitor {
+    /// Enable the RCC interrupt, to report the failures.
+    pub fn new(
+        _irq: impl interrupt::typelevel::Binding<interrupt::typelevel::RCC, InterruptHandler> + 'static,
+    ) -> Self {
+        interrupt::typelevel::RCC::unpend();
+        unsafe { interrupt::typelevel::RCC::enable() };
+
+        // A failure before, reported now.
+        if PENDING.load(Ordering::Acquire) {
+            interrupt::typelevel::RCC::pend();
+        }
+
+        Self { _private: () }
+    }
+
+    /// Call `callback` from the RCC interrupt on a failure, replacing the previous one.
+    pub fn set_callback(&mut self, callback: fn()) {
+        CALLBACK.store(callback as usize, Ordering::Release);
+    }
+
+    /// Wait for HSE to fail, returns right away if it already has.
+    pub async fn wait(&mut self) {
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+            if has_failed() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
@@ -1,4 +1,5 @@
 use crate::time::Hertz;
+use crate::RccPeripheral;
 
 const DEFAULT_FREQUENCY: Hertz = Hertz(8_000_000);
 
@@ -11,8 +12,18 @@ static mut CLOCKS: Clocks = Clocks {
 
     pclk1_tim: DEFAULT_FREQUENCY,
     pclk2_tim: DEFAULT_FREQUENCY,
+
+    adc: Hertz(DEFAULT_FREQUENCY.0 / 2),
+
+    usb: None,
+
+    rtc: None,
 };
 
+/// Clock frequencies, set by [`crate::init`]
+///
+/// Drivers compute their baud rates and timings from these. They only change on an HSE failure,
+/// see `css`.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct Clocks {
     pub sysclk: Hertz,
@@ -23,8 +34,19 @@ pub struct Clocks {
     /// APB2 clock
     pub pclk2: Hertz,
 
-    pub(crate) pclk1_tim: Hertz,
-    pub(crate) pclk2_tim: Hertz,
+    /// Clock of the timers on APB1, twice PCLK1 if APB1 is divided
+    pub pclk1_tim: Hertz,
+    /// Clock of the timers on APB2, twice PCLK2 if APB2 is divided
+    pub pclk2_tim: Hertz,
+
+    /// ADC clock, before the divider of the ADC itself on chips that have one
+    pub adc: Hertz,
+
+    /// USB full-speed clock, `None` if the PLL can't be divided to 48 MHz
+    pub usb: Option<Hertz>,
+
+    /// RTC clock, `None` if disabled
+    pub rtc: Option<Hertz>,
 }
 
 #[inline]
@@ -32,6 +54,111 @@ pub fn clocks() -> &'static Clocks {
     unsafe { &CLOCKS }
 }
 
+/// USB clock errors
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UsbClockError {
+    /// The PLL frequency can't be divided to 48 MHz by the USB prescaler.
+    NoClock,
+    /// The USB clock is off 48 MHz by more than the 0.25% USB allows.
+    Frequency(Hertz),
+    /// HSE isn't running or isn't a multiple of 4 MHz up to 32 MHz, for the USBHS PHY PLL.
+    Hse,
+}
+
+/// The USB full-speed clock, checked to be 48 MHz.
+///
+/// From HSI, it is only as accurate as HSI, see `rtc::Rtc::trim_hsi`.
+#[allow(unused)]
+pub(crate) fn usb_clock() -> Result<Hertz, UsbClockError> {
+    const USB_FREQUENCY: u32 = 48_000_000;
+    const TOLERANCE: u32 = USB_FREQUENCY / 400;
+
+    let usb = clocks().usb.ok_or(UsbClockError::NoClock)?;
+    if usb.0.abs_diff(USB_FREQUENCY) > TOLERANCE {
+        return Err(UsbClockError::Frequency(usb));
+    }
+    Ok(usb)
+}
+
+/// Number of drivers using `T`, its clock is gated once they are all dropped.
+///
+/// Drivers that don't release their peripheral on drop keep it counted.
+pub fn refcount<T: RccPeripheral>() -> u8 {
+    critical_section::with(|cs| T::refcount().borrow(cs).get())
+}
+
+/// Whether the clock of `T` is enabled.
+pub fn is_enabled<T: RccPeripheral>() -> bool {
+    T::is_enabled()
+}
+
+/// Gate the clock of `T`, unless a driver uses it. Returns whether the clock is gated.
+///
+/// A peripheral accessed through the PAC only isn't counted, and stops responding once gated.
+pub fn disable_unused<T: RccPeripheral>() -> bool {
+    critical_section::with(|cs| {
+        if T::refcount().borrow(cs).get() == 0 {
+            T::disable_with_cs(cs);
+            true
+        } else {
+            false
+        }
+    })
+}
+
+/// Clock bring-up errors
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ClockError {
+    /// HSE didn't start: no crystal, a broken one, or wrong load capacitors.
+    HseTimeout,
+    /// The PLL didn't lock.
+    PllLockTimeout,
+}
+
+/// What [`crate::init`] does if the clocks don't start
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ClockFallback {
+    /// Panic with the [`ClockError`].
+    #[default]
+    Panic,
+    /// Run from HSI with the default config, and report the error by [`clock_error`].
+    Hsi,
+}
+
+static mut CLOCK_ERROR: Option<ClockError> = None;
+
+/// The error the clocks fell back to HSI from, see [`ClockFallback::Hsi`].
+pub fn clock_error() -> Option<ClockError> {
+    unsafe { CLOCK_ERROR }
+}
+
+#[allow(unused)]
+pub(crate) const HSE_TIMEOUT_MS: u32 = 100;
+#[allow(unused)]
+pub(crate) const PLL_TIMEOUT_MS: u32 = 10;
+
+/// Wait up to `timeout_ms` for `ready`, counting the time in cycles of `sysclk`. Returns whether it
+/// got ready.
+#[allow(unused)]
+pub(crate) fn wait_for(timeout_ms: u32, sysclk: Hertz, mut ready: impl FnMut() -> bool) -> bool {
+    let cycles_per_ms = sysclk.0 / 1000;
+    for _ in 0..timeout_ms {
+        if ready() {
+            return true;
+        }
+        qingke::riscv::asm::delay(cycles_per_ms);
+    }
+    ready()
+}
+
+/// Update the RTC clock, once measured.
+#[cfg(any(ch32v2, ch32v3))]
+pub(crate) unsafe fn set_rtc_frequency(frequency: Hertz) {
+    CLOCKS.rtc = Some(frequency);
+}
+
 #[cfg(ch32v0)]
 #[path = "v0.rs"]
 mod rcc_impl;
@@ -54,34 +181,55 @@ mod rcc_impl;
 
 pub use rcc_impl::*;
 
+#[cfg(any(ch32v2, ch32v3))]
+pub mod css;
+
 #[cfg(not(ch32v208))]
 pub const LSI_FREQ: Hertz = Hertz(40_000);
 #[cfg(ch32v208)]
 pub const LSI_FREQ: Hertz = Hertz(32_768);
 
-#[allow(dead_code)]
-#[derive(Clone, Copy)]
+/// LSE input
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum LseMode {
-    Oscillator, // (LseDrive),
+    /// 32.768 kHz crystal on OSC32_IN and OSC32_OUT.
+    Oscillator,
+    /// External clock on OSC32_IN, e.g. from an oscillator or another chip, OSC32_OUT is free.
     Bypass,
 }
 
 pub struct LseConfig {
     pub frequency: Hertz,
     pub mode: LseMode,
+    /// Time for LSE to start, a crystal takes up to about a second.
+    pub startup_timeout_ms: u32,
+    /// If LSE doesn't start and is the RTC clock, use LSI instead, rather than leave the RTC
+    /// without clock. See [`rtc_clock_source`].
+    pub lsi_fallback: bool,
 }
 
+/// RTC clock mux
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum RtcClockSource {
     LSE,
+    /// Within ±50% of [`LSI_FREQ`], see `rtc::Rtc::calibrate`.
     LSI,
     // HSE divided by 128
     HSE,
     DISABLE,
 }
 
+/// Low-speed clocks and RTC clock, in the backup domain
+///
+/// The RTC clock can only be changed by a backup domain reset: if the RTC runs from another clock
+/// than `rtc`, including after a fallback from LSE to LSI on an earlier boot, the backup domain is
+/// reset, which clears the backup registers and the RTC counter.
 pub struct LsConfig {
     pub rtc: RtcClockSource,
+    /// Enable LSI, it must be if it is the RTC clock or for the independent watchdog. It is left
+    /// as it is otherwise.
     pub lsi: bool,
+    /// Enable LSE. It is left as it is with `None`.
     pub lse: Option<LseConfig>,
 }
 
@@ -91,7 +239,23 @@ impl LsConfig {
             rtc: RtcClockSource::LSE,
             lse: Some(LseConfig {
                 frequency: Hertz(32_768),
-                mode: LseMode::Oscillator, // (LseDrive::MediumHigh),
+                mode: LseMode::Oscillator,
+                startup_timeout_ms: 2000,
+                lsi_fallback: true,
+            }),
+            lsi: false,
+        }
+    }
+
+    /// LSE from an external 32.768 kHz clock as the RTC clock.
+    pub const fn default_lse_bypass() -> Self {
+        Self {
+            rtc: RtcClockSource::LSE,
+            lse: Some(LseConfig {
+                frequency: Hertz(32_768),
+                mode: LseMode::Bypass,
+                startup_timeout_ms: 100,
+                lsi_fallback: true,
             }),
             lsi: false,
         }
@@ -120,12 +284,198 @@ impl Default for LsConfig {
     }
 }
 
+#[cfg(any(ch32v2, ch32v3))]
 impl LsConfig {
-    pub(crate) fn init(&self) -> Option<Hertz> {
-        todo!()
+    /// Configure the backup domain, returns the RTC clock.
+    ///
+    /// The backup domain is only reset if the RTC clock differs from the config, so the RTC keeps
+    /// running across resets. If LSE doesn't start, the RTC runs from LSI with `lsi_fallback`, or
+    /// is disabled.
+    pub(crate) fn init(&self, hse: Option<Hertz>) -> Option<Hertz> {
+        use crate::pac::rcc::vals::Rtcsel;
+        use crate::pac::{PWR, RCC};
+
+        // Backup domain write access.
+        RCC.apb1pcenr().modify(|w| {
+            w.set_pwren(true);
+            w.set_bkpen(true);
+        });
+        PWR.ctlr().modify(|w| w.set_dbp(true));
+
+        // Start LSE first, its result decides the RTC clock.
+        let lse_running = match &self.lse {
+            Some(lse) => start_lse(lse),
+            None => false,
+        };
+
+        let mut rtc = self.rtc;
+        if rtc == RtcClockSource::LSE {
+            let lse = self.lse.as_ref().expect("LSE must be configured as the RTC clock");
+            if !lse_running {
+                rtc = if lse.lsi_fallback {
+                    RtcClockSource::LSI
+                } else {
+                    RtcClockSource::DISABLE
+                };
+            }
+        }
+
+        if self.lsi || rtc == RtcClockSource::LSI {
+            RCC.rstsckr().modify(|w| w.set_lsion(true));
+            while !RCC.rstsckr().read().lsirdy() {}
+        }
+
+        let (rtcsel, rtc_clk) = match rtc {
+            RtcClockSource::LSE => (Rtcsel::LSE, self.lse.as_ref().map(|lse| lse.frequency)),
+            RtcClockSource::LSI => (Rtcsel::LSI, Some(LSI_FREQ)),
+            RtcClockSource::HSE => (
+                Rtcsel::HSE,
+                Some(hse.expect("HSE must be enabled as the RTC clock") / 128u32),
+            ),
+            RtcClockSource::DISABLE => (Rtcsel::NOCLOCK, None),
+        };
+
+        let bdctlr = RCC.bdctlr().read();
+        if bdctlr.rtcsel() == rtcsel && bdctlr.rtcen() == rtc_clk.is_some() {
+            return rtc_clk;
+        }
+
+        // RTCSEL can only be changed by a backup domain reset, which stops LSE. See `LsConfig`.
+        if bdctlr.rtcsel() != rtcsel && bdctlr.rtcsel() != Rtcsel::NOCLOCK {
+            RCC.bdctlr().modify(|w| w.set_bdrst(true));
+            RCC.bdctlr().modify(|w| w.set_bdrst(false));
+            if let (true, Some(lse)) = (lse_running, &self.lse) {
+                if !start_lse(lse) && rtc == RtcClockSource::LSE {
+                    return None;
+                }
+            }
+        }
+
+        RCC.bdctlr().modify(|w| {
+            w.set_rtcsel(rtcsel);
+            w.set_rtcen(rtc_clk.is_some());
+        });
+
+        rtc_clk
+    }
+}
+
+/// Start LSE, unless it is already running in the configured mode. Returns whether it runs.
+#[cfg(any(ch32v2, ch32v3))]
+fn start_lse(lse: &LseConfig) -> bool {
+    use crate::pac::RCC;
+
+    let bypass = lse.mode == LseMode::Bypass;
+    let bdctlr = RCC.bdctlr().read();
+    if bdctlr.lseon() && bdctlr.lserdy() && bdctlr.lsebyp() == bypass {
+        return true;
+    }
+
+    RCC.bdctlr().modify(|w| {
+        w.set_lseon(false);
+        w.set_lsebyp(bypass);
+    });
+    RCC.bdctlr().modify(|w| w.set_lseon(true));
+
+    if wait_for(lse.startup_timeout_ms, clocks().sysclk, || RCC.bdctlr().read().lserdy()) {
+        return true;
+    }
+
+    RCC.bdctlr().modify(|w| w.set_lseon(false));
+    false
+}
+
+/// Source of the RTC clock, which is LSI if LSE didn't start, see [`LseConfig::lsi_fallback`].
+#[cfg(any(ch32v2, ch32v3))]
+pub fn rtc_clock_source() -> RtcClockSource {
+    use crate::pac::rcc::vals::Rtcsel;
+
+    let bdctlr = crate::pac::RCC.bdctlr().read();
+    if !bdctlr.rtcen() {
+        return RtcClockSource::DISABLE;
+    }
+    match bdctlr.rtcsel() {
+        Rtcsel::LSE => RtcClockSource::LSE,
+        Rtcsel::LSI => RtcClockSource::LSI,
+        Rtcsel::HSE => RtcClockSource::HSE,
+        _ => RtcClockSource::DISABLE,
     }
 }
 
-pub unsafe fn init(config: Config) {
-    rcc_impl::init(config);
+pub unsafe fn init(config: Config) -> Result<(), ClockError> {
+    rcc_impl::init(config)
+}
+
+/// [`init`], then `fallback` on an error.
+pub(crate) unsafe fn init_or_fallback(config: Config, fallback: ClockFallback) {
+    if let Err(error) = rcc_impl::init(config) {
+        match fallback {
+            ClockFallback::Panic => panic!("clocks didn't start: {:?}", error),
+            ClockFallback::Hsi => {
+                CLOCK_ERROR = Some(error);
+                rcc_impl::init(Config::default()).expect("HSI didn't start");
+            }
+        }
+    }
+}
+
+#[cfg(any(ch32v1, ch32l1, ch32v2, ch32v3))]
+const RECLOCK_HOOK_COUNT: usize = 8;
+
+#[cfg(any(ch32v1, ch32l1, ch32v2, ch32v3))]
+static RECLOCK_HOOKS: critical_section::Mutex<core::cell::Cell<[Option<fn(&Clocks)>; RECLOCK_HOOK_COUNT]>> =
+    critical_section::Mutex::new(core::cell::Cell::new([None; RECLOCK_HOOK_COUNT]));
+
+/// Call `hook` after each [`reclock`], with the new clocks, e.g. to set the baud rate of a UART
+/// again. Up to 8 hooks.
+#[cfg(any(ch32v1, ch32l1, ch32v2, ch32v3))]
+pub fn add_reclock_hook(hook: fn(&Clocks)) {
+    critical_section::with(|cs| {
+        let cell = RECLOCK_HOOKS.borrow(cs);
+        let mut hooks = cell.get();
+        let slot = hooks.iter_mut().find(|h| h.is_none()).expect("too many reclock hooks");
+        *slot = Some(hook);
+        cell.set(hooks);
+    })
+}
+
+/// Change the clock configuration at runtime, e.g. 8 MHz from HSI when idle and 144 MHz from the
+/// PLL for bursts of work.
+///
+/// SYSCLK runs from HSI meanwhile, so the PLL and HSE can be reconfigured, and the flash wait
+/// states are set for the new frequency before switching to it. The hooks of
+/// [`add_reclock_hook`] are called afterwards.
+///
+/// If HSE or the PLL doesn't start, the clocks are set to the default config, from HSI, and the
+/// error is returned.
+///
+/// # Safety
+///
+/// Drivers keep the dividers computed from the previous clocks, until reconfigured by a hook: their
+/// baud rates and timings scale with their bus clock meanwhile. The SysTick and timer time drivers
+/// too, `embassy-time` is only right across a reclock with the RTC time driver. The cycle counter
+/// converts its ticks at the new HCLK, see [`counter`](crate::counter).
+#[cfg(any(ch32v1, ch32l1, ch32v2, ch32v3))]
+pub unsafe fn reclock(config: Config) -> Result<(), ClockError> {
+    use crate::pac::RCC;
+
+    let result = critical_section::with(|_| {
+        RCC.ctlr().modify(|w| w.set_hsion(true));
+        while !RCC.ctlr().read().hsirdy() {}
+        RCC.cfgr0().modify(|w| w.set_sw(Sysclk::HSI));
+        while RCC.cfgr0().read().sws() != Sysclk::HSI {}
+
+        let result = rcc_impl::init(config);
+        if result.is_err() {
+            rcc_impl::init(Config::default()).expect("HSI didn't start");
+        }
+        result
+    });
+
+    let clocks = *clocks();
+    let hooks = critical_section::with(|cs| RECLOCK_HOOKS.borrow(cs).get());
+    for hook in hooks.into_iter().flatten() {
+        hook(&clocks);
+    }
+    result
 }
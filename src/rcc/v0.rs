@@ -69,6 +69,35 @@ impl Config {
     };
 }
 
+/// Presets, for a 24 MHz crystal when from HSE
+impl Config {
+    /// 48 MHz from HSI.
+    pub const fn sysclk_48mhz_hsi() -> Self {
+        Self::SYSCLK_FREQ_48MHZ_HSI
+    }
+
+    /// 24 MHz from HSI, without the PLL.
+    pub const fn sysclk_24mhz_hsi() -> Self {
+        Config {
+            hse: None,
+            sys: Sysclk::HSI,
+            pll_src: PllSource::HSI,
+            ahb_pre: AHBPrescaler::DIV1,
+            apb2_pre: APBPrescaler::DIV1,
+        }
+    }
+
+    /// 48 MHz from a 24 MHz HSE crystal.
+    pub const fn sysclk_48mhz_hse24() -> Self {
+        Self::SYSCLK_FREQ_48MHZ_HSE
+    }
+
+    /// 24 MHz from a 24 MHz HSE crystal, without the PLL.
+    pub const fn sysclk_24mhz_hse24() -> Self {
+        Self::SYSCLK_FREQ_24MHZ_HSE
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -83,7 +112,7 @@ impl Default for Config {
 }
 
 #[allow(unused_variables)]
-pub(crate) unsafe fn init(config: Config) {
+pub(crate) unsafe fn init(config: Config) -> Result<(), super::ClockError> {
     if config.sys == Sysclk::HSE || (config.sys == Sysclk::PLL && config.pll_src == PllSource::HSE) {
         // enable HSE pins
         RCC.apb2pcenr().modify(|w| w.set_afioen(true));
@@ -94,7 +123,11 @@ pub(crate) unsafe fn init(config: Config) {
             w.set_hsebyp(config.hse.unwrap().mode == HseMode::Bypass);
             w.set_hseon(true);
         });
-        while !RCC.ctlr().read().hserdy() {}
+        if !super::wait_for(super::HSE_TIMEOUT_MS, HSI_FREQUENCY, || RCC.ctlr().read().hserdy()) {
+            RCC.ctlr().modify(|w| w.set_hseon(false));
+            AFIO.pcfr1().modify(|w| w.set_pa12_rm(false));
+            return Err(super::ClockError::HseTimeout);
+        }
     }
 
     let sysclk = match config.sys {
@@ -111,7 +144,10 @@ pub(crate) unsafe fn init(config: Config) {
         Sysclk::PLL => {
             RCC.cfgr0().modify(|w| w.set_pllsrc(config.pll_src));
             RCC.ctlr().modify(|w| w.set_pllon(true));
-            while !RCC.ctlr().read().pllrdy() {}
+            if !super::wait_for(super::PLL_TIMEOUT_MS, HSI_FREQUENCY, || RCC.ctlr().read().pllrdy()) {
+                RCC.ctlr().modify(|w| w.set_pllon(false));
+                return Err(super::ClockError::PllLockTimeout);
+            }
 
             RCC.cfgr0().modify(|w| w.set_sw(Sysclk::PLL));
             while RCC.cfgr0().read().sws() != Sysclk::PLL {}
@@ -162,6 +198,11 @@ pub(crate) unsafe fn init(config: Config) {
 
     super::CLOCKS.pclk1_tim = Hertz(sysclk);
     super::CLOCKS.pclk2_tim = Hertz(sysclk);
+
+    // APB2 is only the ADC clock, ADCPRE is left at its reset value, divided by 2.
+    super::CLOCKS.adc = pclk2 / 2u32;
+
+    Ok(())
 }
 
 impl ops::Div<APBPrescaler> for Hertz {
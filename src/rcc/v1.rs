@@ -8,8 +8,9 @@
 
 use core::ops;
 
-use crate::pac::rcc::vals::{
-    Hpre as AHBPrescaler, PllMul, Pllsrc as PllSource, Ppre as APBPrescaler, Sw as Sysclk, Usbpre,
+pub use crate::pac::rcc::vals::{
+    Adcpre as ADCPrescaler, Hpre as AHBPrescaler, PllMul, Pllsrc as PllSource, Ppre as APBPrescaler, Sw as Sysclk,
+    Usbpre,
 };
 use crate::pac::{EXTEND, FLASH, RCC};
 use crate::time::Hertz;
@@ -17,6 +18,13 @@ use crate::time::Hertz;
 pub const HSI_FREQUENCY: Hertz = Hertz(8_000_000);
 pub const LSI_FREQUENCY: Hertz = Hertz(40_000);
 
+/// Highest SYSCLK frequency, without overclocking.
+#[cfg(ch32v1)]
+pub const SYSCLK_MAX: Hertz = Hertz(80_000_000);
+/// Highest SYSCLK frequency, without overclocking.
+#[cfg(ch32l1)]
+pub const SYSCLK_MAX: Hertz = Hertz(96_000_000);
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum HseMode {
     /// crystal/ceramic oscillator (HSEBYP=0)
@@ -60,6 +68,11 @@ pub struct Config {
     pub ahb_pre: AHBPrescaler,
     pub apb1_pre: APBPrescaler,
     pub apb2_pre: APBPrescaler,
+    /// ADC clock prescaler, from PCLK2. `None` picks the lowest one that keeps the ADC clock within
+    /// [`adc::MAX_FREQUENCY`](crate::adc::MAX_FREQUENCY), or divides by 8.
+    pub adc_pre: Option<ADCPrescaler>,
+    /// USB clock prescaler, from the PLL, for 48 MHz. `None` picks it from the PLL frequency.
+    pub usb_pre: Option<Usbpre>,
 }
 
 impl Config {
@@ -77,7 +90,10 @@ impl Config {
         ahb_pre: AHBPrescaler::DIV1,
         apb1_pre: APBPrescaler::DIV1,
         apb2_pre: APBPrescaler::DIV1,
-    };
+        adc_pre: None,
+        usb_pre: None,
+    }
+    .checked();
     pub const SYSCLK_FREQ_72MHZ_HSE: Config = Self {
         hse: Some(Hse {
             freq: Hertz(8_000_000),
@@ -92,7 +108,10 @@ impl Config {
         ahb_pre: AHBPrescaler::DIV1,
         apb1_pre: APBPrescaler::DIV2,
         apb2_pre: APBPrescaler::DIV2,
-    };
+        adc_pre: None,
+        usb_pre: None,
+    }
+    .checked();
     pub const SYSCLK_FREQ_96MHZ_HSE: Config = Self {
         hse: Some(Hse {
             freq: Hertz(8_000_000),
@@ -107,7 +126,48 @@ impl Config {
         ahb_pre: AHBPrescaler::DIV1,
         apb1_pre: APBPrescaler::DIV2,
         apb2_pre: APBPrescaler::DIV2,
-    };
+        adc_pre: None,
+        usb_pre: None,
+    }
+    .checked();
+}
+
+/// Presets, with the flash latency for their SYSCLK set by `init`
+impl Config {
+    /// 96 MHz from an 8 MHz HSE crystal, USB at 48 MHz.
+    #[cfg(ch32l1)]
+    pub const fn sysclk_96mhz_hse8() -> Self {
+        Self::SYSCLK_FREQ_96MHZ_HSE
+    }
+
+    /// 72 MHz from an 8 MHz HSE crystal, USB at 48 MHz.
+    pub const fn sysclk_72mhz_hse8() -> Self {
+        Self::SYSCLK_FREQ_72MHZ_HSE
+    }
+
+    /// 48 MHz from an 8 MHz HSE crystal, USB at 48 MHz.
+    pub const fn sysclk_48mhz_hse8() -> Self {
+        Self::SYSCLK_FREQ_48MHZ_HSE
+    }
+
+    /// 48 MHz from HSI, USB at 48 MHz.
+    pub const fn sysclk_48mhz_hsi() -> Self {
+        Self {
+            hse: None,
+            sys: Sysclk::PLL,
+            pll_src: PllSource::HSI,
+            pll: Some(Pll {
+                prediv: PllPreDiv::DIV1,
+                mul: PllMul::MUL6,
+            }),
+            ahb_pre: AHBPrescaler::DIV1,
+            apb1_pre: APBPrescaler::DIV1,
+            apb2_pre: APBPrescaler::DIV1,
+            adc_pre: None,
+            usb_pre: None,
+        }
+        .checked()
+    }
 }
 
 impl Default for Config {
@@ -121,12 +181,51 @@ impl Default for Config {
             ahb_pre: AHBPrescaler::DIV1,
             apb1_pre: APBPrescaler::DIV1,
             apb2_pre: APBPrescaler::DIV1,
+            adc_pre: None,
+            usb_pre: None,
+        }
+    }
+}
+
+impl Config {
+    /// SYSCLK frequency of the config.
+    pub const fn sysclk(&self) -> Hertz {
+        match self.sys {
+            Sysclk::HSI => HSI_FREQUENCY,
+            Sysclk::HSE => match &self.hse {
+                Some(hse) => hse.freq,
+                None => panic!("HSE is SYSCLK but isn't configured"),
+            },
+            Sysclk::PLL => match &self.pll {
+                Some(pll) => {
+                    let src = match self.pll_src {
+                        PllSource::HSI => HSI_FREQUENCY,
+                        PllSource::HSE => match &self.hse {
+                            Some(hse) => hse.freq,
+                            None => panic!("HSE is the PLL source but isn't configured"),
+                        },
+                    };
+                    pll_mul(Hertz(src.0 / pll.prediv as u32), pll.mul)
+                }
+                None => panic!("PLL is SYSCLK but isn't configured"),
+            },
+            _ => panic!("invalid SYSCLK source"),
         }
     }
+
+    /// Check the config, panics if it is invalid. For a `const` config, it is checked at compile
+    /// time.
+    pub const fn checked(self) -> Self {
+        let sysclk = self.sysclk();
+        assert!(sysclk.0 <= SYSCLK_MAX.0, "SYSCLK above its maximum");
+        self
+    }
 }
 
 #[allow(unused_variables)]
-pub(crate) unsafe fn init(config: Config) {
+pub(crate) unsafe fn init(config: Config) -> Result<(), super::ClockError> {
+    let config = config.checked();
+
     // Configure HSI
     while !RCC.ctlr().read().hsirdy() {}
     let hsi = Some(HSI_FREQUENCY);
@@ -140,13 +239,17 @@ pub(crate) unsafe fn init(config: Config) {
         Some(hse) => {
             RCC.ctlr().modify(|w| w.set_hsebyp(hse.mode != HseMode::Oscillator));
             RCC.ctlr().modify(|w| w.set_hseon(true));
-            while !RCC.ctlr().read().hserdy() {}
+            if !super::wait_for(super::HSE_TIMEOUT_MS, HSI_FREQUENCY, || RCC.ctlr().read().hserdy()) {
+                RCC.ctlr().modify(|w| w.set_hseon(false));
+                return Err(super::ClockError::HseTimeout);
+            }
             Some(hse.freq)
         }
     };
 
     // Configure PLLs.
     // Configure PLL
+    let mut usb_clk = None;
     let pll_clk = {
         // Disable PLL
         RCC.ctlr().modify(|w| w.set_pllon(false));
@@ -161,8 +264,9 @@ pub(crate) unsafe fn init(config: Config) {
                 let vco_freq = in_freq * pll.mul;
 
                 // Usb clock must be 48MHz
-                if let Some(usb_pre) = calc_usbpre(vco_freq) {
+                if let Some(usb_pre) = config.usb_pre.or(calc_usbpre(vco_freq)) {
                     RCC.cfgr0().modify(|w| w.set_usbpre(usb_pre));
+                    usb_clk = Some(calc_usb_clk(vco_freq, usb_pre));
                 }
 
                 RCC.cfgr0().modify(|w| w.set_pllmul(pll.mul));
@@ -186,7 +290,10 @@ pub(crate) unsafe fn init(config: Config) {
 
                 // Enable PLL
                 RCC.ctlr().modify(|w| w.set_pllon(true));
-                while !RCC.ctlr().read().pllrdy() {}
+                if !super::wait_for(super::PLL_TIMEOUT_MS, HSI_FREQUENCY, || RCC.ctlr().read().pllrdy()) {
+                    RCC.ctlr().modify(|w| w.set_pllon(false));
+                    return Err(super::ClockError::PllLockTimeout);
+                }
                 Some(vco_freq)
             }
         }
@@ -219,11 +326,13 @@ pub(crate) unsafe fn init(config: Config) {
         _ => FLASH.actlr().modify(|w| w.set_latency(2)),
     }
 
+    let adc_pre = config.adc_pre.unwrap_or_else(|| calc_adcpre(pclk2));
     RCC.cfgr0().modify(|w| {
         w.set_sw(config.sys);
         w.set_hpre(config.ahb_pre);
         w.set_ppre1(config.apb1_pre);
         w.set_ppre2(config.apb2_pre);
+        w.set_adcpre(adc_pre);
     });
     while RCC.cfgr0().read().sws() != config.sys {}
 
@@ -234,6 +343,11 @@ pub(crate) unsafe fn init(config: Config) {
 
     super::CLOCKS.pclk1_tim = pclk1_tim;
     super::CLOCKS.pclk2_tim = pclk2_tim;
+
+    super::CLOCKS.adc = pclk2 / adc_pre;
+    super::CLOCKS.usb = usb_clk;
+
+    Ok(())
 }
 
 fn calc_pclk<D>(hclk: Hertz, ppre: D) -> (Hertz, Hertz)
@@ -245,6 +359,13 @@ where
     (pclk, pclk_tim)
 }
 
+fn calc_adcpre(pclk2: Hertz) -> ADCPrescaler {
+    [ADCPrescaler::DIV2, ADCPrescaler::DIV4, ADCPrescaler::DIV6]
+        .into_iter()
+        .find(|&adc_pre| pclk2 / adc_pre <= crate::adc::MAX_FREQUENCY)
+        .unwrap_or(ADCPrescaler::DIV8)
+}
+
 fn calc_usbpre(pllclk: Hertz) -> Option<Usbpre> {
     // output 48MHz
     match pllclk.0 {
@@ -256,6 +377,17 @@ fn calc_usbpre(pllclk: Hertz) -> Option<Usbpre> {
     }
 }
 
+fn calc_usb_clk(pllclk: Hertz, usb_pre: Usbpre) -> Hertz {
+    match usb_pre {
+        Usbpre::DIV1 => pllclk,
+        Usbpre::DIV1_5 => Hertz(pllclk.0 * 2 / 3),
+        #[cfg(ch32l1)]
+        Usbpre::DIV2 => pllclk / 2u32,
+        #[allow(unreachable_patterns)]
+        _ => unreachable!(),
+    }
+}
+
 impl ops::Div<PllPreDiv> for Hertz {
     type Output = Hertz;
     fn div(self, rhs: PllPreDiv) -> Hertz {
@@ -266,19 +398,31 @@ impl ops::Div<PllPreDiv> for Hertz {
     }
 }
 
+const fn pll_mul(freq: Hertz, mul: PllMul) -> Hertz {
+    #[cfg(ch32v1)]
+    match mul {
+        PllMul::MUL16_ALT => Hertz(freq.0 * 16),
+        _ => Hertz(freq.0 * (mul as u32 + 2)),
+    }
+    #[cfg(ch32l1)]
+    match mul {
+        PllMul::MUL18 => Hertz(freq.0 * 18),
+        _ => Hertz(freq.0 * (mul as u32 + 2)),
+    }
+}
+
 impl ops::Mul<PllMul> for Hertz {
     type Output = Hertz;
     fn mul(self, rhs: PllMul) -> Hertz {
-        #[cfg(ch32v1)]
-        match rhs {
-            PllMul::MUL16_ALT => Hertz(self.0 * 16),
-            _ => Hertz(self.0 * (rhs as u32 + 2)),
-        }
-        #[cfg(ch32l1)]
-        match rhs {
-            PllMul::MUL18 => Hertz(self.0 * 18),
-            _ => Hertz(self.0 * (rhs as u32 + 2)),
-        }
+        pll_mul(self, rhs)
+    }
+}
+
+impl ops::Div<ADCPrescaler> for Hertz {
+    type Output = Hertz;
+    fn div(self, rhs: ADCPrescaler) -> Hertz {
+        // 2, 4, 6, 8
+        Hertz(self.0 / ((rhs as u32 + 1) * 2))
     }
 }
 
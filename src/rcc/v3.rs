@@ -2,12 +2,16 @@
 use core::ops;
 
 pub use crate::pac::rcc::vals::{
-    Hpre as AHBPrescaler, PllMul, PllxMul, Ppre as APBPrescaler, Prediv as PllPreDiv, Sw as Sysclk, Usbpre,
+    Adcpre as ADCPrescaler, Hpre as AHBPrescaler, PllMul, PllxMul, Ppre as APBPrescaler, Prediv as PllPreDiv,
+    Sw as Sysclk, Usbpre,
 };
 use crate::pac::{EXTEND, FLASH, RCC};
 use crate::time::Hertz;
 
-const HSI_FREQUENCY: Hertz = Hertz(8_000_000);
+pub const HSI_FREQUENCY: Hertz = Hertz(8_000_000);
+
+/// Highest SYSCLK frequency, without overclocking.
+pub const SYSCLK_MAX: Hertz = Hertz(144_000_000);
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum HseMode {
@@ -57,6 +61,8 @@ pub struct Config {
     // won't close hsi
     // pub hsi: bool,
     pub hse: Option<Hse>,
+    /// Enable the clock security system on HSE, see [`css`](super::css).
+    pub css: bool,
     pub sys: Sysclk,
 
     pub pll_src: PllSource,
@@ -68,8 +74,15 @@ pub struct Config {
     pub ahb_pre: AHBPrescaler,
     pub apb1_pre: APBPrescaler,
     pub apb2_pre: APBPrescaler,
-
-    pub ls: super::LsConfig,
+    /// ADC clock prescaler, from PCLK2. `None` picks the lowest one that keeps the ADC clock within
+    /// [`adc::MAX_FREQUENCY`](crate::adc::MAX_FREQUENCY), or divides by 8.
+    pub adc_pre: Option<ADCPrescaler>,
+    /// USB clock prescaler, from the PLL, for 48 MHz. `None` picks it from the PLL frequency.
+    pub usb_pre: Option<Usbpre>,
+
+    /// Backup domain clocks, for the RTC. `None` leaves the backup domain as it is, with the RTC
+    /// clock unknown to the HAL.
+    pub ls: Option<super::LsConfig>,
     // /// Per-peripheral kernel clock selection muxes
     // pub mux: super::mux::ClockMux,
 }
@@ -81,6 +94,7 @@ impl Config {
                 freq: Hertz(8_000_000),
                 mode: HseMode::Oscillator,
             }),
+            css: false,
             sys: Sysclk::PLL,
             pll_src: PllSource::HSE,
             pll: Some(Pll {
@@ -92,8 +106,11 @@ impl Config {
             ahb_pre: AHBPrescaler::DIV1,
             apb1_pre: APBPrescaler::DIV1,
             apb2_pre: APBPrescaler::DIV1,
-            ls: super::LsConfig::default_lsi(),
+            adc_pre: None,
+            usb_pre: None,
+            ls: None,
         }
+        .checked()
     };
     pub const SYSCLK_FREQ_144MHZ_HSE: Config = {
         Config {
@@ -101,6 +118,7 @@ impl Config {
                 freq: Hertz(8_000_000),
                 mode: HseMode::Oscillator,
             }),
+            css: false,
             sys: Sysclk::PLL,
             pll_src: PllSource::HSE,
             pll: Some(Pll {
@@ -111,12 +129,16 @@ impl Config {
             ahb_pre: AHBPrescaler::DIV1,
             apb1_pre: APBPrescaler::DIV1,
             apb2_pre: APBPrescaler::DIV1,
-            ls: super::LsConfig::default_lsi(),
+            adc_pre: None,
+            usb_pre: None,
+            ls: None,
         }
+        .checked()
     };
     pub const SYSCLK_FREQ_144MHZ_HSI: Config = {
         Config {
             hse: None,
+            css: false,
             sys: Sysclk::PLL,
             pll_src: PllSource::HSI,
             pll: Some(Pll {
@@ -127,12 +149,16 @@ impl Config {
             ahb_pre: AHBPrescaler::DIV1,
             apb1_pre: APBPrescaler::DIV1,
             apb2_pre: APBPrescaler::DIV1,
-            ls: super::LsConfig::default_lsi(),
+            adc_pre: None,
+            usb_pre: None,
+            ls: None,
         }
+        .checked()
     };
     pub const SYSCLK_FREQ_96MHZ_HSI: Config = {
         Config {
             hse: None,
+            css: false,
             sys: Sysclk::PLL,
             pll_src: PllSource::HSI,
             pll: Some(Pll {
@@ -143,16 +169,68 @@ impl Config {
             ahb_pre: AHBPrescaler::DIV1,
             apb1_pre: APBPrescaler::DIV4, // 24MHz
             apb2_pre: APBPrescaler::DIV4,
-            ls: super::LsConfig::default_lsi(),
+            adc_pre: None,
+            usb_pre: None,
+            ls: None,
         }
+        .checked()
     };
 }
 
+/// Presets, for an 8 MHz crystal when from HSE. The flash access mode for SYSCLK above 72 MHz is
+/// set by `init`.
+impl Config {
+    /// 144 MHz from an 8 MHz HSE crystal, USB at 48 MHz.
+    pub const fn sysclk_144mhz_hse8() -> Self {
+        Self::SYSCLK_FREQ_144MHZ_HSE
+    }
+
+    /// 96 MHz from an 8 MHz HSE crystal, USB at 48 MHz.
+    pub const fn sysclk_96mhz_hse8() -> Self {
+        Self::SYSCLK_FREQ_96MHZ_HSE
+    }
+
+    /// 72 MHz from an 8 MHz HSE crystal, USB at 48 MHz.
+    pub const fn sysclk_72mhz_hse8() -> Self {
+        Config {
+            pll: Some(Pll {
+                prediv: PllPreDiv::DIV1,
+                mul: PllMul::MUL9,
+            }),
+            ..Self::SYSCLK_FREQ_96MHZ_HSE
+        }
+        .checked()
+    }
+
+    /// 144 MHz from HSI, USB at 48 MHz.
+    pub const fn sysclk_144mhz_hsi() -> Self {
+        Self::SYSCLK_FREQ_144MHZ_HSI
+    }
+
+    /// 96 MHz from HSI, APB1 and APB2 at 24 MHz, USB at 48 MHz.
+    pub const fn sysclk_96mhz_hsi() -> Self {
+        Self::SYSCLK_FREQ_96MHZ_HSI
+    }
+
+    /// 48 MHz from HSI, USB at 48 MHz.
+    pub const fn sysclk_48mhz_hsi() -> Self {
+        Config {
+            pll: Some(Pll {
+                prediv: PllPreDiv::DIV1,
+                mul: PllMul::MUL6,
+            }),
+            ..Self::SYSCLK_FREQ_144MHZ_HSI
+        }
+        .checked()
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             // hsi: true,
             hse: None,
+            css: false,
             sys: Sysclk::HSI,
             pll_src: PllSource::HSI,
             pll: None,
@@ -160,18 +238,68 @@ impl Default for Config {
             ahb_pre: AHBPrescaler::DIV1,
             apb1_pre: APBPrescaler::DIV1,
             apb2_pre: APBPrescaler::DIV1,
-            ls: super::LsConfig::default(),
+            adc_pre: None,
+            usb_pre: None,
+            ls: None,
+        }
+    }
+}
+
+impl Config {
+    /// SYSCLK frequency of the config.
+    pub const fn sysclk(&self) -> Hertz {
+        match self.sys {
+            Sysclk::HSI => HSI_FREQUENCY,
+            Sysclk::HSE => match &self.hse {
+                Some(hse) => hse.freq,
+                None => panic!("HSE is SYSCLK but isn't configured"),
+            },
+            Sysclk::PLL => match &self.pll {
+                Some(pll) => {
+                    let src = match self.pll_src {
+                        PllSource::HSI => HSI_FREQUENCY,
+                        PllSource::HSE => match &self.hse {
+                            Some(hse) => hse.freq,
+                            None => panic!("HSE is the PLL source but isn't configured"),
+                        },
+                        #[cfg(d8c)]
+                        PllSource::PLL2 => panic!("PLL2 isn't supported yet"),
+                    };
+                    pll_mul(Hertz(src.0 / (pll.prediv as u32 + 1)), pll.mul)
+                }
+                None => panic!("PLL is SYSCLK but isn't configured"),
+            },
+            _ => panic!("invalid SYSCLK source"),
         }
     }
+
+    /// Check the config, panics if it is invalid. For a `const` config, it is checked at compile
+    /// time.
+    pub const fn checked(self) -> Self {
+        let sysclk = self.sysclk();
+        assert!(sysclk.0 <= SYSCLK_MAX.0, "SYSCLK above its maximum");
+
+        if let (PllSource::HSI, Some(pll)) = (self.pll_src, &self.pll) {
+            assert!(
+                matches!(pll.prediv, PllPreDiv::DIV1 | PllPreDiv::DIV2),
+                "HSI as the PLL source is only divided by 1 or 2"
+            );
+        }
+
+        self
+    }
 }
 
 #[allow(unused_variables)]
-pub(crate) unsafe fn init(config: Config) {
+pub(crate) unsafe fn init(config: Config) -> Result<(), super::ClockError> {
+    let config = config.checked();
+
     // Configure HSI
     while !RCC.ctlr().read().hsirdy() {}
     let hsi = Some(HSI_FREQUENCY);
 
-    // Configure HSE
+    // Configure HSE, without CSS meanwhile
+    RCC.ctlr().modify(|w| w.set_csson(false));
     let hse = match config.hse {
         None => {
             RCC.ctlr().modify(|w| w.set_hseon(false));
@@ -180,10 +308,14 @@ pub(crate) unsafe fn init(config: Config) {
         Some(hse) => {
             RCC.ctlr().modify(|w| w.set_hsebyp(hse.mode != HseMode::Oscillator));
             RCC.ctlr().modify(|w| w.set_hseon(true));
-            while !RCC.ctlr().read().hserdy() {}
+            if !super::wait_for(super::HSE_TIMEOUT_MS, HSI_FREQUENCY, || RCC.ctlr().read().hserdy()) {
+                RCC.ctlr().modify(|w| w.set_hseon(false));
+                return Err(super::ClockError::HseTimeout);
+            }
             Some(hse.freq)
         }
     };
+    RCC.ctlr().modify(|w| w.set_csson(config.css && hse.is_some()));
 
     // Configure PLLs.
     //
@@ -195,6 +327,7 @@ pub(crate) unsafe fn init(config: Config) {
         (None, None)
     };
     // Configure PLL
+    let mut usb_clk = None;
     let pll_clk = {
         // Disable PLL
         RCC.ctlr().modify(|w| w.set_pllon(false));
@@ -209,7 +342,8 @@ pub(crate) unsafe fn init(config: Config) {
             let vco_freq = in_freq * pll.mul;
 
             // Usb clock must be 48MHz
-            let usb_pre = calc_usbpre(vco_freq);
+            let usb_pre = config.usb_pre.or(calc_usbpre(vco_freq));
+            usb_clk = usb_pre.map(|usb_pre| calc_usb_clk(vco_freq, usb_pre));
             if let Some(usb_pre) = usb_pre {
                 RCC.cfgr0().modify(|w| w.set_usbpre(usb_pre));
             }
@@ -281,7 +415,10 @@ pub(crate) unsafe fn init(config: Config) {
 
             // Enable PLL
             RCC.ctlr().modify(|w| w.set_pllon(true));
-            while !RCC.ctlr().read().pllrdy() {}
+            if !super::wait_for(super::PLL_TIMEOUT_MS, HSI_FREQUENCY, || RCC.ctlr().read().pllrdy()) {
+                RCC.ctlr().modify(|w| w.set_pllon(false));
+                return Err(super::ClockError::PllLockTimeout);
+            }
 
             Some(vco_freq)
         } else {
@@ -307,11 +444,13 @@ pub(crate) unsafe fn init(config: Config) {
         w.set_enhancemode(true);
     });
 
+    let adc_pre = config.adc_pre.unwrap_or_else(|| calc_adcpre(pclk2));
     RCC.cfgr0().modify(|w| {
         w.set_sw(config.sys);
         w.set_hpre(config.ahb_pre);
         w.set_ppre1(config.apb1_pre);
         w.set_ppre2(config.apb2_pre);
+        w.set_adcpre(adc_pre);
     });
     while RCC.cfgr0().read().sws() != config.sys {}
 
@@ -322,9 +461,16 @@ pub(crate) unsafe fn init(config: Config) {
 
     super::CLOCKS.pclk1_tim = pclk1_tim;
     super::CLOCKS.pclk2_tim = pclk2_tim;
+
+    super::CLOCKS.adc = pclk2 / adc_pre;
+    super::CLOCKS.usb = usb_clk;
+
+    super::CLOCKS.rtc = config.ls.as_ref().and_then(|ls| ls.init(hse));
+
+    Ok(())
 }
 
-fn calc_pclk<D>(hclk: Hertz, ppre: D) -> (Hertz, Hertz)
+pub(super) fn calc_pclk<D>(hclk: Hertz, ppre: D) -> (Hertz, Hertz)
 where
     Hertz: ops::Div<D, Output = Hertz>,
 {
@@ -333,6 +479,13 @@ where
     (pclk, pclk_tim)
 }
 
+fn calc_adcpre(pclk2: Hertz) -> ADCPrescaler {
+    [ADCPrescaler::DIV2, ADCPrescaler::DIV4, ADCPrescaler::DIV6]
+        .into_iter()
+        .find(|&adc_pre| pclk2 / adc_pre <= crate::adc::MAX_FREQUENCY)
+        .unwrap_or(ADCPrescaler::DIV8)
+}
+
 fn calc_usbpre(pllclk: Hertz) -> Option<Usbpre> {
     // output 48MHz
     match pllclk.0 {
@@ -345,6 +498,18 @@ fn calc_usbpre(pllclk: Hertz) -> Option<Usbpre> {
     }
 }
 
+fn calc_usb_clk(pllclk: Hertz, usb_pre: Usbpre) -> Hertz {
+    match usb_pre {
+        Usbpre::DIV1 => pllclk,
+        Usbpre::DIV2 => pllclk / 2u32,
+        Usbpre::DIV3 => pllclk / 3u32,
+        #[cfg(d8w)]
+        Usbpre::DIV5 => pllclk / 5u32,
+        #[allow(unreachable_patterns)]
+        _ => unreachable!(),
+    }
+}
+
 impl ops::Div<PllPreDiv> for Hertz {
     type Output = Hertz;
     fn div(self, rhs: PllPreDiv) -> Hertz {
@@ -352,18 +517,30 @@ impl ops::Div<PllPreDiv> for Hertz {
     }
 }
 
+const fn pll_mul(freq: Hertz, mul: PllMul) -> Hertz {
+    match mul {
+        PllMul::MUL15 => Hertz(freq.0 * 15),
+        PllMul::MUL16 => Hertz(freq.0 * 16),
+        PllMul::MUL18 => Hertz(freq.0 * 18),
+        #[cfg(d8c)]
+        PllMul::MUL6_5 => Hertz(freq.0 * 13 / 2),
+        // All the others are covered by this case
+        _ => Hertz(freq.0 * (mul as u32 + 2)),
+    }
+}
+
 impl ops::Mul<PllMul> for Hertz {
     type Output = Hertz;
     fn mul(self, rhs: PllMul) -> Hertz {
-        match rhs {
-            PllMul::MUL15 => Hertz(self.0 * 15),
-            PllMul::MUL16 => Hertz(self.0 * 16),
-            PllMul::MUL18 => Hertz(self.0 * 18),
-            #[cfg(d8c)]
-            PllMul::MUL6_5 => Hertz(self.0 * 13 / 2),
-            // All the others are covered by this case
-            _ => Hertz(self.0 * (rhs as u32 + 2)),
-        }
+        pll_mul(self, rhs)
+    }
+}
+
+impl ops::Div<ADCPrescaler> for Hertz {
+    type Output = Hertz;
+    fn div(self, rhs: ADCPrescaler) -> Hertz {
+        // 2, 4, 6, 8
+        Hertz(self.0 / ((rhs as u32 + 1) * 2))
     }
 }
 
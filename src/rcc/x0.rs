@@ -41,8 +41,31 @@ impl Config {
     };
 }
 
+/// Presets, HSI divided down. `init` sets the flash latency to match.
+impl Config {
+    /// 48 MHz, for USB.
+    pub const fn sysclk_48mhz_hsi() -> Self {
+        Self::SYSCLK_FREQ_48MHZ_HSI
+    }
+
+    /// 24 MHz.
+    pub const fn sysclk_24mhz_hsi() -> Self {
+        Self::SYSCLK_FREQ_24MHZ_HSI
+    }
+
+    /// 16 MHz.
+    pub const fn sysclk_16mhz_hsi() -> Self {
+        Self::SYSCLK_FREQ_16MHZ_HSI
+    }
+
+    /// 12 MHz, without flash wait states.
+    pub const fn sysclk_12mhz_hsi() -> Self {
+        Self::SYSCLK_FREQ_12MHZ_HSI
+    }
+}
+
 #[allow(unused_variables)]
-pub(crate) unsafe fn init(config: Config) {
+pub(crate) unsafe fn init(config: Config) -> Result<(), super::ClockError> {
     RCC.ctlr().modify(|w| w.set_hsion(true));
     //while !RCC.ctlr().read().hsirdy() {}
 
@@ -81,6 +104,13 @@ pub(crate) unsafe fn init(config: Config) {
 
     super::CLOCKS.pclk1_tim = hclk;
     super::CLOCKS.pclk2_tim = hclk;
+
+    // Divided by the ADC itself.
+    super::CLOCKS.adc = hclk;
+    // The USB transceiver runs from HSI directly.
+    super::CLOCKS.usb = Some(HSI_FREQUENCY);
+
+    Ok(())
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
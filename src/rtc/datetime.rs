@@ -0,0 +1,161 @@
+//! Calendar date and time, without time zones
+
+/// Errors of [`DateTime::from`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The year is out of the range of the counter, 1970 to 2105.
+    InvalidYear,
+    InvalidMonth,
+    InvalidDay,
+    InvalidHour,
+    InvalidMinute,
+    InvalidSecond,
+}
+
+/// Day of the week
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DayOfWeek {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+/// Date and time, to the second
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DateTime {
+    year: u16,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+}
+
+const MIN_YEAR: u16 = 1970;
+/// The 32-bit counter overflows in February 2106.
+const MAX_YEAR: u16 = 2105;
+
+const SECONDS_PER_DAY: u32 = 86_400;
+
+fn is_leap_year(year: u16) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl DateTime {
+    /// `month` and `day` start at 1.
+    pub fn from(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Result<Self, Error> {
+        if !(MIN_YEAR..=MAX_YEAR).contains(&year) {
+            Err(Error::InvalidYear)
+        } else if !(1..=12).contains(&month) {
+            Err(Error::InvalidMonth)
+        } else if day < 1 || day > days_in_month(year, month) {
+            Err(Error::InvalidDay)
+        } else if hour > 23 {
+            Err(Error::InvalidHour)
+        } else if minute > 59 {
+            Err(Error::InvalidMinute)
+        } else if second > 59 {
+            Err(Error::InvalidSecond)
+        } else {
+            Ok(Self {
+                year,
+                month,
+                day,
+                hour,
+                minute,
+                second,
+            })
+        }
+    }
+
+    /// Date and time `secs` seconds after 1970-01-01 00:00:00.
+    pub fn from_unix_seconds(secs: u32) -> Self {
+        // Days from civil, by Howard Hinnant, shifted so years start on March 1st.
+        let days = secs / SECONDS_PER_DAY + 719_468;
+        let era = days / 146_097;
+        let doe = days % 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + u32::from(month <= 2);
+
+        let secs = secs % SECONDS_PER_DAY;
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+        }
+    }
+
+    /// Seconds since 1970-01-01 00:00:00.
+    pub fn to_unix_seconds(&self) -> u32 {
+        let year = u32::from(self.year) - u32::from(self.month <= 2);
+        let month = u32::from(self.month);
+        let era = year / 400;
+        let yoe = year % 400;
+        let mp = if month > 2 { month - 3 } else { month + 9 };
+        let doy = (153 * mp + 2) / 5 + u32::from(self.day) - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+
+        days * SECONDS_PER_DAY + u32::from(self.hour) * 3600 + u32::from(self.minute) * 60 + u32::from(self.second)
+    }
+
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+
+    pub fn hour(&self) -> u8 {
+        self.hour
+    }
+
+    pub fn minute(&self) -> u8 {
+        self.minute
+    }
+
+    pub fn second(&self) -> u8 {
+        self.second
+    }
+
+    pub fn day_of_week(&self) -> DayOfWeek {
+        // 1970-01-01 was a Thursday.
+        match (self.to_unix_seconds() / SECONDS_PER_DAY + 3) % 7 {
+            0 => DayOfWeek::Monday,
+            1 => DayOfWeek::Tuesday,
+            2 => DayOfWeek::Wednesday,
+            3 => DayOfWeek::Thursday,
+            4 => DayOfWeek::Friday,
+            5 => DayOfWeek::Saturday,
+            _ => DayOfWeek::Sunday,
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! Real-time clock (RTC)
+//!
+//! The RTC is a 32-bit seconds counter in the backup domain, it keeps counting across resets and,
+//! with a battery on VBAT, power cycles. [`Rtc`] counts the seconds since 1970-01-01 00:00:00, so
+//! it reads and writes a [`DateTime`] until 2106.
+//!
+//! Its clock is selected by [`rcc::LsConfig`](crate::rcc::LsConfig) in `rcc::Config::ls`, which
+//! is `None` by default: LSE, LSI or HSE divided by 128, the prescaler divides it down to 1 Hz.
+//! LSI is only accurate to tens of percent, it should be measured with [`Rtc::calibrate`].
+//!
+//! With LSE, or HSE, as its clock, the RTC is a reference for HSI: [`Rtc::trim_hsi`] measures it
+//! and trims it, for USB or UARTs on boards without a crystal for HSI.
+//!
+//! Reads have sub-second resolution through the prescaler divider, see [`Rtc::counter_micros`],
+//! and [`Rtc::adjust`] slews the rate by a few ppm, to follow an external reference without
+//! stepping the time.
+//!
+//! With the `time-driver-rtc` feature the RTC counts for `embassy-time` instead, [`Rtc`] can't be
+//! used then.
+mod datetime;
+
+pub use self::datetime::{DateTime, DayOfWeek, Error as DateTimeError};
+use crate::pac::rtc::Rtc as Regs;
+use crate::rcc::RtcClockSource;
+use crate::time::Hertz;
+use crate::{into_ref, peripherals, Peripheral, PeripheralRef};
+
+/// RTC errors
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RtcError {
+    /// The RTC has no clock, see [`rcc::LsConfig`](crate::rcc::LsConfig).
+    NotRunning,
+    /// LSI is the RTC clock, it can't be a reference.
+    NoReference,
+    /// SYSCLK doesn't run from HSI, so it can't be measured.
+    NotFromHsi,
+}
+
+/// Real-time clock driver
+pub struct Rtc<'d> {
+    _peri: PeripheralRef<'d, peripherals::RTC>,
+    frequency: Hertz,
+    /// RTC clock cycles per second, as programmed.
+    prescaler: u32,
+    /// Rate adjustment.
+    ppm: i32,
+}
+
+/// Clock pulses dropped every 2^20 by the calibration register, at most 127.
+const CALIBRATION_PERIOD: u64 = 1 << 20;
+const CALIBRATION_MAX: u64 = 0x7F;
+
+/// HSITRIM is 5 bits, centered on 16.
+const HSITRIM_MAX: u8 = 0x1F;
+/// Approximate HSI change per HSITRIM step.
+const HSITRIM_STEP: Hertz = Hertz(40_000);
+
+impl<'d> Rtc<'d> {
+    /// Create the RTC driver, the counter is left as is.
+    ///
+    /// Panics if the RTC has no clock, e.g. `rcc::Config::ls` is `None`.
+    pub fn new(rtc: impl Peripheral<P = peripherals::RTC> + 'd) -> Self {
+        into_ref!(rtc);
+
+        let frequency = crate::rcc::clocks().rtc.expect("RTC clock not enabled");
+
+        let mut this = Self {
+            _peri: rtc,
+            frequency,
+            prescaler: frequency.0,
+            ppm: 0,
+        };
+
+        // The registers are read through the APB1 interface, resynchronized after a reset.
+        let regs = this.regs();
+        regs.ctlrl().modify(|w| w.set_rsf(false));
+        while !regs.ctlrl().read().rsf() {}
+
+        this.set_rate();
+
+        this
+    }
+
+    /// Program the prescaler and calibration for `frequency` and `ppm`.
+    ///
+    /// The prescaler, an integer, is rounded so the clock runs fast, and the calibration register
+    /// drops clock pulses to slow it back, by steps of about 1 ppm.
+    fn set_rate(&mut self) {
+        // Clock cycles per second, as a fraction.
+        let num = u64::from(self.frequency.0) * 1_000_000;
+        let den = (1_000_000 + i64::from(self.ppm)) as u64;
+
+        let prescaler = (num / den) as u32;
+        let calibration = ((num % den) * CALIBRATION_PERIOD + num / 2) / num;
+
+        self.prescaler = prescaler;
+        let reload = prescaler - 1;
+        self.configure(|regs| {
+            regs.pscrh().write(|w| w.0 = (reload >> 16) & 0x0F);
+            regs.pscrl().write(|w| w.0 = reload & 0xFFFF);
+        });
+        crate::pac::BKP
+            .octlr()
+            .modify(|w| w.set_cal(calibration.min(CALIBRATION_MAX) as u8));
+    }
+
+    /// Run the clock faster by `ppm` parts per million, or slower if negative, within ±10000.
+    ///
+    /// Replaces the previous adjustment. The time isn't stepped: to correct an offset of `t`
+    /// seconds, adjust by `t / d * 1e6` ppm for `d` seconds, then back to the drift rate.
+    pub fn adjust(&mut self, ppm: i32) {
+        assert!((-10_000..=10_000).contains(&ppm));
+        self.ppm = ppm;
+        self.set_rate();
+    }
+
+    /// Current rate adjustment, in ppm.
+    pub fn adjustment(&self) -> i32 {
+        self.ppm
+    }
+
+    /// Source of the RTC clock, see [`rcc::rtc_clock_source`](crate::rcc::rtc_clock_source).
+    pub fn clock_source(&self) -> RtcClockSource {
+        crate::rcc::rtc_clock_source()
+    }
+
+    /// Measure the RTC clock over `seconds` RTC seconds, against HCLK, and trim the prescaler to
+    /// it. Returns the measured frequency.
+    ///
+    /// Meant for LSI: with HSE as the system clock, the RTC is then within ±20 ppm plus the HSE
+    /// tolerance, until the temperature or voltage drift. The counter doesn't stop meanwhile, and
+    /// [`crate::rcc::clocks`] reports the measured frequency afterwards.
+    pub fn calibrate(&mut self, seconds: u32) -> Hertz {
+        let (rtc_cycles, cycles) = self.measure(seconds);
+
+        // RTC clock cycles over counter cycles, rounded.
+        let counter_hz = u64::from(crate::counter::frequency().0);
+        let measured = ((rtc_cycles * counter_hz + cycles / 2) / cycles) as u32;
+
+        self.frequency = Hertz(measured);
+        self.set_rate();
+        unsafe { crate::rcc::set_rtc_frequency(self.frequency) };
+
+        self.frequency
+    }
+
+    /// RTC clock cycles and counter cycles over `seconds` RTC seconds.
+    fn measure(&mut self, seconds: u32) -> (u64, u64) {
+        assert!(seconds > 0);
+        let regs = self.regs();
+
+        // Measured without adjustment, no pulses dropped.
+        let ppm = self.ppm;
+        self.ppm = 0;
+        self.set_rate();
+
+        // Start on a second boundary.
+        regs.ctlrl().modify(|w| w.set_secf(false));
+        while !regs.ctlrl().read().secf() {}
+        let start = crate::counter::cycle_counter();
+
+        for _ in 0..seconds {
+            regs.ctlrl().modify(|w| w.set_secf(false));
+            while !regs.ctlrl().read().secf() {}
+        }
+        let cycles = crate::counter::cycle_counter() - start;
+
+        self.ppm = ppm;
+        self.set_rate();
+
+        (u64::from(self.prescaler) * u64::from(seconds), cycles)
+    }
+
+    /// Measure HSI against the RTC clock, LSE or HSE, over `seconds` RTC seconds per step, and
+    /// trim it to its nominal frequency. Returns the frequency measured last.
+    ///
+    /// SYSCLK must run from HSI, directly or through the PLL. It takes a few steps, the trim being
+    /// about 0.5% per step: called again every so often, it follows the temperature.
+    pub fn trim_hsi(&mut self, seconds: u32) -> Result<Hertz, RtcError> {
+        use crate::pac::rcc::vals::Sw;
+        use crate::pac::RCC;
+
+        if !self.is_running() {
+            return Err(RtcError::NotRunning);
+        }
+        if self.clock_source() == RtcClockSource::LSI {
+            return Err(RtcError::NoReference);
+        }
+        let cfgr0 = RCC.cfgr0().read();
+        let from_hsi = match cfgr0.sws() {
+            Sw::HSI => true,
+            Sw::PLL => !cfgr0.pllsrc(),
+            _ => false,
+        };
+        if !from_hsi {
+            return Err(RtcError::NotFromHsi);
+        }
+
+        let nominal = i64::from(crate::rcc::HSI_FREQUENCY.0);
+        let mut hsi = nominal;
+        for _ in 0..4 {
+            let (rtc_cycles, cycles) = self.measure(seconds);
+
+            // Counter cycles expected from a nominal HSI, over the ones counted.
+            let counter_hz = u64::from(crate::counter::frequency().0);
+            let expected = rtc_cycles * counter_hz / u64::from(self.frequency.0);
+            hsi = (nominal as u64 * cycles / expected) as i64;
+
+            // Rounded to the closest step.
+            let error = nominal - hsi;
+            let step = i64::from(HSITRIM_STEP.0);
+            let steps = (error + error.signum() * step / 2) / step;
+            if steps == 0 {
+                break;
+            }
+            let trim = i64::from(RCC.ctlr().read().hsitrim()) + steps;
+            let trim = trim.clamp(0, i64::from(HSITRIM_MAX)) as u8;
+            if trim == RCC.ctlr().read().hsitrim() {
+                break;
+            }
+            RCC.ctlr().modify(|w| w.set_hsitrim(trim));
+        }
+
+        Ok(Hertz(hsi as u32))
+    }
+
+    fn regs(&self) -> Regs {
+        crate::pac::RTC
+    }
+
+    /// Frequency of the RTC clock.
+    pub fn frequency(&self) -> Hertz {
+        self.frequency
+    }
+
+    /// Write the configuration registers, in configuration mode.
+    fn configure(&self, f: impl FnOnce(Regs)) {
+        let regs = self.regs();
+        while !regs.ctlrl().read().rtoff() {}
+        regs.ctlrl().modify(|w| w.set_cnf(true));
+        f(regs);
+        regs.ctlrl().modify(|w| w.set_cnf(false));
+        // The write completes in the RTC clock domain.
+        while !regs.ctlrl().read().rtoff() {}
+    }
+
+    /// Seconds counter.
+    pub fn counter(&self) -> u32 {
+        let regs = self.regs();
+        // The halves are read separately, the low one may wrap in between.
+        loop {
+            let high = regs.cnth().read().0 & 0xFFFF;
+            let low = regs.cntl().read().0 & 0xFFFF;
+            if regs.cnth().read().0 & 0xFFFF == high {
+                return (high << 16) | low;
+            }
+        }
+    }
+
+    /// Prescaler divider, counts down from the prescaler to 0 every second.
+    pub fn divider(&self) -> u32 {
+        let regs = self.regs();
+        ((regs.divh().read().0 & 0x0F) << 16) | (regs.divl().read().0 & 0xFFFF)
+    }
+
+    /// Seconds counter, in microseconds.
+    pub fn counter_micros(&self) -> u64 {
+        // The divider is read between two reads of the same second.
+        let (seconds, divider) = loop {
+            let seconds = self.counter();
+            let divider = self.divider();
+            if self.counter() == seconds {
+                break (seconds, divider);
+            }
+        };
+
+        let elapsed = u64::from((self.prescaler - 1).saturating_sub(divider));
+        u64::from(seconds) * 1_000_000 + elapsed * 1_000_000 / u64::from(self.prescaler)
+    }
+
+    /// Set the seconds counter.
+    pub fn set_counter(&mut self, counter: u32) {
+        self.configure(|regs| {
+            regs.cnth().write(|w| w.0 = counter >> 16);
+            regs.cntl().write(|w| w.0 = counter & 0xFFFF);
+        });
+    }
+
+    /// Set the date and time.
+    pub fn set_datetime(&mut self, t: DateTime) -> Result<(), RtcError> {
+        if !self.is_running() {
+            return Err(RtcError::NotRunning);
+        }
+        self.set_counter(t.to_unix_seconds());
+        Ok(())
+    }
+
+    /// Current date and time.
+    pub fn now(&self) -> Result<DateTime, RtcError> {
+        if !self.is_running() {
+            return Err(RtcError::NotRunning);
+        }
+        Ok(DateTime::from_unix_seconds(self.counter()))
+    }
+
+    fn is_running(&self) -> bool {
+        crate::pac::RCC.bdctlr().read().rtcen()
+    }
+}
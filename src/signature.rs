@@ -1,17 +1,66 @@
 //! E-signature values for CH32 series MCUs
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
+const ESIG_FLACAP: *const u16 = 0x1FFFF7E0 as *const u16;
+const ESIG_UID: *const u32 = 0x1FFFF7E8 as *const u32;
+
 /// Returns the flash size in KByte
+///
+/// On CH32V30x this is the whole flash, the part running without wait states depends on the
+/// option bytes.
 pub fn flash_size_kb() -> u16 {
-    const ESIG_FLACAP: *const u16 = 0x1FFFF7E0 as *const u16;
-
     unsafe { core::ptr::read_volatile(ESIG_FLACAP) }
 }
 
 /// Returns the unique ID
 pub fn unique_id() -> [u8; 12] {
-    const ESIG_UID: *const [u8; 12] = 0x1FFFF7E8 as *const [u8; 12];
+    const ESIG_UID_BYTES: *const [u8; 12] = ESIG_UID as *const [u8; 12];
+
+    unsafe { core::ptr::read_volatile(ESIG_UID_BYTES) }
+}
+
+/// Returns the 96-bit unique ID, the three words of [`unique_id`] from the least significant.
+///
+/// Distinct for every chip, e.g. to derive per-device keys.
+pub fn uid() -> u128 {
+    (0..3).fold(0, |uid, i| {
+        let word = unsafe { core::ptr::read_volatile(ESIG_UID.add(i)) };
+        uid | (u128::from(word) << (32 * i))
+    })
+}
 
-    unsafe { core::ptr::read_volatile(ESIG_UID) }
+/// Returns the unique ID as 24 uppercase hex digits, a serial number distinct for every chip.
+///
+/// Usable as is for the USB serial number string, e.g.
+/// `config.serial_number = Some(signature::serial_number())`, which embassy-usb sends as UTF-16.
+pub fn serial_number() -> &'static str {
+    static mut SERIAL: [u8; 24] = [0; 24];
+    static INIT: AtomicBool = AtomicBool::new(false);
+
+    critical_section::with(|_| unsafe {
+        if !INIT.load(Ordering::Relaxed) {
+            SERIAL = hex_digits(&unique_id());
+            INIT.store(true, Ordering::Relaxed);
+        }
+        core::str::from_utf8_unchecked(&*core::ptr::addr_of!(SERIAL))
+    })
+}
+
+/// Returns the [`serial_number`] as UTF-16, for string descriptors built by hand.
+pub fn serial_number_utf16() -> [u16; 24] {
+    hex_digits(&unique_id()).map(u16::from)
+}
+
+fn hex_digits(id: &[u8; 12]) -> [u8; 24] {
+    const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+    let mut out = [0; 24];
+    for (chunk, byte) in out.chunks_exact_mut(2).zip(id) {
+        chunk[0] = DIGITS[(byte >> 4) as usize];
+        chunk[1] = DIGITS[(byte & 0xF) as usize];
+    }
+    out
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
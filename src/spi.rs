@@ -3,31 +3,36 @@
 //! Capabilities:
 //!
 //! - Supports full-duplex synchronous serial mode
-//! - Supports single-wire half-duplex mode
+//! - Supports single-wire half-duplex mode, see [`Spi::new_blocking_half_duplex`]
 //! - Supports master and slave modes, multiple slave modes
 //! - Supports 8-bit or 16-bit data structures
 //! - The highest clock frequency supports up to half of F_HCLK
 //! - Data order supports MSB or LSB first (CH32V003 supports MSB first only)
 //! - Supports hardware or software control of NSS pin
-//! - Transmission and reception support hardware CRC check
+//! - Transmission and reception support hardware CRC check, see [`Spi::set_crc_polynomial`]
 //! - Transmission and reception buffers support DMA transfer
 //! - Supports changing clock phase and polarity
 
 use core::marker::PhantomData;
 use core::ptr;
 
+use embassy_embedded_hal::SetConfig;
 use embassy_futures::join::join;
 use embedded_hal::spi::{Mode, Phase, Polarity, MODE_0};
 use pac::spi::vals::BaudRate;
 use pac::spi::Spi as Regs;
 
-use crate::dma::{slice_ptr_parts, word, ChannelAndRequest};
-use crate::gpio::AFType;
+use crate::dma::{slice_ptr_parts, slice_ptr_parts_mut, word, ChannelAndRequest, Transfer, TransferOptions};
+use crate::gpio::{AFType, SealedPin};
 use crate::gpio::{AnyPin, Pull, Speed};
+use crate::low_power::LowPowerPeripheral;
 use crate::mode::{Async, Blocking, Mode as PeriMode};
 use crate::time::Hertz;
 use crate::{into_ref, pac, peripherals, Peripheral, PeripheralRef};
 
+mod shared;
+pub use shared::{SharedSpi, SharedSpiDevice};
+
 /// SPI Error
 #[derive(Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -37,8 +42,10 @@ pub enum Error {
     /// CRC error (only if hardware CRC checking is enabled).
     Crc,
     /// Mode fault
+    ///
+    /// The SPI left master mode. It is restored before the next transfer.
     ModeFault,
-    /// Overrun.
+    /// Overrun, a received word was lost.
     Overrun,
 }
 
@@ -50,12 +57,39 @@ pub enum BitOrder {
     MsbFirst,
 }
 
+/// Data line direction in half-duplex (BIDIMODE) mode
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    /// The SPI drives the data line
+    Transmit,
+    /// The SPI samples the data line, and generates the clock as long as it is enabled
+    Receive,
+}
+
+/// State of the SCK and MOSI pins once the bus is released, see [`Spi::release_pins`]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum PinRelease {
+    /// Disconnect the pins, as analog inputs
+    Disconnect,
+    /// Make the pins inputs, e.g. for another master to drive them
+    Input(Pull),
+    /// Drive SCK at its idle level and MOSI low
+    DriveIdle,
+    /// Leave the pins in alternate function mode
+    KeepAlternate,
+}
+
 #[non_exhaustive]
 #[derive(Copy, Clone)]
 pub struct Config {
     pub mode: Mode,
     pub bit_order: BitOrder,
+    /// Maximum SCK frequency. The prescaler closest to it from below is used, see
+    /// [`Spi::set_frequency`].
     pub frequency: Hertz,
+    /// What SCK and MOSI do when the driver is dropped or the pins are released.
+    pub pin_release: PinRelease,
 }
 
 impl Default for Config {
@@ -64,6 +98,7 @@ impl Default for Config {
             mode: MODE_0,
             bit_order: BitOrder::MsbFirst,
             frequency: Hertz::hz(1_000_000),
+            pin_release: PinRelease::Disconnect,
         }
     }
 }
@@ -131,6 +166,7 @@ impl Config {
             mode,
             bit_order,
             frequency: spi_freq,
+            pin_release: PinRelease::Disconnect,
         }
     }
 }
@@ -145,6 +181,9 @@ pub struct Spi<'d, T: Instance, M: PeriMode> {
     rx_dma: Option<ChannelAndRequest<'d>>,
     _phantom: PhantomData<M>,
     current_word_size: word_impl::Config,
+    pin_release: PinRelease,
+    /// Configuration saved by [`LowPowerPeripheral::suspend`].
+    suspended_config: Option<Config>,
 }
 
 impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
@@ -179,7 +218,8 @@ impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
             w.set_cpha(cpha);
             w.set_mstr(true); // master
             w.set_br(div);
-            w.set_spe(true);
+            // In RX-only mode, the clock runs as long as the SPI is enabled
+            w.set_spe(mosi.is_some());
             w.set_lsbfirst(config.lsb_first());
             w.set_ssi(true);
             w.set_ssm(true);
@@ -197,11 +237,16 @@ impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
             tx_dma,
             rx_dma,
             current_word_size: <u8 as SealedWord>::CONFIG,
+            pin_release: config.pin_release,
+            suspended_config: None,
             _phantom: PhantomData,
         }
     }
 
     /// Reconfigure the SPI peripheral.
+    ///
+    /// Waits for the current frame to complete, so one bus can serve devices using different
+    /// modes, frequencies or bit orders.
     pub fn set_config(&mut self, config: &Config) -> Result<(), ()> {
         let cpha = config.raw_phase();
         let cpol = config.raw_polarity();
@@ -210,28 +255,88 @@ impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
 
         let br = calculate_baud_rate(T::frequency().0, config.frequency.0);
 
-        T::REGS.ctlr1().modify(|w| {
+        let regs = T::REGS;
+        // the clock format must not change in the middle of a frame
+        while regs.statr().read().bsy() {}
+        let spe = regs.ctlr1().read().spe();
+        regs.ctlr1().modify(|w| w.set_spe(false));
+        regs.ctlr1().modify(|w| {
             w.set_cpol(cpol);
             w.set_cpha(cpha);
             w.set_br(br);
             w.set_lsbfirst(lsbfirst);
         });
+        // keep SCK driven at the new idle level
+        regs.ctlr1().modify(|w| w.set_spe(spe));
+
+        self.pin_release = config.pin_release;
 
         Ok(())
     }
 
+    /// Set the SCK frequency, returning the frequency achieved.
+    ///
+    /// This is the highest frequency not above `frequency` that the bus clock divider can
+    /// produce, or the lowest one if `frequency` is out of reach.
+    pub fn set_frequency(&mut self, frequency: Hertz) -> Hertz {
+        let mut config = self.get_current_config();
+        config.frequency = frequency;
+        let _ = self.set_config(&config);
+
+        self.get_current_config().frequency
+    }
+
     /// Get current SPI configuration. Useful for get the current baudrate.
     pub fn get_current_config(&self) -> Config {
         let bus_freq = T::frequency();
 
-        Config::from_cfgr(&T::REGS.ctlr1().read(), bus_freq)
+        let mut config = Config::from_cfgr(&T::REGS.ctlr1().read(), bus_freq);
+        config.pin_release = self.pin_release;
+        config
+    }
+
+    /// Release the bus pins, as set by [`Config::pin_release`].
+    ///
+    /// Waits for the current frame to complete. MISO is left as an input.
+    pub fn release_pins(&mut self) {
+        while T::REGS.statr().read().bsy() {}
+
+        let idle_high = T::REGS.ctlr1().read().cpol();
+        let release = |pin: &PeripheralRef<'d, AnyPin>, idle_high: bool| match self.pin_release {
+            PinRelease::Disconnect => pin.set_as_disconnected(),
+            PinRelease::Input(pull) => pin.set_as_input(pull),
+            PinRelease::DriveIdle => {
+                if idle_high {
+                    pin.set_high();
+                } else {
+                    pin.set_low();
+                }
+                pin.set_as_output(Speed::High);
+            }
+            PinRelease::KeepAlternate => {}
+        };
+
+        self.sck.as_ref().map(|x| release(x, idle_high));
+        self.mosi.as_ref().map(|x| release(x, false));
+    }
+
+    /// Take the bus pins back after [`release_pins`](Self::release_pins).
+    pub fn reclaim_pins(&mut self) {
+        self.sck
+            .as_ref()
+            .map(|x| x.set_as_af_output(AFType::OutputPushPull, Speed::High));
+        self.mosi
+            .as_ref()
+            .map(|x| x.set_as_af_output(AFType::OutputPushPull, Speed::High));
     }
 
     fn set_word_size(&mut self, config: word_impl::Config) {
         if self.current_word_size == config {
             return;
         }
+        // DFF must only be changed while the SPI is disabled
         T::REGS.ctlr1().modify(|w| {
+            w.set_spe(false);
             w.set_dff(config == <u16 as SealedWord>::CONFIG);
         });
         self.current_word_size = config;
@@ -239,11 +344,32 @@ impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
 
     // blocking functions
 
+    /// Set the data line direction in half-duplex mode.
+    ///
+    /// [`blocking_write`](Self::blocking_write) and [`blocking_read`](Self::blocking_read) switch
+    /// the direction themselves, this allows turning the line around ahead of time, e.g. to
+    /// release it for the device before it answers.
+    pub fn set_direction(&mut self, dir: Direction) {
+        let regs = T::REGS;
+        assert!(regs.ctlr1().read().bidimode());
+
+        while regs.statr().read().bsy() {}
+        // In receive mode, the clock runs as soon as the SPI is enabled
+        regs.ctlr1().modify(|w| {
+            w.set_spe(false);
+            w.set_bidioe(dir == Direction::Transmit);
+        });
+    }
+
     /// Blocking write.
     pub fn blocking_write<W: Word>(&mut self, words: &[W]) -> Result<(), Error> {
+        if T::REGS.ctlr1().read().bidimode() {
+            return self.half_duplex_write(words);
+        }
+
+        self.set_word_size(W::CONFIG);
         T::REGS.ctlr1().modify(|w| w.set_spe(true));
         flush_rx_fifo(T::REGS);
-        self.set_word_size(W::CONFIG);
         for word in words.iter() {
             let _ = transfer_word(&T::REGS, *word)?;
         }
@@ -252,9 +378,17 @@ impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
 
     /// Blocking read.
     pub fn blocking_read<W: Word>(&mut self, words: &mut [W]) -> Result<(), Error> {
+        let cr = T::REGS.ctlr1().read();
+        if cr.bidimode() {
+            self.set_direction(Direction::Receive);
+            return self.blocking_read_clocked(words);
+        } else if cr.rxonly() {
+            return self.blocking_read_clocked(words);
+        }
+
+        self.set_word_size(W::CONFIG);
         T::REGS.ctlr1().modify(|w| w.set_spe(true));
         flush_rx_fifo(T::REGS);
-        self.set_word_size(W::CONFIG);
         for word in words.iter_mut() {
             *word = transfer_word(&T::REGS, W::default())?;
         }
@@ -265,9 +399,9 @@ impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
     ///
     /// This writes the contents of `data` on MOSI, and puts the received data on MISO in `data`, at the same time.
     pub fn blocking_transfer_in_place<W: Word>(&mut self, words: &mut [W]) -> Result<(), Error> {
+        self.set_word_size(W::CONFIG);
         T::REGS.ctlr1().modify(|w| w.set_spe(true));
         flush_rx_fifo(T::REGS);
-        self.set_word_size(W::CONFIG);
         for word in words.iter_mut() {
             *word = transfer_word(&T::REGS, *word)?;
         }
@@ -278,12 +412,12 @@ impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
     ///
     /// This transfers both buffers at the same time, so it is NOT equivalent to `write` followed by `read`.
     ///
-    /// The transfer runs for `max(read.len(), write.len())` bytes. If `read` is shorter extra bytes are ignored.
-    /// If `write` is shorter it is padded with zero bytes.
+    /// The transfer runs for `max(read.len(), write.len())` words. If `read` is shorter extra words are ignored.
+    /// If `write` is shorter it is padded with zero words.
     pub fn blocking_transfer<W: Word>(&mut self, read: &mut [W], write: &[W]) -> Result<(), Error> {
+        self.set_word_size(W::CONFIG);
         T::REGS.ctlr1().modify(|w| w.set_spe(true));
         flush_rx_fifo(T::REGS);
-        self.set_word_size(W::CONFIG);
         let len = read.len().max(write.len());
         for i in 0..len {
             let wb = write.get(i).copied().unwrap_or_default();
@@ -294,6 +428,167 @@ impl<'d, T: Instance, M: PeriMode> Spi<'d, T, M> {
         }
         Ok(())
     }
+
+    /// Enable the hardware CRC unit with the given polynomial, or disable it.
+    ///
+    /// The CRC is only appended and checked by the `*_with_crc` methods.
+    pub fn set_crc_polynomial(&mut self, polynomial: Option<u16>) {
+        let regs = T::REGS;
+
+        while regs.statr().read().bsy() {}
+        // CRCEN must only be changed while the SPI is disabled
+        regs.ctlr1().modify(|w| {
+            w.set_spe(false);
+            w.set_crcen(false);
+        });
+        if let Some(polynomial) = polynomial {
+            regs.crcr().write(|w| w.set_crcpoly(polynomial));
+            regs.ctlr1().modify(|w| w.set_crcen(true));
+        }
+    }
+
+    /// Blocking write, followed by the CRC of `words`.
+    ///
+    /// Requires a polynomial set with [`set_crc_polynomial`](Self::set_crc_polynomial).
+    pub fn blocking_write_with_crc<W: Word>(&mut self, words: &[W]) -> Result<(), Error> {
+        // Nothing is meant to be received, so neither is a CRC to check
+        self.blocking_transfer_crc(&mut [][..], words, false)
+    }
+
+    /// Blocking read, followed by a CRC word which is checked against the CRC of the received words.
+    ///
+    /// Returns [`Error::Crc`] on mismatch. Requires a polynomial set with
+    /// [`set_crc_polynomial`](Self::set_crc_polynomial).
+    pub fn blocking_read_with_crc<W: Word>(&mut self, words: &mut [W]) -> Result<(), Error> {
+        self.blocking_transfer_crc(words, &[][..], true)
+    }
+
+    /// Blocking in-place bidirectional transfer, with a CRC appended on MOSI and checked on MISO.
+    ///
+    /// Returns [`Error::Crc`] on mismatch, `words` then holds the received data nonetheless.
+    /// Requires a polynomial set with [`set_crc_polynomial`](Self::set_crc_polynomial).
+    pub fn blocking_transfer_in_place_with_crc<W: Word>(&mut self, words: &mut [W]) -> Result<(), Error> {
+        self.blocking_transfer_crc(words, words, true)
+    }
+
+    // Runs for `max(read.len(), write.len())` words, like `blocking_transfer`, then the CRC word,
+    // which is checked with `check_crc`.
+    fn blocking_transfer_crc<W: Word>(
+        &mut self,
+        read: *mut [W],
+        write: *const [W],
+        check_crc: bool,
+    ) -> Result<(), Error> {
+        let regs = T::REGS;
+        assert!(regs.ctlr1().read().crcen());
+
+        let (rx_ptr, rx_len) = slice_ptr_parts_mut(read);
+        let (tx_ptr, tx_len) = slice_ptr_parts(write);
+        let rx_ptr = rx_ptr as *mut W;
+        let tx_ptr = tx_ptr as *const W;
+        let len = rx_len.max(tx_len);
+        if len == 0 {
+            return Ok(());
+        }
+
+        self.set_word_size(W::CONFIG);
+        // Toggling CRCEN resets both CRC registers, it must only be changed while the SPI is disabled
+        while regs.statr().read().bsy() {}
+        regs.ctlr1().modify(|w| {
+            w.set_spe(false);
+            w.set_crcen(false);
+        });
+        regs.ctlr1().modify(|w| w.set_crcen(true));
+        regs.statr().modify(|w| w.set_crcerr(false));
+        regs.ctlr1().modify(|w| w.set_spe(true));
+        flush_rx_fifo(regs);
+
+        for i in 0..len {
+            let wb = if i < tx_len {
+                unsafe { ptr::read(tx_ptr.add(i)) }
+            } else {
+                W::default()
+            };
+
+            spin_until_tx_ready(&regs)?;
+            unsafe {
+                ptr::write_volatile(regs.datar().as_ptr() as _, wb);
+            }
+            if i == len - 1 {
+                // The CRC is sent right after the word being transmitted
+                regs.ctlr1().modify(|w| w.set_crcnext(true));
+            }
+
+            spin_until_rx_ready(&regs)?;
+            let rb: W = unsafe { ptr::read_volatile(regs.datar().as_ptr() as _) };
+            if i < rx_len {
+                unsafe { ptr::write(rx_ptr.add(i), rb) };
+            }
+        }
+
+        // The received CRC word, CRCERR is flagged along with it
+        let mut crc_error = match spin_until_rx_ready(&regs) {
+            Err(Error::Crc) => {
+                while !regs.statr().read().rxne() {}
+                true
+            }
+            res => res.map(|_| false)?,
+        };
+        let _: W = unsafe { ptr::read_volatile(regs.datar().as_ptr() as _) };
+        while regs.statr().read().bsy() {}
+
+        if regs.statr().read().crcerr() {
+            regs.statr().modify(|w| w.set_crcerr(false));
+            crc_error = true;
+        }
+        if crc_error && check_crc {
+            return Err(Error::Crc);
+        }
+        Ok(())
+    }
+
+    fn half_duplex_write<W: Word>(&mut self, words: &[W]) -> Result<(), Error> {
+        let regs = T::REGS;
+
+        self.set_direction(Direction::Transmit);
+        self.set_word_size(W::CONFIG);
+        regs.ctlr1().modify(|w| w.set_spe(true));
+        for word in words.iter() {
+            // The receiver also shifts in the transmitted words, ignore the resulting overrun
+            while !regs.statr().read().txe() {}
+            unsafe {
+                ptr::write_volatile(regs.datar().as_ptr() as _, *word);
+            }
+        }
+        while regs.statr().read().bsy() {}
+
+        clear_overrun(regs);
+        check_error_flags(&regs, &regs.statr().read())
+    }
+
+    /// Read with a clock that only runs while the SPI is enabled, i.e. in half-duplex receive
+    /// and RX-only mode. The SPI is disabled during the last word so the clock stops after it.
+    fn blocking_read_clocked<W: Word>(&mut self, words: &mut [W]) -> Result<(), Error> {
+        let regs = T::REGS;
+        let Some((last, words)) = words.split_last_mut() else {
+            return Ok(());
+        };
+
+        self.set_word_size(W::CONFIG);
+        flush_rx_fifo(regs);
+
+        // Number of CPU cycles of one SPI clock period
+        let spi_clock_cycles = crate::rcc::clocks().hclk.0 / self.get_current_config().frequency.0;
+
+        // The timing of SPE against the last word must not be disturbed by interrupts
+        critical_section::with(|_| {
+            let res = read_clocked(regs, words, last, spi_clock_cycles);
+            if res.is_err() {
+                regs.ctlr1().modify(|w| w.set_spe(false));
+            }
+            res
+        })
+    }
 }
 
 impl<'d, T: Instance> Spi<'d, T, Blocking> {
@@ -325,6 +620,8 @@ impl<'d, T: Instance> Spi<'d, T, Blocking> {
     }
 
     /// Create a new SPI driver, in RX-only mode (only MISO pin, no MOSI).
+    ///
+    /// The clock is generated only for the words read, no dummy words are written.
     pub fn new_blocking_rxonly<const REMAP: u8>(
         peri: impl Peripheral<P = T> + 'd,
         sck: impl Peripheral<P = impl SckPin<T, REMAP>> + 'd,
@@ -390,6 +687,41 @@ impl<'d, T: Instance> Spi<'d, T, Blocking> {
 
         Self::new_inner(peri, None, Some(mosi.map_into()), None, None, None, config)
     }
+
+    /// Create a new half-duplex SPI driver, with a single bidirectional data line on MOSI.
+    ///
+    /// Starts in [`Direction::Transmit`], see [`set_direction`](Self::set_direction).
+    pub fn new_blocking_half_duplex<const REMAP: u8>(
+        peri: impl Peripheral<P = T> + 'd,
+        sck: impl Peripheral<P = impl SckPin<T, REMAP>> + 'd,
+        mosi: impl Peripheral<P = impl MosiPin<T, REMAP>> + 'd,
+        config: Config,
+    ) -> Self {
+        into_ref!(sck, mosi);
+
+        T::set_remap(REMAP);
+
+        sck.set_as_af_output(AFType::OutputPushPull, Speed::High);
+        mosi.set_as_af_output(AFType::OutputPushPull, Speed::High);
+
+        let this = Self::new_inner(
+            peri,
+            Some(sck.map_into()),
+            Some(mosi.map_into()),
+            None,
+            None,
+            None,
+            config,
+        );
+
+        T::REGS.ctlr1().modify(|w| {
+            w.set_spe(false);
+            w.set_bidimode(true);
+            w.set_bidioe(true);
+        });
+
+        this
+    }
 }
 
 impl<'d, T: Instance> Spi<'d, T, Async> {
@@ -423,6 +755,9 @@ impl<'d, T: Instance> Spi<'d, T, Async> {
     }
 
     /// Create a new SPI driver, in RX-only mode (only MISO pin, no MOSI).
+    ///
+    /// Reads only use the RX DMA channel. The clock runs from the start of a read until the DMA
+    /// has received all words, the words clocked in before the SPI is stopped are discarded.
     pub fn new_rxonly<const REMAP: u8>(
         peri: impl Peripheral<P = T> + 'd,
         sck: impl Peripheral<P = impl SckPin<T, REMAP>> + 'd,
@@ -520,10 +855,15 @@ impl<'d, T: Instance> Spi<'d, T, Async> {
 
         finish_dma(T::REGS);
 
-        Ok(())
+        // Nothing reads the received words, drop them and the overrun they caused
+        clear_overrun(T::REGS);
+
+        check_error_flags(&T::REGS, &T::REGS.statr().read())
     }
 
     /// SPI read, using DMA.
+    ///
+    /// The clock is generated by writing `W::default()` words, MOSI is held low.
     pub async fn read<W: Word>(&mut self, data: &mut [W]) -> Result<(), Error> {
         if data.is_empty() {
             return Ok(());
@@ -536,20 +876,24 @@ impl<'d, T: Instance> Spi<'d, T, Async> {
 
         flush_rx_fifo(T::REGS);
 
+        if T::REGS.ctlr1().read().rxonly() {
+            return self.read_rxonly(data).await;
+        }
+
         T::REGS.ctlr2().modify(|w| w.set_rxdmaen(true)); // set rxdma en
 
-        let clock_byte_count = data.len();
+        let clock_word_count = data.len();
 
         let rx_src = T::REGS.datar().as_ptr() as *mut _;
         let rx_f = unsafe { self.rx_dma.as_mut().unwrap().read(rx_src, data, Default::default()) };
 
-        let tx_dst = T::REGS.datar().as_ptr() as *mut _;
-        let clock_byte = 0x00u8;
+        let tx_dst = T::REGS.datar().as_ptr() as *mut W;
+        let clock_word = W::default();
         let tx_f = unsafe {
             self.tx_dma
                 .as_mut()
                 .unwrap()
-                .write_repeated(&clock_byte, clock_byte_count, tx_dst, Default::default())
+                .write_repeated(&clock_word, clock_word_count, tx_dst, Default::default())
         };
 
         T::REGS.ctlr2().modify(|w| w.set_txdmaen(true));
@@ -562,7 +906,32 @@ impl<'d, T: Instance> Spi<'d, T, Async> {
 
         finish_dma(T::REGS);
 
-        Ok(())
+        check_error_flags(&T::REGS, &T::REGS.statr().read())
+    }
+
+    // The clock runs from enabling the SPI until disabling it after the DMA completed. The few
+    // words clocked in meanwhile are discarded.
+    async fn read_rxonly<W: Word>(&mut self, data: &mut [W]) -> Result<(), Error> {
+        T::REGS.ctlr2().modify(|w| w.set_rxdmaen(true));
+
+        let rx_src = T::REGS.datar().as_ptr() as *mut _;
+        let rx_f = unsafe { self.rx_dma.as_mut().unwrap().read(rx_src, data, Default::default()) };
+
+        T::REGS.ctlr1().modify(|w| {
+            w.set_spe(true);
+        });
+
+        rx_f.await;
+
+        T::REGS.ctlr1().modify(|w| {
+            w.set_spe(false);
+        });
+        while T::REGS.statr().read().bsy() {}
+        T::REGS.ctlr2().modify(|w| w.set_rxdmaen(false));
+
+        clear_overrun(T::REGS);
+
+        check_error_flags(&T::REGS, &T::REGS.statr().read())
     }
 
     async fn transfer_inner<W: Word>(&mut self, read: *mut [W], write: *const [W]) -> Result<(), Error> {
@@ -603,17 +972,27 @@ impl<'d, T: Instance> Spi<'d, T, Async> {
 
         finish_dma(T::REGS);
 
-        Ok(())
+        check_error_flags(&T::REGS, &T::REGS.statr().read())
     }
 
     /// Bidirectional transfer, using DMA.
     ///
     /// This transfers both buffers at the same time, so it is NOT equivalent to `write` followed by `read`.
     ///
-    /// The transfer runs for `max(read.len(), write.len())` bytes. If `read` is shorter extra bytes are ignored.
-    /// If `write` is shorter it is padded with zero bytes.
+    /// The transfer runs for `max(read.len(), write.len())` words. If `read` is shorter extra words are ignored.
+    /// If `write` is shorter it is padded with zero words.
     pub async fn transfer<W: Word>(&mut self, read: &mut [W], write: &[W]) -> Result<(), Error> {
-        self.transfer_inner(read, write).await
+        let common = read.len().min(write.len());
+        let (read, read_rest) = read.split_at_mut(common);
+        let (write, write_rest) = write.split_at(common);
+
+        self.transfer_inner(read, write).await?;
+        if !write_rest.is_empty() {
+            self.write(write_rest).await?;
+        } else if !read_rest.is_empty() {
+            self.read(read_rest).await?;
+        }
+        Ok(())
     }
 
     /// In-place bidirectional transfer, using DMA.
@@ -624,23 +1003,104 @@ impl<'d, T: Instance> Spi<'d, T, Async> {
     }
 }
 
+/// Continuous SPI output of a repeating buffer, using circular DMA.
+///
+/// Created with [`Spi::into_tx_stream`]. Received data is discarded.
+pub struct SpiTxStream<'d, T: Instance, W: Word> {
+    // dropped before the driver
+    transfer: Option<Transfer<'d>>,
+    spi: Spi<'d, T, Async>,
+    buf: &'d [W],
+}
+
+impl<'d, T: Instance> Spi<'d, T, Async> {
+    /// Start clocking out `buf` over and over, until the stream is stopped.
+    pub fn into_tx_stream<W: Word>(mut self, buf: &'d [W]) -> SpiTxStream<'d, T, W> {
+        self.set_word_size(W::CONFIG);
+        T::REGS.ctlr1().modify(|w| {
+            w.set_spe(false);
+        });
+
+        let mut stream = SpiTxStream {
+            transfer: None,
+            spi: self,
+            buf,
+        };
+        stream.start();
+        stream
+    }
+}
+
+impl<'d, T: Instance, W: Word> SpiTxStream<'d, T, W> {
+    fn start(&mut self) {
+        let options = TransferOptions {
+            circular: true,
+            ..Default::default()
+        };
+
+        let tx_dma = self.spi.tx_dma.as_mut().unwrap();
+        let tx_dst = T::REGS.datar().as_ptr() as *mut W;
+        // safety: the buffer and the channel live for 'd, the transfer is stopped before they are released
+        self.transfer = Some(unsafe {
+            Transfer::new_write_raw(
+                tx_dma.channel.clone_unchecked(),
+                tx_dma.request,
+                self.buf as *const [W],
+                tx_dst,
+                options,
+            )
+        });
+
+        T::REGS.ctlr2().modify(|w| w.set_txdmaen(true));
+        T::REGS.ctlr1().modify(|w| {
+            w.set_spe(true);
+        });
+    }
+
+    /// Replace the repeated buffer, returning the previous one.
+    ///
+    /// The current pass over the previous buffer is completed first, and the new buffer is
+    /// output from its start. The clock pauses in between until the new transfer is set up.
+    pub async fn swap(&mut self, buf: &'d [W]) -> &'d [W] {
+        if let Some(transfer) = self.transfer.as_mut() {
+            transfer.stop_at_end().await;
+        }
+        // The old transfer must be stopped before the channel is configured again
+        self.transfer = None;
+
+        let prev = core::mem::replace(&mut self.buf, buf);
+        self.start();
+        prev
+    }
+
+    /// Complete the current pass over the buffer, then stop and return the driver.
+    pub async fn stop(mut self) -> Spi<'d, T, Async> {
+        if let Some(transfer) = self.transfer.as_mut() {
+            transfer.stop_at_end().await;
+        }
+        self.transfer = None;
+
+        finish_dma(T::REGS);
+        clear_overrun(T::REGS);
+
+        self.spi
+    }
+}
+
 impl<'d, T: Instance, M: PeriMode> Drop for Spi<'d, T, M> {
     fn drop(&mut self) {
-        use crate::gpio::SealedPin;
-
-        self.sck.as_ref().map(|x| x.set_as_disconnected());
-        self.mosi.as_ref().map(|x| x.set_as_disconnected());
+        self.release_pins();
         self.miso.as_ref().map(|x| x.set_as_disconnected());
 
         T::disable();
     }
 }
 
-// Get CTRL1.BR
+// Get CTRL1.BR, for the highest frequency not above `clk`
 #[inline]
-fn calculate_baud_rate(hclk: u32, clk: u32) -> BaudRate {
+fn calculate_baud_rate(pclk: u32, clk: u32) -> BaudRate {
     // only div2, div4 to div256 are valid
-    let div = hclk / clk;
+    let div = pclk.div_ceil(clk);
 
     match div {
         1..=2 => BaudRate::DIV_2,
@@ -657,14 +1117,21 @@ fn calculate_baud_rate(hclk: u32, clk: u32) -> BaudRate {
     }
 }
 
-fn check_error_flags(sr: &pac::spi::regs::Statr) -> Result<(), Error> {
+// Report the first error flagged in `sr`, and clear it so the next transfer can proceed
+fn check_error_flags(regs: &pac::spi::Spi, sr: &pac::spi::regs::Statr) -> Result<(), Error> {
     if sr.ovr() {
+        // the unread word is lost anyway
+        clear_overrun(*regs);
         return Err(Error::Overrun);
     }
     if sr.modf() {
+        // SR was read, writing CR1 clears MODF. The hardware cleared MSTR and SPE, the next
+        // transfer enables the SPI again.
+        regs.ctlr1().modify(|w| w.set_mstr(true));
         return Err(Error::ModeFault);
     }
     if sr.crcerr() {
+        regs.statr().modify(|w| w.set_crcerr(false));
         return Err(Error::Crc);
     }
 
@@ -675,7 +1142,7 @@ fn spin_until_tx_ready(regs: &pac::spi::Spi) -> Result<(), Error> {
     loop {
         let sr = regs.statr().read();
 
-        check_error_flags(&sr)?;
+        check_error_flags(regs, &sr)?;
 
         if sr.txe() {
             return Ok(());
@@ -687,7 +1154,7 @@ fn spin_until_rx_ready(regs: &pac::spi::Spi) -> Result<(), Error> {
     loop {
         let sr = regs.statr().read();
 
-        check_error_flags(&sr)?;
+        check_error_flags(regs, &sr)?;
 
         if sr.rxne() {
             return Ok(());
@@ -717,6 +1184,30 @@ fn finish_dma(regs: Regs) {
     });
 }
 
+fn read_clocked<W: Word>(regs: Regs, words: &mut [W], last: &mut W, spi_clock_cycles: u32) -> Result<(), Error> {
+    regs.ctlr1().modify(|w| w.set_spe(true));
+    for word in words.iter_mut() {
+        spin_until_rx_ready(&regs)?;
+        *word = unsafe { ptr::read_volatile(regs.datar().as_ptr() as _) };
+    }
+
+    // The last word is being received, stop the clock once it completes
+    for _ in 0..spi_clock_cycles {
+        core::hint::spin_loop();
+    }
+    regs.ctlr1().modify(|w| w.set_spe(false));
+
+    spin_until_rx_ready(&regs)?;
+    *last = unsafe { ptr::read_volatile(regs.datar().as_ptr() as _) };
+    Ok(())
+}
+
+// Reading DR then SR clears OVR
+fn clear_overrun(regs: Regs) {
+    flush_rx_fifo(regs);
+    let _ = regs.statr().read();
+}
+
 fn transfer_word<W: Word>(regs: &pac::spi::Spi, tx_word: W) -> Result<W, Error> {
     spin_until_tx_ready(regs)?;
 
@@ -730,12 +1221,37 @@ fn transfer_word<W: Word>(regs: &pac::spi::Spi, tx_word: W) -> Result<W, Error>
     Ok(rx_word)
 }
 
+impl<'d, T: Instance, M: PeriMode> SetConfig for Spi<'d, T, M> {
+    type Config = Config;
+    type ConfigError = ();
+
+    fn set_config(&mut self, config: &Self::Config) -> Result<(), Self::ConfigError> {
+        self.set_config(config)
+    }
+}
+
+impl<'d, T: Instance, M: PeriMode> LowPowerPeripheral for Spi<'d, T, M> {
+    /// Wait for the current frame, and save the SCK frequency at the current bus clock.
+    fn suspend(&mut self) {
+        while T::REGS.statr().read().bsy() {}
+        self.suspended_config = Some(self.get_current_config());
+    }
+
+    /// Pick the prescaler for the saved SCK frequency again, at the bus clock after wake-up.
+    fn resume(&mut self) {
+        if let Some(config) = self.suspended_config.take() {
+            let _ = self.set_config(&config);
+        }
+    }
+}
+
 impl<'d, T: Instance, M: PeriMode> embedded_hal::spi::ErrorType for Spi<'d, T, M> {
     type Error = Error;
 }
 
 impl<'d, T: Instance, W: Word, M: PeriMode> embedded_hal::spi::SpiBus<W> for Spi<'d, T, M> {
     fn flush(&mut self) -> Result<(), Self::Error> {
+        while T::REGS.statr().read().bsy() {}
         Ok(())
     }
 
@@ -756,6 +1272,28 @@ impl<'d, T: Instance, W: Word, M: PeriMode> embedded_hal::spi::SpiBus<W> for Spi
     }
 }
 
+impl<'d, T: Instance, W: Word> embedded_hal_async::spi::SpiBus<W> for Spi<'d, T, Async> {
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn write(&mut self, words: &[W]) -> Result<(), Self::Error> {
+        self.write(words).await
+    }
+
+    async fn read(&mut self, words: &mut [W]) -> Result<(), Self::Error> {
+        self.read(words).await
+    }
+
+    async fn transfer(&mut self, read: &mut [W], write: &[W]) -> Result<(), Self::Error> {
+        self.transfer(read, write).await
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [W]) -> Result<(), Self::Error> {
+        self.transfer_in_place(words).await
+    }
+}
+
 impl embedded_hal::spi::Error for Error {
     fn kind(&self) -> embedded_hal::spi::ErrorKind {
         match *self {
@@ -772,6 +1310,9 @@ trait SealedWord {
 }
 
 /// Word sizes usable for SPI.
+///
+/// `u8` selects 8-bit frames, `u16` selects 16-bit frames (DFF). The frame size is switched
+/// on the fly by the first transfer using another word type.
 #[allow(private_bounds)]
 pub trait Word: word::Word + SealedWord {}
 
@@ -793,7 +1334,7 @@ mod word_impl {
     impl_word!(u16, 1);
 }
 
-trait SealedInstance {
+pub(crate) trait SealedInstance {
     const REGS: Regs;
 }
 
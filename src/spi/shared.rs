@@ -0,0 +1,99 @@
+//! Blocking bus sharing, for several devices with a software chip select on one `Spi`.
+
+use core::cell::RefCell;
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::{Operation, SpiBus};
+
+use super::{Config, Error, Instance, Spi, Word};
+use crate::gpio::Output;
+use crate::mode::Mode as PeriMode;
+
+/// An SPI bus shared between several [`SharedSpiDevice`]s.
+///
+/// The bus is borrowed for the duration of a transaction, so devices must only be used from
+/// one execution context (not from interrupt handlers).
+pub struct SharedSpi<'d, T: Instance, M: PeriMode> {
+    bus: RefCell<Spi<'d, T, M>>,
+}
+
+impl<'d, T: Instance, M: PeriMode> SharedSpi<'d, T, M> {
+    /// Share `spi` between devices.
+    pub fn new(spi: Spi<'d, T, M>) -> Self {
+        Self { bus: RefCell::new(spi) }
+    }
+
+    /// Create a device on the bus, selected by driving `cs` low.
+    ///
+    /// `delay` implements the delay operations of the transactions.
+    pub fn device<'a, D: DelayNs>(&'a self, cs: Output<'a>, delay: D) -> SharedSpiDevice<'a, 'd, T, M, D> {
+        SharedSpiDevice {
+            bus: &self.bus,
+            cs,
+            delay,
+            config: None,
+        }
+    }
+
+    /// Create a device on the bus, which applies `config` before each transaction.
+    pub fn device_with_config<'a, D: DelayNs>(
+        &'a self,
+        cs: Output<'a>,
+        delay: D,
+        config: Config,
+    ) -> SharedSpiDevice<'a, 'd, T, M, D> {
+        SharedSpiDevice {
+            config: Some(config),
+            ..self.device(cs, delay)
+        }
+    }
+
+    /// Get the driver back.
+    pub fn into_inner(self) -> Spi<'d, T, M> {
+        self.bus.into_inner()
+    }
+}
+
+/// A device on a [`SharedSpi`] bus.
+pub struct SharedSpiDevice<'a, 'd, T: Instance, M: PeriMode, D> {
+    bus: &'a RefCell<Spi<'d, T, M>>,
+    cs: Output<'a>,
+    delay: D,
+    config: Option<Config>,
+}
+
+impl<'a, 'd, T: Instance, M: PeriMode, D> embedded_hal::spi::ErrorType for SharedSpiDevice<'a, 'd, T, M, D> {
+    type Error = Error;
+}
+
+impl<'a, 'd, T: Instance, M: PeriMode, D: DelayNs, W: Word> embedded_hal::spi::SpiDevice<W>
+    for SharedSpiDevice<'a, 'd, T, M, D>
+{
+    fn transaction(&mut self, operations: &mut [Operation<'_, W>]) -> Result<(), Self::Error> {
+        // Panics if a transaction is started while another one is running
+        let mut bus = self.bus.borrow_mut();
+        if let Some(config) = &self.config {
+            let _ = bus.set_config(config);
+        }
+
+        self.cs.set_low();
+
+        let res = operations.iter_mut().try_for_each(|op| match op {
+            Operation::Read(buf) => bus.read(buf),
+            Operation::Write(buf) => bus.write(buf),
+            Operation::Transfer(read, write) => bus.transfer(read, write),
+            Operation::TransferInPlace(buf) => bus.transfer_in_place(buf),
+            Operation::DelayNs(ns) => {
+                SpiBus::<W>::flush(&mut *bus)?;
+                self.delay.delay_ns(*ns);
+                Ok(())
+            }
+        });
+
+        // deselect only once the last frame is out, even after an error
+        let flush = SpiBus::<W>::flush(&mut *bus);
+        self.cs.set_high();
+
+        res.and(flush)
+    }
+}
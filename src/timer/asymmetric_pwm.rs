@@ -0,0 +1,147 @@
+//! Asymmetric PWM driver.
+//!
+//! The timer runs in center-aligned mode, and the update DMA request reloads the channel's
+//! compare value on every overflow and underflow. One compare value places the falling edge
+//! while counting up, the other the rising edge while counting down, so both edges of the
+//! pulse can be placed independently within a period (e.g. for synchronous rectification).
+//!
+//! With a max duty of `M` and the cycle starting at counter 0, the output (PWM mode 1, active high) is:
+//!
+//! - active from the start of the cycle until the counter reaches `fall` while counting up,
+//! - inactive until the counter drops below `rise` while counting down, i.e. at `2 * M - rise`,
+//! - active until the end of the cycle.
+
+use core::ptr;
+
+use super::low_level::{CountingMode, OutputCompareMode, OutputPolarity, Timer};
+use super::simple_pwm::{Ch1, Ch2, Ch3, Ch4, PwmPin};
+use super::{Channel, GeneralInstance16bit, UpDma};
+use crate::dma::{AnyChannel, Transfer, TransferOptions};
+use crate::time::Hertz;
+use crate::{into_ref, Peripheral, PeripheralRef};
+
+/// Asymmetric PWM driver.
+pub struct AsymmetricPwm<'d, T: GeneralInstance16bit> {
+    inner: Timer<'d, T>,
+    channel: Channel,
+    dma: PeripheralRef<'d, AnyChannel>,
+    buf: *mut [u16; 2],
+    transfer: Option<Transfer<'d>>,
+}
+
+impl<'d, T: GeneralInstance16bit> AsymmetricPwm<'d, T> {
+    /// Create a new asymmetric PWM driver on `channel`.
+    ///
+    /// `buf` holds the compare values and is read by the DMA on every half period,
+    /// so it must outlive the driver.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        tim: impl Peripheral<P = T> + 'd,
+        _ch1: Option<PwmPin<'d, T, Ch1>>,
+        _ch2: Option<PwmPin<'d, T, Ch2>>,
+        _ch3: Option<PwmPin<'d, T, Ch3>>,
+        _ch4: Option<PwmPin<'d, T, Ch4>>,
+        channel: Channel,
+        dma: impl Peripheral<P = impl UpDma<T>> + 'd,
+        buf: &'d mut [u16; 2],
+        freq: Hertz,
+    ) -> Self {
+        into_ref!(dma);
+
+        let mut this = Self {
+            inner: Timer::new(tim),
+            channel,
+            dma: dma.map_into(),
+            buf,
+            transfer: None,
+        };
+
+        this.inner.set_counting_mode(CountingMode::CenterAlignedBothInterrupts);
+        this.set_frequency(freq);
+        this.inner.enable_outputs();
+
+        this.inner.set_output_compare_mode(channel, OutputCompareMode::PwmMode1);
+        this.inner.set_output_compare_preload(channel, true);
+
+        this
+    }
+
+    /// Set PWM frequency, as the number of full (up and down) periods per second.
+    ///
+    /// Note: when you call this, the max duty value changes, so you will have to
+    /// call `set_edges` with values calculated based on the new max duty.
+    pub fn set_frequency(&mut self, freq: Hertz) {
+        self.inner.set_frequency(freq * 2u8);
+    }
+
+    /// Get max duty value.
+    ///
+    /// This value depends on the configured frequency and the timer's clock rate from RCC.
+    pub fn get_max_duty(&self) -> u16 {
+        self.inner.get_max_compare_value() as u16 + 1
+    }
+
+    /// Set the edge positions, both ranging from 0 to [`get_max_duty`](Self::get_max_duty).
+    ///
+    /// `fall` is the compare value used while counting up, `rise` while counting down.
+    /// Takes effect from the next period on.
+    pub fn set_edges(&mut self, rise: u16, fall: u16) {
+        let max = self.get_max_duty();
+        assert!(rise <= max && fall <= max);
+
+        // safety: the DMA only reads the buffer, and aligned u16 writes are atomic
+        unsafe {
+            let buf = self.buf as *mut u16;
+            ptr::write_volatile(buf, fall);
+            ptr::write_volatile(buf.add(1), rise);
+        }
+    }
+
+    /// Set the output polarity.
+    pub fn set_polarity(&mut self, polarity: OutputPolarity) {
+        self.inner.set_output_polarity(self.channel, polarity);
+    }
+
+    /// Start generating the waveform.
+    pub fn start(&mut self) {
+        if self.transfer.is_some() {
+            return;
+        }
+
+        let (fall, rise) = unsafe { ((*self.buf)[0], (*self.buf)[1]) };
+
+        // Load `fall` for the first up-counting half, and preload `rise` for the following one.
+        self.inner.set_compare_value(self.channel, fall as u32);
+        self.inner.regs_basic().swevgr().write(|w| w.set_ug(true));
+        self.inner.set_compare_value(self.channel, rise as u32);
+
+        let options = TransferOptions {
+            circular: true,
+            complete_transfer_ir: false,
+            ..Default::default()
+        };
+        let ccr = self.inner.regs_gp16().chcvr(self.channel.index()).as_ptr() as *mut u16;
+        // safety: the buffer and the channel live for 'd, the transfer is stopped before they are released
+        self.transfer = Some(unsafe {
+            Transfer::new_write_raw(self.dma.clone_unchecked(), (), self.buf as *const [u16], ccr, options)
+        });
+
+        self.inner.enable_update_dma(true);
+        self.inner.enable_channel(self.channel, true);
+        self.inner.start();
+    }
+
+    /// Stop generating the waveform.
+    pub fn stop(&mut self) {
+        self.inner.stop();
+        self.inner.enable_channel(self.channel, false);
+        self.inner.enable_update_dma(false);
+        self.transfer = None;
+    }
+}
+
+impl<'d, T: GeneralInstance16bit> Drop for AsymmetricPwm<'d, T> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -2,7 +2,7 @@
 
 use core::marker::PhantomData;
 
-use super::low_level::{CountingMode, OutputPolarity, Timer};
+use super::low_level::{CountingMode, LockLevel, OutputPolarity, Timer};
 use super::simple_pwm::{Ch1, Ch2, Ch3, Ch4, PwmPin};
 use super::{AdvancedInstance, Channel, Channel1ComplementaryPin, Channel2ComplementaryPin, Channel3ComplementaryPin};
 use crate::gpio::AFType;
@@ -147,6 +147,46 @@ impl<'d, T: AdvancedInstance> ComplementaryPwm<'d, T> {
         self.inner.set_dead_time_clock_division(ckd);
         self.inner.set_dead_time_value(value);
     }
+
+    /// Set the lock level of the break and dead-time configuration.
+    ///
+    /// Note: this can be written only once after reset, further writes are ignored by the hardware.
+    pub fn set_lock_level(&mut self, level: LockLevel) {
+        self.inner.set_lock_level(level);
+    }
+
+    /// Set the off-state selection for idle mode (OSSI) and run mode (OSSR).
+    ///
+    /// When set, disabled outputs are driven to their inactive level instead of being released.
+    pub fn set_off_state_selection(&mut self, idle: bool, run: bool) {
+        self.inner.set_ossi(idle);
+        self.inner.set_ossr(run);
+    }
+
+    /// Enable/disable automatic output enable, so outputs come back on the next update event after a break.
+    pub fn set_automatic_output_enable(&mut self, enable: bool) {
+        self.inner.set_automatic_output(enable);
+    }
+
+    /// Configure the break input. `None` disables it.
+    ///
+    /// `ActiveHigh` polarity means a high level on BKIN stops the outputs.
+    pub fn set_break(&mut self, polarity: Option<OutputPolarity>) {
+        match polarity {
+            Some(polarity) => {
+                self.inner.set_break_polarity(polarity);
+                self.inner.set_break_enable(true);
+            }
+            None => self.inner.set_break_enable(false),
+        }
+    }
+
+    /// Enable/disable the main outputs (MOE).
+    ///
+    /// This is cleared by the hardware on a break event.
+    pub fn set_main_output_enable(&mut self, enable: bool) {
+        self.inner.set_moe(enable);
+    }
 }
 
 fn compute_dead_time_value(value: u16) -> (Ckd, u8) {
@@ -0,0 +1,165 @@
+//! Timer events as DMA request sources.
+//!
+//! Every timer event (update, capture/compare) is hard-wired to one DMA channel.
+//! The `UpDma` / `ChxDma` traits encode this mapping, so a [`DmaTrigger`] can only be
+//! built with the channel that actually receives the timer's request.
+//!
+//! The DMA channel itself can then move data between memory and any register, which is
+//! useful for paced transfers such as driving a parallel bus through a GPIO port's `BSHR`.
+
+use super::low_level::{OutputCompareMode, Timer};
+use super::{Ch1Dma, Ch2Dma, Ch3Dma, Ch4Dma, Channel, GeneralInstance16bit, UpDma};
+use crate::dma::word::Word;
+use crate::dma::{ChannelAndRequest, TransferOptions};
+use crate::pac::timer::vals::Ccds;
+use crate::time::Hertz;
+use crate::{into_ref, Peripheral};
+
+/// Timer event used as DMA request.
+#[derive(Clone, Copy, PartialEq)]
+pub enum TriggerEvent {
+    /// Counter overflow/underflow (update event).
+    Update,
+    /// Compare match on the given channel.
+    Compare(Channel),
+}
+
+/// DMA transfers paced by a timer event.
+pub struct DmaTrigger<'d, T: GeneralInstance16bit> {
+    inner: Timer<'d, T>,
+    event: TriggerEvent,
+    dma: ChannelAndRequest<'d>,
+}
+
+macro_rules! compare_trigger_impl {
+    ($new_chx:ident, $channel:ident, $dma_trait:ident) => {
+        impl<'d, T: GeneralInstance16bit> DmaTrigger<'d, T> {
+            #[doc = concat!("Create a DMA trigger on the ", stringify!($channel), " compare event.")]
+            ///
+            /// The event happens once per timer period, when the counter reaches `compare`.
+            pub fn $new_chx(
+                tim: impl Peripheral<P = T> + 'd,
+                dma: impl Peripheral<P = impl $dma_trait<T>> + 'd,
+                freq: Hertz,
+                compare: u32,
+            ) -> Self {
+                into_ref!(dma);
+
+                Self::new_inner(
+                    tim,
+                    new_dma!(dma).unwrap(),
+                    TriggerEvent::Compare(Channel::$channel),
+                    freq,
+                    compare,
+                )
+            }
+        }
+    };
+}
+
+compare_trigger_impl!(new_ch1, Ch1, Ch1Dma);
+compare_trigger_impl!(new_ch2, Ch2, Ch2Dma);
+compare_trigger_impl!(new_ch3, Ch3, Ch3Dma);
+compare_trigger_impl!(new_ch4, Ch4, Ch4Dma);
+
+impl<'d, T: GeneralInstance16bit> DmaTrigger<'d, T> {
+    /// Create a DMA trigger on the update event, which happens `freq` times per second.
+    pub fn new_update(
+        tim: impl Peripheral<P = T> + 'd,
+        dma: impl Peripheral<P = impl UpDma<T>> + 'd,
+        freq: Hertz,
+    ) -> Self {
+        into_ref!(dma);
+
+        Self::new_inner(tim, new_dma!(dma).unwrap(), TriggerEvent::Update, freq, 0)
+    }
+
+    fn new_inner(
+        tim: impl Peripheral<P = T> + 'd,
+        dma: ChannelAndRequest<'d>,
+        event: TriggerEvent,
+        freq: Hertz,
+        compare: u32,
+    ) -> Self {
+        let this = Self {
+            inner: Timer::new(tim),
+            event,
+            dma,
+        };
+
+        this.inner.set_frequency(freq);
+
+        match event {
+            TriggerEvent::Update => this.inner.enable_update_dma(true),
+            TriggerEvent::Compare(channel) => {
+                this.inner.set_output_compare_mode(channel, OutputCompareMode::Frozen);
+                this.inner.set_compare_value(channel, compare);
+                this.inner.set_cc_dma_selection(Ccds::ONCOMPARE);
+                this.inner.set_cc_dma_enable_state(channel, true);
+            }
+        }
+
+        this
+    }
+
+    /// The event this trigger is bound to.
+    pub fn event(&self) -> TriggerEvent {
+        self.event
+    }
+
+    /// Set the trigger frequency.
+    pub fn set_frequency(&mut self, freq: Hertz) {
+        self.inner.set_frequency(freq);
+    }
+
+    /// Set the compare value of a compare trigger.
+    ///
+    /// Has no effect on update triggers.
+    pub fn set_compare_value(&mut self, compare: u32) {
+        if let TriggerEvent::Compare(channel) = self.event {
+            self.inner.set_compare_value(channel, compare);
+        }
+    }
+
+    /// Write one word of `buf` to `dst` on every timer event.
+    ///
+    /// The timer runs only for the duration of the transfer.
+    ///
+    /// # Safety
+    ///
+    /// `dst` must be a valid, writable register (or memory location) for the whole transfer.
+    pub async unsafe fn write<W: Word>(&mut self, buf: &[W], dst: *mut W) {
+        let transfer = self.dma.write(buf, dst, TransferOptions::default());
+
+        self.inner.reset();
+        self.inner.start();
+        transfer.await;
+        self.inner.stop();
+    }
+
+    /// Read one word from `src` into `buf` on every timer event.
+    ///
+    /// The timer runs only for the duration of the transfer.
+    ///
+    /// # Safety
+    ///
+    /// `src` must be a valid, readable register (or memory location) for the whole transfer.
+    pub async unsafe fn read<W: Word>(&mut self, src: *mut W, buf: &mut [W]) {
+        let transfer = self.dma.read(src, buf, TransferOptions::default());
+
+        self.inner.reset();
+        self.inner.start();
+        transfer.await;
+        self.inner.stop();
+    }
+}
+
+impl<'d, T: GeneralInstance16bit> Drop for DmaTrigger<'d, T> {
+    fn drop(&mut self) {
+        self.inner.stop();
+        match self.event {
+            TriggerEvent::Update => self.inner.enable_update_dma(false),
+            TriggerEvent::Compare(channel) => self.inner.set_cc_dma_enable_state(channel, false),
+        }
+    }
+}
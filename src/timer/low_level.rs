@@ -167,6 +167,87 @@ impl From<OutputPolarity> for bool {
     }
 }
 
+/// Trigger output (TRGO) source, sent to other timers, ADC or DAC.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TriggerOutputSource {
+    /// UG bit of SWEVGR (software update event).
+    Reset = 0b000,
+    /// Counter enable (CEN), or trigger input in gated mode.
+    Enable = 0b001,
+    /// Update event.
+    Update = 0b010,
+    /// Capture/compare pulse, when CC1IF is set.
+    ComparePulse = 0b011,
+    /// OC1REF.
+    Compare1 = 0b100,
+    /// OC2REF.
+    Compare2 = 0b101,
+    /// OC3REF.
+    Compare3 = 0b110,
+    /// OC4REF.
+    Compare4 = 0b111,
+}
+
+/// Slave mode, how the timer reacts to its trigger input (TRGI).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SlaveMode {
+    /// Slave mode disabled, the prescaler is clocked by the internal clock.
+    Disabled = 0b000,
+    /// Encoder mode 1, counting on TI2FP2 edges.
+    Encoder1 = 0b001,
+    /// Encoder mode 2, counting on TI1FP1 edges.
+    Encoder2 = 0b010,
+    /// Encoder mode 3, counting on both TI1FP1 and TI2FP2 edges.
+    Encoder3 = 0b011,
+    /// Rising edge of TRGI resets the counter.
+    Reset = 0b100,
+    /// Counter is enabled while TRGI is high.
+    Gated = 0b101,
+    /// Rising edge of TRGI starts the counter.
+    Trigger = 0b110,
+    /// Rising edges of TRGI clock the counter.
+    ExternalClock = 0b111,
+}
+
+/// Trigger input (TRGI) selection.
+///
+/// Internal triggers ITRx connect to the TRGO of other timers,
+/// see the reference manual for the mapping of each timer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TriggerSource {
+    /// Internal trigger 0.
+    Itr0 = 0b000,
+    /// Internal trigger 1.
+    Itr1 = 0b001,
+    /// Internal trigger 2.
+    Itr2 = 0b010,
+    /// Internal trigger 3.
+    Itr3 = 0b011,
+    /// TI1 edge detector.
+    Ti1FEdge = 0b100,
+    /// Filtered timer input 1.
+    Ti1Fp1 = 0b101,
+    /// Filtered timer input 2.
+    Ti2Fp2 = 0b110,
+    /// External trigger input.
+    Etrf = 0b111,
+}
+
+/// Break and dead-time register lock level.
+///
+/// Once written, the lock level can only be changed by a reset.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LockLevel {
+    /// No write protection.
+    Off = 0b00,
+    /// DTG, BKE, BKP, AOE, OISx and OISxN are write protected.
+    Level1 = 0b01,
+    /// Level 1, plus CCxP, CCxNP, OSSR and OSSI.
+    Level2 = 0b10,
+    /// Level 2, plus OCxM and OCxPE.
+    Level3 = 0b11,
+}
+
 /// Low-level timer driver.
 pub struct Timer<'d, T: CoreInstance> {
     tim: PeripheralRef<'d, T>,
@@ -514,6 +595,29 @@ impl<'d, T: GeneralInstance16bit> Timer<'d, T> {
         self.regs_gp16().ctlr2().modify(|w| w.set_ccds(ccds))
     }
 
+    /// Set the trigger output (TRGO) source, used in master mode.
+    #[cfg(not(timer_x0))] // no CTLR2
+    pub fn set_trigger_output(&self, source: TriggerOutputSource) {
+        self.regs_gp16().ctlr2().modify(|w| w.set_mms(source as u8));
+    }
+
+    /// Set the slave mode and its trigger input.
+    #[cfg(not(timer_x0))]
+    pub fn set_slave_mode(&self, mode: SlaveMode, trigger: TriggerSource) {
+        self.regs_gp16().smcfgr().modify(|w| {
+            w.set_ts(trigger as u8);
+            w.set_sms(mode as u8);
+        });
+    }
+
+    /// Enable/disable master/slave mode (MSM).
+    ///
+    /// When enabled, the trigger input is delayed so this timer and its slaves start in sync.
+    #[cfg(not(timer_x0))]
+    pub fn set_master_slave_mode(&self, enable: bool) {
+        self.regs_gp16().smcfgr().modify(|w| w.set_msm(enable));
+    }
+
     /// Get capture compare DMA enable state
     #[cfg(not(timer_x0))]
     pub fn get_cc_dma_enable_state(&self, channel: Channel) -> bool {
@@ -550,6 +654,37 @@ impl<'d, T: AdvancedInstance> Timer<'d, T> {
         self.regs_advanced().bdtr().modify(|w| w.set_moe(enable));
     }
 
+    /// Set the lock level of the break and dead-time related bits.
+    pub fn set_lock_level(&self, level: LockLevel) {
+        self.regs_advanced().bdtr().modify(|w| w.set_lock(level as u8));
+    }
+
+    /// Set the off-state selection for idle mode (OSSI), used when MOE is cleared.
+    pub fn set_ossi(&self, enable: bool) {
+        self.regs_advanced().bdtr().modify(|w| w.set_ossi(enable));
+    }
+
+    /// Set the off-state selection for run mode (OSSR), used when MOE is set and a channel is disabled.
+    pub fn set_ossr(&self, enable: bool) {
+        self.regs_advanced().bdtr().modify(|w| w.set_ossr(enable));
+    }
+
+    /// Enable/disable automatic output, MOE is set again at the next update event after a break.
+    pub fn set_automatic_output(&self, enable: bool) {
+        self.regs_advanced().bdtr().modify(|w| w.set_aoe(enable));
+    }
+
+    /// Enable/disable the break input.
+    pub fn set_break_enable(&self, enable: bool) {
+        self.regs_advanced().bdtr().modify(|w| w.set_bke(enable));
+    }
+
+    /// Set the break input polarity.
+    pub fn set_break_polarity(&self, polarity: OutputPolarity) {
+        // BKP=1 means break input is active high, which is the opposite of CCxP
+        self.regs_advanced().bdtr().modify(|w| w.set_bkp(!bool::from(polarity)));
+    }
+
     /// Set complementary output polarity.
     pub fn set_complementary_output_polarity(&self, channel: Channel, polarity: OutputPolarity) {
         self.regs_advanced()
@@ -14,7 +14,11 @@
 use crate::peripheral::RccPeripheral;
 use crate::{interrupt, RemapPeripheral};
 
+#[cfg(not(timer_x0))]
+pub mod asymmetric_pwm;
 pub mod complementary_pwm;
+#[cfg(not(timer_x0))]
+pub mod dma_trigger;
 pub mod low_level;
 pub mod simple_pwm;
 
@@ -0,0 +1,513 @@
+//! Interrupt-driven buffered UART.
+//!
+//! Received bytes are pushed into a ring buffer from the USART interrupt, and bytes written are
+//! queued into another one and sent from the interrupt, so no byte is lost between two reads
+//! as long as the RX buffer doesn't overflow.
+
+use core::sync::atomic::{AtomicBool, AtomicU8};
+
+use super::*;
+use crate::internal::atomic_ring_buffer::RingBuffer;
+
+/// Interrupt handler.
+pub struct BufferedInterruptHandler<T: Instance> {
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for BufferedInterruptHandler<T> {
+    unsafe fn on_interrupt() {
+        on_interrupt(T::regs(), T::buffered_state())
+    }
+}
+
+unsafe fn on_interrupt(r: pac::usart::Usart, state: &'static State) {
+    // RX
+    let sr = r.statr().read();
+    if sr.rxne() || sr.idle() || sr.pe() || sr.fe() || sr.ne() || sr.ore() {
+        // Reading DR after SR clears the RXNE, IDLE and error flags.
+        let dr = r.datar().read().dr() as u8;
+
+        let error = sr_error(sr);
+        // Keep the first error until the reader has seen it
+        if let Some(error) = error {
+            if state.rx_error.load(Ordering::Relaxed) == 0 {
+                state.rx_error.store(error_code(error), Ordering::Relaxed);
+            }
+        }
+
+        // On overrun the byte in DR is still valid, only the following one was lost
+        if sr.rxne() && matches!(error, None | Some(Error::Overrun)) {
+            let mut rx_writer = state.rx_buf.writer();
+            // If the buffer is full, the byte is dropped.
+            rx_writer.push_one(dr);
+        }
+
+        state.rx_waker.wake();
+    }
+
+    // TX
+    let cr1 = r.ctlr1().read();
+    if sr.tc() && cr1.tcie() {
+        r.ctlr1().modify(|w| w.set_tcie(false));
+
+        state.tx_done.store(true, Ordering::Release);
+        state.tx_waker.wake();
+    }
+
+    if sr.txe() && cr1.txeie() {
+        let mut tx_reader = state.tx_buf.reader();
+        if let Some(byte) = tx_reader.pop_one() {
+            r.ctlr1().modify(|w| {
+                w.set_txeie(true);
+                w.set_tcie(true);
+            });
+            state.tx_done.store(false, Ordering::Release);
+
+            r.datar().write(|w| w.set_dr(byte as u16));
+
+            state.tx_waker.wake();
+        } else {
+            // Disable interrupt until we have something to transmit again.
+            r.ctlr1().modify(|w| w.set_txeie(false));
+        }
+    }
+}
+
+pub(super) struct State {
+    rx_waker: AtomicWaker,
+    rx_buf: RingBuffer,
+    tx_waker: AtomicWaker,
+    tx_buf: RingBuffer,
+    tx_done: AtomicBool,
+    rx_error: AtomicU8,
+}
+
+impl State {
+    pub(super) const fn new() -> Self {
+        Self {
+            rx_buf: RingBuffer::new(),
+            tx_buf: RingBuffer::new(),
+            rx_waker: AtomicWaker::new(),
+            tx_waker: AtomicWaker::new(),
+            tx_done: AtomicBool::new(true),
+            rx_error: AtomicU8::new(0),
+        }
+    }
+}
+
+/// Bidirectional buffered UART
+pub struct BufferedUart<'d, T: Instance> {
+    rx: BufferedUartRx<'d, T>,
+    tx: BufferedUartTx<'d, T>,
+}
+
+/// Tx-only buffered UART
+///
+/// Created with [BufferedUart::split]
+pub struct BufferedUartTx<'d, T: Instance> {
+    _phantom: PhantomData<&'d mut T>,
+    tx: Option<PeripheralRef<'d, AnyPin>>,
+    cts: Option<PeripheralRef<'d, AnyPin>>,
+}
+
+/// Rx-only buffered UART
+///
+/// Created with [BufferedUart::split]
+pub struct BufferedUartRx<'d, T: Instance> {
+    _phantom: PhantomData<&'d mut T>,
+    rx: Option<PeripheralRef<'d, AnyPin>>,
+    rts: Option<PeripheralRef<'d, AnyPin>>,
+}
+
+impl<'d, T: Instance> BufferedUart<'d, T> {
+    /// Create a new bidirectional buffered UART driver
+    pub fn new<const REMAP: u8>(
+        peri: impl Peripheral<P = T> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, BufferedInterruptHandler<T>> + 'd,
+        rx: impl Peripheral<P = impl RxPin<T, REMAP>> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T, REMAP>> + 'd,
+        tx_buffer: &'d mut [u8],
+        rx_buffer: &'d mut [u8],
+        config: Config,
+    ) -> Result<Self, ConfigError> {
+        into_ref!(peri, rx, tx);
+
+        rx.set_as_input(Pull::None);
+        tx.set_as_af_output(AFType::OutputPushPull, Speed::High);
+        T::set_remap(REMAP);
+
+        Self::new_inner(
+            peri,
+            Some(rx.map_into()),
+            Some(tx.map_into()),
+            None,
+            None,
+            tx_buffer,
+            rx_buffer,
+            config,
+        )
+    }
+
+    /// Create a new bidirectional buffered UART driver with request-to-send and clear-to-send pins
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_rtscts<const REMAP: u8>(
+        peri: impl Peripheral<P = T> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, BufferedInterruptHandler<T>> + 'd,
+        rx: impl Peripheral<P = impl RxPin<T, REMAP>> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T, REMAP>> + 'd,
+        rts: impl Peripheral<P = impl RtsPin<T, REMAP>> + 'd,
+        cts: impl Peripheral<P = impl CtsPin<T, REMAP>> + 'd,
+        tx_buffer: &'d mut [u8],
+        rx_buffer: &'d mut [u8],
+        config: Config,
+    ) -> Result<Self, ConfigError> {
+        into_ref!(peri, rx, tx, rts, cts);
+
+        rx.set_as_input(Pull::None);
+        tx.set_as_af_output(AFType::OutputPushPull, Speed::High);
+        rts.set_as_af_output(AFType::OutputPushPull, Speed::High);
+        cts.set_as_input(Pull::None);
+        T::set_remap(REMAP);
+
+        Self::new_inner(
+            peri,
+            Some(rx.map_into()),
+            Some(tx.map_into()),
+            Some(rts.map_into()),
+            Some(cts.map_into()),
+            tx_buffer,
+            rx_buffer,
+            config,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn new_inner(
+        _peri: impl Peripheral<P = T> + 'd,
+        rx: Option<PeripheralRef<'d, AnyPin>>,
+        tx: Option<PeripheralRef<'d, AnyPin>>,
+        rts: Option<PeripheralRef<'d, AnyPin>>,
+        cts: Option<PeripheralRef<'d, AnyPin>>,
+        tx_buffer: &'d mut [u8],
+        rx_buffer: &'d mut [u8],
+        config: Config,
+    ) -> Result<Self, ConfigError> {
+        T::enable_and_reset();
+
+        let state = T::buffered_state();
+        let len = tx_buffer.len();
+        unsafe { state.tx_buf.init(tx_buffer.as_mut_ptr(), len) };
+        let len = rx_buffer.len();
+        unsafe { state.rx_buf.init(rx_buffer.as_mut_ptr(), len) };
+        state.tx_done.store(true, Ordering::Relaxed);
+        state.rx_error.store(0, Ordering::Relaxed);
+
+        let r = T::regs();
+        r.ctlr3().write(|w| {
+            w.set_rtse(rts.is_some());
+            w.set_ctse(cts.is_some());
+        });
+        configure(&r, &config, T::frequency(), true, true)?;
+
+        r.ctlr1().modify(|w| {
+            w.set_rxneie(true);
+            w.set_idleie(true);
+        });
+
+        T::Interrupt::unpend();
+        unsafe { T::Interrupt::enable() };
+
+        Ok(Self {
+            rx: BufferedUartRx {
+                _phantom: PhantomData,
+                rx,
+                rts,
+            },
+            tx: BufferedUartTx {
+                _phantom: PhantomData,
+                tx,
+                cts,
+            },
+        })
+    }
+
+    /// Split the driver into a Tx and Rx part (useful for sending to separate tasks)
+    pub fn split(self) -> (BufferedUartTx<'d, T>, BufferedUartRx<'d, T>) {
+        (self.tx, self.rx)
+    }
+
+    /// Split the driver into a Tx and Rx part by reference
+    pub fn split_ref(&mut self) -> (&mut BufferedUartTx<'d, T>, &mut BufferedUartRx<'d, T>) {
+        (&mut self.tx, &mut self.rx)
+    }
+
+    /// Reconfigure the driver
+    pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+        reconfigure::<T>(config)
+    }
+}
+
+fn error_code(error: Error) -> u8 {
+    match error {
+        Error::Parity => 1,
+        Error::Framing => 2,
+        Error::Noise => 3,
+        _ => 4,
+    }
+}
+
+/// Take the error recorded by the interrupt handler, if any.
+fn take_rx_error(state: &State) -> Result<(), Error> {
+    let code = critical_section::with(|_| {
+        let code = state.rx_error.load(Ordering::Relaxed);
+        state.rx_error.store(0, Ordering::Relaxed);
+        code
+    });
+
+    match code {
+        0 => Ok(()),
+        1 => Err(Error::Parity),
+        2 => Err(Error::Framing),
+        3 => Err(Error::Noise),
+        _ => Err(Error::Overrun),
+    }
+}
+
+impl<'d, T: Instance> BufferedUartRx<'d, T> {
+    /// Bytes received with a parity, framing or noise error are dropped, and the error is
+    /// returned by the next read. Bytes received before the error can be read afterwards.
+    async fn read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        poll_fn(move |cx| {
+            let state = T::buffered_state();
+            if let Err(e) = take_rx_error(state) {
+                return Poll::Ready(Err(e));
+            }
+
+            let mut rx_reader = unsafe { state.rx_buf.reader() };
+            let data = rx_reader.pop_slice();
+
+            if !data.is_empty() {
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                rx_reader.pop_done(len);
+
+                return Poll::Ready(Ok(len));
+            }
+
+            state.rx_waker.register(cx.waker());
+            Poll::Pending
+        })
+        .await
+    }
+
+    async fn fill_buf(&self) -> Result<&[u8], Error> {
+        poll_fn(move |cx| {
+            let state = T::buffered_state();
+            if let Err(e) = take_rx_error(state) {
+                return Poll::Ready(Err(e));
+            }
+
+            let mut rx_reader = unsafe { state.rx_buf.reader() };
+            let (p, n) = rx_reader.pop_buf();
+            if n == 0 {
+                state.rx_waker.register(cx.waker());
+                return Poll::Pending;
+            }
+
+            let buf = unsafe { core::slice::from_raw_parts(p, n) };
+            Poll::Ready(Ok(buf))
+        })
+        .await
+    }
+
+    fn consume(&self, amt: usize) {
+        let state = T::buffered_state();
+        let mut rx_reader = unsafe { state.rx_buf.reader() };
+        rx_reader.pop_done(amt);
+    }
+
+    /// Reconfigure the driver
+    pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+        reconfigure::<T>(config)
+    }
+}
+
+impl<'d, T: Instance> BufferedUartTx<'d, T> {
+    async fn write(&self, buf: &[u8]) -> Result<usize, Error> {
+        poll_fn(move |cx| {
+            let state = T::buffered_state();
+            let empty = state.tx_buf.is_empty();
+
+            let mut tx_writer = unsafe { state.tx_buf.writer() };
+            let data = tx_writer.push_slice();
+            if data.is_empty() {
+                state.tx_waker.register(cx.waker());
+                return Poll::Pending;
+            }
+
+            let n = data.len().min(buf.len());
+            data[..n].copy_from_slice(&buf[..n]);
+            tx_writer.push_done(n);
+
+            if empty {
+                // The interrupt handler stopped transmitting, TXE fires as soon as TDR is free.
+                critical_section::with(|_| T::regs().ctlr1().modify(|w| w.set_txeie(true)));
+            }
+
+            Poll::Ready(Ok(n))
+        })
+        .await
+    }
+
+    async fn flush(&self) -> Result<(), Error> {
+        poll_fn(move |cx| {
+            let state = T::buffered_state();
+
+            if !state.tx_buf.is_empty() || !state.tx_done.load(Ordering::Acquire) {
+                state.tx_waker.register(cx.waker());
+                return Poll::Pending;
+            }
+
+            Poll::Ready(Ok(()))
+        })
+        .await
+    }
+
+    /// Reconfigure the driver
+    pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+        reconfigure::<T>(config)
+    }
+}
+
+impl<'d, T: Instance> Drop for BufferedUartRx<'d, T> {
+    fn drop(&mut self) {
+        let state = T::buffered_state();
+        T::regs().ctlr1().modify(|w| {
+            w.set_rxneie(false);
+            w.set_idleie(false);
+        });
+        unsafe { state.rx_buf.deinit() };
+
+        self.rx.as_ref().map(|x| x.set_as_disconnected());
+        self.rts.as_ref().map(|x| x.set_as_disconnected());
+
+        // TX is inactive if the the buffer is not available.
+        // We can now unregister the interrupt handler
+        if state.tx_buf.len() == 0 {
+            T::Interrupt::disable();
+            T::disable();
+        }
+    }
+}
+
+impl<'d, T: Instance> Drop for BufferedUartTx<'d, T> {
+    fn drop(&mut self) {
+        let state = T::buffered_state();
+        T::regs().ctlr1().modify(|w| {
+            w.set_txeie(false);
+            w.set_tcie(false);
+        });
+        unsafe { state.tx_buf.deinit() };
+
+        self.tx.as_ref().map(|x| x.set_as_disconnected());
+        self.cts.as_ref().map(|x| x.set_as_disconnected());
+
+        // RX is inactive if the the buffer is not available.
+        // We can now unregister the interrupt handler
+        if state.rx_buf.len() == 0 {
+            T::Interrupt::disable();
+            T::disable();
+        }
+    }
+}
+
+impl<'d, T: Instance> SetConfig for BufferedUart<'d, T> {
+    type Config = Config;
+    type ConfigError = ConfigError;
+
+    fn set_config(&mut self, config: &Self::Config) -> Result<(), Self::ConfigError> {
+        self.set_config(config)
+    }
+}
+
+impl<'d, T: Instance> SetConfig for BufferedUartRx<'d, T> {
+    type Config = Config;
+    type ConfigError = ConfigError;
+
+    fn set_config(&mut self, config: &Self::Config) -> Result<(), Self::ConfigError> {
+        self.set_config(config)
+    }
+}
+
+impl<'d, T: Instance> SetConfig for BufferedUartTx<'d, T> {
+    type Config = Config;
+    type ConfigError = ConfigError;
+
+    fn set_config(&mut self, config: &Self::Config) -> Result<(), Self::ConfigError> {
+        self.set_config(config)
+    }
+}
+
+impl<'d, T: Instance> embedded_io_async::ErrorType for BufferedUart<'d, T> {
+    type Error = Error;
+}
+
+impl<'d, T: Instance> embedded_io_async::ErrorType for BufferedUartRx<'d, T> {
+    type Error = Error;
+}
+
+impl<'d, T: Instance> embedded_io_async::ErrorType for BufferedUartTx<'d, T> {
+    type Error = Error;
+}
+
+impl<'d, T: Instance> embedded_io_async::Read for BufferedUart<'d, T> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.rx.read(buf).await
+    }
+}
+
+impl<'d, T: Instance> embedded_io_async::Read for BufferedUartRx<'d, T> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Self::read(self, buf).await
+    }
+}
+
+impl<'d, T: Instance> embedded_io_async::BufRead for BufferedUart<'d, T> {
+    async fn fill_buf(&mut self) -> Result<&[u8], Self::Error> {
+        self.rx.fill_buf().await
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.rx.consume(amt)
+    }
+}
+
+impl<'d, T: Instance> embedded_io_async::BufRead for BufferedUartRx<'d, T> {
+    async fn fill_buf(&mut self) -> Result<&[u8], Self::Error> {
+        Self::fill_buf(self).await
+    }
+
+    fn consume(&mut self, amt: usize) {
+        Self::consume(self, amt)
+    }
+}
+
+impl<'d, T: Instance> embedded_io_async::Write for BufferedUart<'d, T> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.tx.write(buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.tx.flush().await
+    }
+}
+
+impl<'d, T: Instance> embedded_io_async::Write for BufferedUartTx<'d, T> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Self::write(self, buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Self::flush(self).await
+    }
+}
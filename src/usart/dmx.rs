@@ -0,0 +1,97 @@
+//! DMX512 transmitter.
+//!
+//! A DMX512 packet is a break of at least 88µs, a mark after break (MAB) of at least 8µs, then a
+//! start code and up to 512 slots sent at 250 kbaud, 8N2.
+//!
+//! SBK only holds the line low for one frame (44µs at 250 kbaud), too short for a DMX break.
+//! Instead, the baud rate is lowered to [`BREAK_BAUDRATE`] and a zero byte is sent: the start bit
+//! and the 8 data bits form the break, and the 2 stop bits form the MAB.
+
+use super::*;
+
+/// Baud rate of DMX512 slots.
+pub const DMX_BAUDRATE: u32 = 250_000;
+
+/// Baud rate used to send the break. This gives a 112.5µs break followed by a 25µs MAB.
+pub const BREAK_BAUDRATE: u32 = 80_000;
+
+/// Maximum number of slots in a universe.
+pub const MAX_SLOTS: usize = 512;
+
+/// DMX512 transmitter
+pub struct DmxTx<'d, T: Instance> {
+    tx: UartTx<'d, T, Async>,
+    brr_break: u32,
+    brr_data: u32,
+    /// Start code and slots, sent by DMA at once.
+    packet: [u8; MAX_SLOTS + 1],
+}
+
+impl<'d, T: Instance> DmxTx<'d, T> {
+    /// Create a new DMX512 transmitter.
+    pub fn new<const REMAP: u8>(
+        peri: impl Peripheral<P = T> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T, REMAP>> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+        tx_dma: impl Peripheral<P = impl TxDma<T>> + 'd,
+    ) -> Result<Self, ConfigError> {
+        let mut config = Config::default();
+        config.baudrate = DMX_BAUDRATE;
+        config.data_bits = DataBits::DataBits8;
+        config.stop_bits = StopBits::STOP2;
+        config.parity = Parity::ParityNone;
+
+        let pclk = T::frequency().0;
+        let brr_break = calc_brr(pclk, BREAK_BAUDRATE, config.max_baudrate_error_ppm)?;
+        let brr_data = calc_brr(pclk, DMX_BAUDRATE, config.max_baudrate_error_ppm)?;
+
+        let tx = UartTx::new(peri, tx, tx_dma, config)?;
+
+        // The break and the end of the packet are waited for through TC
+        T::Interrupt::unpend();
+        unsafe { T::Interrupt::enable() };
+
+        Ok(Self {
+            tx,
+            brr_break,
+            brr_data,
+            packet: [0; MAX_SLOTS + 1],
+        })
+    }
+
+    /// Send a packet with the NULL start code (dimmer data).
+    pub async fn write(&mut self, slots: &[u8]) -> Result<(), Error> {
+        self.write_with_start_code(0, slots).await
+    }
+
+    /// Send a packet with the given start code, e.g. `0xCC` for RDM.
+    ///
+    /// `slots` holds up to [`MAX_SLOTS`] bytes. Returns once the last slot has been handed to
+    /// the USART, the next packet waits for it to go out before sending its break.
+    pub async fn write_with_start_code(&mut self, start_code: u8, slots: &[u8]) -> Result<(), Error> {
+        if slots.len() > MAX_SLOTS {
+            return Err(Error::BufferTooLong);
+        }
+
+        self.packet[0] = start_code;
+        self.packet[1..=slots.len()].copy_from_slice(slots);
+
+        self.send_break().await;
+        self.tx.write(&self.packet[..=slots.len()]).await
+    }
+
+    /// Send break and MAB, then switch back to the slot baud rate.
+    async fn send_break(&mut self) {
+        let r = T::regs();
+
+        // the baud rate must not change in the middle of a frame
+        wait_transmission_complete::<T>().await;
+        r.brr().write(|w| w.0 = self.brr_break);
+
+        // STATR was just read, so this write clears TC
+        r.datar().write(|w| w.set_dr(0));
+        wait_transmission_complete::<T>().await;
+
+        r.brr().write(|w| w.0 = self.brr_data);
+    }
+}
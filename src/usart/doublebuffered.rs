@@ -0,0 +1,124 @@
+//! Double-buffered UART receiver, backed by a circular DMA transfer split into two halves.
+
+use core::mem;
+
+use super::*;
+use crate::dma::ReadableDoubleBuffer;
+
+/// Rx-only double-buffered UART driver
+///
+/// Created with [UartRx::into_double_buffered]
+///
+/// The DMA continuously receives into one half of the buffer while the other half is handed out
+/// in place by [`read_chunk`](Self::read_chunk), so no copy is made. Each chunk has to be
+/// processed before the DMA fills the other half, otherwise [`Error::Overrun`] is returned.
+pub struct DoubleBufferedUartRx<'d, T: Instance> {
+    _phantom: PhantomData<T>,
+    rx: Option<PeripheralRef<'d, AnyPin>>,
+    rts: Option<PeripheralRef<'d, AnyPin>>,
+    double_buf: ReadableDoubleBuffer<'d, u8>,
+}
+
+impl<'d, T: Instance> UartRx<'d, T, Async> {
+    /// Turn the `UartRx` into a double-buffered uart which continously receives in the background.
+    /// The `dma_buf` is split into two halves of `dma_buf.len() / 2` bytes, its length must be even.
+    pub fn into_double_buffered(mut self, dma_buf: &'d mut [u8]) -> DoubleBufferedUartRx<'d, T> {
+        assert!(dma_buf.len() >= 2 && dma_buf.len() <= 0xFFFF && dma_buf.len() % 2 == 0);
+
+        let opts = Default::default();
+
+        // Safety: we forget the struct before this function returns.
+        let rx_dma = self.rx_dma.as_mut().unwrap();
+        let request = rx_dma.request;
+        let rx_dma = unsafe { rx_dma.channel.clone_unchecked() };
+
+        let rx = unsafe { self.rx.as_ref().map(|x| x.clone_unchecked()) };
+        let rts = unsafe { self.rts.as_ref().map(|x| x.clone_unchecked()) };
+
+        let double_buf =
+            unsafe { ReadableDoubleBuffer::new(rx_dma, request, T::regs().datar().as_ptr() as _, dma_buf, opts) };
+
+        // Don't disable the clock, the refcount is taken over
+        mem::forget(self);
+
+        DoubleBufferedUartRx {
+            _phantom: PhantomData,
+            rx,
+            rts,
+            double_buf,
+        }
+    }
+}
+
+impl<'d, T: Instance> DoubleBufferedUartRx<'d, T> {
+    /// Reconfigure the driver
+    pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+        reconfigure::<T>(config)
+    }
+
+    /// Start the background reception. Called automatically on the first [`read_chunk`](Self::read_chunk).
+    pub fn start(&mut self) {
+        compiler_fence(Ordering::SeqCst);
+        self.double_buf.start();
+
+        T::regs().ctlr3().modify(|w| w.set_dmar(true));
+    }
+
+    /// Stop the background reception.
+    pub fn stop(&mut self) {
+        T::regs().ctlr3().modify(|w| w.set_dmar(false));
+
+        self.double_buf.request_stop();
+        while self.double_buf.is_running() {}
+
+        compiler_fence(Ordering::SeqCst);
+    }
+
+    /// Length of the chunks returned by [`read_chunk`](Self::read_chunk).
+    pub fn chunk_len(&self) -> usize {
+        self.double_buf.chunk_len()
+    }
+
+    /// Wait until the next half of the buffer is filled and return it.
+    ///
+    /// Returns [`Error::Overrun`] if a chunk was overwritten before it was returned, or while the
+    /// previous one was processed. Reception then continues with the next chunk to be filled.
+    ///
+    /// Parity, framing and noise errors are reported once, the bytes received with them
+    /// are kept in the buffer.
+    pub async fn read_chunk(&mut self) -> Result<&[u8], Error> {
+        let r = T::regs();
+        if !r.ctlr3().read().dmar() {
+            self.start();
+        }
+
+        let sr = r.statr().read();
+        if let Some(error) = sr_error(sr) {
+            // The DMA already moved the data byte out of DATAR, so reading it only clears the flags
+            if !sr.rxne() {
+                let _ = r.datar().read().dr();
+            }
+            return Err(error);
+        }
+
+        self.double_buf.read_chunk().await.map_err(|_| Error::Overrun)
+    }
+}
+
+impl<'d, T: Instance> SetConfig for DoubleBufferedUartRx<'d, T> {
+    type Config = Config;
+    type ConfigError = ConfigError;
+
+    fn set_config(&mut self, config: &Self::Config) -> Result<(), Self::ConfigError> {
+        self.set_config(config)
+    }
+}
+
+impl<'d, T: Instance> Drop for DoubleBufferedUartRx<'d, T> {
+    fn drop(&mut self) {
+        self.stop();
+        self.rx.as_ref().map(|x| x.set_as_disconnected());
+        self.rts.as_ref().map(|x| x.set_as_disconnected());
+        drop_tx_rx::<T>();
+    }
+}
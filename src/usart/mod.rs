@@ -13,21 +13,33 @@ Multiple interrupt sources
 
 use core::future::poll_fn;
 use core::marker::PhantomData;
-use core::sync::atomic::{compiler_fence, Ordering};
+use core::sync::atomic::{compiler_fence, AtomicU32, AtomicU8, Ordering};
 use core::task::Poll;
 
+use embassy_embedded_hal::SetConfig;
 use embassy_sync::waitqueue::AtomicWaker;
 use futures::future::{select, Either};
 
 use crate::dma::ChannelAndRequest;
 use crate::gpio::{AFType, SealedPin};
-use crate::gpio::{AnyPin, Pull, Speed};
+use crate::gpio::{AnyPin, Level, Output, Pin, Pull, Speed};
 use crate::internal::drop::OnDrop;
 use crate::interrupt::typelevel::Interrupt;
+use crate::low_power::LowPowerPeripheral;
 use crate::mode::{Async, Blocking, Mode};
 use crate::time::Hertz;
+use crate::timer::low_level::Timer;
+use crate::timer::BasicInstance;
 use crate::{interrupt, into_ref, pac, peripherals, Peripheral, PeripheralRef};
 
+mod buffered;
+pub use buffered::*;
+mod doublebuffered;
+pub use doublebuffered::DoubleBufferedUartRx;
+pub mod dmx;
+mod ringbuffered;
+pub use ringbuffered::RingBufferedUartRx;
+
 /// Interrupt handler.
 pub struct InterruptHandler<T: Instance> {
     _phantom: PhantomData<T>,
@@ -38,7 +50,24 @@ impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandl
         let r = T::regs();
         let s = T::state();
 
-        let (sr, cr1, cr3) = (r.statr().read(), r.ctlr1().read(), r.ctlr3().read());
+        let (sr, cr1, cr2, cr3) = (r.statr().read(), r.ctlr1().read(), r.ctlr2().read(), r.ctlr3().read());
+
+        if cr1.tcie() && sr.tc() {
+            // Last frame sent, the flag is left for the listener
+            r.ctlr1().modify(|w| w.set_tcie(false));
+
+            compiler_fence(Ordering::SeqCst);
+            s.tx_waker.wake();
+        }
+
+        if cr2.lbdie() && sr.lbd() {
+            // LIN break detected, the flag is cleared by the listener
+            r.ctlr2().modify(|w| w.set_lbdie(false));
+
+            compiler_fence(Ordering::SeqCst);
+            s.rx_waker.wake();
+            return;
+        }
 
         let has_errors = (sr.pe() && cr1.peie()) || ((sr.fe() || sr.ne() || sr.ore()) && cr3.eie());
         if has_errors {
@@ -66,7 +95,11 @@ impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandl
         } else if cr1.rxneie() {
             // We cannot check the RXNE flag as it is auto-cleared by the DMA controller
 
-            // It is up to the listener to determine if this in fact was a RX event and disable the RXNE detection
+            // It is up to the listener to determine if this in fact was a RX event and re-enable the RXNE detection
+            r.ctlr1().modify(|w| {
+                // disable RXNE interrupt, RXNE stays set until the listener reads the data
+                w.set_rxneie(false);
+            });
         } else {
             return;
         }
@@ -104,6 +137,26 @@ pub enum StopBits {
     STOP1P5 = 0b11,
 }
 
+/// LIN break detection length
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BreakLength {
+    /// 10-bit break detection
+    Bits10,
+    /// 11-bit break detection
+    Bits11,
+}
+
+/// Receiver wakeup method, see [`UartRx::enter_mute`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Wakeup {
+    /// Leave mute mode when the line goes idle.
+    IdleLine,
+    /// Leave mute mode on an address frame (MSB set) matching the node address.
+    AddressMark,
+}
+
 #[non_exhaustive]
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Config {
@@ -118,6 +171,11 @@ pub struct Config {
     /// If false: the error is ignored and cleared
     pub detect_previous_overrun: bool,
 
+    /// Maximum deviation of the generated baud rate from `baudrate`, in ppm.
+    ///
+    /// Configuration fails with [`ConfigError::BaudrateTooInaccurate`] above it.
+    pub max_baudrate_error_ppm: u32,
+
     half_duplex: bool,
 }
 impl Default for Config {
@@ -131,6 +189,9 @@ impl Default for Config {
 
             detect_previous_overrun: false,
 
+            // 2%, leaving margin for the other end
+            max_baudrate_error_ppm: 20_000,
+
             half_duplex: false,
         }
     }
@@ -156,11 +217,182 @@ pub enum Error {
     BufferTooLong,
 }
 
+/// First error flagged in `sr`, in the order [`UartRx`] reports them.
+fn sr_error(sr: pac::usart::regs::Statr) -> Option<Error> {
+    if sr.pe() {
+        Some(Error::Parity)
+    } else if sr.fe() {
+        Some(Error::Framing)
+    } else if sr.ne() {
+        Some(Error::Noise)
+    } else if sr.ore() {
+        Some(Error::Overrun)
+    } else {
+        None
+    }
+}
+
+impl embedded_io::Error for Error {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        embedded_io::ErrorKind::Other
+    }
+}
+
+/// RS-485 driver enable (DE) pin settings.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeConfig {
+    /// Level of the DE pin while transmitting.
+    pub active_level: Level,
+    /// Delay between asserting DE and the first start bit, in microseconds.
+    pub pre_delay_us: u32,
+    /// Delay between the end of the last stop bit (TC) and releasing DE, in microseconds.
+    pub post_delay_us: u32,
+}
+
+impl Default for DeConfig {
+    /// Active high, no delays
+    fn default() -> Self {
+        Self {
+            active_level: Level::High,
+            pre_delay_us: 0,
+            post_delay_us: 0,
+        }
+    }
+}
+
+/// DE pin, driven around every write.
+struct DriverEnable<'d> {
+    pin: Output<'d>,
+    config: DeConfig,
+}
+
+impl<'d> DriverEnable<'d> {
+    fn new(pin: impl Peripheral<P = impl Pin> + 'd, config: DeConfig) -> Self {
+        let inactive = Level::from(!bool::from(config.active_level));
+        Self {
+            pin: Output::new(pin, inactive, Speed::High),
+            config,
+        }
+    }
+
+    fn set_active(&mut self, active: bool) {
+        self.pin
+            .set_level(Level::from(active == bool::from(self.config.active_level)));
+    }
+
+    fn assert(&mut self) {
+        self.set_active(true);
+        if self.config.pre_delay_us != 0 {
+            crate::delay::Delay.delay_us(self.config.pre_delay_us);
+        }
+    }
+
+    /// Release DE once the last frame left the shift register.
+    fn release(&mut self, r: pac::usart::Usart) {
+        while !r.statr().read().tc() {}
+        if self.config.post_delay_us != 0 {
+            crate::delay::Delay.delay_us(self.config.post_delay_us);
+        }
+        self.set_active(false);
+    }
+}
+
+/// DE asserted by an async write, released right away when dropped, e.g. if the write is cancelled.
+struct AssertedDe<'a, 'd>(&'a mut DriverEnable<'d>);
+
+impl<'a, 'd> AssertedDe<'a, 'd> {
+    async fn assert(de: &'a mut DriverEnable<'d>) -> Self {
+        de.set_active(true);
+        let this = Self(de);
+        if this.0.config.pre_delay_us != 0 {
+            embassy_time::Timer::after_micros(this.0.config.pre_delay_us as u64).await;
+        }
+        this
+    }
+
+    /// Release DE once the last frame left the shift register.
+    async fn release<T: Instance>(self) {
+        wait_transmission_complete::<T>().await;
+        if self.0.config.post_delay_us != 0 {
+            embassy_time::Timer::after_micros(self.0.config.post_delay_us as u64).await;
+        }
+    }
+}
+
+impl<'a, 'd> Drop for AssertedDe<'a, 'd> {
+    fn drop(&mut self) {
+        self.0.set_active(false);
+    }
+}
+
+/// Wait for the last frame to leave the shift register (TC), through the USART interrupt.
+async fn wait_transmission_complete<T: Instance>() {
+    let r = T::regs();
+
+    let _on_drop = OnDrop::new(move || {
+        r.ctlr1().modify(|w| w.set_tcie(false));
+    });
+
+    poll_fn(|cx| {
+        T::state().tx_waker.register(cx.waker());
+        if r.statr().read().tc() {
+            return Poll::Ready(());
+        }
+        r.ctlr1().modify(|w| w.set_tcie(true));
+        Poll::Pending
+    })
+    .await
+}
+
+/// Smartcard (ISO 7816-3) mode settings
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SmartcardConfig {
+    /// Card clock prescaler, the CK pin outputs PCLK / (2 * prescaler). Range 1..=31
+    pub prescaler: u8,
+    /// Guard time after each transmitted frame, in baud clocks
+    pub guard_time: u8,
+    /// Send a NACK when a parity error is detected on reception
+    pub nack: bool,
+}
+
+impl Default for SmartcardConfig {
+    /// 4 MHz card clock at 48 MHz PCLK, 1 etu guard time, NACK enabled
+    fn default() -> Self {
+        Self {
+            prescaler: 6,
+            guard_time: 1,
+            nack: true,
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ConfigError {
     BaudrateTooLow,
     BaudrateTooHigh,
+    /// The closest baud rate the clock can generate is off by more than `max_baudrate_error_ppm`
+    BaudrateTooInaccurate,
+}
+
+/// Baud rate detection errors
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DetectBaudError {
+    /// The UART has no RX pin to sample, e.g. in half-duplex mode
+    NoRxPin,
+    /// No whole character was received in time
+    Timeout,
+    /// The detected baud rate can't be generated
+    Config(ConfigError),
+}
+
+impl From<ConfigError> for DetectBaudError {
+    fn from(e: ConfigError) -> Self {
+        Self::Config(e)
+    }
 }
 
 enum ReadCompletionEvent {
@@ -178,11 +410,14 @@ pub struct UartTx<'d, T: Instance, M: Mode> {
     _phantom: PhantomData<(T, M)>,
     tx: Option<PeripheralRef<'d, AnyPin>>,
     cts: Option<PeripheralRef<'d, AnyPin>>,
+    ck: Option<PeripheralRef<'d, AnyPin>>,
+    de: Option<DriverEnable<'d>>,
     tx_dma: Option<ChannelAndRequest<'d>>,
 }
 
 impl<'d, T: Instance, M: Mode> UartTx<'d, T, M> {
-    fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+    /// Reconfigure the driver
+    pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
         reconfigure::<T>(config)
     }
 
@@ -199,13 +434,15 @@ impl<'d, T: Instance, M: Mode> UartTx<'d, T, M> {
         rb.ctlr3().modify(|w| w.set_ctse(cts.is_some()));
         configure(&rb, &config, T::frequency(), true, false)?;
 
-        // create state once!
-        let _s = T::state();
+        let s = T::state();
+        s.tx_rx_refcount.store(1, Ordering::Relaxed);
 
         Ok(Self {
             _phantom: PhantomData,
             tx,
             cts,
+            ck: None,
+            de: None,
             tx_dma,
         })
     }
@@ -214,10 +451,36 @@ impl<'d, T: Instance, M: Mode> UartTx<'d, T, M> {
     pub fn blocking_write(&mut self, buffer: &[u8]) -> Result<(), Error> {
         let rb = T::regs();
 
+        if let Some(de) = self.de.as_mut() {
+            de.assert();
+        }
         for &c in buffer {
             while !rb.statr().read().tc() {} // wait tx complete
             rb.datar().write(|w| w.set_dr(c as u16));
         }
+        if let Some(de) = self.de.as_mut() {
+            de.release(rb);
+        }
+        Ok(())
+    }
+
+    /// Perform a blocking write of 9-bit frames
+    ///
+    /// Requires [`DataBits::DataBits9`] without parity. The 9th bit is used as address mark in
+    /// multiprocessor communication.
+    pub fn blocking_write_9bit(&mut self, buffer: &[u16]) -> Result<(), Error> {
+        let rb = T::regs();
+
+        if let Some(de) = self.de.as_mut() {
+            de.assert();
+        }
+        for &c in buffer {
+            while !rb.statr().read().tc() {} // wait tx complete
+            rb.datar().write(|w| w.set_dr(c & 0x1FF));
+        }
+        if let Some(de) = self.de.as_mut() {
+            de.release(rb);
+        }
         Ok(())
     }
 
@@ -228,6 +491,18 @@ impl<'d, T: Instance, M: Mode> UartTx<'d, T, M> {
         while !rb.statr().read().txe() {} // wait tx ends
         Ok(())
     }
+
+    /// Send a break frame (all zeros, 10 or 11 bits long depending on the word length)
+    ///
+    /// Blocks until the break has been sent.
+    pub fn send_break(&mut self) {
+        let rb = T::regs();
+
+        while !rb.statr().read().txe() {}
+        rb.ctlr1().modify(|w| w.set_sbk(true));
+        // SBK is cleared by hardware at the stop bit of the break frame
+        while rb.ctlr1().read().sbk() {}
+    }
 }
 
 impl<'d, T: Instance> UartTx<'d, T, Async> {
@@ -271,14 +546,39 @@ impl<'d, T: Instance> UartTx<'d, T, Async> {
 
     /// Initiate an asynchronous UART write
     pub async fn write(&mut self, buffer: &[u8]) -> Result<(), Error> {
+        if buffer.is_empty() {
+            return Ok(());
+        } else if buffer.len() > 0xFFFF {
+            return Err(Error::BufferTooLong);
+        }
+
+        let r = T::regs();
+
+        // make sure DMA Tx Request is cleared when this future is dropped (or completes)
+        let _on_drop = OnDrop::new(move || {
+            r.ctlr3().modify(|w| w.set_dmat(false));
+        });
+
+        // DE is released once the last frame is sent, or right away if this future is dropped
+        let de = match self.de.as_mut() {
+            Some(de) => Some(AssertedDe::assert(de).await),
+            None => None,
+        };
+        // TC is only cleared by a DATAR write following a STATR read, DMA writes don't
+        r.statr().modify(|w| w.set_tc(false));
+
         let ch = self.tx_dma.as_mut().unwrap();
-        T::regs().ctlr3().modify(|reg| {
+        r.ctlr3().modify(|reg| {
             reg.set_dmat(true);
         });
         // If we don't assign future to a variable, the data register pointer
         // is held across an await and makes the future non-Send.
-        let transfer = unsafe { ch.write(buffer, T::regs().datar().as_ptr() as _, Default::default()) };
+        let transfer = unsafe { ch.write(buffer, r.datar().as_ptr() as _, Default::default()) };
         transfer.await;
+
+        if let Some(de) = de {
+            de.release::<T>().await;
+        }
         Ok(())
     }
 }
@@ -349,8 +649,8 @@ impl<'d, T: Instance, M: Mode> UartRx<'d, T, M> {
         T::Interrupt::unpend();
         unsafe { T::Interrupt::enable() };
 
-        // create state once!
-        let _s = T::state();
+        let s = T::state();
+        s.tx_rx_refcount.store(1, Ordering::Relaxed);
 
         Ok(Self {
             _phantom: PhantomData,
@@ -364,6 +664,7 @@ impl<'d, T: Instance, M: Mode> UartRx<'d, T, M> {
 
     /// Reconfigure the driver
     pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+        self.detect_previous_overrun = config.detect_previous_overrun;
         reconfigure::<T>(config)
     }
 
@@ -420,8 +721,192 @@ impl<'d, T: Instance, M: Mode> UartRx<'d, T, M> {
         }
         Ok(())
     }
+
+    /// Perform a blocking read of 9-bit frames into `buffer`
+    ///
+    /// Requires [`DataBits::DataBits9`] without parity.
+    pub fn blocking_read_9bit(&mut self, buffer: &mut [u16]) -> Result<(), Error> {
+        let r = T::regs();
+        for b in buffer {
+            while !self.check_rx_flags()? {}
+            *b = r.datar().read().dr() & 0x1FF
+        }
+        Ok(())
+    }
+
+    /// Enable or disable LIN mode, detecting breaks of the given length
+    ///
+    /// LIN mode requires 1 stop bit, and no clock output, smartcard, half-duplex or IrDA mode.
+    pub fn set_lin_mode(&mut self, break_length: Option<BreakLength>) {
+        T::regs().ctlr2().modify(|w| {
+            w.set_linen(break_length.is_some());
+            w.set_lbdl(break_length == Some(BreakLength::Bits11));
+        });
+    }
+
+    /// Whether a LIN break was detected since the last call
+    pub fn check_break(&mut self) -> bool {
+        let r = T::regs();
+        let detected = r.statr().read().lbd();
+        if detected {
+            r.statr().modify(|w| w.set_lbd(false));
+        }
+        detected
+    }
+
+    /// Set the node address used to leave mute mode with [`Wakeup::AddressMark`]
+    ///
+    /// Only the lower 4 bits are compared with the address frames.
+    pub fn set_address(&mut self, address: u8) {
+        T::regs().ctlr2().modify(|w| w.set_add(address & 0x0F));
+    }
+
+    /// Put the receiver in mute mode, ignoring all frames until the wakeup condition
+    ///
+    /// With [`Wakeup::AddressMark`], frames with the MSB set are address frames, and the receiver
+    /// wakes on the one matching [`set_address`](Self::set_address). That address frame is received as well.
+    pub fn enter_mute(&mut self, wakeup: Wakeup) {
+        T::regs().ctlr1().modify(|w| {
+            w.set_wake(wakeup == Wakeup::AddressMark);
+            w.set_rwu(true);
+        });
+    }
+
+    /// Whether the receiver is still in mute mode
+    pub fn is_muted(&self) -> bool {
+        T::regs().ctlr1().read().rwu()
+    }
+
+    /// Perform a blocking read into `buffer`, until it is full or the line goes idle
+    /// after at least one byte was received.
+    ///
+    /// Returns the number of bytes read.
+    pub fn blocking_read_until_idle(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        let r = T::regs();
+
+        // Discard an idle flag left over from a previous reception
+        let sr = r.statr().read();
+        if sr.idle() && !sr.rxne() {
+            // This read also clears the error and idle interrupt flags on v1.
+            let _ = r.datar().read().dr();
+        }
+        self.buffered_sr.set_idle(false);
+
+        let mut n = 0;
+        while n < buffer.len() {
+            if self.check_rx_flags()? {
+                buffer[n] = r.datar().read().dr() as u8;
+                n += 1;
+            } else if n > 0 {
+                // The idle flag may have been buffered along with the last byte
+                if self.buffered_sr.idle() {
+                    self.buffered_sr.set_idle(false);
+                    break;
+                }
+
+                let sr = r.statr().read();
+                if sr.idle() && !sr.rxne() {
+                    let _ = r.datar().read().dr();
+                    break;
+                }
+            }
+        }
+        Ok(n)
+    }
+
+    /// Detect the baud rate of the sender from the next received character, and switch to it
+    ///
+    /// The width of the start bit is measured by sampling the RX pin against `tim`, which runs
+    /// free at its full clock rate during the measurement. This requires the least significant bit
+    /// of the character to be 1, as for `0x55` (`'U'`), `0x0D` (`'\r'`) or `0x7F`. The character
+    /// itself is discarded.
+    ///
+    /// Blocks until a character is received, or for `timeout_ms`. The result is rounded to a
+    /// common baud rate when it is within 5% of one. Returns the new baud rate.
+    pub fn detect_baud<TIM: BasicInstance>(
+        &mut self,
+        tim: impl Peripheral<P = TIM> + '_,
+        timeout_ms: u32,
+    ) -> Result<u32, DetectBaudError> {
+        let r = T::regs();
+        let pin = self.rx.as_ref().ok_or(DetectBaudError::NoRxPin)?;
+        let is_high = || pin.block().indr().read().idr(pin._pin() as usize);
+
+        let tim = Timer::new(tim);
+        let regs = tim.regs_basic();
+        regs.psc().write_value(0);
+        regs.atrlr().write_value(0xFFFF);
+        regs.swevgr().write(|w| w.set_ug(true));
+        tim.start();
+
+        // Accumulates the counter, which wraps far slower than one loop iteration, and checks the
+        // total against the timeout
+        let timeout = TIM::frequency().0 as u64 * timeout_ms as u64 / 1000;
+        let mut total = 0u64;
+        let mut last = regs.cnt().read();
+        let mut elapsed = || {
+            let now = regs.cnt().read();
+            let delta = now.wrapping_sub(last) as u32;
+            last = now;
+            total += delta as u64;
+            if total > timeout {
+                Err(DetectBaudError::Timeout)
+            } else {
+                Ok(delta)
+            }
+        };
+
+        while !is_high() {
+            elapsed()?;
+        }
+        while is_high() {
+            elapsed()?;
+        }
+        elapsed()?;
+
+        let mut bit = 0;
+        while !is_high() {
+            bit += elapsed()?;
+        }
+        bit += elapsed()?;
+
+        let measured = TIM::frequency().0 / bit.max(1);
+        let baudrate = AUTOBAUD_RATES
+            .iter()
+            .copied()
+            .find(|&rate| measured.abs_diff(rate) <= rate / 20)
+            .unwrap_or(measured);
+
+        // Skip the rest of the character: wait for the line to stay high for a whole frame
+        let mut high = 0;
+        while high < bit * 12 {
+            if is_high() {
+                high += elapsed()?;
+            } else {
+                elapsed()?;
+                high = 0;
+            }
+        }
+
+        drop(tim);
+
+        let brr = calc_brr(T::frequency().0, baudrate, Config::default().max_baudrate_error_ppm)?;
+        r.brr().write(|w| w.0 = brr);
+
+        // Clear the flags and data of the character received at the wrong baud rate
+        let _ = r.statr().read();
+        let _ = r.datar().read().dr();
+        self.buffered_sr = ch32_metapac::usart::regs::Statr(0);
+
+        Ok(baudrate)
+    }
 }
 
+/// Baud rates [`UartRx::detect_baud`] rounds to.
+const AUTOBAUD_RATES: [u32; 12] = [
+    1200, 2400, 4800, 9600, 14400, 19200, 38400, 57600, 115200, 230400, 460800, 921600,
+];
+
 impl<'d, T: Instance> UartRx<'d, T, Async> {
     /// Create a new rx-only UART with no hardware flow control.
     ///
@@ -477,6 +962,77 @@ impl<'d, T: Instance> UartRx<'d, T, Async> {
         self.inner_read(buffer, true).await
     }
 
+    /// Wait for a break condition on the line
+    ///
+    /// In LIN mode (see [`set_lin_mode`](Self::set_lin_mode)) this uses the hardware break detection.
+    /// Otherwise a break is recognized as a frame of all zeros with a framing error, and the data
+    /// received meanwhile is discarded.
+    ///
+    /// Breaks detected before this call are ignored.
+    pub async fn wait_for_break(&mut self) {
+        if T::regs().ctlr2().read().linen() {
+            self.wait_for_lin_break().await
+        } else {
+            self.wait_for_framing_break().await
+        }
+    }
+
+    async fn wait_for_framing_break(&mut self) {
+        let r = T::regs();
+
+        // make sure the RXNE interrupt is disabled when this future is dropped
+        let _on_drop = OnDrop::new(move || {
+            r.ctlr1().modify(|w| w.set_rxneie(false));
+        });
+
+        // Discard stale data and flags
+        let _ = r.statr().read();
+        let _ = r.datar().read().dr();
+
+        poll_fn(move |cx| {
+            T::state().rx_waker.register(cx.waker());
+
+            let sr = r.statr().read();
+            if sr.rxne() {
+                // This read also clears the error flags
+                let data = r.datar().read().dr();
+                if sr.fe() && data == 0 {
+                    return Poll::Ready(());
+                }
+            }
+
+            // The interrupt handler disables RXNE detection every time it fires
+            r.ctlr1().modify(|w| w.set_rxneie(true));
+
+            Poll::Pending
+        })
+        .await
+    }
+
+    async fn wait_for_lin_break(&mut self) {
+        let r = T::regs();
+
+        // make sure the break interrupt is disabled when this future is dropped
+        let _on_drop = OnDrop::new(move || {
+            r.ctlr2().modify(|w| w.set_lbdie(false));
+        });
+
+        r.statr().modify(|w| w.set_lbd(false));
+        r.ctlr2().modify(|w| w.set_lbdie(true));
+
+        poll_fn(move |cx| {
+            T::state().rx_waker.register(cx.waker());
+
+            if r.statr().read().lbd() {
+                r.statr().modify(|w| w.set_lbd(false));
+                return Poll::Ready(());
+            }
+
+            Poll::Pending
+        })
+        .await
+    }
+
     async fn inner_read_run(
         &mut self,
         buffer: &mut [u8],
@@ -714,7 +1270,8 @@ impl<'d, T: Instance, M: Mode> Drop for UartTx<'d, T, M> {
     fn drop(&mut self) {
         self.tx.as_ref().map(|x| x.set_as_disconnected());
         self.cts.as_ref().map(|x| x.set_as_disconnected());
-        T::disable();
+        self.ck.as_ref().map(|x| x.set_as_disconnected());
+        drop_tx_rx::<T>();
     }
 }
 
@@ -722,6 +1279,19 @@ impl<'d, T: Instance, M: Mode> Drop for UartRx<'d, T, M> {
     fn drop(&mut self) {
         self.rx.as_ref().map(|x| x.set_as_disconnected());
         self.rts.as_ref().map(|x| x.set_as_disconnected());
+        drop_tx_rx::<T>();
+    }
+}
+
+/// Disable the peripheral once both halves are dropped.
+fn drop_tx_rx<T: Instance>() {
+    let s = T::state();
+    let is_last_drop = critical_section::with(|_| {
+        let refcount = s.tx_rx_refcount.load(Ordering::Relaxed).saturating_sub(1);
+        s.tx_rx_refcount.store(refcount, Ordering::Relaxed);
+        refcount == 0
+    });
+    if is_last_drop {
         T::disable();
     }
 }
@@ -733,10 +1303,11 @@ impl<'d, T: Instance, M: Mode> Drop for UartRx<'d, T, M> {
 ///
 /// ### Notes on [`embedded_io::Read`]
 ///
-/// `embedded_io::Read` requires guarantees that the base [`UartRx`] cannot provide.
+/// `embedded_io::Read` is implemented in blocking mode, but the base [`UartRx`] does not buffer:
+/// bytes arriving while no read is in progress are lost to overruns.
 ///
-/// See [`UartRx`] for more details, and see [`BufferedUart`] and [`RingBufferedUartRx`]
-/// as alternatives that do provide the necessary guarantees for `embedded_io::Read`.
+/// See [`BufferedUart`] and [`RingBufferedUartRx`] as alternatives that keep receiving
+/// in the background.
 pub struct Uart<'d, T: Instance, M: Mode> {
     tx: UartTx<'d, T, M>,
     rx: UartRx<'d, T, M>,
@@ -758,9 +1329,7 @@ impl<'d, T: Instance, M: Mode> Uart<'d, T, M> {
         rx_dma: Option<ChannelAndRequest<'d>>,
         config: Config,
     ) -> Result<Self, ConfigError> {
-        // UartRx and UartTx have one refcount each.
         T::enable_and_reset();
-        T::enable_and_reset(); // CH32: rfcnt is not used yet
 
         let r = T::regs();
 
@@ -773,14 +1342,17 @@ impl<'d, T: Instance, M: Mode> Uart<'d, T, M> {
         T::Interrupt::unpend();
         unsafe { T::Interrupt::enable() };
 
-        // create state once!
-        let _s = T::state();
+        // UartRx and UartTx have one refcount each.
+        let s = T::state();
+        s.tx_rx_refcount.store(2, Ordering::Relaxed);
 
         Ok(Self {
             tx: UartTx {
                 _phantom: PhantomData,
                 tx,
                 cts,
+                ck: None,
+                de: None,
                 tx_dma,
             },
             rx: UartRx {
@@ -814,12 +1386,63 @@ impl<'d, T: Instance, M: Mode> Uart<'d, T, M> {
         self.rx.blocking_read(buffer)
     }
 
+    /// Perform a blocking read into `buffer` until it is full or the line goes idle
+    pub fn blocking_read_until_idle(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        self.rx.blocking_read_until_idle(buffer)
+    }
+
+    /// Detect the baud rate of the sender from the next received character, and switch to it
+    pub fn detect_baud<TIM: BasicInstance>(
+        &mut self,
+        tim: impl Peripheral<P = TIM> + '_,
+        timeout_ms: u32,
+    ) -> Result<u32, DetectBaudError> {
+        self.rx.detect_baud(tim, timeout_ms)
+    }
+
+    /// Perform a blocking write of 9-bit frames
+    pub fn blocking_write_9bit(&mut self, buffer: &[u16]) -> Result<(), Error> {
+        self.tx.blocking_write_9bit(buffer)
+    }
+
+    /// Send a break frame
+    pub fn send_break(&mut self) {
+        self.tx.send_break()
+    }
+
+    /// Enable or disable LIN mode, detecting breaks of the given length
+    pub fn set_lin_mode(&mut self, break_length: Option<BreakLength>) {
+        self.rx.set_lin_mode(break_length)
+    }
+
+    /// Perform a blocking read of 9-bit frames into `buffer`
+    pub fn blocking_read_9bit(&mut self, buffer: &mut [u16]) -> Result<(), Error> {
+        self.rx.blocking_read_9bit(buffer)
+    }
+
+    /// Set the node address used to leave mute mode with [`Wakeup::AddressMark`]
+    pub fn set_address(&mut self, address: u8) {
+        self.rx.set_address(address)
+    }
+
+    /// Put the receiver in mute mode until the wakeup condition
+    pub fn enter_mute(&mut self, wakeup: Wakeup) {
+        self.rx.enter_mute(wakeup)
+    }
+
     /// Split the Uart into a transmitter and receiver, which is
     /// particularly useful when having two tasks correlating to
     /// transmitting and receiving.
     pub fn split(self) -> (UartTx<'d, T, M>, UartRx<'d, T, M>) {
         (self.tx, self.rx)
     }
+
+    /// Split the Uart into a transmitter and receiver by reference.
+    ///
+    /// Useful to run reading and writing futures concurrently without giving up the `Uart`.
+    pub fn split_ref(&mut self) -> (&mut UartTx<'d, T, M>, &mut UartRx<'d, T, M>) {
+        (&mut self.tx, &mut self.rx)
+    }
 }
 
 impl<'d, T: Instance> Uart<'d, T, Async> {
@@ -837,6 +1460,7 @@ impl<'d, T: Instance> Uart<'d, T, Async> {
 
         rx.set_as_input(Pull::None);
         tx.set_as_af_output(AFType::OutputPushPull, Speed::High);
+        T::set_remap(REMAP);
 
         Self::new_inner(
             peri,
@@ -868,6 +1492,7 @@ impl<'d, T: Instance> Uart<'d, T, Async> {
         tx.set_as_af_output(AFType::OutputPushPull, Speed::High);
         rts.set_as_af_output(AFType::OutputPushPull, Speed::High);
         cts.set_as_input(Pull::None);
+        T::set_remap(REMAP);
 
         Self::new_inner(
             peri,
@@ -881,6 +1506,27 @@ impl<'d, T: Instance> Uart<'d, T, Async> {
         )
     }
 
+    /// Create a new bidirectional UART driving an RS-485 transceiver
+    ///
+    /// `de` is asserted before every write and released once the last frame is
+    /// completely sent (TC), with the delays given in `de_config`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_de<const REMAP: u8>(
+        peri: impl Peripheral<P = T> + 'd,
+        rx: impl Peripheral<P = impl RxPin<T, REMAP>> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T, REMAP>> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+        de: impl Peripheral<P = impl Pin> + 'd,
+        tx_dma: impl Peripheral<P = impl TxDma<T>> + 'd,
+        rx_dma: impl Peripheral<P = impl RxDma<T>> + 'd,
+        config: Config,
+        de_config: DeConfig,
+    ) -> Result<Self, ConfigError> {
+        let mut this = Self::new(peri, rx, tx, _irq, tx_dma, rx_dma, config)?;
+        this.tx.de = Some(DriverEnable::new(de, de_config));
+        Ok(this)
+    }
+
     /// Half-duplex
     ///
     /// Note: Half duplex requires TX pin to have a pull-up resistor
@@ -898,6 +1544,26 @@ impl<'d, T: Instance> Uart<'d, T, Async> {
 
         Self::new_inner(_peri, None, Some(tx.map_into()), None, None, None, None, config)
     }
+
+    /// Perform an asynchronous write
+    pub async fn write(&mut self, buffer: &[u8]) -> Result<(), Error> {
+        self.tx.write(buffer).await
+    }
+
+    /// Perform an asynchronous read into `buffer`
+    pub async fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        self.rx.read(buffer).await
+    }
+
+    /// Perform an asynchronous read with idle line detection enabled
+    pub async fn read_until_idle(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        self.rx.read_until_idle(buffer).await
+    }
+
+    /// Wait for a break condition on the line
+    pub async fn wait_for_break(&mut self) {
+        self.rx.wait_for_break().await
+    }
 }
 
 impl<'d, T: Instance> Uart<'d, T, Blocking> {
@@ -955,6 +1621,64 @@ impl<'d, T: Instance> Uart<'d, T, Blocking> {
         )
     }
 
+    /// Create a new blocking bidirectional UART driving an RS-485 transceiver
+    ///
+    /// `de` is asserted before every write and released once the last frame is
+    /// completely sent (TC), with the delays given in `de_config`.
+    pub fn new_blocking_with_de<const REMAP: u8>(
+        peri: impl Peripheral<P = T> + 'd,
+        rx: impl Peripheral<P = impl RxPin<T, REMAP>> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T, REMAP>> + 'd,
+        de: impl Peripheral<P = impl Pin> + 'd,
+        config: Config,
+        de_config: DeConfig,
+    ) -> Result<Self, ConfigError> {
+        let mut this = Self::new_blocking(peri, rx, tx, config)?;
+        this.tx.de = Some(DriverEnable::new(de, de_config));
+        Ok(this)
+    }
+
+    /// Create a new blocking UART in smartcard (ISO 7816-3) mode
+    ///
+    /// `tx` is the bidirectional I/O line and needs an external pull-up, `ck` clocks the card.
+    /// `config` is forced to 9 data bits (8 data + parity) and 1.5 stop bits, with even parity
+    /// unless a parity is set.
+    pub fn new_blocking_smartcard<const REMAP: u8>(
+        peri: impl Peripheral<P = T> + 'd,
+        tx: impl Peripheral<P = impl TxPin<T, REMAP>> + 'd,
+        ck: impl Peripheral<P = impl CkPin<T, REMAP>> + 'd,
+        mut config: Config,
+        smartcard_config: SmartcardConfig,
+    ) -> Result<Self, ConfigError> {
+        into_ref!(peri, tx, ck);
+
+        tx.set_as_af_output(AFType::OutputOpenDrain, Speed::High);
+        ck.set_as_af_output(AFType::OutputPushPull, Speed::High);
+        T::set_remap(REMAP);
+
+        config.data_bits = DataBits::DataBits9;
+        config.stop_bits = StopBits::STOP1P5;
+        if config.parity == Parity::ParityNone {
+            config.parity = Parity::ParityEven;
+        }
+
+        let mut this = Self::new_inner(peri, None, Some(tx.map_into()), None, None, None, None, config)?;
+        this.tx.ck = Some(ck.map_into());
+
+        let r = T::regs();
+        r.gpr().write(|w| {
+            w.set_psc(smartcard_config.prescaler);
+            w.set_gt(smartcard_config.guard_time);
+        });
+        r.ctlr2().modify(|w| w.set_clken(true));
+        r.ctlr3().modify(|w| {
+            w.set_nack(smartcard_config.nack);
+            w.set_scen(true);
+        });
+
+        Ok(this)
+    }
+
     pub fn new_blocking_half_duplex<const REMAP: u8>(
         peri: impl Peripheral<P = T> + 'd,
         tx: impl Peripheral<P = impl TxPin<T, REMAP>> + 'd,
@@ -976,7 +1700,13 @@ fn reconfigure<T: Instance>(config: &Config) -> Result<(), ConfigError> {
     let r = T::regs();
 
     let cr = r.ctlr1().read();
-    configure(&r, config, T::frequency(), cr.re(), cr.te())?;
+    if cr.te() {
+        // let the frame being sent complete with the old settings
+        while !r.statr().read().tc() {}
+    }
+    // the frame format must not change while the USART is enabled
+    r.ctlr1().modify(|w| w.set_ue(false));
+    configure(&r, config, T::frequency(), cr.te(), cr.re())?;
 
     T::Interrupt::unpend();
     unsafe { T::Interrupt::enable() };
@@ -995,6 +1725,8 @@ fn configure(
         panic!("USART: At least one of RX or TX should be enabled");
     }
 
+    let brr = calc_brr(pclk_freq.0, config.baudrate, config.max_baudrate_error_ppm)?;
+
     rb.ctlr2().modify(|w| w.set_stop(config.stop_bits as u8));
 
     rb.ctlr1().modify(|w| {
@@ -1009,17 +1741,7 @@ fn configure(
         rb.ctlr3().modify(|w| w.set_hdsel(true));
     }
 
-    // HCLK/(16*USARTDIV)
-    // USARTDIV = DIV_M+(DIV_F/16)  via USART_BRR
-
-    let clock_in = pclk_freq.0;
-    let div_m = 25 * clock_in / (4 * config.baudrate);
-    let mut tmpreg = (div_m / 100) << 4;
-
-    let div_f = div_m - 100 * (tmpreg >> 4);
-    tmpreg |= ((div_f * 16 + 50) / 100) & 0x0F;
-
-    rb.brr().write(|w| w.0 = tmpreg);
+    rb.brr().write(|w| w.0 = brr);
 
     // enable uart
     rb.ctlr1().modify(|w| w.set_ue(true));
@@ -1027,6 +1749,31 @@ fn configure(
     Ok(())
 }
 
+/// Compute the BRR value for `baudrate`, checking the achievable accuracy.
+fn calc_brr(pclk: u32, baudrate: u32, max_error_ppm: u32) -> Result<u32, ConfigError> {
+    // baud = PCLK/(16*USARTDIV)
+    // USARTDIV = DIV_M+(DIV_F/16) via USART_BRR, so BRR = 16*USARTDIV = PCLK/baud
+    if baudrate == 0 {
+        return Err(ConfigError::BaudrateTooLow);
+    }
+    let brr = (pclk as u64 + baudrate as u64 / 2) / baudrate as u64;
+
+    if brr < 16 {
+        // DIV_M must be at least 1
+        return Err(ConfigError::BaudrateTooHigh);
+    } else if brr > 0xFFFF {
+        return Err(ConfigError::BaudrateTooLow);
+    }
+
+    let actual = pclk as u64 / brr;
+    let error_ppm = actual.abs_diff(baudrate as u64) * 1_000_000 / baudrate as u64;
+    if error_ppm > max_error_ppm as u64 {
+        return Err(ConfigError::BaudrateTooInaccurate);
+    }
+
+    Ok(brr as u32)
+}
+
 impl<'d, T: Instance> core::fmt::Write for UartTx<'d, T, Blocking> {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
         self.blocking_write(s.as_bytes()).map_err(|_| core::fmt::Error)?;
@@ -1040,15 +1787,182 @@ impl<'d, T: Instance> core::fmt::Write for Uart<'d, T, Blocking> {
     }
 }
 
+impl<'d, T: Instance, M: Mode> SetConfig for Uart<'d, T, M> {
+    type Config = Config;
+    type ConfigError = ConfigError;
+
+    fn set_config(&mut self, config: &Self::Config) -> Result<(), Self::ConfigError> {
+        self.set_config(config)
+    }
+}
+
+impl<'d, T: Instance, M: Mode> SetConfig for UartTx<'d, T, M> {
+    type Config = Config;
+    type ConfigError = ConfigError;
+
+    fn set_config(&mut self, config: &Self::Config) -> Result<(), Self::ConfigError> {
+        self.set_config(config)
+    }
+}
+
+impl<'d, T: Instance, M: Mode> SetConfig for UartRx<'d, T, M> {
+    type Config = Config;
+    type ConfigError = ConfigError;
+
+    fn set_config(&mut self, config: &Self::Config) -> Result<(), Self::ConfigError> {
+        self.set_config(config)
+    }
+}
+
+impl<'d, T: Instance, M: Mode> LowPowerPeripheral for Uart<'d, T, M> {
+    fn suspend(&mut self) {
+        suspend::<T>();
+    }
+
+    fn resume(&mut self) {
+        resume::<T>();
+    }
+}
+
+impl<'d, T: Instance, M: Mode> LowPowerPeripheral for UartTx<'d, T, M> {
+    fn suspend(&mut self) {
+        suspend::<T>();
+    }
+
+    fn resume(&mut self) {
+        resume::<T>();
+    }
+}
+
+impl<'d, T: Instance, M: Mode> LowPowerPeripheral for UartRx<'d, T, M> {
+    fn suspend(&mut self) {
+        suspend::<T>();
+    }
+
+    fn resume(&mut self) {
+        resume::<T>();
+    }
+}
+
+/// Let the frame being sent complete, and save the baud rate at the current bus clock.
+fn suspend<T: Instance>() {
+    let r = T::regs();
+    if r.ctlr1().read().te() {
+        while !r.statr().read().tc() {}
+    }
+    let brr = r.brr().read().0;
+    if brr != 0 {
+        T::state()
+            .suspended_baudrate
+            .store(T::frequency().0 / brr, Ordering::Relaxed);
+    }
+}
+
+/// Set the saved baud rate again, at the bus clock after wake-up.
+fn resume<T: Instance>() {
+    let baudrate = T::state().suspended_baudrate.load(Ordering::Relaxed);
+    if baudrate == 0 {
+        return;
+    }
+    // However inaccurate, the closest divider beats the one for the old clock.
+    if let Ok(brr) = calc_brr(T::frequency().0, baudrate, u32::MAX) {
+        let r = T::regs();
+        let ue = r.ctlr1().read().ue();
+        r.ctlr1().modify(|w| w.set_ue(false));
+        r.brr().write(|w| w.0 = brr);
+        r.ctlr1().modify(|w| w.set_ue(ue));
+    }
+}
+
+impl<'d, T: Instance, M: Mode> embedded_io::ErrorType for Uart<'d, T, M> {
+    type Error = Error;
+}
+
+impl<'d, T: Instance, M: Mode> embedded_io::ErrorType for UartTx<'d, T, M> {
+    type Error = Error;
+}
+
+impl<'d, T: Instance, M: Mode> embedded_io::ErrorType for UartRx<'d, T, M> {
+    type Error = Error;
+}
+
+impl<'d, T: Instance> embedded_io::Read for UartRx<'d, T, Blocking> {
+    /// Block until at least one byte is received, then read what is available without blocking.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let r = T::regs();
+        while !self.check_rx_flags()? {}
+        buf[0] = r.datar().read().dr() as u8;
+
+        let mut n = 1;
+        while n < buf.len() && self.check_rx_flags()? {
+            buf[n] = r.datar().read().dr() as u8;
+            n += 1;
+        }
+        Ok(n)
+    }
+}
+
+impl<'d, T: Instance> embedded_io::Read for Uart<'d, T, Blocking> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        embedded_io::Read::read(&mut self.rx, buf)
+    }
+}
+
+impl<'d, T: Instance> embedded_io::ReadReady for UartRx<'d, T, Blocking> {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.buffered_sr.rxne() || T::regs().statr().read().rxne())
+    }
+}
+
+impl<'d, T: Instance> embedded_io::ReadReady for Uart<'d, T, Blocking> {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        embedded_io::ReadReady::read_ready(&mut self.rx)
+    }
+}
+
+impl<'d, T: Instance> embedded_io::Write for UartTx<'d, T, Blocking> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.blocking_write(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.blocking_flush()
+    }
+}
+
+impl<'d, T: Instance> embedded_io::Write for Uart<'d, T, Blocking> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.blocking_write(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.blocking_flush()
+    }
+}
+
 // Peripheral traits
 struct State {
     rx_waker: AtomicWaker,
+    /// Woken on TC.
+    tx_waker: AtomicWaker,
+    tx_rx_refcount: AtomicU8,
+    /// Baud rate saved by [`LowPowerPeripheral::suspend`], 0 if none.
+    suspended_baudrate: AtomicU32,
 }
 
 impl State {
     const fn new() -> Self {
         Self {
             rx_waker: AtomicWaker::new(),
+            tx_waker: AtomicWaker::new(),
+            tx_rx_refcount: AtomicU8::new(0),
+            suspended_baudrate: AtomicU32::new(0),
         }
     }
 }
@@ -1056,8 +1970,7 @@ impl State {
 trait SealedInstance: crate::peripheral::RccPeripheral + crate::peripheral::RemapPeripheral {
     fn regs() -> crate::pac::usart::Usart;
     fn state() -> &'static State;
-
-    // fn buffered_state() -> &'static buffered::State;
+    fn buffered_state() -> &'static buffered::State;
 }
 
 #[allow(private_bounds)]
@@ -1077,6 +1990,11 @@ foreach_peripheral!(
                 static STATE: State = State::new();
                 &STATE
             }
+
+            fn buffered_state() -> &'static buffered::State {
+                static BUFFERED_STATE: buffered::State = buffered::State::new();
+                &BUFFERED_STATE
+            }
         }
 
         impl Instance for peripherals::$inst {
@@ -0,0 +1,178 @@
+//! Ring-buffered UART receiver, backed by a circular DMA transfer.
+
+use core::mem;
+
+use super::*;
+use crate::dma::ReadableRingBuffer;
+
+/// Rx-only ring-buffered UART driver
+///
+/// Created with [UartRx::into_ring_buffered]
+///
+/// The DMA keeps receiving into the buffer in the background, so no byte is lost between two reads
+/// unless the reader falls behind by more than the buffer size. In that case the unread data is
+/// discarded instead of being mixed with newer data, and the number of lost bytes is accumulated,
+/// see [`take_lost_count`](Self::take_lost_count).
+pub struct RingBufferedUartRx<'d, T: Instance> {
+    _phantom: PhantomData<T>,
+    rx: Option<PeripheralRef<'d, AnyPin>>,
+    rts: Option<PeripheralRef<'d, AnyPin>>,
+    ring_buf: ReadableRingBuffer<'d, u8>,
+    lost: usize,
+}
+
+impl<'d, T: Instance> UartRx<'d, T, Async> {
+    /// Turn the `UartRx` into a buffered uart which can continously receive in the background
+    /// without the possibility of losing bytes. The `dma_buf` is a buffer registered to the
+    /// DMA controller, and must be large enough to prevent overflows.
+    pub fn into_ring_buffered(mut self, dma_buf: &'d mut [u8]) -> RingBufferedUartRx<'d, T> {
+        assert!(!dma_buf.is_empty() && dma_buf.len() <= 0xFFFF);
+
+        let opts = Default::default();
+
+        // Safety: we forget the struct before this function returns.
+        let rx_dma = self.rx_dma.as_mut().unwrap();
+        let request = rx_dma.request;
+        let rx_dma = unsafe { rx_dma.channel.clone_unchecked() };
+
+        let rx = unsafe { self.rx.as_ref().map(|x| x.clone_unchecked()) };
+        let rts = unsafe { self.rts.as_ref().map(|x| x.clone_unchecked()) };
+
+        let ring_buf =
+            unsafe { ReadableRingBuffer::new(rx_dma, request, T::regs().datar().as_ptr() as _, dma_buf, opts) };
+
+        // Don't disable the clock, the refcount is taken over
+        mem::forget(self);
+
+        RingBufferedUartRx {
+            _phantom: PhantomData,
+            rx,
+            rts,
+            ring_buf,
+            lost: 0,
+        }
+    }
+}
+
+impl<'d, T: Instance> RingBufferedUartRx<'d, T> {
+    /// Reconfigure the driver
+    pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+        reconfigure::<T>(config)
+    }
+
+    /// Start the background reception. Called automatically on the first [`read`](Self::read).
+    pub fn start(&mut self) {
+        compiler_fence(Ordering::SeqCst);
+        self.ring_buf.start();
+
+        T::regs().ctlr3().modify(|w| w.set_dmar(true));
+    }
+
+    /// Stop the background reception. Data already received can still be read.
+    pub fn stop(&mut self) {
+        let r = T::regs();
+        r.ctlr3().modify(|w| w.set_dmar(false));
+        critical_section::with(|_| r.ctlr1().modify(|w| w.set_idleie(false)));
+
+        self.ring_buf.request_stop();
+        while self.ring_buf.is_running() {}
+
+        compiler_fence(Ordering::SeqCst);
+    }
+
+    /// Read bytes that are readily available in the ring buffer.
+    /// If no bytes are currently available in the buffer the call waits until some bytes are
+    /// received or the line goes idle.
+    ///
+    /// Returns [`Error::Overrun`] if unread data was overwritten. The unread data is then
+    /// discarded, its length added to the lost count, and the next read continues with new data.
+    ///
+    /// Parity, framing and noise errors are reported once, the bytes received with them
+    /// are kept in the buffer.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let r = T::regs();
+        if !r.ctlr3().read().dmar() {
+            self.start();
+        }
+
+        loop {
+            let sr = r.statr().read();
+            if let Some(error) = sr_error(sr) {
+                // The DMA already moved the data byte out of DATAR, so reading it only clears the flags
+                if !sr.rxne() {
+                    let _ = r.datar().read().dr();
+                }
+                return Err(error);
+            }
+
+            match self.ring_buf.read(buf) {
+                Ok((0, _)) => {}
+                Ok((len, _)) => return Ok(len),
+                Err(_) => {
+                    self.lost += self.ring_buf.discard();
+                    return Err(Error::Overrun);
+                }
+            }
+
+            self.wait_for_data_or_idle().await;
+        }
+    }
+
+    /// Number of bytes lost to overruns since the last call.
+    pub fn take_lost_count(&mut self) -> usize {
+        mem::take(&mut self.lost)
+    }
+
+    /// Wait for the DMA half/full transfer event, or an idle line.
+    async fn wait_for_data_or_idle(&mut self) {
+        let r = T::regs();
+        let mut polled = false;
+
+        poll_fn(|cx| {
+            if polled {
+                return Poll::Ready(());
+            }
+            polled = true;
+
+            self.ring_buf.set_waker(cx.waker());
+            T::state().rx_waker.register(cx.waker());
+
+            // Let the reader report the error before the flags get cleared below
+            let sr = r.statr().read();
+            if sr_error(sr).is_some() {
+                return Poll::Ready(());
+            }
+
+            // The DMA already moved the data byte out of DATAR, so reading it only clears IDLE
+            if sr.idle() && !sr.rxne() {
+                let _ = r.datar().read().dr();
+                // The line may have gone idle after the last read, check the buffer again
+                return Poll::Ready(());
+            }
+
+            // The interrupt handler disables idle detection every time it fires
+            critical_section::with(|_| r.ctlr1().modify(|w| w.set_idleie(true)));
+
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl<'d, T: Instance> SetConfig for RingBufferedUartRx<'d, T> {
+    type Config = Config;
+    type ConfigError = ConfigError;
+
+    fn set_config(&mut self, config: &Self::Config) -> Result<(), Self::ConfigError> {
+        self.set_config(config)
+    }
+}
+
+impl<'d, T: Instance> Drop for RingBufferedUartRx<'d, T> {
+    fn drop(&mut self) {
+        self.stop();
+        self.rx.as_ref().map(|x| x.set_as_disconnected());
+        self.rts.as_ref().map(|x| x.set_as_disconnected());
+        drop_tx_rx::<T>();
+    }
+}
@@ -1,8 +1,8 @@
 //! Universal Serial Bus(USB)
 //!
 //! Type of USB IP cores used in CH32 MCUs:
-//! - USBD, device only
-//! - USBFS
+//! - USBD, device only, see `usbd`
+//! - USBFS, see `usbfs`
 //! - USB_OTG_FS, USBFS with OTG
-//! - USBHS, CH32V305/CH32V307
+//! - USBHS, CH32V305/CH32V307, see `usbhs`
 //! - USBSS, CH569/CH565 - won't be implemented here as CH5xx series is not supported
@@ -0,0 +1,221 @@
+//! CDC-ACM logger over USB
+//!
+//! Routes [`log`] output, and `defmt` frames with the `usb-logger-defmt` feature, to a CDC-ACM
+//! interface on any of the USB device drivers, for boards without a UART header.
+//!
+//! Records are queued in a [`BUFFER_SIZE`] byte buffer and sent by [`run`]. Logging never blocks:
+//! whatever doesn't fit in the buffer, e.g. while no terminal is open, is dropped.
+//!
+//! The [`State`] borrowed by the USB device lives as long as the driver, `'static` in a task:
+//!
+//! ```ignore
+//! #[embassy_executor::task]
+//! async fn logger_task(driver: usbfs::Driver<'static, peripherals::OTG_FS>) {
+//!     static STATE: StaticCell<usb_logger::State> = StaticCell::new();
+//!     let state = STATE.init(usb_logger::State::new());
+//!     usb_logger::run(state, driver, usb_logger::Config::default()).await
+//! }
+//! ```
+
+use core::fmt::Write as _;
+
+use embassy_futures::join::join;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pipe::Pipe;
+use embassy_usb::class::cdc_acm::{self, CdcAcmClass, Sender};
+use embassy_usb::driver::{Driver, EndpointError};
+use embassy_usb::Builder;
+
+/// Size of the log buffer, in bytes.
+pub const BUFFER_SIZE: usize = 1024;
+
+const MAX_PACKET_SIZE: usize = 64;
+
+static BUFFER: Pipe<CriticalSectionRawMutex, BUFFER_SIZE> = Pipe::new();
+
+/// USB logger config
+pub struct Config {
+    /// Vendor ID
+    pub vid: u16,
+    /// Product ID
+    pub pid: u16,
+    /// Manufacturer string
+    pub manufacturer: Option<&'static str>,
+    /// Product string
+    pub product: Option<&'static str>,
+    /// Serial number string, the chip unique ID by default
+    pub serial_number: Option<&'static str>,
+    /// Max level of the `log` records
+    pub level: log::LevelFilter,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            vid: 0xc0de,
+            pid: 0xcafe,
+            manufacturer: Some("ch32-hal"),
+            product: Some("USB logger"),
+            serial_number: Some(crate::signature::serial_number()),
+            level: log::LevelFilter::Info,
+        }
+    }
+}
+
+/// Descriptor and class buffers of the USB device.
+pub struct State<'d> {
+    config_descriptor: [u8; 128],
+    bos_descriptor: [u8; 16],
+    msos_descriptor: [u8; 0],
+    control_buf: [u8; 64],
+    cdc_acm: cdc_acm::State<'d>,
+}
+
+impl<'d> State<'d> {
+    /// Create a new state.
+    pub fn new() -> Self {
+        Self {
+            config_descriptor: [0; 128],
+            bos_descriptor: [0; 16],
+            msos_descriptor: [],
+            control_buf: [0; 64],
+            cdc_acm: cdc_acm::State::new(),
+        }
+    }
+}
+
+impl<'d> Default for State<'d> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            let _ = write!(
+                BufferWriter,
+                "{} {}: {}\r\n",
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+struct BufferWriter;
+
+impl core::fmt::Write for BufferWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let _ = BUFFER.try_write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Install the logger and run the USB device, sending the buffered log output whenever a terminal
+/// is connected.
+pub async fn run<'d, D: Driver<'d>>(state: &'d mut State<'d>, driver: D, config: Config) -> ! {
+    // Another logger may already be installed, its output then doesn't go over USB.
+    let _ = log::set_logger(&LOGGER).map(|_| log::set_max_level(config.level));
+
+    let mut usb_config = embassy_usb::Config::new(config.vid, config.pid);
+    usb_config.manufacturer = config.manufacturer;
+    usb_config.product = config.product;
+    usb_config.serial_number = config.serial_number;
+    usb_config.max_packet_size_0 = MAX_PACKET_SIZE as u8;
+
+    // Windows needs the IAD to bind the CDC-ACM driver to the composite interface.
+    usb_config.device_class = 0xEF;
+    usb_config.device_sub_class = 0x02;
+    usb_config.device_protocol = 0x01;
+    usb_config.composite_with_iads = true;
+
+    let mut builder = Builder::new(
+        driver,
+        usb_config,
+        &mut state.config_descriptor,
+        &mut state.bos_descriptor,
+        &mut state.msos_descriptor,
+        &mut state.control_buf,
+    );
+    let class = CdcAcmClass::new(&mut builder, &mut state.cdc_acm, MAX_PACKET_SIZE as u16);
+    let mut device = builder.build();
+
+    let (mut sender, _receiver) = class.split();
+    let log_fut = async {
+        loop {
+            sender.wait_connection().await;
+            let _ = send(&mut sender).await;
+        }
+    };
+
+    join(device.run(), log_fut).await.0
+}
+
+async fn send<'d, D: Driver<'d>>(sender: &mut Sender<'d, D>) -> Result<(), EndpointError> {
+    let mut buf = [0; MAX_PACKET_SIZE];
+    loop {
+        let n = BUFFER.read(&mut buf).await;
+        sender.write_packet(&buf[..n]).await?;
+    }
+}
+
+#[cfg(feature = "usb-logger-defmt")]
+mod defmt_logger {
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    use super::BUFFER;
+
+    #[defmt::global_logger]
+    struct DefmtLogger;
+
+    static TAKEN: AtomicBool = AtomicBool::new(false);
+    static mut CS_RESTORE: critical_section::RestoreState = critical_section::RestoreState::invalid();
+    static mut ENCODER: defmt::Encoder = defmt::Encoder::new();
+
+    unsafe impl defmt::Logger for DefmtLogger {
+        fn acquire() {
+            let restore = unsafe { critical_section::acquire() };
+
+            if TAKEN.load(Ordering::Relaxed) {
+                panic!("defmt logger taken reentrantly")
+            }
+            TAKEN.store(true, Ordering::Relaxed);
+
+            unsafe {
+                CS_RESTORE = restore;
+                ENCODER.start_frame(write);
+            }
+        }
+
+        unsafe fn flush() {}
+
+        unsafe fn release() {
+            ENCODER.end_frame(write);
+            TAKEN.store(false, Ordering::Relaxed);
+
+            let restore = CS_RESTORE;
+            critical_section::release(restore);
+        }
+
+        unsafe fn write(bytes: &[u8]) {
+            ENCODER.write(bytes, write);
+        }
+    }
+
+    fn write(bytes: &[u8]) {
+        // A partly written frame is dropped by the decoder, at the next frame delimiter.
+        let _ = BUFFER.try_write(bytes);
+    }
+}
@@ -1,6 +1,9 @@
 //! Universal Serial Bus Device(USBD)
 //!
 //! This peripheral is almost the same as usb_v1 in embassy-stm32.
+//!
+//! Implements [`embassy_usb_driver`] like the `usbfs` and `usbhs` drivers, so the same embassy-usb
+//! classes run on chips with either core.
 
 #![macro_use]
 
@@ -9,19 +12,28 @@ use core::marker::PhantomData;
 use core::sync::atomic::{AtomicBool, Ordering};
 use core::task::Poll;
 
+use embassy_futures::select::{select, Either};
 use embassy_sync::waitqueue::AtomicWaker;
+use embassy_time::{Duration, Instant, Timer};
 use embassy_usb_driver as driver;
 use embassy_usb_driver::{
     Direction, EndpointAddress, EndpointAllocError, EndpointError, EndpointInfo, EndpointType, Event, Unsupported,
 };
 
+use crate::exti::ExtiInput;
 use crate::gpio::Speed;
 use crate::pac::usbd::regs;
 use crate::pac::usbd::vals::{EpType, Stat};
 use crate::pac::{EXTEND, USBRAM};
 use crate::peripheral::RccPeripheral;
+use crate::rcc::UsbClockError;
 use crate::{interrupt, into_ref, Peripheral};
 
+/// Bus idle time before a suspended device may signal remote wakeup.
+const REMOTE_WAKEUP_IDLE: Duration = Duration::from_millis(5);
+/// Duration of the resume signaling, 1 to 15 ms.
+const RESUME_SIGNALING: Duration = Duration::from_millis(10);
+
 /// Interrupt handler.
 pub struct InterruptHandler<T: Instance> {
     _phantom: PhantomData<T>,
@@ -30,13 +42,10 @@ pub struct InterruptHandler<T: Instance> {
 impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandler<T> {
     unsafe fn on_interrupt() {
         let regs = T::regs();
-        let x = regs.istr().read().0;
-        crate::println!("USB IRQ: {:08x}", x);
 
         let istr = regs.istr().read();
 
         if istr.susp() {
-            //crate::println!("USB IRQ: susp");
             IRQ_SUSPEND.store(true, Ordering::Relaxed);
             regs.cntr().modify(|w| {
                 w.set_fsusp(true);
@@ -53,7 +62,6 @@ impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandl
         }
 
         if istr.wkup() {
-            crate::println!("USB IRQ: wkup");
             IRQ_RESUME.store(true, Ordering::Relaxed);
             regs.cntr().modify(|w| {
                 w.set_fsusp(false);
@@ -70,7 +78,6 @@ impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandl
         }
 
         if istr.reset() {
-            crate::println!("USB IRQ: reset");
             IRQ_RESET.store(true, Ordering::Relaxed);
 
             // Write 0 to clear.
@@ -89,11 +96,9 @@ impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandl
                 if index == 0 && epr.setup() {
                     EP0_SETUP.store(true, Ordering::Relaxed);
                 }
-                crate::println!("EP {} RX, setup={}", index, epr.setup());
                 EP_OUT_WAKERS[index].wake();
             }
             if epr.ctr_tx() {
-                //crate::println!("EP {} TX", index);
                 EP_IN_WAKERS[index].wake();
             }
             epr.set_dtog_rx(false);
@@ -219,17 +224,21 @@ struct EndpointData {
 pub struct Driver<'d, T: Instance> {
     phantom: PhantomData<&'d mut T>,
     alloc: [EndpointData; EP_COUNT],
+    vbus: Option<ExtiInput<'d>>,
     ep_mem_free: u16, // first free address in EP mem, in bytes.
 }
 
 impl<'d, T: Instance> Driver<'d, T> {
     /// Create a new USB driver.
+    ///
+    /// Fails if the USB clock isn't 48 MHz, see [`rcc::Config`](crate::rcc::Config).
     pub fn new(
         _usb: impl Peripheral<P = T> + 'd,
         _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
         dp: impl Peripheral<P = impl DpPin<T, 0>> + 'd,
         dm: impl Peripheral<P = impl DmPin<T, 0>> + 'd,
-    ) -> Self {
+    ) -> Result<Self, UsbClockError> {
+        crate::rcc::usb_clock()?;
         into_ref!(dp, dm);
 
         {
@@ -268,15 +277,34 @@ impl<'d, T: Instance> Driver<'d, T> {
         // Initialize the bus so that it signals that power is available
         BUS_WAKER.wake();
 
-        Self {
+        Ok(Self {
             phantom: PhantomData,
             alloc: [EndpointData {
                 ep_type: EndpointType::Bulk,
                 used_in: false,
                 used_out: false,
             }; EP_COUNT],
+            vbus: None,
             ep_mem_free: EP_COUNT as u16 * 8, // for each EP, 4 regs, so 8 bytes
-        }
+        })
+    }
+
+    /// Create a new USB driver, sensing VBUS on `vbus`.
+    ///
+    /// `vbus` reads the VBUS line, through a divider, and should be pulled down. The device
+    /// connects to the bus only while VBUS is present, as self-powered devices must.
+    pub fn new_with_vbus(
+        usb: impl Peripheral<P = T> + 'd,
+        irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+        dp: impl Peripheral<P = impl DpPin<T, 0>> + 'd,
+        dm: impl Peripheral<P = impl DmPin<T, 0>> + 'd,
+        vbus: ExtiInput<'d>,
+    ) -> Result<Self, UsbClockError> {
+        let mut this = Self::new(usb, irq, dp, dm)?;
+        // Connect once VBUS is detected.
+        EXTEND.ctr().modify(|w| w.set_usbdpu(false));
+        this.vbus = Some(vbus);
+        Ok(this)
     }
 
     fn alloc_ep_mem(&mut self, len: u16) -> u16 {
@@ -295,14 +323,6 @@ impl<'d, T: Instance> Driver<'d, T> {
         max_packet_size: u16,
         interval_ms: u8,
     ) -> Result<Endpoint<'d, T, D>, driver::EndpointAllocError> {
-        crate::println!(
-            "allocating type={:?} mps={:?} interval_ms={}, dir={:?}",
-            ep_type,
-            max_packet_size,
-            interval_ms,
-            D::dir()
-        );
-
         let index = self.alloc.iter_mut().enumerate().find(|(i, ep)| {
             if *i == 0 && ep_type != EndpointType::Control {
                 return false; // reserved for control pipe
@@ -330,7 +350,6 @@ impl<'d, T: Instance> Driver<'d, T> {
                 let (len, len_bits) = calc_out_len(max_packet_size);
                 let addr = self.alloc_ep_mem(len);
 
-                crate::println!("  len_bits = {:04x}", len_bits);
                 btable::write_out::<T>(index, addr, len_bits);
 
                 EndpointBuffer {
@@ -357,8 +376,6 @@ impl<'d, T: Instance> Driver<'d, T> {
             }
         };
 
-        crate::println!("  index={} addr={} len={}", index, buf.addr, buf.len);
-
         Ok(Endpoint {
             _phantom: PhantomData,
             info: EndpointInfo {
@@ -417,8 +434,6 @@ impl<'d, T: Instance> driver::Driver<'d> for Driver<'d, T> {
             w.set_ctrm(true);
         });
 
-        crate::println!("enabled");
-
         let mut ep_types = [EpType::BULK; EP_COUNT - 1];
         for i in 1..EP_COUNT {
             ep_types[i - 1] = convert_type(self.alloc[i].ep_type);
@@ -429,6 +444,9 @@ impl<'d, T: Instance> driver::Driver<'d> for Driver<'d, T> {
                 phantom: PhantomData,
                 ep_types,
                 inited: false,
+                suspended_at: None,
+                vbus: self.vbus.take(),
+                powered: false,
             },
             ControlPipe {
                 _phantom: PhantomData,
@@ -445,16 +463,50 @@ pub struct Bus<'d, T: Instance> {
     phantom: PhantomData<&'d mut T>,
     ep_types: [EpType; EP_COUNT - 1],
     inited: bool,
+    /// When the bus was suspended, for the remote wakeup timing.
+    suspended_at: Option<Instant>,
+    vbus: Option<ExtiInput<'d>>,
+    /// VBUS level last reported, when it is sensed.
+    powered: bool,
 }
 
 impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
     async fn poll(&mut self) -> Event {
-        poll_fn(move |cx| {
+        if let Some(vbus) = &self.vbus {
+            if vbus.is_high() != self.powered {
+                self.powered = !self.powered;
+                return if self.powered {
+                    Event::PowerDetected
+                } else {
+                    Event::PowerRemoved
+                };
+            }
+        }
+
+        let Self {
+            phantom: _,
+            vbus,
+            powered,
+            inited,
+            suspended_at,
+            ep_types,
+        } = self;
+        let sensed = vbus.is_some();
+
+        let vbus_change = async {
+            match vbus {
+                Some(vbus) if *powered => vbus.wait_for_low().await,
+                Some(vbus) => vbus.wait_for_high().await,
+                None => core::future::pending().await,
+            }
+        };
+
+        let bus_event = poll_fn(move |cx| {
             BUS_WAKER.register(cx.waker());
 
-            // TODO: implement VBUS detection.
-            if !self.inited {
-                self.inited = true;
+            // Without VBUS sensing, assume the bus is powered.
+            if !sensed && !*inited {
+                *inited = true;
                 return Poll::Ready(Event::PowerDetected);
             }
 
@@ -462,13 +514,14 @@ impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
 
             if IRQ_RESUME.load(Ordering::Acquire) {
                 IRQ_RESUME.store(false, Ordering::Relaxed);
+                *suspended_at = None;
                 return Poll::Ready(Event::Resume);
             }
 
             if IRQ_RESET.load(Ordering::Acquire) {
                 IRQ_RESET.store(false, Ordering::Relaxed);
+                *suspended_at = None;
 
-                crate::println!("RESET");
                 regs.daddr().write(|w| {
                     w.set_ef(true);
                     w.set_add(0);
@@ -483,7 +536,7 @@ impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
                 for i in 1..EP_COUNT {
                     regs.epr(i).write(|w| {
                         w.set_ea(i as _);
-                        w.set_ep_type(self.ep_types[i - 1]);
+                        w.set_ep_type(ep_types[i - 1]);
                     })
                 }
 
@@ -499,12 +552,24 @@ impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
 
             if IRQ_SUSPEND.load(Ordering::Acquire) {
                 IRQ_SUSPEND.store(false, Ordering::Relaxed);
+                *suspended_at = Some(Instant::now());
                 return Poll::Ready(Event::Suspend);
             }
 
             Poll::Pending
-        })
-        .await
+        });
+
+        match select(vbus_change, bus_event).await {
+            Either::First(()) => {
+                *powered = !*powered;
+                if *powered {
+                    Event::PowerDetected
+                } else {
+                    Event::PowerRemoved
+                }
+            }
+            Either::Second(event) => event,
+        }
     }
 
     fn endpoint_set_stalled(&mut self, ep_addr: EndpointAddress, stalled: bool) {
@@ -562,10 +627,8 @@ impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
     }
 
     fn endpoint_set_enabled(&mut self, ep_addr: EndpointAddress, enabled: bool) {
-        crate::println!("set_enabled {:x?} {}", ep_addr, enabled);
         // This can race, so do a retry loop.
         let reg = T::regs().epr(ep_addr.index() as _);
-        crate::println!("EPR before: {:04x}", reg.read().0);
         match ep_addr.direction() {
             Direction::In => {
                 loop {
@@ -600,14 +663,33 @@ impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
                 EP_OUT_WAKERS[ep_addr.index()].wake();
             }
         }
-        crate::println!("EPR after: {:04x}", reg.read().0);
     }
 
-    async fn enable(&mut self) {}
-    async fn disable(&mut self) {}
+    async fn enable(&mut self) {
+        EXTEND.ctr().modify(|w| w.set_usbdpu(true));
+    }
+
+    async fn disable(&mut self) {
+        EXTEND.ctr().modify(|w| w.set_usbdpu(false));
+    }
 
     async fn remote_wakeup(&mut self) -> Result<(), Unsupported> {
-        Err(Unsupported)
+        // The host enables remote wakeup with SET_FEATURE, which embassy-usb tracks before
+        // calling this.
+        if let Some(suspended_at) = self.suspended_at.take() {
+            Timer::at(suspended_at + REMOTE_WAKEUP_IDLE).await;
+        }
+
+        let regs = T::regs();
+        regs.cntr().modify(|w| {
+            w.set_fsusp(false);
+            w.set_lpmode(false);
+            w.set_resume(true);
+        });
+        Timer::after(RESUME_SIGNALING).await;
+        regs.cntr().modify(|w| w.set_resume(false));
+
+        Ok(())
     }
 }
 
@@ -648,7 +730,6 @@ impl<'d, T: Instance, D> Endpoint<'d, T, D> {
     fn read_data(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
         let index = self.info.addr.index();
         let rx_len = btable::read_out_len::<T>(index) as usize & 0x3FF;
-        crate::println!("READ DONE, rx_len = {}", rx_len);
         if rx_len > buf.len() {
             return Err(EndpointError::BufferOverflow);
         }
@@ -663,7 +744,6 @@ impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, In> {
     }
 
     async fn wait_enabled(&mut self) {
-        crate::println!("wait_enabled IN WAITING");
         let index = self.info.addr.index();
         poll_fn(|cx| {
             EP_IN_WAKERS[index].register(cx.waker());
@@ -675,7 +755,6 @@ impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, In> {
             }
         })
         .await;
-        crate::println!("wait_enabled IN OK");
     }
 }
 
@@ -685,7 +764,6 @@ impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, Out> {
     }
 
     async fn wait_enabled(&mut self) {
-        crate::println!("wait_enabled OUT WAITING");
         let index = self.info.addr.index();
         poll_fn(|cx| {
             EP_OUT_WAKERS[index].register(cx.waker());
@@ -697,13 +775,11 @@ impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, Out> {
             }
         })
         .await;
-        crate::println!("wait_enabled OUT OK");
     }
 }
 
 impl<'d, T: Instance> driver::EndpointOut for Endpoint<'d, T, Out> {
     async fn read(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
-        crate::println!("READ WAITING, buf.len() = {}", buf.len());
         let index = self.info.addr.index();
         let stat = poll_fn(|cx| {
             EP_OUT_WAKERS[index].register(cx.waker());
@@ -732,7 +808,6 @@ impl<'d, T: Instance> driver::EndpointOut for Endpoint<'d, T, Out> {
             w.set_ctr_rx(true); // don't clear
             w.set_ctr_tx(true); // don't clear
         });
-        crate::println!("READ OK, rx_len = {}", rx_len);
 
         Ok(rx_len)
     }
@@ -746,7 +821,6 @@ impl<'d, T: Instance> driver::EndpointIn for Endpoint<'d, T, In> {
 
         let index = self.info.addr.index();
 
-        crate::println!("WRITE WAITING");
         let stat = poll_fn(|cx| {
             EP_IN_WAKERS[index].register(cx.waker());
             let regs = T::regs();
@@ -775,8 +849,6 @@ impl<'d, T: Instance> driver::EndpointIn for Endpoint<'d, T, In> {
             w.set_ctr_tx(true); // don't clear
         });
 
-        crate::println!("WRITE OK");
-
         Ok(())
     }
 }
@@ -796,7 +868,6 @@ impl<'d, T: Instance> driver::ControlPipe for ControlPipe<'d, T> {
 
     async fn setup(&mut self) -> [u8; 8] {
         loop {
-            crate::println!("SETUP read waiting");
             poll_fn(|cx| {
                 EP_OUT_WAKERS[0].register(cx.waker());
                 if EP0_SETUP.load(Ordering::Relaxed) {
@@ -810,13 +881,11 @@ impl<'d, T: Instance> driver::ControlPipe for ControlPipe<'d, T> {
             let mut buf = [0; 8];
             let rx_len = self.ep_out.read_data(&mut buf);
             if rx_len != Ok(8) {
-                crate::println!("SETUP read failed: {:?}", rx_len);
                 continue;
             }
 
             EP0_SETUP.store(false, Ordering::Relaxed);
 
-            crate::println!("SETUP read ok");
             return buf;
         }
     }
@@ -852,7 +921,6 @@ impl<'d, T: Instance> driver::ControlPipe for ControlPipe<'d, T> {
             });
         }
 
-        crate::println!("data_out WAITING, buf.len() = {}", buf.len());
         poll_fn(|cx| {
             EP_OUT_WAKERS[0].register(cx.waker());
             let regs = T::regs();
@@ -865,7 +933,6 @@ impl<'d, T: Instance> driver::ControlPipe for ControlPipe<'d, T> {
         .await;
 
         if EP0_SETUP.load(Ordering::Relaxed) {
-            crate::println!("received another SETUP, aborting data_out.");
             return Err(EndpointError::Disabled);
         }
 
@@ -887,8 +954,6 @@ impl<'d, T: Instance> driver::ControlPipe for ControlPipe<'d, T> {
     }
 
     async fn data_in(&mut self, data: &[u8], first: bool, last: bool) -> Result<(), EndpointError> {
-        crate::println!("control: data_in");
-
         if data.len() > self.ep_in.info.max_packet_size as usize {
             return Err(EndpointError::BufferOverflow);
         }
@@ -919,7 +984,6 @@ impl<'d, T: Instance> driver::ControlPipe for ControlPipe<'d, T> {
             });
         }
 
-        crate::println!("WRITE WAITING");
         poll_fn(|cx| {
             EP_IN_WAKERS[0].register(cx.waker());
             EP_OUT_WAKERS[0].register(cx.waker());
@@ -933,7 +997,6 @@ impl<'d, T: Instance> driver::ControlPipe for ControlPipe<'d, T> {
         .await;
 
         if EP0_SETUP.load(Ordering::Relaxed) {
-            crate::println!("received another SETUP, aborting data_in.");
             return Err(EndpointError::Disabled);
         }
 
@@ -948,14 +1011,11 @@ impl<'d, T: Instance> driver::ControlPipe for ControlPipe<'d, T> {
             w.set_ctr_tx(true); // don't clear
         });
 
-        crate::println!("WRITE OK");
-
         Ok(())
     }
 
     async fn accept(&mut self) {
         let regs = T::regs();
-        crate::println!("control: accept");
 
         self.ep_in.write_data(&[]);
 
@@ -968,7 +1028,6 @@ impl<'d, T: Instance> driver::ControlPipe for ControlPipe<'d, T> {
             w.set_ctr_rx(true); // don't clear
             w.set_ctr_tx(true); // don't clear
         });
-        crate::println!("control: accept WAITING");
 
         // Wait is needed, so that we don't set the address too soon, breaking the status stage.
         // (embassy-usb sets the address after accept() returns)
@@ -982,13 +1041,10 @@ impl<'d, T: Instance> driver::ControlPipe for ControlPipe<'d, T> {
             }
         })
         .await;
-
-        crate::println!("control: accept OK");
     }
 
     async fn reject(&mut self) {
         let regs = T::regs();
-        crate::println!("control: reject");
 
         // Set IN+OUT to stall
         let epr = regs.epr(0).read();
@@ -1005,7 +1061,6 @@ impl<'d, T: Instance> driver::ControlPipe for ControlPipe<'d, T> {
         self.accept().await;
 
         let regs = T::regs();
-        crate::println!("setting addr: {}", addr);
         regs.daddr().write(|w| {
             w.set_ef(true);
             w.set_add(addr);
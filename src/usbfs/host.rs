@@ -0,0 +1,686 @@
+//! USB full-speed host
+//!
+//! Enumerates a single device connected directly to the port, and runs control, bulk and
+//! interrupt transfers with it. Hubs are not supported.
+//!
+//! Every transaction is started by software: NAKed bulk and control transactions are retried until
+//! [`TRANSFER_TIMEOUT`] elapses, NAKed interrupt transactions are reported as [`HostError::Nak`] so
+//! the caller can poll at the endpoint interval.
+
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::sync::atomic::{compiler_fence, Ordering};
+use core::task::Poll;
+
+use embassy_sync::waitqueue::AtomicWaker;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+
+use super::{DmPin, DpPin, Instance};
+use crate::gpio::Pull;
+use crate::interrupt::typelevel::Interrupt as _;
+use crate::peripheral::RccPeripheral;
+use crate::rcc::UsbClockError;
+use crate::{interrupt, into_ref, Peripheral};
+
+/// How long NAKed bulk and control transactions are retried.
+pub const TRANSFER_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Time for a single transaction to complete.
+const TRANSACTION_TIMEOUT: Duration = Duration::from_millis(50);
+
+const MAX_PACKET_SIZE: usize = 64;
+
+// Token PIDs
+const PID_OUT: u8 = 0x1;
+const PID_IN: u8 = 0x9;
+const PID_SETUP: u8 = 0xD;
+
+// Handshake PIDs
+const PID_ACK: u8 = 0x2;
+const PID_NAK: u8 = 0xA;
+const PID_STALL: u8 = 0xE;
+
+const GET_DESCRIPTOR: u8 = 6;
+const SET_ADDRESS: u8 = 5;
+const SET_CONFIGURATION: u8 = 9;
+const CLEAR_FEATURE: u8 = 1;
+
+const DESCRIPTOR_DEVICE: u8 = 1;
+const DESCRIPTOR_CONFIGURATION: u8 = 2;
+/// Descriptor type of an interface descriptor, see [`descriptors`].
+pub const DESCRIPTOR_INTERFACE: u8 = 4;
+/// Descriptor type of an endpoint descriptor, see [`descriptors`].
+pub const DESCRIPTOR_ENDPOINT: u8 = 5;
+
+#[repr(C, align(4))]
+struct HostBuffers {
+    rx: [u8; MAX_PACKET_SIZE],
+    tx: [u8; MAX_PACKET_SIZE],
+}
+
+static mut HOST_BUFFERS: HostBuffers = HostBuffers {
+    rx: [0; MAX_PACKET_SIZE],
+    tx: [0; MAX_PACKET_SIZE],
+};
+
+static HOST_WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Host interrupt handler.
+pub struct HostInterruptHandler<T: Instance> {
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for HostInterruptHandler<T> {
+    unsafe fn on_interrupt() {
+        // Mask the interrupts, the flags are handled and cleared by the waiting task.
+        T::regs().int_en().write(|_| {});
+        HOST_WAKER.wake();
+    }
+}
+
+/// Host error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum HostError {
+    /// No device is connected.
+    Disconnected,
+    /// The device stalled the endpoint, or rejected the request.
+    Stall,
+    /// The device NAKed an interrupt transaction, it has no data.
+    Nak,
+    /// The device didn't answer, or kept NAKing for [`TRANSFER_TIMEOUT`].
+    Timeout,
+    /// The device answered with an unexpected PID or data toggle.
+    Protocol,
+    /// The device sent more data than fits the buffer.
+    BufferOverflow,
+    /// A descriptor is too short or of the wrong type.
+    InvalidDescriptor,
+}
+
+/// Speed of the connected device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DeviceSpeed {
+    /// 12 Mbps
+    Full,
+    /// 1.5 Mbps
+    Low,
+}
+
+/// Standard device descriptor, the fields used by class drivers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeviceDescriptor {
+    /// USB version, BCD
+    pub usb_version: u16,
+    /// Device class, 0 if defined per interface
+    pub class: u8,
+    /// Device subclass
+    pub subclass: u8,
+    /// Device protocol
+    pub protocol: u8,
+    /// Max packet size of the control endpoint
+    pub max_packet_size0: u8,
+    /// Vendor ID
+    pub vendor_id: u16,
+    /// Product ID
+    pub product_id: u16,
+    /// Device version, BCD
+    pub device_version: u16,
+    /// Number of configurations
+    pub num_configurations: u8,
+}
+
+impl DeviceDescriptor {
+    /// Parse a device descriptor.
+    pub fn parse(buf: &[u8]) -> Result<Self, HostError> {
+        if buf.len() < 18 || buf[1] != DESCRIPTOR_DEVICE {
+            return Err(HostError::InvalidDescriptor);
+        }
+        Ok(Self {
+            usb_version: u16::from_le_bytes([buf[2], buf[3]]),
+            class: buf[4],
+            subclass: buf[5],
+            protocol: buf[6],
+            max_packet_size0: buf[7],
+            vendor_id: u16::from_le_bytes([buf[8], buf[9]]),
+            product_id: u16::from_le_bytes([buf[10], buf[11]]),
+            device_version: u16::from_le_bytes([buf[12], buf[13]]),
+            num_configurations: buf[17],
+        })
+    }
+}
+
+/// Standard interface descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InterfaceDescriptor {
+    /// Interface number
+    pub number: u8,
+    /// Alternate setting
+    pub alternate_setting: u8,
+    /// Number of endpoints, without the control endpoint
+    pub num_endpoints: u8,
+    /// Interface class, e.g. 3 for HID, 8 for mass storage
+    pub class: u8,
+    /// Interface subclass
+    pub subclass: u8,
+    /// Interface protocol
+    pub protocol: u8,
+}
+
+impl InterfaceDescriptor {
+    /// Parse an interface descriptor.
+    pub fn parse(buf: &[u8]) -> Result<Self, HostError> {
+        if buf.len() < 9 || buf[1] != DESCRIPTOR_INTERFACE {
+            return Err(HostError::InvalidDescriptor);
+        }
+        Ok(Self {
+            number: buf[2],
+            alternate_setting: buf[3],
+            num_endpoints: buf[4],
+            class: buf[5],
+            subclass: buf[6],
+            protocol: buf[7],
+        })
+    }
+}
+
+/// Transfer type of a pipe
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TransferType {
+    /// Bulk transfers
+    Bulk,
+    /// Interrupt transfers
+    Interrupt,
+}
+
+/// Standard endpoint descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EndpointDescriptor {
+    /// Endpoint address, bit 7 set for IN endpoints
+    pub address: u8,
+    /// Transfer type, `None` for control and isochronous endpoints
+    pub transfer_type: Option<TransferType>,
+    /// Max packet size
+    pub max_packet_size: u16,
+    /// Polling interval of interrupt endpoints, in ms
+    pub interval: u8,
+}
+
+impl EndpointDescriptor {
+    /// Parse an endpoint descriptor.
+    pub fn parse(buf: &[u8]) -> Result<Self, HostError> {
+        if buf.len() < 7 || buf[1] != DESCRIPTOR_ENDPOINT {
+            return Err(HostError::InvalidDescriptor);
+        }
+        Ok(Self {
+            address: buf[2],
+            transfer_type: match buf[3] & 0x3 {
+                2 => Some(TransferType::Bulk),
+                3 => Some(TransferType::Interrupt),
+                _ => None,
+            },
+            max_packet_size: u16::from_le_bytes([buf[4], buf[5]]) & 0x7FF,
+            interval: buf[6],
+        })
+    }
+
+    /// Whether this is an IN endpoint.
+    pub fn is_in(&self) -> bool {
+        self.address & 0x80 != 0
+    }
+}
+
+/// Iterate over the descriptors of a configuration descriptor, as `(descriptor type, descriptor)`.
+pub fn descriptors(config: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut rest = config;
+    core::iter::from_fn(move || {
+        let len = *rest.first()? as usize;
+        if len < 2 || len > rest.len() {
+            return None;
+        }
+        let (descriptor, next) = rest.split_at(len);
+        rest = next;
+        Some((descriptor[1], descriptor))
+    })
+}
+
+/// An enumerated device.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Device {
+    /// Assigned address
+    pub address: u8,
+    /// Speed
+    pub speed: DeviceSpeed,
+    /// Device descriptor
+    pub descriptor: DeviceDescriptor,
+}
+
+/// A bulk or interrupt endpoint of a device.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Pipe {
+    address: u8,
+    endpoint: EndpointDescriptor,
+    transfer_type: TransferType,
+    toggle: bool,
+}
+
+impl Pipe {
+    /// Pipe to `endpoint` of `device`, returns `None` for control and isochronous endpoints.
+    pub fn new(device: &Device, endpoint: EndpointDescriptor) -> Option<Self> {
+        Some(Self {
+            address: device.address,
+            endpoint,
+            transfer_type: endpoint.transfer_type?,
+            toggle: false,
+        })
+    }
+
+    /// Endpoint descriptor
+    pub fn endpoint(&self) -> &EndpointDescriptor {
+        &self.endpoint
+    }
+}
+
+/// Setup packet of a control transfer.
+pub fn setup_packet(request_type: u8, request: u8, value: u16, index: u16, length: u16) -> [u8; 8] {
+    let mut setup = [request_type, request, 0, 0, 0, 0, 0, 0];
+    setup[2..4].copy_from_slice(&value.to_le_bytes());
+    setup[4..6].copy_from_slice(&index.to_le_bytes());
+    setup[6..8].copy_from_slice(&length.to_le_bytes());
+    setup
+}
+
+/// USB host driver.
+pub struct Host<'d, T: Instance> {
+    _phantom: PhantomData<&'d mut T>,
+    speed: DeviceSpeed,
+    /// Max packet size of the control endpoint of the device.
+    max_packet_size0: u16,
+}
+
+impl<'d, T: Instance> Host<'d, T> {
+    /// Create a new USB host driver.
+    ///
+    /// Fails if the USB clock isn't 48 MHz, see [`rcc::Config`](crate::rcc::Config).
+    pub fn new(
+        _usb: impl Peripheral<P = T> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, HostInterruptHandler<T>> + 'd,
+        dp: impl Peripheral<P = impl DpPin<T, 0>> + 'd,
+        dm: impl Peripheral<P = impl DmPin<T, 0>> + 'd,
+    ) -> Result<Self, UsbClockError> {
+        crate::rcc::usb_clock()?;
+        into_ref!(dp, dm);
+
+        // The pins are taken over by the transceiver once it is enabled.
+        dp.set_as_input(Pull::None);
+        dm.set_as_input(Pull::None);
+
+        T::enable_and_reset();
+
+        let regs = T::regs();
+
+        // Reset the SIE and clear the FIFOs and interrupt flags.
+        regs.ctrl().write(|w| {
+            w.set_clr_all(true);
+            w.set_reset_sie(true);
+        });
+        embassy_time::block_for(Duration::from_micros(10));
+        regs.ctrl().write(|w| w.set_host_mode(true));
+
+        regs.uhost_ctrl().write(|_| {});
+        regs.dev_ad().write(|w| w.set_usb_addr(0));
+        regs.uh_ep_mod().write(|w| {
+            w.set_uh_ep_tx_en(true);
+            w.set_uh_ep_rx_en(true);
+        });
+        unsafe {
+            regs.uh_rx_dma()
+                .write(|w| w.0 = core::ptr::addr_of!(HOST_BUFFERS.rx) as u32);
+            regs.uh_tx_dma()
+                .write(|w| w.0 = core::ptr::addr_of!(HOST_BUFFERS.tx) as u32);
+        }
+        regs.uh_rx_ctrl().write(|_| {});
+        regs.uh_tx_ctrl().write(|_| {});
+
+        regs.ctrl().write(|w| {
+            w.set_host_mode(true);
+            w.set_int_busy(true);
+            w.set_dma_en(true);
+        });
+        regs.int_fg().write(|w| w.0 = 0xFF);
+
+        T::Interrupt::unpend();
+        unsafe { T::Interrupt::enable() };
+
+        Ok(Self {
+            _phantom: PhantomData,
+            speed: DeviceSpeed::Full,
+            max_packet_size0: 8,
+        })
+    }
+
+    /// Whether a device is attached.
+    pub fn is_connected(&self) -> bool {
+        T::regs().mis_st().read().dev_attach()
+    }
+
+    /// Wait for the interrupt flags selected by `enable`, and clear them.
+    async fn wait_flags(&mut self, enable: impl Fn(&mut crate::pac::usbfs::regs::IntEn)) {
+        let regs = T::regs();
+        poll_fn(|cx| {
+            HOST_WAKER.register(cx.waker());
+
+            let mut mask = crate::pac::usbfs::regs::IntEn(0);
+            enable(&mut mask);
+            let pending = regs.int_fg().read().0 & mask.0;
+            if pending != 0 {
+                regs.int_fg().write(|w| w.0 = pending);
+                Poll::Ready(())
+            } else {
+                regs.int_en().write_value(mask);
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Wait until a device is attached.
+    pub async fn wait_connected(&mut self) {
+        while !self.is_connected() {
+            self.wait_flags(|w| w.set_detect(true)).await;
+        }
+    }
+
+    /// Wait until the device is detached.
+    pub async fn wait_disconnected(&mut self) {
+        while self.is_connected() {
+            self.wait_flags(|w| w.set_detect(true)).await;
+        }
+        let regs = T::regs();
+        regs.uhost_ctrl().write(|_| {});
+        regs.uh_setup().write(|_| {});
+    }
+
+    /// Reset the bus and enable the port at the speed of the attached device.
+    pub async fn bus_reset(&mut self) -> Result<DeviceSpeed, HostError> {
+        let regs = T::regs();
+
+        regs.dev_ad().write(|w| w.set_usb_addr(0));
+        regs.uhost_ctrl().write(|w| w.set_bus_reset(true));
+        Timer::after_millis(15).await;
+        regs.uhost_ctrl().write(|_| {});
+        Timer::after_micros(2).await;
+
+        if !self.is_connected() {
+            return Err(HostError::Disconnected);
+        }
+
+        // A low-speed device pulls D- up.
+        let low_speed = regs.mis_st().read().dm_level();
+        self.speed = if low_speed { DeviceSpeed::Low } else { DeviceSpeed::Full };
+
+        regs.ctrl().modify(|w| w.set_low_speed(low_speed));
+        regs.uhost_ctrl().write(|w| {
+            w.set_low_speed(low_speed);
+            w.set_port_en(true);
+        });
+        regs.uh_setup().write(|w| w.set_sof_en(true));
+
+        // Let the device recover from the reset.
+        Timer::after_millis(20).await;
+
+        Ok(self.speed)
+    }
+
+    /// Run a single transaction, returns the number of received bytes for IN.
+    ///
+    /// The TX buffer must be filled for OUT and SETUP.
+    async fn transaction(&mut self, address: u8, pid: u8, endpoint: u8, toggle: bool) -> Result<usize, HostError> {
+        let regs = T::regs();
+
+        regs.dev_ad().write(|w| w.set_usb_addr(address));
+        match pid {
+            PID_IN => regs.uh_rx_ctrl().write(|w| w.set_r_tog(toggle)),
+            _ => regs.uh_tx_ctrl().write(|w| w.set_t_tog(toggle)),
+        }
+
+        regs.int_fg().write(|w| w.set_transfer(true));
+        regs.uh_ep_pid().write(|w| {
+            w.set_token(pid);
+            w.set_endp(endpoint);
+        });
+
+        let done = with_timeout(TRANSACTION_TIMEOUT, self.wait_flags(|w| w.set_transfer(true))).await;
+        regs.uh_ep_pid().write(|_| {});
+        if done.is_err() {
+            return Err(HostError::Timeout);
+        }
+        if !self.is_connected() {
+            return Err(HostError::Disconnected);
+        }
+
+        let st = regs.int_st().read();
+        let response = st.mask_uis_h_res();
+        match pid {
+            PID_IN if st.tog_ok() => Ok(regs.rx_len().read().0 as usize),
+            _ if response == PID_ACK && pid != PID_IN => Ok(0),
+            _ if response == PID_NAK => Err(HostError::Nak),
+            _ if response == PID_STALL => Err(HostError::Stall),
+            _ => Err(HostError::Protocol),
+        }
+    }
+
+    /// Run a transaction, retrying while it is NAKed.
+    async fn transaction_retry(
+        &mut self,
+        address: u8,
+        pid: u8,
+        endpoint: u8,
+        toggle: bool,
+    ) -> Result<usize, HostError> {
+        let deadline = Instant::now() + TRANSFER_TIMEOUT;
+        loop {
+            match self.transaction(address, pid, endpoint, toggle).await {
+                Err(HostError::Nak) if Instant::now() < deadline => {}
+                Err(HostError::Nak) => return Err(HostError::Timeout),
+                res => return res,
+            }
+        }
+    }
+
+    fn write_tx(&mut self, data: &[u8]) {
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                core::ptr::addr_of_mut!(HOST_BUFFERS.tx) as *mut u8,
+                data.len(),
+            );
+        }
+        compiler_fence(Ordering::SeqCst);
+        T::regs().uh_tx_len().write(|w| w.set_len(data.len() as _));
+    }
+
+    fn read_rx(&mut self, buf: &mut [u8]) {
+        compiler_fence(Ordering::SeqCst);
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                core::ptr::addr_of!(HOST_BUFFERS.rx) as *const u8,
+                buf.as_mut_ptr(),
+                buf.len(),
+            );
+        }
+    }
+
+    async fn setup_stage(&mut self, address: u8, setup: &[u8; 8]) -> Result<(), HostError> {
+        self.write_tx(setup);
+        self.transaction_retry(address, PID_SETUP, 0, false).await?;
+        Ok(())
+    }
+
+    /// Run a control transfer with an IN data stage, returns the number of received bytes.
+    pub async fn control_in(&mut self, device: &Device, setup: &[u8; 8], buf: &mut [u8]) -> Result<usize, HostError> {
+        self.control_in_raw(device.address, setup, buf).await
+    }
+
+    async fn control_in_raw(&mut self, address: u8, setup: &[u8; 8], buf: &mut [u8]) -> Result<usize, HostError> {
+        self.setup_stage(address, setup).await?;
+
+        let len = buf.len().min(u16::from_le_bytes([setup[6], setup[7]]) as usize);
+        let mut received = 0;
+        let mut toggle = true;
+        while received < len {
+            let n = self.transaction_retry(address, PID_IN, 0, toggle).await?;
+            if received + n > len {
+                return Err(HostError::BufferOverflow);
+            }
+            self.read_rx(&mut buf[received..received + n]);
+            received += n;
+            toggle = !toggle;
+            if n < self.max_packet_size0 as usize {
+                break;
+            }
+        }
+
+        // Status stage
+        self.write_tx(&[]);
+        self.transaction_retry(address, PID_OUT, 0, true).await?;
+
+        Ok(received)
+    }
+
+    /// Run a control transfer with an OUT data stage, or none if `data` is empty.
+    pub async fn control_out(&mut self, device: &Device, setup: &[u8; 8], data: &[u8]) -> Result<(), HostError> {
+        self.control_out_raw(device.address, setup, data).await
+    }
+
+    async fn control_out_raw(&mut self, address: u8, setup: &[u8; 8], data: &[u8]) -> Result<(), HostError> {
+        self.setup_stage(address, setup).await?;
+
+        let mut toggle = true;
+        for chunk in data.chunks(self.max_packet_size0 as usize) {
+            self.write_tx(chunk);
+            self.transaction_retry(address, PID_OUT, 0, toggle).await?;
+            toggle = !toggle;
+        }
+
+        // Status stage
+        self.transaction_retry(address, PID_IN, 0, true).await?;
+
+        Ok(())
+    }
+
+    /// Reset the bus and enumerate the attached device, assigning it `address`.
+    pub async fn enumerate(&mut self, address: u8) -> Result<Device, HostError> {
+        assert!((1..=127).contains(&address));
+
+        let speed = self.bus_reset().await?;
+
+        // Read the max packet size of the control endpoint first, it is at least 8.
+        self.max_packet_size0 = 8;
+        let mut buf = [0; 18];
+        let setup = setup_packet(0x80, GET_DESCRIPTOR, (DESCRIPTOR_DEVICE as u16) << 8, 0, 8);
+        if self.control_in_raw(0, &setup, &mut buf[..8]).await? < 8 {
+            return Err(HostError::InvalidDescriptor);
+        }
+        self.max_packet_size0 = buf[7].clamp(8, MAX_PACKET_SIZE as u8) as u16;
+
+        let setup = setup_packet(0x00, SET_ADDRESS, address as u16, 0, 0);
+        self.control_out_raw(0, &setup, &[]).await?;
+        // Set address recovery interval
+        Timer::after_millis(2).await;
+
+        let setup = setup_packet(0x80, GET_DESCRIPTOR, (DESCRIPTOR_DEVICE as u16) << 8, 0, 18);
+        let n = self.control_in_raw(address, &setup, &mut buf).await?;
+
+        Ok(Device {
+            address,
+            speed,
+            descriptor: DeviceDescriptor::parse(&buf[..n])?,
+        })
+    }
+
+    /// Read configuration descriptor `index` with its interface and endpoint descriptors, returns
+    /// the number of bytes read.
+    ///
+    /// The descriptor is truncated to the length of `buf`.
+    pub async fn get_configuration_descriptor(
+        &mut self,
+        device: &Device,
+        index: u8,
+        buf: &mut [u8],
+    ) -> Result<usize, HostError> {
+        let value = (DESCRIPTOR_CONFIGURATION as u16) << 8 | index as u16;
+        let len = buf.len().min(u16::MAX as usize) as u16;
+        let n = self
+            .control_in(device, &setup_packet(0x80, GET_DESCRIPTOR, value, 0, len), buf)
+            .await?;
+        if n < 9 || buf[1] != DESCRIPTOR_CONFIGURATION {
+            return Err(HostError::InvalidDescriptor);
+        }
+        Ok(n)
+    }
+
+    /// Select configuration `value`, as found in the configuration descriptor.
+    pub async fn set_configuration(&mut self, device: &Device, value: u8) -> Result<(), HostError> {
+        self.control_out(device, &setup_packet(0x00, SET_CONFIGURATION, value as u16, 0, 0), &[])
+            .await
+    }
+
+    /// Clear the halt of a stalled endpoint and reset the data toggle of its pipe.
+    pub async fn clear_halt(&mut self, pipe: &mut Pipe) -> Result<(), HostError> {
+        let setup = setup_packet(0x02, CLEAR_FEATURE, 0, pipe.endpoint.address as u16, 0);
+        self.control_out_raw(pipe.address, &setup, &[]).await?;
+        pipe.toggle = false;
+        Ok(())
+    }
+
+    /// Read from an IN pipe until a short packet or until `buf` is full, returns the number of
+    /// received bytes.
+    ///
+    /// An interrupt pipe reads a single packet, and returns [`HostError::Nak`] if the device has no
+    /// data.
+    pub async fn in_transfer(&mut self, pipe: &mut Pipe, buf: &mut [u8]) -> Result<usize, HostError> {
+        let endpoint = pipe.endpoint.address & 0x0F;
+        let max_packet_size = pipe.endpoint.max_packet_size as usize;
+
+        let mut received = 0;
+        loop {
+            let n = match pipe.transfer_type {
+                TransferType::Bulk => {
+                    self.transaction_retry(pipe.address, PID_IN, endpoint, pipe.toggle)
+                        .await?
+                }
+                TransferType::Interrupt => self.transaction(pipe.address, PID_IN, endpoint, pipe.toggle).await?,
+            };
+            if received + n > buf.len() {
+                return Err(HostError::BufferOverflow);
+            }
+            self.read_rx(&mut buf[received..received + n]);
+            received += n;
+            pipe.toggle = !pipe.toggle;
+
+            if pipe.transfer_type == TransferType::Interrupt || n < max_packet_size || received == buf.len() {
+                return Ok(received);
+            }
+        }
+    }
+
+    /// Write `data` to an OUT pipe, in packets of the max packet size.
+    pub async fn out_transfer(&mut self, pipe: &mut Pipe, data: &[u8]) -> Result<(), HostError> {
+        let endpoint = pipe.endpoint.address & 0x0F;
+        for chunk in data.chunks(pipe.endpoint.max_packet_size as usize) {
+            self.write_tx(chunk);
+            self.transaction_retry(pipe.address, PID_OUT, endpoint, pipe.toggle)
+                .await?;
+            pipe.toggle = !pipe.toggle;
+        }
+        Ok(())
+    }
+}
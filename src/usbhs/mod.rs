@@ -0,0 +1,1246 @@
+//! USB high-speed device (USBHS)
+//!
+//! Implements [`embassy_usb_driver`] for the USBHS core of the CH32V305/CH32V307, at 480 Mbps or
+//! at full speed.
+//!
+//! Packets are moved by DMA from endpoint buffers carved out of a user-provided RAM buffer, one per
+//! endpoint direction of its max packet size. Isochronous endpoints take a whole endpoint number and
+//! two buffers, one for the frame on the bus and one for the frame read or queued by the
+//! application.
+//!
+//! Bulk endpoints get two buffers as well: the next packet is received, or queued for sending, in
+//! one buffer while the application copies the other, so back-to-back packets aren't NAKed.
+
+use core::future::poll_fn;
+use core::marker::PhantomData;
+use core::sync::atomic::{compiler_fence, AtomicBool, AtomicU16, AtomicU32, Ordering};
+use core::task::Poll;
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::waitqueue::AtomicWaker;
+use embassy_time::{Duration, Instant, Timer};
+use embassy_usb_driver as driver;
+use embassy_usb_driver::{
+    Direction, EndpointAddress, EndpointAllocError, EndpointError, EndpointInfo, EndpointType, Event, Unsupported,
+};
+
+use crate::exti::ExtiInput;
+use crate::gpio::Pull;
+use crate::interrupt::typelevel::Interrupt as _;
+use crate::pac::usbhs::vals::{EpRxResponse, EpTog, EpTxResponse, SpeedType, UsbToken};
+use crate::pac::RCC;
+use crate::peripheral::RccPeripheral;
+use crate::rcc::UsbClockError;
+use crate::time::Hertz;
+use crate::{interrupt, into_ref, Peripheral};
+
+/// Bus idle time before a suspended device may signal remote wakeup.
+const REMOTE_WAKEUP_IDLE: Duration = Duration::from_millis(5);
+/// Duration of the resume signaling, 1 to 15 ms.
+const RESUME_SIGNALING: Duration = Duration::from_millis(10);
+
+/// Interrupt handler.
+pub struct InterruptHandler<T: Instance> {
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandler<T> {
+    unsafe fn on_interrupt() {
+        let regs = T::regs();
+        let flag = regs.int_fg().read();
+
+        if flag.bus_rst() {
+            IRQ_RESET.store(true, Ordering::Relaxed);
+            // Write 1 to clear.
+            regs.int_fg().write(|w| w.set_bus_rst(true));
+            BUS_WAKER.wake();
+        }
+
+        if flag.suspend() {
+            // Raised on both suspend and resume.
+            if regs.mis_st().read().suspend() {
+                IRQ_SUSPEND.store(true, Ordering::Relaxed);
+            } else {
+                IRQ_RESUME.store(true, Ordering::Relaxed);
+            }
+            regs.int_fg().write(|w| w.set_suspend(true));
+            BUS_WAKER.wake();
+        }
+
+        if flag.setup_act() {
+            // The data and status stages start with DATA1, hold them until the setup packet is
+            // handled.
+            regs.uep_tx_ctrl(0).write(|w| {
+                w.set_t_tog(EpTog::DATA1);
+                w.set_t_res(EpTxResponse::NAK);
+            });
+            regs.uep_rx_ctrl(0).write(|w| {
+                w.set_r_tog(EpTog::DATA1);
+                w.set_r_res(EpRxResponse::NAK);
+            });
+            EP_OUT_READY[0].store(false, Ordering::Relaxed);
+            EP_IN_BUSY[0].store(false, Ordering::Relaxed);
+            EP0_SETUP.store(true, Ordering::Release);
+            regs.int_fg().write(|w| w.set_setup_act(true));
+            EP_OUT_WAKERS[0].wake();
+            EP_IN_WAKERS[0].wake();
+        }
+
+        if flag.transfer() {
+            let st = regs.int_st().read();
+            let index = st.mask_uis_endp() as usize;
+
+            match st.mask_token() {
+                UsbToken::OUT if is_iso(index) => {
+                    // No retransmissions, take every frame and receive the next one in the other
+                    // buffer.
+                    let slots = &EP_OUT_SLOTS[index];
+                    let slot = !slots.dma_slot();
+                    regs.uep_rx_dma(index - 1).write(|w| w.0 = slots.buffer(slot) as u32);
+                    slots.set_dma_slot(slot);
+                    EP_OUT_LEN[index].store(regs.rx_len().read().0 as u16, Ordering::Relaxed);
+                    EP_OUT_READY[index].store(true, Ordering::Release);
+                    EP_OUT_WAKERS[index].wake();
+                }
+                UsbToken::IN if is_iso(index) => {
+                    // Send the queued frame in the next one, or a zero-length packet.
+                    if EP_IN_BUSY[index].load(Ordering::Relaxed) {
+                        let slots = &EP_IN_SLOTS[index];
+                        let slot = !slots.dma_slot();
+                        regs.uep_tx_dma(index - 1).write(|w| w.0 = slots.buffer(slot) as u32);
+                        slots.set_dma_slot(slot);
+                        regs.uep_t_len(index)
+                            .write(|w| w.set_len(EP_IN_LEN[index].load(Ordering::Relaxed) as _));
+                        EP_IN_BUSY[index].store(false, Ordering::Release);
+                        EP_IN_WAKERS[index].wake();
+                    } else {
+                        regs.uep_t_len(index).write(|w| w.set_len(0));
+                    }
+                }
+                UsbToken::OUT if EP_OUT_SLOTS[index].is_set() => {
+                    // Packets with an unexpected toggle are retransmissions, already received.
+                    if st.tog_ok() {
+                        let slots = &EP_OUT_SLOTS[index];
+                        let slot = slots.dma_slot();
+                        EP_OUT_SLOT_LEN[index][slot as usize].store(regs.rx_len().read().0 as u16, Ordering::Relaxed);
+                        if EP_OUT_READY[index].load(Ordering::Relaxed) {
+                            // Both buffers are full, NAK until one is read.
+                            EP_OUT_FULL[index].store(true, Ordering::Relaxed);
+                            regs.uep_rx_ctrl(index).modify(|w| w.set_r_res(EpRxResponse::NAK));
+                        } else {
+                            // Receive the next packet in the other buffer while this one is read.
+                            regs.uep_rx_dma(index - 1).write(|w| w.0 = slots.buffer(!slot) as u32);
+                            slots.set_dma_slot(!slot);
+                        }
+                        EP_OUT_READY[index].store(true, Ordering::Release);
+                        EP_OUT_WAKERS[index].wake();
+                    }
+                }
+                UsbToken::IN if EP_IN_SLOTS[index].is_set() => {
+                    // Send the packet queued in the other buffer right away.
+                    if EP_IN_QUEUED[index].load(Ordering::Relaxed) {
+                        let slots = &EP_IN_SLOTS[index];
+                        let slot = !slots.dma_slot();
+                        regs.uep_tx_dma(index - 1).write(|w| w.0 = slots.buffer(slot) as u32);
+                        slots.set_dma_slot(slot);
+                        regs.uep_t_len(index)
+                            .write(|w| w.set_len(EP_IN_LEN[index].load(Ordering::Relaxed) as _));
+                        EP_IN_QUEUED[index].store(false, Ordering::Release);
+                    } else {
+                        regs.uep_tx_ctrl(index).modify(|w| w.set_t_res(EpTxResponse::NAK));
+                        EP_IN_BUSY[index].store(false, Ordering::Release);
+                    }
+                    EP_IN_WAKERS[index].wake();
+                }
+                UsbToken::OUT => {
+                    // Packets with an unexpected toggle are retransmissions, already received.
+                    if st.tog_ok() {
+                        EP_OUT_LEN[index].store(regs.rx_len().read().0 as u16, Ordering::Relaxed);
+                        // NAK the next packet until this one is read. The other endpoints toggle
+                        // automatically.
+                        regs.uep_rx_ctrl(index).modify(|w| {
+                            if index == 0 {
+                                w.set_r_tog(toggle(w.r_tog()));
+                            }
+                            w.set_r_res(EpRxResponse::NAK);
+                        });
+                        EP_OUT_READY[index].store(true, Ordering::Release);
+                        EP_OUT_WAKERS[index].wake();
+                    }
+                }
+                UsbToken::IN => {
+                    regs.uep_tx_ctrl(index).modify(|w| {
+                        if index == 0 {
+                            w.set_t_tog(toggle(w.t_tog()));
+                        }
+                        w.set_t_res(EpTxResponse::NAK);
+                    });
+                    EP_IN_BUSY[index].store(false, Ordering::Release);
+                    EP_IN_WAKERS[index].wake();
+                }
+                _ => {}
+            }
+
+            regs.int_fg().write(|w| w.set_transfer(true));
+        }
+    }
+}
+
+const EP_COUNT: usize = 16;
+const EP0_MAX_PACKET_SIZE: u16 = 64;
+
+const NEW_AW: AtomicWaker = AtomicWaker::new();
+const NEW_FLAG: AtomicBool = AtomicBool::new(false);
+const NEW_LEN: AtomicU16 = AtomicU16::new(0);
+static BUS_WAKER: AtomicWaker = NEW_AW;
+static EP0_SETUP: AtomicBool = AtomicBool::new(false);
+static EP_IN_WAKERS: [AtomicWaker; EP_COUNT] = [NEW_AW; EP_COUNT];
+static EP_OUT_WAKERS: [AtomicWaker; EP_COUNT] = [NEW_AW; EP_COUNT];
+static EP_IN_ENABLED: [AtomicBool; EP_COUNT] = [NEW_FLAG; EP_COUNT];
+static EP_OUT_ENABLED: [AtomicBool; EP_COUNT] = [NEW_FLAG; EP_COUNT];
+/// A packet was queued and not yet sent.
+static EP_IN_BUSY: [AtomicBool; EP_COUNT] = [NEW_FLAG; EP_COUNT];
+/// A received packet of `EP_OUT_LEN` bytes waits in the buffer.
+static EP_OUT_READY: [AtomicBool; EP_COUNT] = [NEW_FLAG; EP_COUNT];
+static EP_OUT_LEN: [AtomicU16; EP_COUNT] = [NEW_LEN; EP_COUNT];
+static EP_ISO: [AtomicBool; EP_COUNT] = [NEW_FLAG; EP_COUNT];
+const NEW_SLOTS: SlotBuffers = SlotBuffers::new();
+static EP_IN_SLOTS: [SlotBuffers; EP_COUNT] = [NEW_SLOTS; EP_COUNT];
+static EP_OUT_SLOTS: [SlotBuffers; EP_COUNT] = [NEW_SLOTS; EP_COUNT];
+/// Length of the queued packet of an isochronous or bulk IN endpoint.
+static EP_IN_LEN: [AtomicU16; EP_COUNT] = [NEW_LEN; EP_COUNT];
+/// A second packet is queued behind the one being sent on a bulk IN endpoint.
+static EP_IN_QUEUED: [AtomicBool; EP_COUNT] = [NEW_FLAG; EP_COUNT];
+/// Both buffers of a bulk OUT endpoint hold a packet, the endpoint NAKs.
+static EP_OUT_FULL: [AtomicBool; EP_COUNT] = [NEW_FLAG; EP_COUNT];
+const NEW_SLOT_LEN: [AtomicU16; 2] = [NEW_LEN; 2];
+/// Length of the packet in each buffer of a bulk OUT endpoint.
+static EP_OUT_SLOT_LEN: [[AtomicU16; 2]; EP_COUNT] = [NEW_SLOT_LEN; EP_COUNT];
+static IRQ_RESET: AtomicBool = AtomicBool::new(false);
+static IRQ_SUSPEND: AtomicBool = AtomicBool::new(false);
+static IRQ_RESUME: AtomicBool = AtomicBool::new(false);
+
+fn is_iso(index: usize) -> bool {
+    EP_ISO[index].load(Ordering::Relaxed)
+}
+
+/// The two buffers of an isochronous or bulk endpoint direction. The DMA points to one, the other
+/// holds the packet read or queued by the application.
+struct SlotBuffers {
+    /// First buffer, 0 for single-buffered endpoints.
+    base: AtomicU32,
+    /// Offset of the second buffer from the first.
+    stride: AtomicU16,
+    /// Buffer the DMA points to.
+    slot: AtomicBool,
+}
+
+impl SlotBuffers {
+    const fn new() -> Self {
+        Self {
+            base: AtomicU32::new(0),
+            stride: AtomicU16::new(0),
+            slot: AtomicBool::new(false),
+        }
+    }
+
+    fn set(&self, base: *mut u8, stride: u16) {
+        self.base.store(base as u32, Ordering::Relaxed);
+        self.stride.store(stride, Ordering::Relaxed);
+        self.slot.store(false, Ordering::Relaxed);
+    }
+
+    fn is_set(&self) -> bool {
+        self.base.load(Ordering::Relaxed) != 0
+    }
+
+    fn buffer(&self, slot: bool) -> *mut u8 {
+        let base = self.base.load(Ordering::Relaxed);
+        let stride = self.stride.load(Ordering::Relaxed) as u32;
+        (base + slot as u32 * stride) as *mut u8
+    }
+
+    fn dma_slot(&self) -> bool {
+        self.slot.load(Ordering::Relaxed)
+    }
+
+    fn set_dma_slot(&self, slot: bool) {
+        self.slot.store(slot, Ordering::Relaxed);
+    }
+}
+
+fn toggle(tog: EpTog) -> EpTog {
+    if tog == EpTog::DATA0 {
+        EpTog::DATA1
+    } else {
+        EpTog::DATA0
+    }
+}
+
+/// Bus speed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Speed {
+    /// 480 Mbps, falls back to full speed on full-speed hosts and hubs.
+    High,
+    /// 12 Mbps
+    Full,
+}
+
+/// USBHS config
+#[non_exhaustive]
+#[derive(Copy, Clone)]
+pub struct Config {
+    /// Highest bus speed to negotiate.
+    pub speed: Speed,
+    /// HSE frequency, divided down to the 4 MHz reference of the PHY PLL. A multiple of 4 MHz up
+    /// to 32 MHz.
+    pub hse: Hertz,
+}
+
+impl Default for Config {
+    /// High speed, with an 8 MHz HSE.
+    fn default() -> Self {
+        Self {
+            speed: Speed::High,
+            hse: Hertz::mhz(8),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct EndpointData {
+    ep_type: EndpointType, // only valid if used_in || used_out
+    used_in: bool,
+    used_out: bool,
+}
+
+/// USB driver.
+pub struct Driver<'d, T: Instance> {
+    phantom: PhantomData<&'d mut T>,
+    alloc: [EndpointData; EP_COUNT],
+    vbus: Option<ExtiInput<'d>>,
+    ep_buffer: &'d mut [u8],
+    ep_buffer_free: usize,
+}
+
+impl<'d, T: Instance> Driver<'d, T> {
+    /// Create a new USB driver.
+    ///
+    /// The endpoint buffers are allocated from `ep_buffer`, which needs room for the max packet
+    /// size of every endpoint direction, rounded up to 4 bytes and twice for bulk and isochronous
+    /// endpoints, plus 64 bytes for the control endpoint.
+    pub fn new(
+        _usb: impl Peripheral<P = T> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+        dp: impl Peripheral<P = impl DpPin<T, 0>> + 'd,
+        dm: impl Peripheral<P = impl DmPin<T, 0>> + 'd,
+        ep_buffer: &'d mut [u8],
+        config: Config,
+    ) -> Result<Self, UsbClockError> {
+        // The PHY PLL runs from HSE, to 480 MHz.
+        let div = config.hse.0 / 4_000_000;
+        if config.hse.0 % 4_000_000 != 0 || !(1..=8).contains(&div) || !RCC.ctlr().read().hserdy() {
+            return Err(UsbClockError::Hse);
+        }
+
+        into_ref!(dp, dm);
+
+        // The pins are taken over by the PHY once it is enabled.
+        dp.set_as_input(Pull::None);
+        dm.set_as_input(Pull::None);
+
+        // PHY PLL from the HSE, with a 4 MHz reference.
+        RCC.cfgr2().modify(|w| {
+            w.set_usbhsdiv((div - 1) as u8);
+            w.set_usbhspllsrc(false);
+            w.set_usbhsclk(1);
+            w.set_usbhspll(true);
+        });
+
+        T::enable_and_reset();
+
+        let regs = T::regs();
+
+        // Reset the SIE and clear the FIFOs and interrupt flags.
+        regs.ctrl().write(|w| {
+            w.set_clr_all(true);
+            w.set_reset_sie(true);
+        });
+        embassy_time::block_for(embassy_time::Duration::from_micros(10));
+        regs.ctrl().write(|_| {});
+
+        // Take the PHY out of suspend.
+        regs.host_ctrl().write(|w| w.set_phy_suspendm(true));
+
+        regs.ctrl().write(|w| {
+            w.set_speed_type(match config.speed {
+                Speed::High => SpeedType::HIGH,
+                Speed::Full => SpeedType::FULL,
+            })
+        });
+
+        for index in 0..EP_COUNT {
+            EP_ISO[index].store(false, Ordering::Relaxed);
+            EP_IN_SLOTS[index].set(core::ptr::null_mut(), 0);
+            EP_OUT_SLOTS[index].set(core::ptr::null_mut(), 0);
+        }
+
+        // The start of the buffer must be 4-byte aligned for the DMA.
+        let ep_buffer_free = ep_buffer.as_ptr().align_offset(4);
+
+        Ok(Self {
+            phantom: PhantomData,
+            alloc: [EndpointData {
+                ep_type: EndpointType::Bulk,
+                used_in: false,
+                used_out: false,
+            }; EP_COUNT],
+            vbus: None,
+            ep_buffer,
+            ep_buffer_free,
+        })
+    }
+
+    /// Create a new USB driver, sensing VBUS on `vbus`.
+    ///
+    /// `vbus` reads the VBUS line, through a divider, and should be pulled down. The device
+    /// connects to the bus only while VBUS is present, as self-powered devices must.
+    pub fn new_with_vbus(
+        usb: impl Peripheral<P = T> + 'd,
+        irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
+        dp: impl Peripheral<P = impl DpPin<T, 0>> + 'd,
+        dm: impl Peripheral<P = impl DmPin<T, 0>> + 'd,
+        ep_buffer: &'d mut [u8],
+        config: Config,
+        vbus: ExtiInput<'d>,
+    ) -> Result<Self, UsbClockError> {
+        let mut this = Self::new(usb, irq, dp, dm, ep_buffer, config)?;
+        this.vbus = Some(vbus);
+        Ok(this)
+    }
+
+    fn alloc_ep_mem(&mut self, len: u16) -> *mut u8 {
+        let len = (len as usize + 3) & !3;
+        let start = self.ep_buffer_free;
+        if start + len > self.ep_buffer.len() {
+            panic!("Endpoint memory full");
+        }
+        self.ep_buffer_free += len;
+        self.ep_buffer[start..].as_mut_ptr()
+    }
+
+    fn alloc_endpoint<D: Dir>(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<Endpoint<'d, T, D>, driver::EndpointAllocError> {
+        let max = match ep_type {
+            EndpointType::Control => EP0_MAX_PACKET_SIZE,
+            EndpointType::Bulk => 512,
+            EndpointType::Interrupt | EndpointType::Isochronous => 1024,
+        };
+        if max_packet_size > max {
+            return Err(EndpointAllocError);
+        }
+
+        let index = self.alloc.iter_mut().enumerate().find(|(i, ep)| {
+            if *i == 0 && ep_type != EndpointType::Control {
+                return false; // reserved for control pipe
+            }
+            let used = ep.used_out || ep.used_in;
+            let used_dir = match D::dir() {
+                Direction::Out => ep.used_out,
+                Direction::In => ep.used_in,
+            };
+            // Isochronous endpoints take the whole endpoint number.
+            let iso = ep_type == EndpointType::Isochronous;
+            !used || (ep.ep_type == ep_type && !iso && !used_dir)
+        });
+
+        let (index, ep) = match index {
+            Some(x) => x,
+            None => return Err(EndpointAllocError),
+        };
+
+        ep.ep_type = ep_type;
+        match D::dir() {
+            Direction::Out => ep.used_out = true,
+            Direction::In => ep.used_in = true,
+        }
+
+        let regs = T::regs();
+        // Endpoint 0 uses a single buffer for both directions.
+        let buf = if index == 0 && D::dir() == Direction::In {
+            regs.uep0_dma().read().0 as *mut u8
+        } else if matches!(ep_type, EndpointType::Bulk | EndpointType::Isochronous) {
+            // One buffer for the packet on the bus, one for the application.
+            let stride = (max_packet_size + 3) & !3;
+            let buf = self.alloc_ep_mem(2 * stride);
+            let slots = match D::dir() {
+                Direction::Out => &EP_OUT_SLOTS[index],
+                Direction::In => &EP_IN_SLOTS[index],
+            };
+            slots.set(buf, stride);
+            EP_ISO[index].store(ep_type == EndpointType::Isochronous, Ordering::Relaxed);
+            buf
+        } else {
+            self.alloc_ep_mem(max_packet_size)
+        };
+
+        match (index, D::dir()) {
+            (0, Direction::Out) => regs.uep0_dma().write(|w| w.0 = buf as u32),
+            (0, Direction::In) => {}
+            (_, Direction::Out) => {
+                regs.uep_rx_dma(index - 1).write(|w| w.0 = buf as u32);
+                regs.uep_max_len(index).write(|w| w.set_len(max_packet_size));
+            }
+            (_, Direction::In) => regs.uep_tx_dma(index - 1).write(|w| w.0 = buf as u32),
+        }
+
+        Ok(Endpoint {
+            _phantom: PhantomData,
+            info: EndpointInfo {
+                addr: EndpointAddress::from_parts(index, D::dir()),
+                ep_type,
+                max_packet_size,
+                interval_ms,
+            },
+            buf,
+        })
+    }
+}
+
+impl<'d, T: Instance> driver::Driver<'d> for Driver<'d, T> {
+    type EndpointOut = Endpoint<'d, T, Out>;
+    type EndpointIn = Endpoint<'d, T, In>;
+    type ControlPipe = ControlPipe<'d, T>;
+    type Bus = Bus<'d, T>;
+
+    fn alloc_endpoint_in(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<Self::EndpointIn, driver::EndpointAllocError> {
+        self.alloc_endpoint(ep_type, max_packet_size, interval_ms)
+    }
+
+    fn alloc_endpoint_out(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<Self::EndpointOut, driver::EndpointAllocError> {
+        self.alloc_endpoint(ep_type, max_packet_size, interval_ms)
+    }
+
+    fn start(mut self, control_max_packet_size: u16) -> (Self::Bus, Self::ControlPipe) {
+        let ep_out = self
+            .alloc_endpoint(EndpointType::Control, control_max_packet_size, 0)
+            .unwrap();
+        let ep_in = self
+            .alloc_endpoint(EndpointType::Control, control_max_packet_size, 0)
+            .unwrap();
+        assert_eq!(ep_out.info.addr.index(), 0);
+        assert_eq!(ep_in.info.addr.index(), 0);
+
+        let regs = T::regs();
+
+        regs.uep_max_len(0).write(|w| w.set_len(control_max_packet_size));
+        regs.uep_config().write(|w| {
+            for (index, ep) in self.alloc.iter().enumerate() {
+                w.set_r_en(index, ep.used_out);
+                w.set_t_en(index, ep.used_in);
+            }
+        });
+        regs.uep_type().write(|w| {
+            for (index, ep) in self.alloc.iter().enumerate() {
+                let iso = ep.ep_type == EndpointType::Isochronous;
+                w.set_r_type(index, iso && ep.used_out);
+                w.set_t_type(index, iso && ep.used_in);
+            }
+        });
+
+        regs.int_fg().write(|w| w.0 = 0xFF);
+        regs.int_en().write(|w| {
+            w.set_bus_rst(true);
+            w.set_transfer(true);
+            w.set_suspend(true);
+            w.set_setup_act(true);
+        });
+        regs.dev_ad().write(|w| w.set_usb_addr(0));
+
+        // NAK automatically while an interrupt flag is pending, and connect the pull-up.
+        regs.ctrl().modify(|w| {
+            w.set_dma_en(true);
+            w.set_int_busy(true);
+            // With VBUS sensing, connect once VBUS is detected.
+            w.set_dev_pu_en(self.vbus.is_none());
+        });
+
+        T::Interrupt::unpend();
+        unsafe { T::Interrupt::enable() };
+
+        (
+            Bus {
+                phantom: PhantomData,
+                inited: false,
+                suspended_at: None,
+                vbus: self.vbus.take(),
+                powered: false,
+            },
+            ControlPipe {
+                _phantom: PhantomData,
+                max_packet_size: control_max_packet_size,
+                ep_out,
+                ep_in,
+            },
+        )
+    }
+}
+
+/// USB bus.
+pub struct Bus<'d, T: Instance> {
+    phantom: PhantomData<&'d mut T>,
+    inited: bool,
+    /// When the bus was suspended, for the remote wakeup timing.
+    suspended_at: Option<Instant>,
+    vbus: Option<ExtiInput<'d>>,
+    /// VBUS level last reported, when it is sensed.
+    powered: bool,
+}
+
+impl<'d, T: Instance> driver::Bus for Bus<'d, T> {
+    async fn poll(&mut self) -> Event {
+        if let Some(vbus) = &self.vbus {
+            if vbus.is_high() != self.powered {
+                self.powered = !self.powered;
+                return if self.powered {
+                    Event::PowerDetected
+                } else {
+                    Event::PowerRemoved
+                };
+            }
+        }
+
+        let Self {
+            phantom: _,
+            vbus,
+            powered,
+            inited,
+            suspended_at,
+        } = self;
+        let sensed = vbus.is_some();
+
+        let vbus_change = async {
+            match vbus {
+                Some(vbus) if *powered => vbus.wait_for_low().await,
+                Some(vbus) => vbus.wait_for_high().await,
+                None => core::future::pending().await,
+            }
+        };
+
+        let bus_event = poll_fn(move |cx| {
+            BUS_WAKER.register(cx.waker());
+
+            // Without VBUS sensing, assume the bus is powered.
+            if !sensed && !*inited {
+                *inited = true;
+                return Poll::Ready(Event::PowerDetected);
+            }
+
+            let regs = T::regs();
+
+            if IRQ_RESUME.load(Ordering::Acquire) {
+                IRQ_RESUME.store(false, Ordering::Relaxed);
+                *suspended_at = None;
+                regs.host_ctrl().modify(|w| w.set_phy_suspendm(true));
+                return Poll::Ready(Event::Resume);
+            }
+
+            if IRQ_RESET.load(Ordering::Acquire) {
+                IRQ_RESET.store(false, Ordering::Relaxed);
+                *suspended_at = None;
+                regs.host_ctrl().modify(|w| w.set_phy_suspendm(true));
+
+                regs.dev_ad().write(|w| w.set_usb_addr(0));
+
+                for i in 0..EP_COUNT {
+                    regs.uep_tx_ctrl(i).write(|w| w.set_t_res(EpTxResponse::NAK));
+                    regs.uep_rx_ctrl(i).write(|w| w.set_r_res(EpRxResponse::NAK));
+                    EP_IN_BUSY[i].store(false, Ordering::Relaxed);
+                    EP_IN_QUEUED[i].store(false, Ordering::Relaxed);
+                    EP_OUT_READY[i].store(false, Ordering::Relaxed);
+                    EP_OUT_FULL[i].store(false, Ordering::Relaxed);
+                }
+                EP0_SETUP.store(false, Ordering::Relaxed);
+
+                for w in &EP_IN_WAKERS {
+                    w.wake()
+                }
+                for w in &EP_OUT_WAKERS {
+                    w.wake()
+                }
+
+                return Poll::Ready(Event::Reset);
+            }
+
+            if IRQ_SUSPEND.load(Ordering::Acquire) {
+                IRQ_SUSPEND.store(false, Ordering::Relaxed);
+                *suspended_at = Some(Instant::now());
+                // Suspend the PHY to meet the suspend current, it still detects resume and reset.
+                regs.host_ctrl().modify(|w| w.set_phy_suspendm(false));
+                return Poll::Ready(Event::Suspend);
+            }
+
+            Poll::Pending
+        });
+
+        match select(vbus_change, bus_event).await {
+            Either::First(()) => {
+                *powered = !*powered;
+                if *powered {
+                    Event::PowerDetected
+                } else {
+                    Event::PowerRemoved
+                }
+            }
+            Either::Second(event) => event,
+        }
+    }
+
+    fn endpoint_set_stalled(&mut self, ep_addr: EndpointAddress, stalled: bool) {
+        let regs = T::regs();
+        let index = ep_addr.index();
+        match ep_addr.direction() {
+            Direction::In => {
+                regs.uep_tx_ctrl(index).modify(|w| {
+                    w.set_t_res(if stalled {
+                        EpTxResponse::STALL
+                    } else {
+                        EpTxResponse::NAK
+                    });
+                    // Clearing the halt feature resets the toggle.
+                    w.set_t_tog(EpTog::DATA0);
+                });
+                EP_IN_BUSY[index].store(false, Ordering::Relaxed);
+                EP_IN_QUEUED[index].store(false, Ordering::Relaxed);
+                EP_IN_WAKERS[index].wake();
+            }
+            Direction::Out => {
+                // A bulk endpoint still has a free buffer with one packet pending.
+                let ready = if EP_OUT_SLOTS[index].is_set() {
+                    EP_OUT_FULL[index].load(Ordering::Relaxed)
+                } else {
+                    EP_OUT_READY[index].load(Ordering::Relaxed)
+                };
+                regs.uep_rx_ctrl(index).modify(|w| {
+                    w.set_r_res(match (stalled, ready) {
+                        (true, _) => EpRxResponse::STALL,
+                        // Keep NAKing until the pending packet is read.
+                        (false, true) => EpRxResponse::NAK,
+                        (false, false) => EpRxResponse::ACK,
+                    });
+                    w.set_r_tog(EpTog::DATA0);
+                });
+                EP_OUT_WAKERS[index].wake();
+            }
+        }
+    }
+
+    fn endpoint_is_stalled(&mut self, ep_addr: EndpointAddress) -> bool {
+        let regs = T::regs();
+        let index = ep_addr.index();
+        match ep_addr.direction() {
+            Direction::In => regs.uep_tx_ctrl(index).read().t_res() == EpTxResponse::STALL,
+            Direction::Out => regs.uep_rx_ctrl(index).read().r_res() == EpRxResponse::STALL,
+        }
+    }
+
+    fn endpoint_set_enabled(&mut self, ep_addr: EndpointAddress, enabled: bool) {
+        let regs = T::regs();
+        let index = ep_addr.index();
+        match ep_addr.direction() {
+            Direction::In if is_iso(index) => {
+                // Send zero-length packets as DATA0 until a frame is queued.
+                let slots = &EP_IN_SLOTS[index];
+                regs.uep_tx_dma(index - 1).write(|w| w.0 = slots.buffer(false) as u32);
+                slots.set_dma_slot(false);
+                regs.uep_t_len(index).write(|w| w.set_len(0));
+                regs.uep_tx_ctrl(index).write(|w| {
+                    w.set_t_tog(EpTog::DATA0);
+                    w.set_t_res(if enabled { EpTxResponse::ACK } else { EpTxResponse::NAK });
+                });
+                EP_IN_BUSY[index].store(false, Ordering::Relaxed);
+                EP_IN_ENABLED[index].store(enabled, Ordering::Release);
+                EP_IN_WAKERS[index].wake();
+            }
+            Direction::Out if is_iso(index) => {
+                let slots = &EP_OUT_SLOTS[index];
+                regs.uep_rx_dma(index - 1).write(|w| w.0 = slots.buffer(false) as u32);
+                slots.set_dma_slot(false);
+                regs.uep_rx_ctrl(index).write(|w| {
+                    w.set_r_tog(EpTog::DATA0);
+                    w.set_r_res(if enabled { EpRxResponse::ACK } else { EpRxResponse::NAK });
+                });
+                EP_OUT_READY[index].store(false, Ordering::Relaxed);
+                EP_OUT_ENABLED[index].store(enabled, Ordering::Release);
+                EP_OUT_WAKERS[index].wake();
+            }
+            Direction::In => {
+                let slots = &EP_IN_SLOTS[index];
+                if slots.is_set() {
+                    regs.uep_tx_dma(index - 1).write(|w| w.0 = slots.buffer(false) as u32);
+                    slots.set_dma_slot(false);
+                }
+                regs.uep_tx_ctrl(index).write(|w| {
+                    w.set_t_auto_tog(index != 0);
+                    w.set_t_res(EpTxResponse::NAK);
+                });
+                EP_IN_BUSY[index].store(false, Ordering::Relaxed);
+                EP_IN_QUEUED[index].store(false, Ordering::Relaxed);
+                EP_IN_ENABLED[index].store(enabled, Ordering::Release);
+                EP_IN_WAKERS[index].wake();
+            }
+            Direction::Out => {
+                let slots = &EP_OUT_SLOTS[index];
+                if slots.is_set() {
+                    regs.uep_rx_dma(index - 1).write(|w| w.0 = slots.buffer(false) as u32);
+                    slots.set_dma_slot(false);
+                }
+                regs.uep_rx_ctrl(index).write(|w| {
+                    w.set_r_auto_tog(index != 0);
+                    w.set_r_res(if enabled { EpRxResponse::ACK } else { EpRxResponse::NAK });
+                });
+                EP_OUT_READY[index].store(false, Ordering::Relaxed);
+                EP_OUT_FULL[index].store(false, Ordering::Relaxed);
+                EP_OUT_ENABLED[index].store(enabled, Ordering::Release);
+                EP_OUT_WAKERS[index].wake();
+            }
+        }
+    }
+
+    async fn enable(&mut self) {
+        T::regs().ctrl().modify(|w| w.set_dev_pu_en(true));
+    }
+
+    async fn disable(&mut self) {
+        T::regs().ctrl().modify(|w| w.set_dev_pu_en(false));
+    }
+
+    async fn remote_wakeup(&mut self) -> Result<(), Unsupported> {
+        // The host enables remote wakeup with SET_FEATURE, which embassy-usb tracks before
+        // calling this.
+        if let Some(suspended_at) = self.suspended_at.take() {
+            Timer::at(suspended_at + REMOTE_WAKEUP_IDLE).await;
+        }
+
+        let regs = T::regs();
+        regs.host_ctrl().modify(|w| w.set_phy_suspendm(true));
+        regs.host_ctrl().modify(|w| w.set_remote_wkup(true));
+        Timer::after(RESUME_SIGNALING).await;
+        regs.host_ctrl().modify(|w| w.set_remote_wkup(false));
+
+        Ok(())
+    }
+}
+
+trait Dir {
+    fn dir() -> Direction;
+}
+
+/// Marker type for the "IN" direction.
+pub enum In {}
+impl Dir for In {
+    fn dir() -> Direction {
+        Direction::In
+    }
+}
+
+/// Marker type for the "OUT" direction.
+pub enum Out {}
+impl Dir for Out {
+    fn dir() -> Direction {
+        Direction::Out
+    }
+}
+
+/// USB endpoint.
+pub struct Endpoint<'d, T: Instance, D> {
+    _phantom: PhantomData<(&'d mut T, D)>,
+    info: EndpointInfo,
+    buf: *mut u8,
+}
+
+// The buffer is owned by the endpoint for 'd.
+unsafe impl<'d, T: Instance, D> Send for Endpoint<'d, T, D> {}
+
+impl<'d, T: Instance, D> Endpoint<'d, T, D> {
+    /// Copy `buf` to the TX buffer and queue it.
+    fn start_write(&mut self, buf: &[u8]) {
+        let index = self.info.addr.index();
+        unsafe { core::ptr::copy_nonoverlapping(buf.as_ptr(), self.buf, buf.len()) };
+        compiler_fence(Ordering::SeqCst);
+
+        let regs = T::regs();
+        regs.uep_t_len(index).write(|w| w.set_len(buf.len() as _));
+        EP_IN_BUSY[index].store(true, Ordering::Relaxed);
+        regs.uep_tx_ctrl(index).modify(|w| w.set_t_res(EpTxResponse::ACK));
+    }
+
+    /// Copy the received packet out of the RX buffer, the packet stays pending.
+    fn read_data(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        let index = self.info.addr.index();
+        let rx_len = EP_OUT_LEN[index].load(Ordering::Relaxed) as usize;
+        if rx_len > buf.len() {
+            return Err(EndpointError::BufferOverflow);
+        }
+
+        compiler_fence(Ordering::SeqCst);
+        unsafe { core::ptr::copy_nonoverlapping(self.buf, buf.as_mut_ptr(), rx_len) };
+        Ok(rx_len)
+    }
+
+    /// Copy the pending frame of an isochronous endpoint out of its buffer, and release it.
+    fn read_iso(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        let index = self.info.addr.index();
+        // The interrupt doesn't switch buffers while the frame is copied.
+        critical_section::with(|_| {
+            let rx_len = EP_OUT_LEN[index].load(Ordering::Relaxed) as usize;
+            EP_OUT_READY[index].store(false, Ordering::Relaxed);
+            if rx_len > buf.len() {
+                return Err(EndpointError::BufferOverflow);
+            }
+
+            let slots = &EP_OUT_SLOTS[index];
+            compiler_fence(Ordering::SeqCst);
+            unsafe { core::ptr::copy_nonoverlapping(slots.buffer(!slots.dma_slot()), buf.as_mut_ptr(), rx_len) };
+            Ok(rx_len)
+        })
+    }
+
+    /// Copy `buf` to the buffer the DMA doesn't point to, it is sent in the frame after the current
+    /// one.
+    fn queue_iso(&mut self, buf: &[u8]) {
+        let index = self.info.addr.index();
+        // Buffers are only switched while a frame is queued.
+        let slots = &EP_IN_SLOTS[index];
+        unsafe { core::ptr::copy_nonoverlapping(buf.as_ptr(), slots.buffer(!slots.dma_slot()), buf.len()) };
+        compiler_fence(Ordering::SeqCst);
+
+        EP_IN_LEN[index].store(buf.len() as u16, Ordering::Relaxed);
+        EP_IN_BUSY[index].store(true, Ordering::Release);
+    }
+
+    /// Copy the oldest received packet of a bulk endpoint out of its buffer, and release it. The
+    /// packet stays pending if it doesn't fit in `buf`.
+    fn read_double(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        let index = self.info.addr.index();
+        // The interrupt doesn't switch buffers while the packet is copied.
+        critical_section::with(|_| {
+            // The DMA always points to the buffer after the oldest packet.
+            let slots = &EP_OUT_SLOTS[index];
+            let slot = !slots.dma_slot();
+            let rx_len = EP_OUT_SLOT_LEN[index][slot as usize].load(Ordering::Relaxed) as usize;
+            if rx_len > buf.len() {
+                return Err(EndpointError::BufferOverflow);
+            }
+
+            compiler_fence(Ordering::SeqCst);
+            unsafe { core::ptr::copy_nonoverlapping(slots.buffer(slot), buf.as_mut_ptr(), rx_len) };
+
+            if EP_OUT_FULL[index].load(Ordering::Relaxed) {
+                // The other packet is still pending, receive the next one in the buffer just read.
+                EP_OUT_FULL[index].store(false, Ordering::Relaxed);
+                let regs = T::regs();
+                regs.uep_rx_dma(index - 1).write(|w| w.0 = slots.buffer(slot) as u32);
+                slots.set_dma_slot(slot);
+                regs.uep_rx_ctrl(index).modify(|w| w.set_r_res(EpRxResponse::ACK));
+            } else {
+                EP_OUT_READY[index].store(false, Ordering::Relaxed);
+            }
+            Ok(rx_len)
+        })
+    }
+
+    /// Send `buf` from the current buffer of a bulk endpoint, or queue it in the other one while a
+    /// packet is being sent.
+    fn write_double(&mut self, buf: &[u8]) {
+        let index = self.info.addr.index();
+        critical_section::with(|_| {
+            let slots = &EP_IN_SLOTS[index];
+            let busy = EP_IN_BUSY[index].load(Ordering::Relaxed);
+            // While the current buffer is being sent, queue in the other one.
+            let slot = slots.dma_slot() != busy;
+            unsafe { core::ptr::copy_nonoverlapping(buf.as_ptr(), slots.buffer(slot), buf.len()) };
+            compiler_fence(Ordering::SeqCst);
+
+            if busy {
+                EP_IN_LEN[index].store(buf.len() as u16, Ordering::Relaxed);
+                EP_IN_QUEUED[index].store(true, Ordering::Relaxed);
+            } else {
+                let regs = T::regs();
+                regs.uep_t_len(index).write(|w| w.set_len(buf.len() as _));
+                EP_IN_BUSY[index].store(true, Ordering::Relaxed);
+                regs.uep_tx_ctrl(index).modify(|w| w.set_t_res(EpTxResponse::ACK));
+            }
+        })
+    }
+
+    /// Release the RX buffer and accept the next packet.
+    fn release_out(&mut self) {
+        let index = self.info.addr.index();
+        EP_OUT_READY[index].store(false, Ordering::Relaxed);
+        T::regs().uep_rx_ctrl(index).modify(|w| w.set_r_res(EpRxResponse::ACK));
+    }
+}
+
+impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, In> {
+    fn info(&self) -> &EndpointInfo {
+        &self.info
+    }
+
+    async fn wait_enabled(&mut self) {
+        let index = self.info.addr.index();
+        poll_fn(|cx| {
+            EP_IN_WAKERS[index].register(cx.waker());
+            if EP_IN_ENABLED[index].load(Ordering::Acquire) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+    }
+}
+
+impl<'d, T: Instance> driver::Endpoint for Endpoint<'d, T, Out> {
+    fn info(&self) -> &EndpointInfo {
+        &self.info
+    }
+
+    async fn wait_enabled(&mut self) {
+        let index = self.info.addr.index();
+        poll_fn(|cx| {
+            EP_OUT_WAKERS[index].register(cx.waker());
+            if EP_OUT_ENABLED[index].load(Ordering::Acquire) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+    }
+}
+
+impl<'d, T: Instance> driver::EndpointOut for Endpoint<'d, T, Out> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        let index = self.info.addr.index();
+        poll_fn(|cx| {
+            EP_OUT_WAKERS[index].register(cx.waker());
+            if !EP_OUT_ENABLED[index].load(Ordering::Acquire) {
+                Poll::Ready(Err(EndpointError::Disabled))
+            } else if EP_OUT_READY[index].load(Ordering::Acquire) {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Pending
+            }
+        })
+        .await?;
+
+        if is_iso(index) {
+            return self.read_iso(buf);
+        }
+        if EP_OUT_SLOTS[index].is_set() {
+            return self.read_double(buf);
+        }
+
+        let rx_len = self.read_data(buf)?;
+        self.release_out();
+
+        Ok(rx_len)
+    }
+}
+
+impl<'d, T: Instance> driver::EndpointIn for Endpoint<'d, T, In> {
+    async fn write(&mut self, buf: &[u8]) -> Result<(), EndpointError> {
+        if buf.len() > self.info.max_packet_size as usize {
+            return Err(EndpointError::BufferOverflow);
+        }
+
+        let index = self.info.addr.index();
+        let double = !is_iso(index) && EP_IN_SLOTS[index].is_set();
+        poll_fn(|cx| {
+            EP_IN_WAKERS[index].register(cx.waker());
+            // A bulk endpoint takes a second packet while the first one is sent.
+            let full = if double { &EP_IN_QUEUED } else { &EP_IN_BUSY };
+            if !EP_IN_ENABLED[index].load(Ordering::Acquire) {
+                Poll::Ready(Err(EndpointError::Disabled))
+            } else if full[index].load(Ordering::Acquire) {
+                Poll::Pending
+            } else {
+                Poll::Ready(Ok(()))
+            }
+        })
+        .await?;
+
+        if is_iso(index) {
+            self.queue_iso(buf);
+        } else if double {
+            self.write_double(buf);
+        } else {
+            self.start_write(buf);
+        }
+
+        Ok(())
+    }
+}
+
+/// USB control pipe.
+pub struct ControlPipe<'d, T: Instance> {
+    _phantom: PhantomData<&'d mut T>,
+    max_packet_size: u16,
+    ep_in: Endpoint<'d, T, In>,
+    ep_out: Endpoint<'d, T, Out>,
+}
+
+impl<'d, T: Instance> ControlPipe<'d, T> {
+    /// Wait until the queued IN packet is sent, returns `false` if a new SETUP arrived meanwhile.
+    async fn wait_in_done(&mut self) -> bool {
+        poll_fn(|cx| {
+            EP_IN_WAKERS[0].register(cx.waker());
+            if EP0_SETUP.load(Ordering::Acquire) {
+                Poll::Ready(false)
+            } else if EP_IN_BUSY[0].load(Ordering::Acquire) {
+                Poll::Pending
+            } else {
+                Poll::Ready(true)
+            }
+        })
+        .await
+    }
+}
+
+impl<'d, T: Instance> driver::ControlPipe for ControlPipe<'d, T> {
+    fn max_packet_size(&self) -> usize {
+        usize::from(self.max_packet_size)
+    }
+
+    async fn setup(&mut self) -> [u8; 8] {
+        poll_fn(|cx| {
+            EP_OUT_WAKERS[0].register(cx.waker());
+            if EP0_SETUP.load(Ordering::Acquire) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+
+        let mut buf = [0; 8];
+        compiler_fence(Ordering::SeqCst);
+        unsafe { core::ptr::copy_nonoverlapping(self.ep_out.buf, buf.as_mut_ptr(), 8) };
+        EP0_SETUP.store(false, Ordering::Release);
+
+        buf
+    }
+
+    async fn data_out(&mut self, buf: &mut [u8], _first: bool, _last: bool) -> Result<usize, EndpointError> {
+        T::regs().uep_rx_ctrl(0).modify(|w| w.set_r_res(EpRxResponse::ACK));
+
+        poll_fn(|cx| {
+            EP_OUT_WAKERS[0].register(cx.waker());
+            if EP0_SETUP.load(Ordering::Acquire) {
+                Poll::Ready(Err(EndpointError::Disabled))
+            } else if EP_OUT_READY[0].load(Ordering::Acquire) {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Pending
+            }
+        })
+        .await?;
+
+        let rx_len = self.ep_out.read_data(buf)?;
+        // The OUT is NAKed by the interrupt until the next stage arms it.
+        EP_OUT_READY[0].store(false, Ordering::Relaxed);
+
+        Ok(rx_len)
+    }
+
+    async fn data_in(&mut self, data: &[u8], _first: bool, last: bool) -> Result<(), EndpointError> {
+        if data.len() > self.ep_in.info.max_packet_size as usize {
+            return Err(EndpointError::BufferOverflow);
+        }
+        if EP0_SETUP.load(Ordering::Acquire) {
+            return Err(EndpointError::Disabled);
+        }
+
+        self.ep_in.start_write(data);
+        if !self.wait_in_done().await {
+            return Err(EndpointError::Disabled);
+        }
+
+        if last {
+            // Acknowledge the zero length OUT of the status stage, sent as DATA1.
+            T::regs().uep_rx_ctrl(0).write(|w| {
+                w.set_r_tog(EpTog::DATA1);
+                w.set_r_res(EpRxResponse::ACK);
+            });
+        }
+
+        Ok(())
+    }
+
+    async fn accept(&mut self) {
+        // Zero length IN of the status stage, sent as DATA1.
+        T::regs().uep_tx_ctrl(0).modify(|w| w.set_t_tog(EpTog::DATA1));
+        self.ep_in.start_write(&[]);
+
+        // Wait is needed, so that we don't set the address too soon, breaking the status stage.
+        // (embassy-usb sets the address after accept() returns)
+        self.wait_in_done().await;
+    }
+
+    async fn reject(&mut self) {
+        let regs = T::regs();
+        regs.uep_tx_ctrl(0).write(|w| w.set_t_res(EpTxResponse::STALL));
+        regs.uep_rx_ctrl(0).write(|w| w.set_r_res(EpRxResponse::STALL));
+    }
+
+    async fn accept_set_address(&mut self, addr: u8) {
+        self.accept().await;
+
+        T::regs().dev_ad().modify(|w| w.set_usb_addr(addr));
+    }
+}
+
+trait SealedInstance {
+    fn regs() -> crate::pac::usbhs::Usbhs;
+}
+
+/// USBHS instance trait.
+#[allow(private_bounds)]
+pub trait Instance: SealedInstance + RccPeripheral + 'static {
+    /// Interrupt for this USBHS instance.
+    type Interrupt: interrupt::typelevel::Interrupt;
+}
+
+// Internal PHY pins
+pin_trait!(DpPin, Instance);
+pin_trait!(DmPin, Instance);
+
+foreach_peripheral!(
+    (usbhs, $inst:ident) => {
+        impl SealedInstance for crate::peripherals::$inst {
+            fn regs() -> crate::pac::usbhs::Usbhs {
+                crate::pac::$inst
+            }
+        }
+
+        impl Instance for crate::peripherals::$inst {
+            type Interrupt = crate::_generated::peripheral_interrupts::$inst::GLOBAL;
+        }
+    };
+);
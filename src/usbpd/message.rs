@@ -0,0 +1,294 @@
+//! USB PD messages
+//!
+//! Header, power data objects and request data objects, as defined by the USB PD 3.0 specification.
+
+/// Control message types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum ControlMessageType {
+    GoodCrc = 1,
+    GotoMin = 2,
+    Accept = 3,
+    Reject = 4,
+    Ping = 5,
+    PsRdy = 6,
+    GetSourceCap = 7,
+    GetSinkCap = 8,
+    DrSwap = 9,
+    PrSwap = 10,
+    VconnSwap = 11,
+    Wait = 12,
+    SoftReset = 13,
+    NotSupported = 16,
+}
+
+/// Data message types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum DataMessageType {
+    SourceCapabilities = 1,
+    Request = 2,
+    Bist = 3,
+    SinkCapabilities = 4,
+    VendorDefined = 15,
+}
+
+/// Specification revision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SpecRevision {
+    Rev2_0,
+    Rev3_0,
+}
+
+/// Message header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Header(pub u16);
+
+impl Header {
+    /// Header of a message sent by a sink in the UFP data role.
+    pub const fn new_sink(message_type: u8, message_id: u8, num_objects: u8, revision: SpecRevision) -> Self {
+        let revision = match revision {
+            SpecRevision::Rev2_0 => 0b01,
+            SpecRevision::Rev3_0 => 0b10,
+        };
+        Self(
+            (message_type as u16 & 0x1F)
+                | (revision << 6)
+                | ((message_id as u16 & 0x7) << 9)
+                | ((num_objects as u16 & 0x7) << 12),
+        )
+    }
+
+    /// Message type, control or data depending on [`Self::num_objects`]
+    pub const fn message_type(&self) -> u8 {
+        (self.0 & 0x1F) as u8
+    }
+
+    /// Control message type, `None` for data messages and unknown types
+    pub fn control_message_type(&self) -> Option<ControlMessageType> {
+        use ControlMessageType::*;
+
+        if self.num_objects() != 0 || self.extended() {
+            return None;
+        }
+        Some(match self.message_type() {
+            1 => GoodCrc,
+            2 => GotoMin,
+            3 => Accept,
+            4 => Reject,
+            5 => Ping,
+            6 => PsRdy,
+            7 => GetSourceCap,
+            8 => GetSinkCap,
+            9 => DrSwap,
+            10 => PrSwap,
+            11 => VconnSwap,
+            12 => Wait,
+            13 => SoftReset,
+            16 => NotSupported,
+            _ => return None,
+        })
+    }
+
+    /// Data message type, `None` for control messages and unknown types
+    pub fn data_message_type(&self) -> Option<DataMessageType> {
+        use DataMessageType::*;
+
+        if self.num_objects() == 0 || self.extended() {
+            return None;
+        }
+        Some(match self.message_type() {
+            1 => SourceCapabilities,
+            2 => Request,
+            3 => Bist,
+            4 => SinkCapabilities,
+            15 => VendorDefined,
+            _ => return None,
+        })
+    }
+
+    /// Specification revision of the sender
+    pub const fn spec_revision(&self) -> SpecRevision {
+        match (self.0 >> 6) & 0x3 {
+            0b00 | 0b01 => SpecRevision::Rev2_0,
+            _ => SpecRevision::Rev3_0,
+        }
+    }
+
+    /// Message ID
+    pub const fn message_id(&self) -> u8 {
+        ((self.0 >> 9) & 0x7) as u8
+    }
+
+    /// Number of 32-bit data objects
+    pub const fn num_objects(&self) -> u8 {
+        ((self.0 >> 12) & 0x7) as u8
+    }
+
+    /// Whether this is an extended message
+    pub const fn extended(&self) -> bool {
+        self.0 & (1 << 15) != 0
+    }
+}
+
+/// Power data object, a power supply offered by a source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PowerDataObject {
+    /// Fixed voltage supply
+    Fixed {
+        /// Voltage, in mV
+        voltage_mv: u32,
+        /// Max current, in mA
+        max_current_ma: u32,
+    },
+    /// Battery supply
+    Battery {
+        /// Min voltage, in mV
+        min_voltage_mv: u32,
+        /// Max voltage, in mV
+        max_voltage_mv: u32,
+        /// Max power, in mW
+        max_power_mw: u32,
+    },
+    /// Variable supply
+    Variable {
+        /// Min voltage, in mV
+        min_voltage_mv: u32,
+        /// Max voltage, in mV
+        max_voltage_mv: u32,
+        /// Max current, in mA
+        max_current_ma: u32,
+    },
+    /// Programmable power supply
+    Pps {
+        /// Min voltage, in mV
+        min_voltage_mv: u32,
+        /// Max voltage, in mV
+        max_voltage_mv: u32,
+        /// Max current, in mA
+        max_current_ma: u32,
+    },
+    /// Unknown augmented power data object
+    Unknown(u32),
+}
+
+impl PowerDataObject {
+    /// Decode a power data object.
+    pub const fn from_raw(raw: u32) -> Self {
+        match raw >> 30 {
+            0b00 => Self::Fixed {
+                voltage_mv: ((raw >> 10) & 0x3FF) * 50,
+                max_current_ma: (raw & 0x3FF) * 10,
+            },
+            0b01 => Self::Battery {
+                min_voltage_mv: ((raw >> 10) & 0x3FF) * 50,
+                max_voltage_mv: ((raw >> 20) & 0x3FF) * 50,
+                max_power_mw: (raw & 0x3FF) * 250,
+            },
+            0b10 => Self::Variable {
+                min_voltage_mv: ((raw >> 10) & 0x3FF) * 50,
+                max_voltage_mv: ((raw >> 20) & 0x3FF) * 50,
+                max_current_ma: (raw & 0x3FF) * 10,
+            },
+            _ if (raw >> 28) & 0x3 == 0b00 => Self::Pps {
+                min_voltage_mv: ((raw >> 8) & 0xFF) * 100,
+                max_voltage_mv: ((raw >> 17) & 0xFF) * 100,
+                max_current_ma: (raw & 0x7F) * 50,
+            },
+            _ => Self::Unknown(raw),
+        }
+    }
+}
+
+/// Power data objects of a Source_Capabilities message
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SourceCapabilities {
+    pdos: [u32; 7],
+    len: u8,
+}
+
+impl SourceCapabilities {
+    /// Decode the data objects of a Source_Capabilities message.
+    pub fn from_objects(objects: &[u32]) -> Self {
+        let mut this = Self::default();
+        for (dst, src) in this.pdos.iter_mut().zip(objects) {
+            *dst = *src;
+            this.len += 1;
+        }
+        this
+    }
+
+    /// Number of power data objects
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    /// Whether there is no power data object
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Power data object at `position`, starting at 1 as in requests.
+    pub fn get(&self, position: u8) -> Option<PowerDataObject> {
+        let index = (position as usize).checked_sub(1)?;
+        (index < self.len()).then(|| PowerDataObject::from_raw(self.pdos[index]))
+    }
+
+    /// Iterate over the power data objects, with their position.
+    pub fn iter(&self) -> impl Iterator<Item = (u8, PowerDataObject)> + '_ {
+        self.pdos[..self.len()]
+            .iter()
+            .enumerate()
+            .map(|(i, raw)| (i as u8 + 1, PowerDataObject::from_raw(*raw)))
+    }
+
+    /// Position and max current of the fixed supply of `voltage_mv`.
+    pub fn find_fixed(&self, voltage_mv: u32) -> Option<(u8, u32)> {
+        self.iter().find_map(|(position, pdo)| match pdo {
+            PowerDataObject::Fixed {
+                voltage_mv: v,
+                max_current_ma,
+            } if v == voltage_mv => Some((position, max_current_ma)),
+            _ => None,
+        })
+    }
+
+    /// Whether the source is dual-role power, the first object is the vSafe5V fixed supply.
+    pub fn dual_role_power(&self) -> bool {
+        self.len > 0 && self.pdos[0] & (1 << 29) != 0
+    }
+}
+
+/// Request data object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Request(pub u32);
+
+impl Request {
+    // USB suspend is not supported, the sink keeps drawing the negotiated current.
+    const NO_USB_SUSPEND: u32 = 1 << 24;
+
+    /// Request `current_ma` from the fixed or variable supply at `position`.
+    pub const fn fixed(position: u8, current_ma: u32) -> Self {
+        let current = (current_ma / 10) & 0x3FF;
+        Self(((position as u32 & 0x7) << 28) | Self::NO_USB_SUSPEND | (current << 10) | current)
+    }
+
+    /// Request `voltage_mv` at `current_ma` from the programmable supply at `position`.
+    pub const fn pps(position: u8, voltage_mv: u32, current_ma: u32) -> Self {
+        let voltage = (voltage_mv / 20) & 0x7FF;
+        let current = (current_ma / 50) & 0x7F;
+        Self(((position as u32 & 0x7) << 28) | Self::NO_USB_SUSPEND | (voltage << 9) | current)
+    }
+
+    /// Position of the requested power data object, starting at 1
+    pub const fn position(&self) -> u8 {
+        ((self.0 >> 28) & 0x7) as u8
+    }
+}
@@ -5,7 +5,7 @@
 //! - CC Pins:
 //! - UsbPdPhy: USBPD PHY layer
 //! - UsbPdSniffer: USBPD Sniffer based on PHY layer, no transmit support
-//! - [ ] UsbPdSink: USBPD Sink layer
+//! - UsbPdSink: USBPD Sink policy engine, negotiates a power contract with a source
 //! - [ ] UsbPdSource: USBPD Source layer
 
 use core::future::poll_fn;
@@ -18,8 +18,17 @@ use embedded_hal::delay::DelayNs;
 use pac::InterruptNumber;
 
 use crate::gpio::Pull;
+use crate::interrupt::typelevel::Interrupt as _;
 use crate::pac::usbpd::vals;
-use crate::{interrupt, into_ref, pac, println, Peripheral, RccPeripheral};
+use crate::{interrupt, into_ref, pac, Peripheral, RccPeripheral};
+
+pub mod message;
+mod sink;
+
+pub use sink::UsbPdSink;
+
+/// Start of packet of messages to the port partner.
+const TX_SEL_SOP0: u8 = 0b00_00_00_00;
 
 #[derive(Debug)]
 pub enum Error {
@@ -44,13 +53,7 @@ impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandl
 
         let status = usbpd.status().read();
 
-        println!("irq 0x{:02x}", status.0);
-
         if status.if_tx_end() {
-            println!(">");
-            //         T::REGS.port_cc1().modify(|w| w.set_cc_lve(false));
-            // T::REGS.port_cc2().modify(|w| w.set_cc_lve(false));
-
             T::REGS.config().modify(|w| w.set_ie_tx_end(false));
         }
 
@@ -62,11 +65,6 @@ impl<T: Instance> interrupt::typelevel::Handler<T::Interrupt> for InterruptHandl
 
         if status.if_rx_reset() {
             T::REGS.config().modify(|w| w.set_ie_rx_reset(false));
-            crate::println!("TODO: reset");
-        }
-
-        if status.buf_err() {
-            crate::println!("TODO: buf_err");
         }
 
         T::REGS.status().write_value(status);
@@ -83,9 +81,10 @@ pub struct UsbPdPhy<'d, T: Instance> {
 }
 
 impl<'d, T: Instance> UsbPdPhy<'d, T> {
-    /// Create a new SPI driver.
+    /// Create a new USBPD PHY driver.
     pub fn new(
         _peri: impl Peripheral<P = T> + 'd,
+        _irq: impl interrupt::typelevel::Binding<T::Interrupt, InterruptHandler<T>> + 'd,
         cc1: impl Peripheral<P = impl CcPin<T>> + 'd,
         cc2: impl Peripheral<P = impl CcPin<T>> + 'd,
     ) -> Result<Self, Error> {
@@ -125,6 +124,9 @@ impl<'d, T: Instance> UsbPdPhy<'d, T> {
         T::port_cc_reg(cc1.port_sel()).write(|w| w.set_cc_ce(vals::PortCcCe::V0_66));
         T::port_cc_reg(cc2.port_sel()).write(|w| w.set_cc_ce(vals::PortCcCe::V0_66));
 
+        T::Interrupt::unpend();
+        unsafe { T::Interrupt::enable() };
+
         let mut this = Self {
             _marker: PhantomData,
             cc1: cc1.port_sel(),
@@ -166,8 +168,6 @@ impl<'d, T: Instance> UsbPdPhy<'d, T> {
         if T::port_cc_reg(self.cc1).read().pa_cc_ai() {
             // CC1 is connected
             T::REGS.config().modify(|w| w.set_cc_sel(vals::CcSel::CC1));
-
-            crate::println!("CC1 connected");
             Ok(())
         } else {
             T::port_cc_reg(self.cc2).modify(|w| w.set_cc_ce(vals::PortCcCe::V0_22));
@@ -176,11 +176,8 @@ impl<'d, T: Instance> UsbPdPhy<'d, T> {
             if T::port_cc_reg(self.cc2).read().pa_cc_ai() {
                 // CC2 is connected
                 T::REGS.config().modify(|w| w.set_cc_sel(vals::CcSel::CC2));
-                crate::println!("CC2 connected");
                 Ok(())
             } else {
-                crate::println!("CC not connected");
-
                 Err(Error::CCNotConnected)
             }
         }
@@ -271,8 +268,6 @@ impl<'d, T: Instance> UsbPdPhy<'d, T> {
         unsafe {
             qingke::pfic::disable_interrupt(interrupt::USBPD.number() as _);
         }
-        println!("begin blocking recv");
-
         T::REGS.control().modify(|w| w.set_bmc_start(true));
 
         while !T::REGS.status().read().if_rx_act() {
@@ -286,7 +281,33 @@ impl<'d, T: Instance> UsbPdPhy<'d, T> {
         Ok(10)
     }
 
-    fn transmit(&mut self, sop: u8, buf: &[u8]) -> Result<(), Error> {
+    /// Transmits a PD message to the port partner, and waits until it is sent.
+    ///
+    /// The CRC is appended by the hardware.
+    pub async fn transmit(&mut self, buf: &[u8]) -> Result<(), Error> {
+        self.enable_tx_interrupt();
+        self.start_transmit(TX_SEL_SOP0, buf);
+
+        let res = poll_fn(|cx| {
+            T::state().waker.register(cx.waker());
+
+            let config = T::REGS.config().read();
+            if !config.ie_rx_reset() {
+                Poll::Ready(Err(Error::HardReset))
+            } else if !config.ie_tx_end() {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+
+        T::port_cc_reg(T::REGS.config().read().cc_sel()).modify(|w| w.set_cc_lve(false));
+
+        res
+    }
+
+    fn start_transmit(&mut self, sop: u8, buf: &[u8]) {
         T::port_cc_reg(T::REGS.config().read().cc_sel()).modify(|w| w.set_cc_lve(true));
 
         T::REGS
@@ -307,8 +328,6 @@ impl<'d, T: Instance> UsbPdPhy<'d, T> {
         T::REGS.status().write(|w| w.0 = 0b11111100);
 
         T::REGS.control().modify(|w| w.set_bmc_start(true));
-
-        Ok(())
     }
 
     /// Transmit a hard reset.
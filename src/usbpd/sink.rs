@@ -0,0 +1,272 @@
+//! Sink policy engine
+//!
+//! The PHY has no protocol layer: the sink answers every received message with a GoodCRC, and
+//! retransmits its own messages until the source answers with one.
+
+use embassy_time::{with_timeout, Duration};
+
+use super::message::{ControlMessageType, DataMessageType, Header, Request, SourceCapabilities, SpecRevision};
+use super::{Error, Instance, UsbPdPhy};
+
+/// tTypeCSinkWaitCap, time for the source to send its capabilities after attach
+const SINK_WAIT_CAP_TIMEOUT: Duration = Duration::from_millis(620);
+/// tReceive, time for the port partner to answer with a GoodCRC
+const RECEIVE_TIMEOUT: Duration = Duration::from_millis(2);
+/// tSenderResponse, time for the source to answer a request
+const SENDER_RESPONSE_TIMEOUT: Duration = Duration::from_millis(30);
+/// tPSTransition, time for the source to reach the requested voltage
+const PS_TRANSITION_TIMEOUT: Duration = Duration::from_millis(550);
+/// nRetryCount
+const RETRY_COUNT: usize = 2;
+
+/// 2 header bytes, up to 7 data objects and the CRC.
+const MAX_MESSAGE_SIZE: usize = 34;
+const CRC_SIZE: usize = 4;
+
+#[repr(C, align(4))]
+struct Buffer([u8; MAX_MESSAGE_SIZE]);
+
+/// A received message.
+struct Message {
+    header: Header,
+    objects: [u32; 7],
+}
+
+impl Message {
+    fn parse(buf: &[u8]) -> Option<Self> {
+        let header = Header(u16::from_le_bytes([*buf.first()?, *buf.get(1)?]));
+        let mut objects = [0; 7];
+        let data = buf.get(2..2 + header.num_objects() as usize * 4)?;
+        for (object, bytes) in objects.iter_mut().zip(data.chunks_exact(4)) {
+            *object = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        Some(Self { header, objects })
+    }
+
+    fn objects(&self) -> &[u32] {
+        &self.objects[..self.header.num_objects() as usize]
+    }
+}
+
+/// USB PD sink
+///
+/// Waits for the capabilities of the source, and requests one of its power supplies.
+pub struct UsbPdSink<'d, T: Instance> {
+    phy: UsbPdPhy<'d, T>,
+    tx_message_id: u8,
+    /// Message ID of the last received message, to drop retransmissions.
+    rx_message_id: Option<u8>,
+    revision: SpecRevision,
+    capabilities: SourceCapabilities,
+}
+
+impl<'d, T: Instance> UsbPdSink<'d, T> {
+    /// Create a new sink on top of a PHY.
+    pub fn new(phy: UsbPdPhy<'d, T>) -> Self {
+        Self {
+            phy,
+            tx_message_id: 0,
+            rx_message_id: None,
+            revision: SpecRevision::Rev3_0,
+            capabilities: SourceCapabilities::default(),
+        }
+    }
+
+    /// The PHY.
+    pub fn phy(&mut self) -> &mut UsbPdPhy<'d, T> {
+        &mut self.phy
+    }
+
+    /// Last capabilities sent by the source.
+    pub fn source_capabilities(&self) -> &SourceCapabilities {
+        &self.capabilities
+    }
+
+    /// Negotiated specification revision.
+    pub fn spec_revision(&self) -> SpecRevision {
+        self.revision
+    }
+
+    fn reset_protocol(&mut self) {
+        self.tx_message_id = 0;
+        self.rx_message_id = None;
+    }
+
+    /// Receive a message from the source and answer it with a GoodCRC, retransmissions are dropped.
+    async fn receive(&mut self) -> Result<Message, Error> {
+        loop {
+            let mut buf = Buffer([0; MAX_MESSAGE_SIZE]);
+            let res = self.phy.receive(&mut buf.0).await;
+            if let Err(Error::HardReset) = res {
+                self.reset_protocol();
+            }
+            let len = res?;
+
+            let Some(message) = buf.0.get(..len.saturating_sub(CRC_SIZE)).and_then(Message::parse) else {
+                continue;
+            };
+            if message.header.control_message_type() == Some(ControlMessageType::GoodCrc) {
+                continue;
+            }
+
+            let good_crc = Header::new_sink(
+                ControlMessageType::GoodCrc as u8,
+                message.header.message_id(),
+                0,
+                self.revision,
+            );
+            self.phy.transmit(&good_crc.0.to_le_bytes()).await?;
+
+            if message.header.control_message_type() == Some(ControlMessageType::SoftReset) {
+                self.reset_protocol();
+            } else if self.rx_message_id == Some(message.header.message_id()) {
+                continue;
+            }
+            self.rx_message_id = Some(message.header.message_id());
+
+            return Ok(message);
+        }
+    }
+
+    /// Receive a message with a timeout.
+    async fn receive_timeout(&mut self, timeout: Duration) -> Result<Message, Error> {
+        with_timeout(timeout, self.receive())
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    /// Transmit a message, and wait for the GoodCRC of the source.
+    async fn transmit(&mut self, message_type: u8, objects: &[u32]) -> Result<(), Error> {
+        let header = Header::new_sink(message_type, self.tx_message_id, objects.len() as u8, self.revision);
+
+        let mut buf = Buffer([0; MAX_MESSAGE_SIZE]);
+        buf.0[..2].copy_from_slice(&header.0.to_le_bytes());
+        for (bytes, object) in buf.0[2..].chunks_exact_mut(4).zip(objects) {
+            bytes.copy_from_slice(&object.to_le_bytes());
+        }
+        let len = 2 + objects.len() * 4;
+
+        for _ in 0..=RETRY_COUNT {
+            self.phy.transmit(&buf.0[..len]).await?;
+
+            let mut rx = Buffer([0; MAX_MESSAGE_SIZE]);
+            let Ok(res) = with_timeout(RECEIVE_TIMEOUT, self.phy.receive(&mut rx.0)).await else {
+                continue;
+            };
+            let len = res?;
+            if let Some(message) = rx.0.get(..len.saturating_sub(CRC_SIZE)).and_then(Message::parse) {
+                if message.header.control_message_type() == Some(ControlMessageType::GoodCrc)
+                    && message.header.message_id() == self.tx_message_id
+                {
+                    self.tx_message_id = (self.tx_message_id + 1) & 0x7;
+                    return Ok(());
+                }
+            }
+        }
+
+        Err(Error::MaxRetry)
+    }
+
+    async fn transmit_control(&mut self, message_type: ControlMessageType) -> Result<(), Error> {
+        self.transmit(message_type as u8, &[]).await
+    }
+
+    /// Answer a message the sink doesn't handle.
+    async fn not_supported(&mut self) -> Result<(), Error> {
+        match self.revision {
+            SpecRevision::Rev2_0 => self.transmit_control(ControlMessageType::Reject).await,
+            SpecRevision::Rev3_0 => self.transmit_control(ControlMessageType::NotSupported).await,
+        }
+    }
+
+    /// Wait for the source to send its capabilities.
+    ///
+    /// Sources send them after attach, after a hard or soft reset, and when their capabilities
+    /// change. In the last case a new request must be sent.
+    pub async fn wait_source_capabilities(&mut self) -> Result<SourceCapabilities, Error> {
+        loop {
+            let message = self.receive_timeout(SINK_WAIT_CAP_TIMEOUT).await?;
+            if self.handle_message(&message).await? {
+                return Ok(self.capabilities);
+            }
+        }
+    }
+
+    /// Handle a message outside of a negotiation, returns whether it carried new source
+    /// capabilities.
+    async fn handle_message(&mut self, message: &Message) -> Result<bool, Error> {
+        if message.header.data_message_type() == Some(DataMessageType::SourceCapabilities) {
+            // Talk the highest revision both ports support.
+            self.revision = self.revision.min(message.header.spec_revision());
+            self.capabilities = SourceCapabilities::from_objects(message.objects());
+            return Ok(true);
+        }
+
+        match message.header.control_message_type() {
+            Some(ControlMessageType::SoftReset) => self.transmit_control(ControlMessageType::Accept).await?,
+            Some(ControlMessageType::Ping) => {}
+            Some(ControlMessageType::GetSourceCap | ControlMessageType::GetSinkCap)
+            | Some(ControlMessageType::DrSwap | ControlMessageType::PrSwap | ControlMessageType::VconnSwap)
+            | None => self.not_supported().await?,
+            Some(_) => {}
+        }
+
+        Ok(false)
+    }
+
+    /// Send a request for one of the advertised power supplies, and wait until the source has
+    /// switched to it.
+    ///
+    /// Returns [`Error::Rejected`] if the source rejects the request or asks to wait.
+    pub async fn request(&mut self, request: Request) -> Result<(), Error> {
+        if self.capabilities.get(request.position()).is_none() {
+            return Err(Error::NotSupported);
+        }
+
+        self.transmit(DataMessageType::Request as u8, &[request.0]).await?;
+
+        let message = self.receive_timeout(SENDER_RESPONSE_TIMEOUT).await?;
+        match message.header.control_message_type() {
+            Some(ControlMessageType::Accept) => {}
+            Some(ControlMessageType::Reject | ControlMessageType::Wait) => return Err(Error::Rejected),
+            _ => return Err(Error::Protocol(message.header.message_type())),
+        }
+
+        let message = self.receive_timeout(PS_TRANSITION_TIMEOUT).await?;
+        match message.header.control_message_type() {
+            Some(ControlMessageType::PsRdy) => Ok(()),
+            _ => Err(Error::Protocol(message.header.message_type())),
+        }
+    }
+
+    /// Wait for the source capabilities, and request the supply picked by `select`.
+    ///
+    /// ```ignore
+    /// let request = sink
+    ///     .negotiate(|caps| match caps.find_fixed(9000) {
+    ///         Some((position, max_current_ma)) => Request::fixed(position, max_current_ma),
+    ///         None => Request::fixed(1, 500),
+    ///     })
+    ///     .await?;
+    /// ```
+    pub async fn negotiate(
+        &mut self,
+        mut select: impl FnMut(&SourceCapabilities) -> Request,
+    ) -> Result<Request, Error> {
+        let capabilities = self.wait_source_capabilities().await?;
+        let request = select(&capabilities);
+        self.request(request).await?;
+        Ok(request)
+    }
+
+    /// Keep the explicit contract, answering the messages of the source, until it sends new
+    /// capabilities, which must be answered with a new [`request`](Self::request).
+    pub async fn wait_capabilities_change(&mut self) -> Result<SourceCapabilities, Error> {
+        loop {
+            let message = self.receive().await?;
+            if self.handle_message(&message).await? {
+                return Ok(self.capabilities);
+            }
+        }
+    }
+}
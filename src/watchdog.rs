@@ -0,0 +1,194 @@
+//! Independent watchdog (IWDG)
+//!
+//! The IWDG runs from LSI, and resets the chip unless it is fed before its timeout. Once started
+//! it can't be stopped.
+//!
+//! With several async tasks, the task feeding the watchdog keeps running when another one is stuck.
+//! [`Supervisor`] feeds it only while every task has checked in within its own deadline.
+
+use crate::pac::{IWDG, RCC};
+use crate::time::Hertz;
+use crate::{into_ref, peripherals, Peripheral, PeripheralRef};
+
+const KEY_RELOAD: u16 = 0xAAAA;
+const KEY_START: u16 = 0xCCCC;
+const KEY_UNLOCK: u16 = 0x5555;
+
+/// Largest prescaler, LSI divided by 256.
+const PRESCALER_MAX: u8 = 6;
+const RELOAD_MAX: u32 = 0xFFF;
+
+#[cfg(any(ch32v0, ch641))]
+const LSI_FREQUENCY: Hertz = crate::rcc::LSI_FREQUENCY;
+#[cfg(not(any(ch32v0, ch641)))]
+const LSI_FREQUENCY: Hertz = crate::rcc::LSI_FREQ;
+
+/// Independent watchdog driver
+pub struct IndependentWatchdog<'d> {
+    _peri: PeripheralRef<'d, peripherals::IWDG>,
+    timeout_us: u32,
+}
+
+impl<'d> IndependentWatchdog<'d> {
+    /// Configure the watchdog for a timeout of about `timeout_us` microseconds, it isn't started.
+    ///
+    /// The timeout depends on LSI, which is only accurate to tens of percent. It is clamped to the
+    /// longest the IWDG counts, 4096 × 256 LSI periods, about 26 s at 40 kHz: see [`timeout_us`]
+    /// for the one in use.
+    ///
+    /// LSI is turned on, the prescaler and reload value are only taken into account with it.
+    ///
+    /// [`timeout_us`]: Self::timeout_us
+    pub fn new(peri: impl Peripheral<P = peripherals::IWDG> + 'd, timeout_us: u32) -> Self {
+        into_ref!(peri);
+
+        let lsi = u64::from(LSI_FREQUENCY.0);
+        let cycles = u64::from(timeout_us) * lsi / 1_000_000;
+
+        // The smallest prescaler, for the finest reload value.
+        let prescaler = (0..=PRESCALER_MAX)
+            .find(|&psc| cycles / (4 << psc) <= u64::from(RELOAD_MAX))
+            .unwrap_or(PRESCALER_MAX);
+        let reload = (cycles / (4 << prescaler)).clamp(1, u64::from(RELOAD_MAX)) as u16;
+
+        RCC.rstsckr().modify(|w| w.set_lsion(true));
+        while !RCC.rstsckr().read().lsirdy() {}
+
+        IWDG.ctlr().write(|w| w.set_key(KEY_UNLOCK));
+        IWDG.pscr().write(|w| w.set_pr(prescaler));
+        IWDG.rldr().write(|w| w.set_rl(reload));
+        // The values are copied to the LSI clock domain.
+        while IWDG.statr().read().pvu() || IWDG.statr().read().rvu() {}
+
+        let timeout_us = (u64::from(reload) * (4 << prescaler) * 1_000_000 / lsi) as u32;
+        Self {
+            _peri: peri,
+            timeout_us,
+        }
+    }
+
+    /// Actual timeout, in microseconds.
+    pub fn timeout_us(&self) -> u32 {
+        self.timeout_us
+    }
+
+    /// Start the watchdog, it can't be stopped afterwards.
+    pub fn unleash(&mut self) {
+        IWDG.ctlr().write(|w| w.set_key(KEY_START));
+    }
+
+    /// Feed the watchdog, restarting its timeout.
+    pub fn pet(&mut self) {
+        IWDG.ctlr().write(|w| w.set_key(KEY_RELOAD));
+    }
+}
+
+#[cfg(feature = "embassy")]
+pub use self::supervisor::{Supervisor, TaskHandle};
+
+#[cfg(feature = "embassy")]
+mod supervisor {
+    use core::cell::Cell;
+
+    use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+    use embassy_sync::blocking_mutex::Mutex;
+    use embassy_time::{Duration, Instant, Timer};
+
+    use super::IndependentWatchdog;
+
+    #[derive(Clone, Copy)]
+    struct TaskState {
+        deadline: Duration,
+        last_check_in: Instant,
+    }
+
+    /// Feeds the watchdog while `N` tasks check in
+    ///
+    /// Each task checks in through its [`TaskHandle`] at least once per deadline. As soon as one
+    /// misses it, the watchdog isn't fed anymore and resets the chip.
+    pub struct Supervisor<const N: usize> {
+        tasks: Mutex<CriticalSectionRawMutex, Cell<[TaskState; N]>>,
+        started: Mutex<CriticalSectionRawMutex, Cell<bool>>,
+    }
+
+    impl<const N: usize> Supervisor<N> {
+        pub const fn new() -> Self {
+            const TASK: TaskState = TaskState {
+                deadline: Duration::MAX,
+                last_check_in: Instant::from_ticks(0),
+            };
+
+            Self {
+                tasks: Mutex::const_new(CriticalSectionRawMutex::new(), Cell::new([TASK; N])),
+                started: Mutex::const_new(CriticalSectionRawMutex::new(), Cell::new(false)),
+            }
+        }
+
+        /// Handles of the tasks, with their deadlines. Can only be called once.
+        pub fn handles(&'static self, deadlines: [Duration; N]) -> [TaskHandle<N>; N] {
+            self.started
+                .lock(|started| assert!(!started.replace(true), "handles already taken"));
+
+            let now = Instant::now();
+            self.tasks.lock(|tasks| {
+                tasks.set(deadlines.map(|deadline| TaskState {
+                    deadline,
+                    last_check_in: now,
+                }))
+            });
+
+            core::array::from_fn(|index| TaskHandle {
+                supervisor: self,
+                index,
+            })
+        }
+
+        /// Index of the first task past its deadline.
+        pub fn expired(&self) -> Option<usize> {
+            let now = Instant::now();
+            self.tasks.lock(|tasks| {
+                tasks
+                    .get()
+                    .iter()
+                    .position(|task| now.saturating_duration_since(task.last_check_in) > task.deadline)
+            })
+        }
+
+        /// Start the watchdog, and feed it while every task meets its deadline.
+        pub async fn run(&self, mut watchdog: IndependentWatchdog<'_>) -> ! {
+            let interval = Duration::from_micros(u64::from(watchdog.timeout_us()) / 2);
+
+            watchdog.unleash();
+            loop {
+                if self.expired().is_none() {
+                    watchdog.pet();
+                }
+                Timer::after(interval).await;
+            }
+        }
+    }
+
+    impl<const N: usize> Default for Supervisor<N> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// A task supervised by a [`Supervisor`]
+    pub struct TaskHandle<const N: usize> {
+        supervisor: &'static Supervisor<N>,
+        index: usize,
+    }
+
+    impl<const N: usize> TaskHandle<N> {
+        /// Report the task as alive.
+        pub fn check_in(&self) {
+            let now = Instant::now();
+            self.supervisor.tasks.lock(|tasks| {
+                let mut states = tasks.get();
+                states[self.index].last_check_in = now;
+                tasks.set(states);
+            });
+        }
+    }
+}